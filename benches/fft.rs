@@ -0,0 +1,49 @@
+//! Times the spectrum backends over the supported frame sizes, run with `cargo bench --bench fft`.
+//! Each backend is timed as used by the application, keeping its plans across frames, and with a
+//! new backend for every frame, which plans again and allocates its buffers every time.
+
+use std::f32::consts::PI;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use spectrum_analyzer::FrequencyLimit;
+
+use audia::engine::analysis::MAX_FFT_SIZE;
+use audia::engine::fft::FftBackend;
+
+/// Up to the largest window the application analyzes
+const FRAME_SIZES: [usize; 4] = [256, 1024, 4096, MAX_FFT_SIZE];
+const SAMPLE_RATE: u32 = 48000;
+/// Each case runs for at least this long
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+/// Average microseconds per call of `analyze`.
+fn time_per_frame<T>(mut analyze: impl FnMut() -> T) -> f64 {
+    let start = Instant::now();
+    let mut frames = 0u32;
+
+    while start.elapsed() < MIN_DURATION {
+        black_box(analyze());
+        frames += 1;
+    }
+
+    start.elapsed().as_secs_f64() * 1e6 / frames as f64
+}
+
+fn main() {
+    println!("{:>8} {:>24} {:>14} {:>14}", "samples", "backend", "cached", "planned");
+
+    for len in FRAME_SIZES {
+        let frame: Vec<f32> = (0..len)
+            .map(|i| (2.0 * PI * 997.0 * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+
+        for backend in FftBackend::ALL {
+            let mut cached = backend.create();
+            let cached_time = time_per_frame(|| cached.spectrum(black_box(&frame), SAMPLE_RATE, FrequencyLimit::All).unwrap());
+            let planned_time = time_per_frame(|| backend.create().spectrum(black_box(&frame), SAMPLE_RATE, FrequencyLimit::All).unwrap());
+
+            println!("{:>8} {:>24} {:>11.1} µs {:>11.1} µs", len, backend.to_string(), cached_time, planned_time);
+        }
+    }
+}
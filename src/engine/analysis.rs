@@ -0,0 +1,1594 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::iter::Skip;
+use std::slice::ChunksExact;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use spectrum_analyzer::{FrequencyLimit, samples_fft_to_spectrum};
+use spectrum_analyzer::scaling::divide_by_N;
+use spectrum_analyzer::windows::hann_window;
+
+use crate::engine::{AudiaError, PacketType, SampleType};
+use crate::engine::fft::SpectrumBackend;
+
+/// Corrects `divide_by_N` magnitudes so a full-scale sine reads 1.0: the Hann window halves the
+/// amplitude and the one-sided spectrum only holds half of the energy.
+pub(crate) const FULL_SCALE_CORRECTION: f32 = 4.0;
+
+/// A single spectrum point as a (frequency in Hz, amplitude) pair.
+pub type SpectrumPoint = (f32, f32);
+
+/// Returns the index of the bin with the highest amplitude. On ties the lower bin wins.
+pub fn peak_bin(spectrum: &[SpectrumPoint]) -> Option<usize> {
+    spectrum.iter()
+        .enumerate()
+        .fold(None, |best: Option<(usize, f32)>, (index, (_freq, amp))| {
+            match best {
+                Some((_, best_amp)) if best_amp >= *amp => best,
+                _ => Some((index, *amp))
+            }
+        })
+        .map(|(index, _amp)| index)
+}
+
+/// Refines the frequency of the bin at `index` by fitting a parabola through the log-magnitudes
+/// of the bin and its two neighbours. Falls back to the bin center at the edges of the spectrum or
+/// when the neighbourhood isn't a proper local maximum.
+pub fn interpolate_peak(spectrum: &[SpectrumPoint], index: usize) -> f32 {
+    let (freq, amp) = spectrum[index];
+
+    if index == 0 || index + 1 >= spectrum.len() {
+        return freq;
+    }
+
+    let left = spectrum[index - 1].1;
+    let right = spectrum[index + 1].1;
+
+    if left <= 0.0 || amp <= 0.0 || right <= 0.0 {
+        return freq;
+    }
+
+    let (alpha, beta, gamma) = (left.ln(), amp.ln(), right.ln());
+    let denominator = alpha - 2.0 * beta + gamma;
+
+    if denominator >= 0.0 {
+        return freq;
+    }
+
+    let offset = 0.5 * (alpha - gamma) / denominator;
+    let bin_width = spectrum[index + 1].0 - freq;
+
+    freq + offset * bin_width
+}
+
+/// Estimates the frequency of the strongest component in the spectrum, interpolating between bins.
+pub fn interpolated_peak_frequency(spectrum: &[SpectrumPoint]) -> Option<f32> {
+    peak_bin(spectrum).map(|index| interpolate_peak(spectrum, index))
+}
+
+/// Frequency and amplitude of the strongest component, with the frequency interpolated between bins.
+pub fn peak(spectrum: &[SpectrumPoint]) -> Option<SpectrumPoint> {
+    peak_bin(spectrum).map(|index| (interpolate_peak(spectrum, index), spectrum[index].1))
+}
+
+/// The spectrum without its lowest `bins` bins, so DC and the window's leakage of it next to 0 Hz
+/// aren't taken for a peak.
+pub fn without_low_bins(spectrum: &[SpectrumPoint], bins: usize) -> &[SpectrumPoint] {
+    &spectrum[bins.min(spectrum.len())..]
+}
+
+/// Up to `count` local maxima of at least `threshold`, strongest first, with interpolated
+/// frequencies. A maximum within `min_separation` Hz of a stronger one is taken for a part of it,
+/// e.g. the neighbouring bin of a tone between two bins.
+pub fn top_peaks(spectrum: &[SpectrumPoint], count: usize, threshold: f32, min_separation: f32) -> Vec<SpectrumPoint> {
+    let mut candidates: Vec<usize> = (0..spectrum.len())
+        .filter(|&index| {
+            let amp = spectrum[index].1;
+
+            // a flat top counts once, at its first bin
+            amp >= threshold
+                && (index == 0 || spectrum[index - 1].1 < amp)
+                && spectrum.get(index + 1).map_or(true, |(_, next)| *next <= amp)
+        })
+        .collect();
+    candidates.sort_by(|a, b| spectrum[*b].1.total_cmp(&spectrum[*a].1));
+
+    let mut peaks: Vec<SpectrumPoint> = Vec::with_capacity(count);
+
+    for index in candidates {
+        if peaks.len() >= count {
+            break;
+        }
+
+        let freq = interpolate_peak(spectrum, index);
+
+        if peaks.iter().all(|(picked, _)| (picked - freq).abs() >= min_separation) {
+            peaks.push((freq, spectrum[index].1));
+        }
+    }
+
+    peaks
+}
+
+/// Hann-windowed magnitude spectrum of a frame, scaled so a full-scale sine reads 1.0. The frame
+/// length has to be a power of two.
+pub fn spectrum(frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+    windowed_spectrum(&hann_window(frame), sample_rate, limit)
+}
+
+/// `spectrum` of a frame the Hann window was already applied to.
+pub(crate) fn windowed_spectrum(windowed: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+    let spectrum = samples_fft_to_spectrum(windowed, sample_rate, limit, Some(&divide_by_N))
+        .map_err(|error| AudiaError::from(format!("Could not extract frequency spectrum: {error:?}")))?;
+
+    Ok(spectrum.data()
+        .iter()
+        .map(|(freq, amp)| (freq.val(), amp.val() * FULL_SCALE_CORRECTION))
+        .collect())
+}
+
+/// A spectrum passed to the registered hooks.
+pub struct Spectrum<'a> {
+    pub points: &'a [SpectrumPoint],
+    /// Sample rate the frame was analyzed at, after decimation
+    pub sample_rate: u32
+}
+
+/// Identifies a registered hook so it can be removed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectrumHookId(u64);
+
+type SpectrumHook = Arc<Mutex<dyn FnMut(&Spectrum) + Send>>;
+
+struct SpectrumHooks {
+    next_id: u64,
+    hooks: Vec<(SpectrumHookId, SpectrumHook)>
+}
+
+static SPECTRUM_HOOKS: Mutex<SpectrumHooks> = Mutex::new(SpectrumHooks { next_id: 0, hooks: Vec::new() });
+
+fn spectrum_hooks() -> MutexGuard<'static, SpectrumHooks> {
+    // a panicking hook doesn't leave the list in an inconsistent state
+    SPECTRUM_HOOKS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registers a hook that is called with every spectrum `analyze_frame` computes, e.g. to log it
+/// or trigger actions. Hooks run on the analysis thread in the order they were registered. They
+/// may register or remove hooks, which applies from the next spectrum.
+pub fn register_spectrum_hook(hook: impl FnMut(&Spectrum) + Send + 'static) -> SpectrumHookId {
+    let mut hooks = spectrum_hooks();
+    let id = SpectrumHookId(hooks.next_id);
+
+    hooks.next_id += 1;
+    hooks.hooks.push((id, Arc::new(Mutex::new(hook))));
+    id
+}
+
+/// Removes a hook, returns false if it wasn't registered.
+pub fn unregister_spectrum_hook(id: SpectrumHookId) -> bool {
+    let mut hooks = spectrum_hooks();
+    let count = hooks.hooks.len();
+
+    hooks.hooks.retain(|(hook_id, _)| *hook_id != id);
+    hooks.hooks.len() != count
+}
+
+/// Calls the registered hooks with a new spectrum, done by `analyze_frame`. Embedders computing
+/// spectra some other way call this to feed the same hooks.
+pub fn notify_spectrum_hooks(spectrum: &Spectrum) {
+    // the list isn't locked while the hooks run, so they can register or remove hooks
+    let hooks: Vec<SpectrumHook> = spectrum_hooks().hooks.iter().map(|(_, hook)| hook.clone()).collect();
+
+    for hook in hooks {
+        (hook.lock().unwrap_or_else(PoisonError::into_inner))(spectrum);
+    }
+}
+
+/// Spectrum of a complete frame: averaged with the earlier frames by `welch` when given, a single
+/// FFT otherwise. The spectrum is passed to the registered hooks.
+pub fn analyze_frame(backend: &mut dyn SpectrumBackend, welch: Option<&mut Welch>, frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+    let points = match welch {
+        Some(welch) => welch.process(backend, frame, sample_rate, limit)?,
+        None => backend.spectrum(frame, sample_rate, limit)?
+    };
+
+    notify_spectrum_hooks(&Spectrum { points: &points, sample_rate });
+    Ok(points)
+}
+
+/// Welch's method: averages the power spectra of the `segment_size` windowed segments of
+/// `samples` that start every `hop` samples, the latest one ending with the samples. Returns the
+/// square root of the average power, so a steady sine reads as in `spectrum`.
+pub fn welch(backend: &mut dyn SpectrumBackend, samples: &[SampleType], segment_size: usize, hop: usize, sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+    if segment_size == 0 || samples.len() < segment_size {
+        return Err(AudiaError::from(format!("Welch's method needs at least {segment_size} samples, got {}", samples.len())));
+    }
+
+    let mut power: Vec<SpectrumPoint> = vec![];
+    let mut segments = 0;
+
+    for start in (0..=samples.len() - segment_size).rev().step_by(hop.max(1)) {
+        let segment = backend.spectrum(&samples[start..start + segment_size], sample_rate, limit)?;
+
+        if power.is_empty() {
+            power = segment.iter().map(|(freq, _)| (*freq, 0.0)).collect();
+        }
+        for ((_, sum), (_, amp)) in power.iter_mut().zip(&segment) {
+            *sum += amp * amp;
+        }
+        segments += 1;
+    }
+
+    Ok(power.into_iter()
+        .map(|(freq, sum)| (freq, (sum / segments as f32).sqrt()))
+        .collect())
+}
+
+/// Keeps the recent samples for Welch's method, so every frame is analyzed together with the
+/// overlapping segments before it. Smoother than a single FFT at the same resolution, but slower
+/// to follow changes.
+pub struct Welch {
+    /// Number of averaged segments once enough samples were collected
+    pub segments: usize,
+    /// Fraction of each segment shared with the next one, from 0 to below 1
+    pub overlap: f32,
+    history: VecDeque<SampleType>
+}
+
+impl Welch {
+    pub fn new(segments: usize, overlap: f32) -> Self {
+        Self { segments, overlap, history: VecDeque::new() }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    fn hop(&self, segment_size: usize) -> usize {
+        ((1.0 - self.overlap.clamp(0.0, 0.95)) * segment_size as f32).round().max(1.0) as usize
+    }
+
+    /// Adds a frame and returns the averaged spectrum of the segments, each as long as the frame.
+    pub fn process(&mut self, backend: &mut dyn SpectrumBackend, frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+        let segment_size = frame.len();
+        let hop = self.hop(segment_size);
+        let span = segment_size + self.segments.saturating_sub(1) * hop;
+
+        self.history.extend(frame);
+        let excess = self.history.len().saturating_sub(span);
+        self.history.drain(..excess);
+
+        welch(backend, self.history.make_contiguous(), segment_size, hop, sample_rate, limit)
+    }
+}
+
+/// Smallest and largest FFT size a window length is rounded to
+pub const MIN_FFT_SIZE: usize = 64;
+pub const MAX_FFT_SIZE: usize = 16384;
+
+/// FFT size for a window of `window_ms` milliseconds: the power of two closest in ratio to the
+/// window's sample count at `sample_rate`, limited to `MIN_FFT_SIZE..=MAX_FFT_SIZE`.
+pub fn fft_size(window_ms: f32, sample_rate: u32) -> usize {
+    let samples = (window_ms / 1000.0 * sample_rate as f32).max(1.0);
+    let exponent = (samples.log2().round() as u32).min(MAX_FFT_SIZE.trailing_zeros());
+
+    (1usize << exponent).max(MIN_FFT_SIZE)
+}
+
+/// Removes all complete frames of `frame_size` samples from the front of `buffer`. Leftover
+/// samples stay in the buffer until enough have been collected for another frame.
+pub fn take_frames(buffer: &mut PacketType, frame_size: usize) -> Vec<PacketType> {
+    if frame_size == 0 {
+        return vec![];
+    }
+
+    let complete = buffer.len() / frame_size * frame_size;
+    let frames = buffer[..complete]
+        .chunks_exact(frame_size)
+        .map(<[SampleType]>::to_vec)
+        .collect();
+
+    buffer.drain(..complete);
+    frames
+}
+
+/// A frame of `frame_size` samples with the latest `samples` in its center and zeros around them,
+/// to show a spectrum before a whole window was collected. Padding adds no resolution: the
+/// spectrum is the one of the shorter signal spread over the bins of the full frame, so peaks get
+/// wider and close tones merge. The samples are scaled up so a tone still reads its level under
+/// the Hann window of the full frame.
+pub fn zero_padded_frame(samples: &[SampleType], frame_size: usize) -> PacketType {
+    let len = samples.len().min(frame_size);
+    let start = (frame_size - len) / 2;
+    let window = hann_window(&vec![1.0; frame_size]);
+    let covered: f32 = window[start..start + len].iter().sum();
+    let gain = if covered > 0.0 { window.iter().sum::<f32>() / covered } else { 1.0 };
+
+    let mut frame = vec![0.0; frame_size];
+    for (padded, sample) in frame[start..start + len].iter_mut().zip(&samples[samples.len() - len..]) {
+        *padded = sample * gain;
+    }
+
+    frame
+}
+
+/// Number of the oldest of `queued` frames to skip: all but the most recent one when more than
+/// `max_queued` are waiting, so a backlog costs a single FFT and the display stays current. A
+/// `max_queued` of 0 never skips any.
+pub fn coalesced_frames(queued: usize, max_queued: usize) -> usize {
+    if max_queued == 0 || queued <= max_queued {
+        return 0;
+    }
+
+    queued - 1
+}
+
+/// The complete frames of `buffer` that are analyzed, with the backlog `coalesced_frames` drops
+/// already skipped, and the number of skipped frames.
+pub fn queued_frames(buffer: &[SampleType], frame_size: usize, max_queued: usize) -> (usize, Skip<ChunksExact<'_, SampleType>>) {
+    let skipped = coalesced_frames(buffer.len() / frame_size, max_queued);
+
+    (skipped, buffer.chunks_exact(frame_size).skip(skipped))
+}
+
+/// Root-mean-square level of the samples, 0.0 for an empty slice.
+pub fn rms(samples: &[SampleType]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Ratio of the peak to the RMS level of the samples in dB, e.g. about 3 dB for a sine. None for
+/// (near) silence, where the ratio is meaningless.
+pub fn crest_factor_db(samples: &[SampleType]) -> Option<f32> {
+    let rms = rms(samples);
+
+    if rms < 1e-6 {
+        return None;
+    }
+
+    let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    Some(20.0 * (peak / rms).log10())
+}
+
+/// Whether the RMS level of a frame lies below `threshold_db` dBFS.
+pub fn is_silent(frame: &[SampleType], threshold_db: f32) -> bool {
+    rms(frame) < 10f32.powf(threshold_db / 20.0)
+}
+
+/// Applies `gain_db` to the samples in place and clamps the result to full scale. Non-finite
+/// samples are replaced with silence. Returns whether any sample had to be clamped.
+pub fn apply_gain(samples: &mut [SampleType], gain_db: f32) -> bool {
+    let gain = 10f32.powf(gain_db / 20.0);
+    let mut clipped = false;
+
+    for sample in samples.iter_mut() {
+        let amplified = *sample * gain;
+
+        *sample = if !amplified.is_finite() {
+            0.0
+        } else if amplified.abs() > 1.0 {
+            clipped = true;
+            amplified.clamp(-1.0, 1.0)
+        } else {
+            amplified
+        };
+    }
+
+    clipped
+}
+
+/// Averages the channels of an interleaved packet into a single mono channel. A trailing partial
+/// frame is dropped.
+pub fn downmix(packet: &[SampleType], channels: usize) -> PacketType {
+    if channels <= 1 {
+        return packet.to_vec();
+    }
+
+    packet.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<SampleType>() / channels as SampleType)
+        .collect()
+}
+
+/// Fits user-supplied channel weights to the channel count: missing weights are 0 and extra ones
+/// are dropped. The result is scaled so the absolute weights add up to 1, which keeps a full-scale
+/// input from exceeding full scale. Without any non-zero weight this is a flat downmix.
+pub fn normalize_weights(weights: &[f32], channels: usize) -> Vec<f32> {
+    let mut fitted: Vec<f32> = weights.iter()
+        .copied()
+        .chain(std::iter::repeat(0.0))
+        .take(channels)
+        .map(|weight| if weight.is_finite() { weight } else { 0.0 })
+        .collect();
+
+    let total: f32 = fitted.iter().map(|weight| weight.abs()).sum();
+
+    if total == 0.0 {
+        return vec![1.0 / channels.max(1) as f32; channels];
+    }
+
+    fitted.iter_mut().for_each(|weight| *weight /= total);
+    fitted
+}
+
+/// Mixes the channels of an interleaved packet into mono with one weight per channel, e.g.
+/// `[0.5, -0.5]` for the side signal of a stereo pair. A trailing partial frame is dropped.
+pub fn weighted_downmix(packet: &[SampleType], weights: &[f32]) -> PacketType {
+    let mut mixed = vec![];
+    weighted_downmix_into(packet, weights, &mut mixed);
+
+    mixed
+}
+
+/// `weighted_downmix` appended to `mixed`, which avoids an allocation per packet.
+pub fn weighted_downmix_into(packet: &[SampleType], weights: &[f32], mixed: &mut PacketType) {
+    if weights.is_empty() {
+        return;
+    }
+
+    mixed.extend(packet.chunks_exact(weights.len())
+        .map(|frame| frame.iter().zip(weights).map(|(sample, weight)| sample * weight).sum::<SampleType>()));
+}
+
+/// Which channels of the input are analyzed: a weighted downmix, a single channel, or left and
+/// right side by side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChannelView {
+    #[default]
+    Downmix,
+    Left,
+    Right,
+    Dual
+}
+
+impl ChannelView {
+    pub const ALL: [ChannelView; 4] = [ChannelView::Downmix, ChannelView::Left, ChannelView::Right, ChannelView::Dual];
+
+    /// Weights for the analyzed signal, plus the weights of the second trace in the dual view.
+    /// A mono input analyzes its only channel in every view.
+    pub fn weights(&self, channel_weights: &[f32], channels: usize) -> (Vec<f32>, Option<Vec<f32>>) {
+        match self {
+            ChannelView::Downmix => (normalize_weights(channel_weights, channels), None),
+            ChannelView::Left => (select_channel(0, channels), None),
+            ChannelView::Right => (select_channel(1, channels), None),
+            ChannelView::Dual => (select_channel(0, channels), Some(select_channel(1, channels)))
+        }
+    }
+}
+
+impl Display for ChannelView {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChannelView::Downmix => "Downmix",
+            ChannelView::Left => "Left only",
+            ChannelView::Right => "Right only",
+            ChannelView::Dual => "Left and right"
+        })
+    }
+}
+
+/// Weights picking a single channel, or the last one if the input has fewer channels.
+fn select_channel(channel: usize, channels: usize) -> Vec<f32> {
+    let channel = channel.min(channels.saturating_sub(1));
+
+    (0..channels).map(|index| if index == channel { 1.0 } else { 0.0 }).collect()
+}
+
+/// Low cut, decimation and framing for a signal analyzed next to the main one, e.g. the right
+/// channel in the dual view. It keeps its own filter state but uses the same settings.
+pub struct ChannelChain {
+    low_cut: Option<HighPass>,
+    decimator: Option<Decimator>,
+    buffer: PacketType,
+    /// The latest complete frame, its allocation is reused for the next one
+    frame: PacketType
+}
+
+impl ChannelChain {
+    pub fn new(low_cut: Option<HighPass>, decimator: Option<Decimator>) -> Self {
+        Self {
+            low_cut,
+            decimator,
+            buffer: vec![],
+            frame: vec![]
+        }
+    }
+
+    /// Filters a packet and returns the latest complete frame, older ones are dropped.
+    pub fn process(&mut self, packet: &mut PacketType, frame_size: usize) -> Option<&[SampleType]> {
+        if let Some(low_cut) = &mut self.low_cut {
+            low_cut.process(packet);
+        }
+
+        if let Some(decimator) = &mut self.decimator {
+            *packet = decimator.process(packet);
+        }
+
+        self.buffer.append(packet);
+
+        let complete = self.buffer.len().checked_div(frame_size).unwrap_or(0) * frame_size;
+        if complete == 0 {
+            return None;
+        }
+
+        self.frame.clear();
+        self.frame.extend_from_slice(&self.buffer[complete - frame_size..complete]);
+        self.buffer.drain(..complete);
+
+        Some(&self.frame)
+    }
+}
+
+/// Parses comma or space separated channel weights, e.g. "1, -1".
+pub fn parse_weights(text: &str) -> Result<Vec<f32>, AudiaError> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f32>()
+            .map_err(|_| AudiaError::from(format!("Invalid channel weight: {part}"))))
+        .collect()
+}
+
+/// Nominal ISO 266 third-octave center frequencies from 31.5 Hz to 16 kHz.
+const THIRD_OCTAVE_NOMINALS: [f32; 28] = [
+    31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0, 500.0, 630.0,
+    800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0, 8000.0, 10000.0,
+    12500.0, 16000.0
+];
+
+/// Index of the 1 kHz reference band in `THIRD_OCTAVE_NOMINALS`
+const REFERENCE_BAND: usize = 15;
+
+/// Width of the fractional-octave bands used by the band analyzer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BandWidth {
+    Octave,
+    #[default]
+    ThirdOctave
+}
+
+impl BandWidth {
+    pub const ALL: [BandWidth; 2] = [BandWidth::Octave, BandWidth::ThirdOctave];
+
+    fn bands_per_octave(&self) -> usize {
+        match self {
+            BandWidth::Octave => 1,
+            BandWidth::ThirdOctave => 3
+        }
+    }
+}
+
+impl Display for BandWidth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BandWidth::Octave => "1/1 octave",
+            BandWidth::ThirdOctave => "1/3 octave"
+        })
+    }
+}
+
+/// A fractional-octave band with its exact (base-10) edges and its nominal center frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub nominal: f32,
+    pub center: f32,
+    pub lower: f32,
+    pub upper: f32
+}
+
+impl Band {
+    /// Standard label of the band, e.g. "31.5" or "1.25k".
+    pub fn label(&self) -> String {
+        if self.nominal >= 1000.0 {
+            format!("{}k", self.nominal / 1000.0)
+        } else {
+            format!("{}", self.nominal)
+        }
+    }
+}
+
+/// The standard bands of the given width that overlap the `0..max_freq` range.
+pub fn bands(width: BandWidth, max_freq: f32) -> Vec<Band> {
+    // base-10 octave ratio as defined by IEC 61260
+    let octave_ratio = 10f32.powf(0.3);
+    let step = 3 / width.bands_per_octave();
+
+    THIRD_OCTAVE_NOMINALS.iter()
+        .enumerate()
+        .filter(|(index, _)| (*index as i32 - REFERENCE_BAND as i32).rem_euclid(step as i32) == 0)
+        .map(|(index, nominal)| {
+            let exponent = (index as f32 - REFERENCE_BAND as f32) / 3.0;
+            let center = 1000.0 * octave_ratio.powf(exponent);
+            let half_band = octave_ratio.powf(1.0 / (2.0 * width.bands_per_octave() as f32));
+
+            Band { nominal: *nominal, center, lower: center / half_band, upper: center * half_band }
+        })
+        .filter(|band| band.lower < max_freq)
+        .collect()
+}
+
+/// Equivalent noise bandwidth of the Hann window in bins: a tone spreads its energy over this many
+/// bins, so summing them overstates the band energy by the same factor.
+const HANN_ENBW: f32 = 1.5;
+
+/// Sums the energy of the spectrum bins falling into each band and returns the band amplitudes.
+pub fn band_levels(spectrum: &[SpectrumPoint], bands: &[Band]) -> Vec<f32> {
+    bands.iter()
+        .map(|band| {
+            let energy: f32 = spectrum.iter()
+                .filter(|(freq, _)| *freq >= band.lower && *freq < band.upper)
+                .map(|(_, amp)| amp * amp)
+                .sum();
+
+            (energy / HANN_ENBW).sqrt()
+        })
+        .collect()
+}
+
+/// Fraction of the decimated Nyquist frequency that is kept by the anti-aliasing filter.
+const DECIMATION_PASS_BAND: f32 = 0.9;
+
+/// Picks the largest decimation factor that divides `sample_rate` and still keeps `max_freq` inside
+/// the pass band of the anti-aliasing filter.
+pub fn decimation_factor(sample_rate: u32, max_freq: f32) -> usize {
+    let limit = (DECIMATION_PASS_BAND * sample_rate as f32 / (2.0 * max_freq)).floor().max(1.0) as usize;
+
+    (1..=limit).rev()
+        .find(|factor| sample_rate as usize % factor == 0)
+        .unwrap_or(1)
+}
+
+/// Low-pass filters and downsamples a stream of samples by an integer factor. The filter state is
+/// kept between calls so packets can be fed one after another.
+pub struct Decimator {
+    factor: usize,
+    taps: Vec<f32>,
+    history: VecDeque<f32>,
+    phase: usize
+}
+
+impl Decimator {
+    pub fn new(factor: usize) -> Self {
+        let factor = factor.max(1);
+        let taps = low_pass_taps(16 * factor + 1, DECIMATION_PASS_BAND * 0.5 / factor as f32);
+
+        Self {
+            factor,
+            history: VecDeque::from(vec![0.0; taps.len()]),
+            taps,
+            phase: 0
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(input.len() / self.factor + 1);
+
+        for sample in input {
+            self.history.pop_front();
+            self.history.push_back(*sample);
+
+            self.phase += 1;
+
+            if self.phase == self.factor {
+                self.phase = 0;
+                output.push(self.history.iter().zip(self.taps.iter().rev()).map(|(x, h)| x * h).sum());
+            }
+        }
+
+        output
+    }
+}
+
+/// One-pole high-pass filter that removes DC offset and low-frequency rumble before analysis. The
+/// filter state is kept between calls so packets can be fed one after another.
+pub struct HighPass {
+    cutoff: f32,
+    coefficient: f32,
+    previous_input: f32,
+    previous_output: f32
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32, sample_rate: u32) -> Self {
+        Self {
+            cutoff,
+            coefficient: (-2.0 * PI * cutoff / sample_rate.max(1) as f32).exp(),
+            previous_input: 0.0,
+            previous_output: 0.0
+        }
+    }
+
+    /// Cutoff frequency in Hz
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub fn process(&mut self, samples: &mut [SampleType]) {
+        for sample in samples.iter_mut() {
+            let output = self.coefficient * (self.previous_output + *sample - self.previous_input);
+
+            self.previous_input = *sample;
+            self.previous_output = output;
+            *sample = output;
+        }
+    }
+}
+
+/// Blackman-windowed sinc low-pass filter with `cutoff` given in cycles per sample.
+fn low_pass_taps(len: usize, cutoff: f32) -> Vec<f32> {
+    let middle = (len - 1) as f32 / 2.0;
+
+    let taps: Vec<f32> = (0..len)
+        .map(|i| {
+            let n = i as f32 - middle;
+            let sinc = if n == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * n).sin() / (PI * n)
+            };
+            let window = 0.42
+                - 0.5 * (2.0 * PI * i as f32 / (len - 1) as f32).cos()
+                + 0.08 * (4.0 * PI * i as f32 / (len - 1) as f32).cos();
+
+            sinc * window
+        })
+        .collect();
+
+    let gain: f32 = taps.iter().sum();
+
+    taps.into_iter().map(|tap| tap / gain).collect()
+}
+
+/// Bins quieter than this (relative to full scale, about -60 dBFS) are never reported as feedback
+const FEEDBACK_FLOOR: f32 = 0.001;
+
+/// Detects acoustic feedback: a narrow peak that stands far above its neighbourhood and stays
+/// there for several consecutive frames. Ordinary peaks in music come and go or are wider.
+pub struct FeedbackDetector {
+    /// How far in dB a bin has to rise above the bins next to its main lobe
+    pub sensitivity_db: f32,
+    /// Number of consecutive frames a peak has to persist
+    pub persistence: usize,
+    /// Consecutive frames each bin has been a candidate
+    counts: Vec<usize>
+}
+
+impl FeedbackDetector {
+    pub fn new(sensitivity_db: f32, persistence: usize) -> Self {
+        Self { sensitivity_db, persistence, counts: vec![] }
+    }
+
+    pub fn reset(&mut self) {
+        self.counts.clear();
+    }
+
+    /// Feeds a spectrum frame and returns the (interpolated) frequencies that currently look like
+    /// feedback.
+    pub fn process(&mut self, spectrum: &[SpectrumPoint]) -> Vec<f32> {
+        if self.counts.len() != spectrum.len() {
+            self.counts = vec![0; spectrum.len()];
+        }
+
+        let ratio = 10f32.powf(self.sensitivity_db / 20.0);
+
+        for index in 0..spectrum.len() {
+            let is_candidate = index >= 3 && index + 3 < spectrum.len() && {
+                let amp = spectrum[index].1;
+                // the window spreads a pure tone over the neighbouring bins, so compare against
+                // the bins just outside of the main lobe
+                let surrounding = spectrum[index - 3].1.max(spectrum[index + 3].1);
+
+                amp >= FEEDBACK_FLOOR
+                    && amp >= spectrum[index - 1].1
+                    && amp >= spectrum[index + 1].1
+                    && amp >= surrounding * ratio
+            };
+
+            self.counts[index] = if is_candidate { self.counts[index] + 1 } else { 0 };
+        }
+
+        self.counts.iter()
+            .enumerate()
+            .filter(|(_, count)| **count >= self.persistence.max(1))
+            .map(|(index, _)| interpolate_peak(spectrum, index))
+            .collect()
+    }
+}
+
+/// Meter ballistics: follows a level with separate attack and release time constants. `process`
+/// is expected to be called `rate` times per second, e.g. once per sample.
+pub struct EnvelopeFollower {
+    /// Attack time constant in ms
+    attack: f32,
+    /// Release time constant in ms
+    release: f32,
+    rate: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    state: f32
+}
+
+impl EnvelopeFollower {
+    pub fn new(attack: f32, release: f32, rate: u32) -> Self {
+        let mut follower = Self {
+            attack,
+            release,
+            rate: rate as f32,
+            attack_coefficient: 0.0,
+            release_coefficient: 0.0,
+            state: 0.0
+        };
+        follower.update_coefficients();
+        follower
+    }
+
+    pub fn attack(&self) -> f32 {
+        self.attack
+    }
+
+    pub fn release(&self) -> f32 {
+        self.release
+    }
+
+    pub fn set_times(&mut self, attack: f32, release: f32) {
+        self.attack = attack;
+        self.release = release;
+        self.update_coefficients();
+    }
+
+    pub fn set_rate(&mut self, rate: u32) {
+        self.rate = rate as f32;
+        self.update_coefficients();
+    }
+
+    /// The level reached so far.
+    pub fn level(&self) -> f32 {
+        self.state
+    }
+
+    pub fn reset(&mut self) {
+        self.state = 0.0;
+    }
+
+    pub fn process(&mut self, level: f32) -> f32 {
+        let coefficient = if level > self.state { self.attack_coefficient } else { self.release_coefficient };
+
+        self.state = level + coefficient * (self.state - level);
+        self.state
+    }
+
+    /// A time constant of `time_ms` means the output covers 1 - 1/e (about 63%) of a step within
+    /// that time.
+    fn coefficient(&self, time_ms: f32) -> f32 {
+        if time_ms <= 0.0 || self.rate <= 0.0 {
+            0.0
+        } else {
+            (-1000.0 / (time_ms * self.rate)).exp()
+        }
+    }
+
+    fn update_coefficients(&mut self) {
+        self.attack_coefficient = self.coefficient(self.attack);
+        self.release_coefficient = self.coefficient(self.release);
+    }
+}
+
+/// Counts down the frames that are analyzed but not shown after a stream starts, while the
+/// device, the decimation filter and the buffers settle.
+pub struct Warmup {
+    frames: usize,
+    remaining: usize
+}
+
+impl Warmup {
+    pub fn new(frames: usize) -> Self {
+        Self { frames, remaining: frames }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Changes the warmup length, which applies from the next restart.
+    pub fn set_frames(&mut self, frames: usize) {
+        self.frames = frames;
+        self.remaining = self.remaining.min(frames);
+    }
+
+    pub fn restart(&mut self) {
+        self.remaining = self.frames;
+    }
+
+    /// Counts frames that were dropped without being analyzed.
+    pub fn skip(&mut self, frames: usize) {
+        self.remaining = self.remaining.saturating_sub(frames);
+    }
+
+    pub fn is_primed(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Counts a frame, returning whether it should be shown.
+    pub fn next_frame(&mut self) -> bool {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Counts down the samples that still have to be analyzed after a clip, so a freeze only happens
+/// once the window the clip fell into was analyzed.
+#[derive(Default)]
+pub struct PendingClip {
+    remaining: Option<usize>
+}
+
+impl PendingClip {
+    /// Marks a clip within the latest `unanalyzed` samples. An earlier clip that is still pending
+    /// is kept, its window completes first.
+    pub fn clip(&mut self, unanalyzed: usize) {
+        self.remaining.get_or_insert(unanalyzed);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.remaining = None;
+    }
+
+    /// Counts analyzed samples, returning whether the window with the clip is complete.
+    pub fn analyzed(&mut self, samples: usize) -> bool {
+        match self.remaining {
+            Some(remaining) if samples >= remaining => {
+                self.remaining = None;
+                true
+            }
+            Some(remaining) => {
+                self.remaining = Some(remaining - samples);
+                false
+            }
+            None => false
+        }
+    }
+}
+
+/// Bins where the sweep stayed this far below its strongest bin are left out of a response, they
+/// were hardly excited
+const RESPONSE_DYNAMIC_RANGE_DB: f32 = 40.0;
+
+/// Measures a frequency response by holding the peak of every bin while a sweep plays: once for
+/// the spectrum of the sweep itself and once for the captured signal. Their ratio is the response
+/// of everything between the output and the input, independently of the latency in between.
+#[derive(Default)]
+pub struct ResponseMeasurement {
+    reference: Vec<SpectrumPoint>,
+    captured: Vec<SpectrumPoint>
+}
+
+impl ResponseMeasurement {
+    pub fn add_reference(&mut self, spectrum: &[SpectrumPoint]) {
+        hold_peaks(&mut self.reference, spectrum);
+    }
+
+    pub fn add_captured(&mut self, spectrum: &[SpectrumPoint]) {
+        hold_peaks(&mut self.captured, spectrum);
+    }
+
+    /// The response in dB per bin, smoothed over `fraction` of an octave (0 for no smoothing).
+    pub fn response(&self, fraction: f32) -> Vec<SpectrumPoint> {
+        if self.reference.len() != self.captured.len() {
+            return vec![];
+        }
+
+        let strongest = self.reference.iter().map(|(_, amp)| *amp).fold(0.0, f32::max);
+        let floor = strongest * 10f32.powf(-RESPONSE_DYNAMIC_RANGE_DB / 20.0);
+
+        let response: Vec<SpectrumPoint> = self.reference.iter()
+            .zip(&self.captured)
+            .filter(|((freq, reference), _)| *freq > 0.0 && *reference > floor)
+            .map(|((freq, reference), (_, captured))| (*freq, 20.0 * (captured.max(1e-9) / reference).log10()))
+            .collect();
+
+        smooth_octave(&response, fraction)
+    }
+}
+
+fn hold_peaks(held: &mut Vec<SpectrumPoint>, spectrum: &[SpectrumPoint]) {
+    if held.len() != spectrum.len() {
+        *held = spectrum.to_vec();
+        return;
+    }
+
+    for (held, (_, amp)) in held.iter_mut().zip(spectrum) {
+        held.1 = held.1.max(*amp);
+    }
+}
+
+/// Averages every point with its neighbours within `fraction` of an octave around it. Meant for
+/// dB values, so the result follows the shape of a curve rather than its peaks.
+pub fn smooth_octave(points: &[SpectrumPoint], fraction: f32) -> Vec<SpectrumPoint> {
+    if fraction <= 0.0 {
+        return points.to_vec();
+    }
+
+    let half_width = 2f32.powf(fraction / 2.0);
+
+    points.iter()
+        .map(|(freq, _)| {
+            let (lower, upper) = (freq / half_width, freq * half_width);
+            let (sum, count) = points.iter()
+                .filter(|(other, _)| *other >= lower && *other <= upper)
+                .fold((0.0, 0), |(sum, count), (_, value)| (sum + value, count + 1));
+
+            (*freq, sum / count.max(1) as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use spectrum_analyzer::scaling::divide_by_N_sqrt;
+
+    use crate::engine::Engine;
+    use crate::engine::fft::SpectrumAnalyzerBackend;
+    use crate::engine::mock::MockEngine;
+    use crate::engine::source::{SyntheticSource, TestSignalKind};
+
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    fn spectrum_of(samples: &[f32], sample_rate: u32) -> Vec<SpectrumPoint> {
+        let windowed = hann_window(samples);
+        samples_fft_to_spectrum(&windowed, sample_rate, FrequencyLimit::Max(2200.0), Some(&divide_by_N_sqrt))
+            .expect("Could not extract frequency spectrum")
+            .data()
+            .iter()
+            .map(|(freq, amp)| (freq.val(), amp.val()))
+            .collect()
+    }
+
+    #[test]
+    fn interpolated_peak_is_within_a_few_cents() {
+        // 440 Hz sits roughly halfway between two bins at this resolution (~11.7 Hz/bin)
+        let spectrum = spectrum_of(&sine(440.0, 48000, 4096), 48000);
+
+        let estimate = interpolated_peak_frequency(&spectrum).unwrap();
+        let cents = 1200.0 * (estimate / 440.0).log2();
+
+        assert!(cents.abs() < 5.0, "estimate {estimate} Hz is {cents} cents off");
+    }
+
+    #[test]
+    fn dc_offset_is_not_reported_as_the_peak() {
+        let samples: Vec<f32> = sine(1000.0, 48000, 1024).iter().map(|sample| 0.8 + 0.3 * sample).collect();
+        let spectrum = spectrum_of(&samples, 48000);
+
+        // the Hann window leaks the offset into the first bin too
+        assert!(peak(&spectrum).unwrap().0 < 50.0);
+        assert!(peak(without_low_bins(&spectrum, 1)).unwrap().0 < 50.0);
+
+        let (freq, _) = peak(without_low_bins(&spectrum, 2)).unwrap();
+        assert!((freq - 1000.0).abs() < 25.0, "peak at {freq} Hz");
+        assert!(without_low_bins(&spectrum, 10_000).is_empty());
+    }
+
+    #[test]
+    fn top_peaks_are_sorted_and_thresholded() {
+        let samples: Vec<f32> = sine(440.0, 48000, 4096).iter()
+            .zip(sine(1000.0, 48000, 4096))
+            .zip(sine(1500.0, 48000, 4096))
+            .map(|((a, b), c)| a + 0.3 * b + 0.01 * c)
+            .collect();
+        let spectrum = spectrum(&samples, 48000, FrequencyLimit::Max(2200.0)).unwrap();
+
+        let peaks = top_peaks(&spectrum, 5, 0.05, 30.0);
+
+        assert_eq!(peaks.len(), 2, "{peaks:?}");
+        assert!((peaks[0].0 - 440.0).abs() < 2.0);
+        assert!((peaks[1].0 - 1000.0).abs() < 2.0);
+        assert!(peaks[0].1 > peaks[1].1);
+    }
+
+    #[test]
+    fn close_peaks_are_merged_into_the_stronger_one() {
+        let spectrum = vec![(0.0, 0.0), (10.0, 0.5), (20.0, 0.4), (30.0, 0.6), (40.0, 0.0), (50.0, 0.2), (60.0, 0.0)];
+
+        assert_eq!(top_peaks(&spectrum, 5, 0.1, 25.0), vec![(30.0, 0.6)]);
+        assert_eq!(top_peaks(&spectrum, 5, 0.1, 15.0), vec![(30.0, 0.6), (10.0, 0.5), (50.0, 0.2)]);
+        assert_eq!(top_peaks(&spectrum, 5, 0.3, 15.0).len(), 2);
+        assert_eq!(top_peaks(&spectrum, 1, 0.1, 15.0).len(), 1);
+    }
+
+    #[test]
+    fn edge_bins_are_not_interpolated() {
+        let spectrum = vec![(0.0, 4.0), (10.0, 2.0), (20.0, 1.0)];
+
+        assert_eq!(interpolated_peak_frequency(&spectrum), Some(0.0));
+        assert_eq!(interpolated_peak_frequency(&[]), None);
+    }
+
+    #[test]
+    fn decimation_factor_divides_the_sample_rate() {
+        assert_eq!(decimation_factor(48000, 2200.0), 8);
+        assert_eq!(decimation_factor(44100, 2200.0), 9);
+        assert_eq!(decimation_factor(48000, 24000.0), 1);
+    }
+
+    #[test]
+    fn decimation_keeps_in_band_tones() {
+        let mut decimator = Decimator::new(8);
+        let output = decimator.process(&sine(500.0, 48000, 8192));
+
+        assert_eq!(output.len(), 1024);
+        // skip the filter's start-up transient
+        assert!((rms(&output[64..]) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn decimation_does_not_alias_out_of_band_tones() {
+        // without filtering, 5 kHz would fold back to 1 kHz at the decimated rate of 6 kHz
+        let mut decimator = Decimator::new(8);
+        let output = decimator.process(&sine(5000.0, 48000, 8192));
+
+        assert!(rms(&output[64..]) < 0.001);
+    }
+
+    #[test]
+    fn silence_gate_engages_below_the_threshold() {
+        let hiss: Vec<f32> = sine(1000.0, 48000, 1024).iter().map(|sample| sample * 1e-4).collect();
+
+        assert!(is_silent(&vec![0.0; 1024], -70.0));
+        // a tone at about -83 dBFS is below the gate, at full scale it isn't
+        assert!(is_silent(&hiss, -70.0));
+        assert!(!is_silent(&sine(1000.0, 48000, 1024), -70.0));
+    }
+
+    #[test]
+    fn crest_factor_of_sine_and_square() {
+        let sine = sine(1000.0, 48000, 4800);
+        let square: Vec<f32> = sine.iter().map(|sample| if *sample >= 0.0 { 0.5 } else { -0.5 }).collect();
+
+        assert!((crest_factor_db(&sine).unwrap() - 3.01).abs() < 0.05);
+        assert!(crest_factor_db(&square).unwrap().abs() < 0.01);
+        assert_eq!(crest_factor_db(&[0.0; 256]), None);
+    }
+
+    #[test]
+    fn downmix_averages_interleaved_channels() {
+        let stereo = vec![1.0, 0.0, 0.5, 0.5, -1.0, 1.0, 0.25, -0.75];
+
+        assert_eq!(downmix(&stereo, 2), vec![0.5, 0.5, 0.0, -0.25]);
+        assert_eq!(downmix(&stereo[..7], 2).len(), 3);
+        assert_eq!(downmix(&stereo, 1), stereo);
+    }
+
+    #[test]
+    fn high_pass_removes_dc_offset() {
+        let mut filter = HighPass::new(20.0, 48000);
+        let mut samples: Vec<f32> = sine(1000.0, 48000, 4096 * 4).iter().map(|sample| 0.5 * sample + 0.3).collect();
+
+        filter.process(&mut samples);
+
+        // skip the settling of the filter
+        let frame = &samples[samples.len() - 4096..];
+        let spectrum = spectrum(frame, 48000, FrequencyLimit::All).unwrap();
+        let (peak_freq, peak_amp) = peak(&spectrum).unwrap();
+
+        assert!(spectrum[0].1 < 0.01, "DC bin still at {}", spectrum[0].1);
+        assert!((peak_freq - 1000.0).abs() < 5.0);
+        assert!((peak_amp - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn response_is_the_ratio_of_captured_and_reference_peaks() {
+        let mut measurement = ResponseMeasurement::default();
+
+        measurement.add_reference(&[(0.0, 1.0), (100.0, 0.5), (200.0, 0.0), (300.0, 0.0)]);
+        measurement.add_reference(&[(0.0, 0.0), (100.0, 0.0), (200.0, 0.5), (300.0, 0.0)]);
+        measurement.add_captured(&[(0.0, 0.5), (100.0, 0.25), (200.0, 0.0), (300.0, 0.1)]);
+        measurement.add_captured(&[(0.0, 0.0), (100.0, 0.1), (200.0, 0.5), (300.0, 0.0)]);
+
+        let response = measurement.response(0.0);
+
+        // DC and the bin the sweep never reached are left out
+        assert_eq!(response.iter().map(|(freq, _)| *freq).collect::<Vec<_>>(), vec![100.0, 200.0]);
+        assert!((response[0].1 + 6.02).abs() < 0.01);
+        assert!(response[1].1.abs() < 0.01);
+    }
+
+    #[test]
+    fn octave_smoothing_spreads_narrow_peaks() {
+        let points = vec![(100.0, 0.0), (105.0, 12.0), (110.0, 0.0), (400.0, 0.0)];
+        let smoothed = smooth_octave(&points, 1.0 / 3.0);
+
+        assert!((smoothed[1].1 - 4.0).abs() < 1e-4);
+        assert_eq!(smoothed[3].1, 0.0);
+    }
+
+    #[test]
+    fn weights_are_fitted_to_the_channels_and_normalized() {
+        assert_eq!(normalize_weights(&[1.0, -1.0], 2), vec![0.5, -0.5]);
+        assert_eq!(normalize_weights(&[3.0], 2), vec![1.0, 0.0]);
+        assert_eq!(normalize_weights(&[1.0, 1.0, 2.0], 2), vec![0.5, 0.5]);
+        assert_eq!(normalize_weights(&[], 4), vec![0.25; 4]);
+    }
+
+    #[test]
+    fn weighted_downmix_extracts_mid_and_side() {
+        let stereo = vec![1.0, 0.5, -0.5, 0.5];
+
+        assert_eq!(weighted_downmix(&stereo, &normalize_weights(&[1.0, 1.0], 2)), vec![0.75, 0.0]);
+        assert_eq!(weighted_downmix(&stereo, &normalize_weights(&[1.0, -1.0], 2)), vec![0.25, -0.5]);
+        assert_eq!(parse_weights("1, -1 0.5").unwrap(), vec![1.0, -1.0, 0.5]);
+        assert!(parse_weights("1, left").is_err());
+    }
+
+    #[test]
+    fn channel_views_select_left_and_right() {
+        let stereo = vec![1.0, 0.5, -0.5, 0.25];
+        let (left, right) = ChannelView::Dual.weights(&[], 2);
+
+        assert_eq!(weighted_downmix(&stereo, &left), vec![1.0, -0.5]);
+        assert_eq!(weighted_downmix(&stereo, &right.unwrap()), vec![0.5, 0.25]);
+        assert_eq!(ChannelView::Right.weights(&[], 2), (vec![0.0, 1.0], None));
+        assert_eq!(ChannelView::Downmix.weights(&[], 2), (vec![0.5, 0.5], None));
+        // a mono input has no right channel, both traces show the same signal
+        assert_eq!(ChannelView::Dual.weights(&[], 1), (vec![1.0], Some(vec![1.0])));
+    }
+
+    #[test]
+    fn band_centers_match_the_nominal_frequencies() {
+        let third_octaves = bands(BandWidth::ThirdOctave, 20000.0);
+        let octaves = bands(BandWidth::Octave, 20000.0);
+
+        assert_eq!(third_octaves.len(), 28);
+        assert_eq!(octaves.iter().map(Band::label).collect::<Vec<_>>(),
+            vec!["31.5", "63", "125", "250", "500", "1k", "2k", "4k", "8k", "16k"]);
+
+        for band in third_octaves.iter().chain(octaves.iter()) {
+            assert!((band.center / band.nominal - 1.0).abs() < 0.03, "{band:?}");
+        }
+
+        for pair in third_octaves.windows(2) {
+            assert!((pair[0].upper - pair[1].lower).abs() < 0.01 * pair[0].upper);
+        }
+    }
+
+    #[test]
+    fn band_levels_collect_the_energy_of_their_bins() {
+        let bands = bands(BandWidth::Octave, 2200.0);
+        let spectrum = vec![(990.0, 0.3), (1010.0, 0.4), (3000.0, 1.0)];
+
+        let levels = band_levels(&spectrum, &bands);
+
+        assert_eq!(bands.len(), 7);
+        assert!((levels[5] - 0.5 / HANN_ENBW.sqrt()).abs() < 1e-6);
+        assert_eq!(levels.iter().filter(|level| **level > 0.0).count(), 1);
+    }
+
+    #[test]
+    fn band_levels_match_the_level_of_tones_and_noise() {
+        let bands = bands(BandWidth::Octave, 24000.0);
+        let tone = spectrum(&sine(1000.0, 48000, 4096), 48000, FrequencyLimit::All).unwrap();
+        let one_k = bands.iter().position(|band| band.nominal == 1000.0).unwrap();
+
+        let level = band_levels(&tone, &bands)[one_k];
+        assert!((level - 1.0).abs() < 0.02, "{level}");
+
+        // white noise spreads its power evenly up to Nyquist, read on the same scale as a sine peak
+        let noise = SyntheticSource::new(TestSignalKind::Noise, 48000, 4096).generate(4096);
+        let variance = noise.iter().map(|sample| sample * sample).sum::<f32>() / noise.len() as f32;
+        let eight_k = bands.iter().position(|band| band.nominal == 8000.0).unwrap();
+        let band = &bands[eight_k];
+
+        let level = band_levels(&spectrum(&noise, 48000, FrequencyLimit::All).unwrap(), &bands)[eight_k];
+        let expected = 2.0 * variance * (band.upper - band.lower) / 24000.0;
+        assert!((level * level / expected - 1.0).abs() < 0.15, "{} vs {expected}", level * level);
+    }
+
+    #[test]
+    fn gain_clamps_and_flags_clipping() {
+        let mut samples = vec![0.1, -0.5, 0.9];
+
+        assert!(!apply_gain(&mut samples, 0.0));
+        assert_eq!(samples, vec![0.1, -0.5, 0.9]);
+
+        // +12 dB pushes the louder samples past full scale
+        assert!(apply_gain(&mut samples, 12.0));
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+        assert_eq!(samples[1..], [-1.0, 1.0]);
+    }
+
+    #[test]
+    fn gain_keeps_samples_finite() {
+        let mut samples = vec![f32::NAN, f32::INFINITY, f32::MAX, 0.5];
+
+        apply_gain(&mut samples, 1000.0);
+
+        assert!(samples.iter().all(|sample| sample.is_finite()));
+        assert_eq!(samples[..2], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn envelope_rises_with_the_attack_time() {
+        // 1 kHz update rate, so 10 ms are 10 steps
+        let mut follower = EnvelopeFollower::new(10.0, 100.0, 1000);
+
+        for _ in 0..10 {
+            follower.process(1.0);
+        }
+        assert!((follower.level() - (1.0 - (-1.0f32).exp())).abs() < 1e-3);
+
+        for _ in 0..40 {
+            follower.process(1.0);
+        }
+        assert!(follower.level() > 0.99);
+    }
+
+    #[test]
+    fn envelope_falls_with_the_release_time() {
+        let mut follower = EnvelopeFollower::new(0.0, 100.0, 1000);
+
+        // no attack time: the level jumps up immediately
+        assert_eq!(follower.process(1.0), 1.0);
+
+        for _ in 0..100 {
+            follower.process(0.0);
+        }
+        assert!((follower.level() - (-1.0f32).exp()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sustained_narrow_peak_is_reported_as_feedback() {
+        let spectrum = spectrum_of(&sine(1000.0, 48000, 4096), 48000);
+        let mut detector = FeedbackDetector::new(20.0, 3);
+
+        assert!(detector.process(&spectrum).is_empty());
+        assert!(detector.process(&spectrum).is_empty());
+
+        let detected = detector.process(&spectrum);
+        assert_eq!(detected.len(), 1);
+        assert!((detected[0] - 1000.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn interrupted_or_flat_spectra_are_not_feedback() {
+        let tone = spectrum_of(&sine(1000.0, 48000, 4096), 48000);
+        let flat: Vec<SpectrumPoint> = tone.iter().map(|(freq, _)| (*freq, 0.1)).collect();
+        let mut detector = FeedbackDetector::new(20.0, 3);
+
+        detector.process(&tone);
+        detector.process(&tone);
+        // the tone stops for a frame, so the count starts over
+        assert!(detector.process(&flat).is_empty());
+        assert!(detector.process(&tone).is_empty());
+        assert!(detector.process(&flat).is_empty());
+    }
+
+    #[test]
+    fn empty_and_short_packets_accumulate_into_frames() {
+        let mut buffer = vec![];
+
+        assert!(take_frames(&mut buffer, 4).is_empty());
+
+        buffer.extend([1.0, 2.0, 3.0]);
+        assert!(take_frames(&mut buffer, 4).is_empty());
+        assert_eq!(buffer.len(), 3);
+
+        buffer.extend([4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let frames = take_frames(&mut buffer, 4);
+
+        assert_eq!(frames, vec![vec![1.0, 2.0, 3.0, 4.0], vec![5.0, 6.0, 7.0, 8.0]]);
+        assert_eq!(buffer, vec![9.0]);
+    }
+
+    #[test]
+    fn zero_padded_half_window_keeps_the_level_at_lower_resolution() {
+        // centered on bin 85, so neither spectrum loses level between bins
+        let tone = 85.0 * 48000.0 / 4096.0;
+        let full = spectrum(&sine(tone, 48000, 4096), 48000, FrequencyLimit::All).unwrap();
+        let (full_freq, full_amp) = peak(&full).unwrap();
+
+        let frame = zero_padded_frame(&sine(tone, 48000, 2048), 4096);
+        assert_eq!(frame.len(), 4096);
+        assert_eq!(frame[..1024], [0.0; 1024]);
+        assert_eq!(frame[3072..], [0.0; 1024]);
+
+        let padded = spectrum(&frame, 48000, FrequencyLimit::All).unwrap();
+        let (freq, amp) = peak(&padded).unwrap();
+        assert!((freq - full_freq).abs() < 1.0, "{freq} vs {full_freq}");
+        assert!((amp - full_amp).abs() < 0.02, "{amp} vs {full_amp}");
+
+        // the peak is twice as wide as with a full window
+        let wide = |points: &[SpectrumPoint], amp: f32| points.iter().filter(|(_, bin_amp)| *bin_amp > amp / 4.0).count();
+        assert!(wide(&padded, amp) > wide(&full, full_amp));
+    }
+
+    #[test]
+    fn channel_chain_returns_the_latest_complete_frame() {
+        let mut chain = ChannelChain::new(None, None);
+
+        assert_eq!(chain.process(&mut vec![1.0, 2.0, 3.0], 4), None);
+        assert_eq!(chain.process(&mut vec![4.0, 5.0, 6.0, 7.0, 8.0, 9.0], 4), Some(&[5.0, 6.0, 7.0, 8.0][..]));
+        assert_eq!(chain.process(&mut vec![10.0, 11.0, 12.0], 4), Some(&[9.0, 10.0, 11.0, 12.0][..]));
+    }
+
+    #[test]
+    fn only_the_latest_frame_of_a_backlog_is_kept() {
+        assert_eq!(coalesced_frames(3, 3), 0);
+        assert_eq!(coalesced_frames(3, 2), 2);
+        assert_eq!(coalesced_frames(0, 2), 0);
+        assert_eq!(coalesced_frames(2, 0), 0);
+    }
+
+    #[test]
+    fn a_drained_backlog_analyzes_only_the_latest_frame() {
+        let mut engine = MockEngine::default();
+        let stream = engine.start_recording().ok().expect("Recording should have started");
+
+        // five packets of one frame each pile up between two ticks
+        for value in 0..5 {
+            engine.send(vec![value as f32; 256]);
+        }
+
+        let buffer: Vec<SampleType> = std::iter::from_fn(|| stream.receive().ok()).flatten().collect();
+        let (skipped, frames) = queued_frames(&buffer, 256, 2);
+
+        assert_eq!(skipped, 4);
+        assert_eq!(frames.collect::<Vec<_>>(), vec![&[4.0; 256][..]]);
+        assert_eq!(queued_frames(&buffer, 256, 0).1.count(), 5);
+    }
+
+    #[test]
+    fn warmup_suppresses_the_first_frames_after_each_restart() {
+        let mut warmup = Warmup::new(2);
+
+        assert_eq!([warmup.next_frame(), warmup.next_frame(), warmup.next_frame()], [false, false, true]);
+        assert!(warmup.is_primed());
+
+        warmup.restart();
+        assert!(!warmup.next_frame());
+
+        warmup.set_frames(0);
+        assert!(warmup.next_frame());
+    }
+
+    #[test]
+    fn clip_is_pending_until_its_window_was_analyzed() {
+        let mut pending = PendingClip::default();
+        assert!(!pending.analyzed(1024));
+
+        // 300 samples of the clipped packet wait in the buffer for the rest of their window
+        pending.clip(300);
+        pending.clip(2000);
+        assert!(!pending.analyzed(0));
+        assert!(pending.is_pending());
+        assert!(pending.analyzed(1024));
+        assert!(!pending.is_pending());
+
+        // the clip was in a window analyzed in the same tick
+        pending.clip(0);
+        assert!(pending.analyzed(0));
+    }
+
+    /// Variance of the bin powers relative to their mean, 1 for the periodogram of white noise
+    fn normalized_variance(spectrum: &[SpectrumPoint]) -> f32 {
+        let powers: Vec<f32> = spectrum[2..spectrum.len() - 2].iter().map(|(_, amp)| amp * amp).collect();
+        let mean = powers.iter().sum::<f32>() / powers.len() as f32;
+        let variance = powers.iter().map(|power| (power - mean).powi(2)).sum::<f32>() / powers.len() as f32;
+
+        variance / (mean * mean)
+    }
+
+    #[test]
+    fn welch_reduces_the_variance_of_noise() {
+        let noise = SyntheticSource::new(TestSignalKind::Noise, 48000, 1024).generate(1024 * 8);
+        let mut welch = Welch::new(15, 0.5);
+
+        let mut averaged = vec![];
+        for frame in noise.chunks(1024) {
+            averaged = welch.process(&mut SpectrumAnalyzerBackend::default(), frame, 48000, FrequencyLimit::All).unwrap();
+        }
+        let single = spectrum(&noise[noise.len() - 1024..], 48000, FrequencyLimit::All).unwrap();
+
+        assert_eq!(averaged.len(), single.len());
+        assert!(normalized_variance(&single) > 0.5, "{}", normalized_variance(&single));
+        assert!(normalized_variance(&averaged) < 0.3 * normalized_variance(&single),
+            "{} vs {}", normalized_variance(&averaged), normalized_variance(&single));
+    }
+
+    #[test]
+    fn welch_keeps_the_level_of_a_sine() {
+        let samples = sine(1000.0, 48000, 4096);
+
+        let (_, single) = peak(&spectrum(&samples[..1024], 48000, FrequencyLimit::All).unwrap()).unwrap();
+        let mut backend = SpectrumAnalyzerBackend::default();
+        let (_, averaged) = peak(&welch(&mut backend, &samples, 1024, 256, 48000, FrequencyLimit::All).unwrap()).unwrap();
+
+        assert!((averaged - single).abs() < 0.02, "{averaged} vs {single}");
+        assert!(welch(&mut backend, &samples[..100], 1024, 256, 48000, FrequencyLimit::All).is_err());
+    }
+
+    #[test]
+    fn invalid_frames_are_reported_instead_of_panicking() {
+        // the FFT only accepts power-of-two lengths
+        assert!(spectrum(&[0.0; 1000], 48000, FrequencyLimit::All).is_err());
+        assert!(spectrum(&[], 48000, FrequencyLimit::All).is_err());
+        assert!(Welch::new(4, 0.5).process(&mut SpectrumAnalyzerBackend::default(), &[0.0; 1000], 48000, FrequencyLimit::All).is_err());
+    }
+
+    #[test]
+    fn window_lengths_round_to_the_nearest_power_of_two() {
+        // 5 ms are 240 samples at 48 kHz
+        assert_eq!(fft_size(5.0, 48000), 256);
+        assert_eq!(fft_size(100.0, 48000), 4096);
+        assert_eq!(fft_size(100.0, 96000), 8192);
+        // the same window needs fewer samples after decimation
+        assert_eq!(fft_size(100.0, 6000), 512);
+        assert_eq!(fft_size(0.1, 48000), MIN_FFT_SIZE);
+        assert_eq!(fft_size(10000.0, 48000), MAX_FFT_SIZE);
+    }
+
+    #[test]
+    fn spectrum_hooks_are_called_until_unregistered() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hook_calls = calls.clone();
+        let id = register_spectrum_hook(move |spectrum| {
+            if spectrum.sample_rate == 48000 {
+                hook_calls.fetch_add(spectrum.points.len(), Ordering::Relaxed);
+            }
+        });
+        let points = spectrum(&sine(1000.0, 48000, 1024), 48000, FrequencyLimit::All).unwrap();
+
+        notify_spectrum_hooks(&Spectrum { points: &points, sample_rate: 48000 });
+        assert_eq!(calls.load(Ordering::Relaxed), points.len());
+
+        assert!(unregister_spectrum_hook(id));
+        assert!(!unregister_spectrum_hook(id));
+        notify_spectrum_hooks(&Spectrum { points: &points, sample_rate: 48000 });
+        assert_eq!(calls.load(Ordering::Relaxed), points.len());
+    }
+
+    #[test]
+    fn analyzed_frames_reach_hooks_that_remove_themselves() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hook_calls = calls.clone();
+        let own_id = Arc::new(Mutex::new(None));
+        let hook_id = own_id.clone();
+        let id = register_spectrum_hook(move |spectrum| {
+            // other tests may analyze frames meanwhile, only the tone of this one is counted
+            if spectrum.sample_rate == 44100 {
+                hook_calls.fetch_add(1, Ordering::Relaxed);
+                assert!(unregister_spectrum_hook(hook_id.lock().unwrap().unwrap()));
+            }
+        });
+        *own_id.lock().unwrap() = Some(id);
+
+        let mut backend = SpectrumAnalyzerBackend::default();
+        let frame = sine(1000.0, 44100, 1024);
+        analyze_frame(&mut backend, None, &frame, 44100, FrequencyLimit::All).unwrap();
+        analyze_frame(&mut backend, Some(&mut Welch::new(2, 0.5)), &frame, 44100, FrequencyLimit::All).unwrap();
+
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+}
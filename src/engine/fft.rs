@@ -0,0 +1,176 @@
+//! Interchangeable implementations of the spectrum computation. Both keep what only depends on
+//! the FFT size, planned once per size and reused for every frame of that size: `realfft` its
+//! plan, window and buffers, `spectrum-analyzer` the window and the windowed frame, its FFT is
+//! set up inside the crate. Both return the same points, compare their speed and the cost of
+//! planning every frame with `cargo bench --bench fft`.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use realfft::{RealFftPlanner, RealToComplex};
+use realfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use spectrum_analyzer::FrequencyLimit;
+use spectrum_analyzer::windows::hann_window;
+
+use crate::engine::{AudiaError, SampleType};
+use crate::engine::analysis::{self, FULL_SCALE_CORRECTION, SpectrumPoint};
+
+/// Computes the Hann-windowed magnitude spectrum of a frame, see `analysis::spectrum`.
+pub trait SpectrumBackend: Send {
+    fn spectrum(&mut self, frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError>;
+}
+
+/// The available spectrum backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FftBackend {
+    #[default]
+    SpectrumAnalyzer,
+    RealFft
+}
+
+impl FftBackend {
+    pub const ALL: [FftBackend; 2] = [FftBackend::SpectrumAnalyzer, FftBackend::RealFft];
+
+    pub fn create(&self) -> Box<dyn SpectrumBackend> {
+        match self {
+            FftBackend::SpectrumAnalyzer => Box::<SpectrumAnalyzerBackend>::default(),
+            FftBackend::RealFft => Box::<RealFftBackend>::default()
+        }
+    }
+}
+
+impl Display for FftBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FftBackend::SpectrumAnalyzer => "spectrum-analyzer",
+            FftBackend::RealFft => "realfft"
+        })
+    }
+}
+
+/// Hann window of `len` samples, the same spectrum-analyzer applies so both backends agree.
+fn window(len: usize) -> Vec<f32> {
+    hann_window(&vec![1.0; len])
+}
+
+/// The spectrum-analyzer crate with the window of each size computed once.
+#[derive(Default)]
+pub struct SpectrumAnalyzerBackend {
+    windows: HashMap<usize, Vec<f32>>,
+    windowed: Vec<SampleType>
+}
+
+impl SpectrumBackend for SpectrumAnalyzerBackend {
+    fn spectrum(&mut self, frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+        let window = self.windows.entry(frame.len()).or_insert_with(|| window(frame.len()));
+
+        self.windowed.clear();
+        self.windowed.extend(frame.iter().zip(window.iter()).map(|(sample, weight)| sample * weight));
+
+        analysis::windowed_spectrum(&self.windowed, sample_rate, limit)
+    }
+}
+
+/// Plan, window and buffers of one FFT size.
+struct PlannedFft {
+    plan: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>
+}
+
+impl PlannedFft {
+    fn new(planner: &mut RealFftPlanner<f32>, len: usize) -> Self {
+        let plan = planner.plan_fft_forward(len);
+
+        Self {
+            window: window(len),
+            input: plan.make_input_vec(),
+            output: plan.make_output_vec(),
+            scratch: plan.make_scratch_vec(),
+            plan
+        }
+    }
+}
+
+/// Real-input FFT, planned once for each frame size. The sizes are powers of two within
+/// `MIN_FFT_SIZE..=MAX_FFT_SIZE`, so only a handful of plans are ever kept.
+#[derive(Default)]
+pub struct RealFftBackend {
+    planner: RealFftPlanner<f32>,
+    plans: HashMap<usize, PlannedFft>
+}
+
+impl SpectrumBackend for RealFftBackend {
+    fn spectrum(&mut self, frame: &[SampleType], sample_rate: u32, limit: FrequencyLimit) -> Result<Vec<SpectrumPoint>, AudiaError> {
+        if frame.len() < 4 || !frame.len().is_power_of_two() {
+            return Err(AudiaError::from(format!("Could not extract frequency spectrum: {} samples aren't a power of two", frame.len())));
+        }
+
+        let planner = &mut self.planner;
+        let fft = self.plans.entry(frame.len()).or_insert_with(|| PlannedFft::new(planner, frame.len()));
+
+        for ((input, sample), weight) in fft.input.iter_mut().zip(frame).zip(&fft.window) {
+            *input = sample * weight;
+        }
+
+        fft.plan.process_with_scratch(&mut fft.input, &mut fft.output, &mut fft.scratch)
+            .map_err(|error| AudiaError::from(format!("Could not extract frequency spectrum: {error}")))?;
+
+        let resolution = sample_rate as f32 / frame.len() as f32;
+        let scale = FULL_SCALE_CORRECTION / frame.len() as f32;
+        let (min, max) = match limit {
+            FrequencyLimit::All => (0.0, f32::INFINITY),
+            FrequencyLimit::Min(min) => (min, f32::INFINITY),
+            FrequencyLimit::Max(max) => (0.0, max),
+            FrequencyLimit::Range(min, max) => (min, max)
+        };
+
+        Ok(fft.output.iter()
+            .enumerate()
+            .map(|(bin, value)| (bin as f32 * resolution, value.norm() * scale))
+            .filter(|(freq, _)| (min..=max).contains(freq))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use spectrum_analyzer::FrequencyLimit;
+
+    use crate::engine::analysis;
+    use crate::engine::fft::FftBackend;
+
+    #[test]
+    fn backends_compute_the_same_spectrum() {
+        let mut backends = FftBackend::ALL.map(|backend| backend.create());
+
+        // the cached plans and windows are reused for repeated sizes, also after other sizes
+        for (len, limit) in [(256, FrequencyLimit::All), (4096, FrequencyLimit::Max(5000.0)), (4096, FrequencyLimit::Range(100.0, 2000.0)), (256, FrequencyLimit::All)] {
+            let frame: Vec<f32> = (0..len)
+                .map(|i| 0.5 * (2.0 * PI * 997.0 * i as f32 / 48000.0).sin() + 0.1 * (2.0 * PI * 60.0 * i as f32 / 48000.0).sin())
+                .collect();
+
+            let expected = analysis::spectrum(&frame, 48000, limit).unwrap();
+
+            for backend in backends.iter_mut() {
+                let actual = backend.spectrum(&frame, 48000, limit).unwrap();
+
+                assert_eq!(actual.len(), expected.len(), "{len} samples, {limit:?}");
+                for ((freq, amp), (expected_freq, expected_amp)) in actual.iter().zip(&expected) {
+                    assert!((freq - expected_freq).abs() < 1e-3);
+                    assert!((amp - expected_amp).abs() < 1e-5, "{freq} Hz: {amp} vs {expected_amp}");
+                }
+            }
+        }
+
+        for backend in backends.iter_mut() {
+            assert!(backend.spectrum(&[0.0; 1000], 48000, FrequencyLimit::All).is_err());
+        }
+    }
+}
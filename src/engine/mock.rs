@@ -0,0 +1,467 @@
+use std::sync::Arc;
+
+use cpal::{BuildStreamError, SampleFormat};
+use crossbeam_channel::Sender;
+
+use crate::engine::monitor::{self, LimiterControl, Monitor, MonitorError, MonitorOutput, MonitorRetry};
+use crate::engine::watcher::DeviceWatcher;
+use crate::engine::{AudiaError, ALREADY_RECORDING, AudioHostName, AudioStream, BufferRequest, CaptureFormat, ChannelRequest, disambiguate, Engine, EXCLUSIVE_UNSUPPORTED, InputDeviceName, keyed_default, OutputDeviceName, PacketType, select_sample_format, ShareMode};
+
+const MOCK_SAMPLE_RATE: u32 = 48000;
+
+/// Hardware-free engine that serves a fixed set of hosts and devices. Tests can make the next
+/// `start_recording` call fail with a given stream error, make the monitor output fail to open or
+/// disappear, add hosts that fail to open or have no input devices, and feed packets into running
+/// streams.
+pub struct MockEngine {
+    hosts: Vec<AudioHostName>,
+    /// Hosts that are listed but fail to open
+    failing_hosts: Vec<AudioHostName>,
+    /// Hosts that open without any input device
+    inputless_hosts: Vec<AudioHostName>,
+    /// Plain names of the input devices, equal names are told apart by `disambiguate`
+    input_devices: Vec<InputDeviceName>,
+    /// Plain name of the default input device
+    default_input: InputDeviceName,
+    output_devices: Vec<OutputDeviceName>,
+    current_host: Option<AudioHostName>,
+    current_input_device: Option<InputDeviceName>,
+    current_output_device: Option<OutputDeviceName>,
+    share_mode: ShareMode,
+    channel_request: ChannelRequest,
+    sample_format: CaptureFormat,
+    buffer_request: BufferRequest,
+    /// Formats the mock device offers, the first one is its default
+    supported_formats: Vec<SampleFormat>,
+    build_error: Option<BuildStreamError>,
+    sender: Option<Sender<PacketType>>,
+    /// Output end of the running monitor; the mock never plays it
+    monitor_output: Option<MonitorOutput>,
+    monitor_retry: MonitorRetry,
+    /// Number of coming attempts to open the monitor output that fail
+    monitor_failures: usize,
+    monitor_lost: bool
+}
+
+impl MockEngine {
+    pub fn fail_next_recording(&mut self, error: BuildStreamError) {
+        self.build_error = Some(error);
+    }
+
+    pub fn set_supported_formats(&mut self, formats: Vec<SampleFormat>) {
+        self.supported_formats = formats;
+    }
+
+    pub fn add_host(&mut self, host_name: &str) {
+        self.hosts.push(String::from(host_name));
+    }
+
+    /// Adds a host that is listed but fails to open.
+    pub fn add_failing_host(&mut self, host_name: &str) {
+        self.add_host(host_name);
+        self.failing_hosts.push(String::from(host_name));
+    }
+
+    /// Adds a host that opens without any input device.
+    pub fn add_inputless_host(&mut self, host_name: &str) {
+        self.add_host(host_name);
+        self.inputless_hosts.push(String::from(host_name));
+    }
+
+    /// Replaces the input devices with devices of the given names, which may repeat, and selects
+    /// the default one like a host would.
+    pub fn set_input_devices(&mut self, names: &[&str], default: &str) {
+        self.input_devices = names.iter().map(|name| String::from(*name)).collect();
+        self.default_input = String::from(default);
+        self.current_input_device = self.current_host.as_ref().and_then(|host_name| self.default_input_of(host_name));
+    }
+
+    /// The keys of the input devices, see `disambiguate`.
+    fn get_input_devices_of(&self, host_name: &AudioHostName) -> Vec<InputDeviceName> {
+        if self.inputless_hosts.contains(host_name) {
+            vec![]
+        } else {
+            disambiguate(self.input_devices.clone())
+        }
+    }
+
+    fn default_input_of(&self, host_name: &AudioHostName) -> Option<InputDeviceName> {
+        let devices: Vec<(InputDeviceName, ())> = self.get_input_devices_of(host_name).into_iter().map(|key| (key, ())).collect();
+
+        (!devices.is_empty()).then(|| keyed_default(devices, self.default_input.clone(), ()).0)
+    }
+
+    /// Makes the next `attempts` attempts to open the monitor output fail with `DeviceNotAvailable`.
+    pub fn fail_next_monitoring(&mut self, attempts: usize) {
+        self.monitor_failures = attempts;
+    }
+
+    /// Stops the monitor output as if its device was unplugged.
+    pub fn lose_monitor_output(&mut self) {
+        self.monitor_output = None;
+        self.monitor_lost = true;
+    }
+
+    pub fn send(&self, packet: PacketType) {
+        if let Some(sender) = &self.sender {
+            sender.send(packet).expect("Mock stream has been closed");
+        }
+    }
+}
+
+impl Default for MockEngine {
+    fn default() -> Self {
+        Self {
+            hosts: vec![String::from("Mock")],
+            failing_hosts: vec![],
+            inputless_hosts: vec![],
+            input_devices: vec![String::from("Mock input")],
+            default_input: String::from("Mock input"),
+            output_devices: vec![String::from("Mock output")],
+            current_host: Some(String::from("Mock")),
+            current_input_device: Some(String::from("Mock input")),
+            current_output_device: Some(String::from("Mock output")),
+            share_mode: ShareMode::default(),
+            channel_request: ChannelRequest::default(),
+            sample_format: CaptureFormat::default(),
+            buffer_request: BufferRequest::default(),
+            supported_formats: vec![SampleFormat::F32, SampleFormat::I16],
+            build_error: None,
+            sender: None,
+            monitor_output: None,
+            monitor_retry: MonitorRetry::default(),
+            monitor_failures: 0,
+            monitor_lost: false
+        }
+    }
+}
+
+impl Engine for MockEngine {
+    fn get_available_hosts(&self) -> Vec<AudioHostName> {
+        self.hosts.clone()
+    }
+
+    fn get_current_host(&self) -> Option<AudioHostName> {
+        self.current_host.clone()
+    }
+
+    fn use_host(&mut self, host_name: AudioHostName) -> Result<(), AudiaError> {
+        if !self.hosts.contains(&host_name) {
+            return Err(AudiaError::from(format!("Unknown audio host: {host_name}")));
+        }
+        if self.failing_hosts.contains(&host_name) {
+            return Err(AudiaError::from(format!("Could not open audio host {host_name}: mock failure")));
+        }
+
+        self.current_input_device = self.default_input_of(&host_name);
+        self.current_host = Some(host_name);
+        self.current_output_device = self.output_devices.first().cloned();
+
+        Ok(())
+    }
+
+    fn get_input_devices(&self) -> Vec<InputDeviceName> {
+        self.current_host.as_ref()
+            .map(|host_name| self.get_input_devices_of(host_name))
+            .unwrap_or_default()
+    }
+
+    fn get_current_input_device(&self) -> Option<InputDeviceName> {
+        self.current_input_device.clone()
+    }
+
+    fn use_input_device(&mut self, device_name: InputDeviceName) {
+        if self.get_input_devices().contains(&device_name) {
+            self.current_input_device = Some(device_name);
+        }
+    }
+
+    fn get_default_sample_rate(&self) -> Option<u32> {
+        self.current_input_device.as_ref().map(|_| MOCK_SAMPLE_RATE)
+    }
+
+    fn get_output_devices(&self) -> Vec<OutputDeviceName> {
+        self.output_devices.clone()
+    }
+
+    fn get_current_output_device(&self) -> Option<OutputDeviceName> {
+        self.current_output_device.clone()
+    }
+
+    fn use_output_device(&mut self, device_name: OutputDeviceName) {
+        if self.output_devices.contains(&device_name) {
+            self.current_output_device = Some(device_name);
+        }
+    }
+
+    fn get_share_mode(&self) -> ShareMode {
+        self.share_mode
+    }
+
+    fn use_share_mode(&mut self, share_mode: ShareMode) {
+        self.share_mode = share_mode;
+    }
+
+    fn get_channel_request(&self) -> ChannelRequest {
+        self.channel_request
+    }
+
+    fn use_channel_request(&mut self, request: ChannelRequest) {
+        self.channel_request = request;
+    }
+
+    fn get_sample_format(&self) -> CaptureFormat {
+        self.sample_format
+    }
+
+    fn use_sample_format(&mut self, format: CaptureFormat) {
+        self.sample_format = format;
+    }
+
+    fn get_buffer_request(&self) -> BufferRequest {
+        self.buffer_request
+    }
+
+    fn use_buffer_request(&mut self, request: BufferRequest) {
+        self.buffer_request = request;
+    }
+
+    fn watch_devices(&self) -> Option<DeviceWatcher> {
+        // the mock devices never change on their own
+        None
+    }
+
+    fn start_recording(&mut self) -> Result<AudioStream, AudiaError> {
+        if self.sender.is_some() {
+            return Err(AudiaError::from(ALREADY_RECORDING));
+        }
+
+        if let Some(error) = self.build_error.take() {
+            return Err(AudiaError::from(error));
+        }
+
+        if self.share_mode == ShareMode::Exclusive {
+            return Err(AudiaError::from(EXCLUSIVE_UNSUPPORTED));
+        }
+
+        let (tx, rx) = crossbeam_channel::unbounded::<PacketType>();
+        self.sender = Some(tx);
+
+        let channels = match self.channel_request {
+            ChannelRequest::DeviceDefault => 1,
+            ChannelRequest::Count(channels) => channels
+        };
+
+        let default_format = self.supported_formats.first().copied().unwrap_or(SampleFormat::F32);
+        let sample_format = select_sample_format(self.sample_format, &self.supported_formats, default_format);
+
+        let buffer_frames = match self.buffer_request {
+            BufferRequest::DeviceDefault => None,
+            BufferRequest::Fixed(frames) => Some(frames)
+        };
+
+        Ok(AudioStream::new(rx, channels, MOCK_SAMPLE_RATE)
+            .with_sample_format(sample_format)
+            .with_buffer_frames(buffer_frames))
+    }
+
+    fn stop_recording(&mut self) {
+        self.sender = None;
+    }
+
+    fn start_monitoring(&mut self, channels: u16, sample_rate: u32, limiter: Arc<LimiterControl>) -> Result<Monitor, MonitorError> {
+        if self.monitor_lost {
+            self.monitor_lost = false;
+            self.current_output_device = self.output_devices.first().cloned();
+        }
+
+        if self.monitor_failures > 0 {
+            self.monitor_failures -= 1;
+            return Err(MonitorError::from(BuildStreamError::DeviceNotAvailable));
+        }
+
+        let max_delay = monitor::delay_samples(monitor::MAX_MONITOR_DELAY_MS, channels, sample_rate);
+        let (monitor, output) = monitor::monitor_channel(sample_rate as usize * channels as usize / 5, max_delay, limiter);
+        self.monitor_output = Some(output);
+
+        Ok(monitor)
+    }
+
+    fn stop_monitoring(&mut self) {
+        self.monitor_output = None;
+    }
+
+    fn get_monitor_retry(&self) -> MonitorRetry {
+        self.monitor_retry
+    }
+
+    fn use_monitor_retry(&mut self, retry: MonitorRetry) {
+        self.monitor_retry = retry;
+    }
+
+    fn monitor_lost(&self) -> bool {
+        self.monitor_lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use cpal::{BackendSpecificError, BuildStreamError, SampleFormat};
+
+    use crate::engine::{CaptureFormat, Engine, EXCLUSIVE_UNSUPPORTED, ShareMode};
+    use crate::engine::mock::MockEngine;
+    use crate::engine::monitor::{LimiterControl, MonitorRetry, RetrySchedule};
+
+    fn recording_error(error: BuildStreamError) -> String {
+        let mut engine = MockEngine::default();
+        engine.fail_next_recording(error);
+
+        engine.start_recording().err().expect("Recording should have failed").to_string()
+    }
+
+    fn backend_error(description: &str) -> BuildStreamError {
+        BuildStreamError::BackendSpecific { err: BackendSpecificError { description: String::from(description) } }
+    }
+
+    #[test]
+    fn busy_devices_are_reported() {
+        assert_eq!(recording_error(backend_error("Device or resource busy")), "Device is in use by another application");
+        assert_eq!(recording_error(backend_error("The device is used in exclusive mode")), "Device is in use by another application");
+        assert!(recording_error(BuildStreamError::DeviceNotAvailable).contains("in use by another application"));
+    }
+
+    #[test]
+    fn other_stream_errors_are_reported() {
+        assert_eq!(recording_error(BuildStreamError::StreamConfigNotSupported), "Device does not support the requested stream configuration");
+        assert_eq!(recording_error(BuildStreamError::InvalidArgument), "Invalid stream configuration");
+        assert_eq!(recording_error(BuildStreamError::StreamIdOverflow), "Too many streams have been opened");
+        assert_eq!(recording_error(backend_error("Something broke")), "Audio backend error: Something broke");
+    }
+
+    #[test]
+    fn recording_recovers_after_failure() {
+        let mut engine = MockEngine::default();
+        engine.fail_next_recording(BuildStreamError::DeviceNotAvailable);
+
+        assert!(engine.start_recording().is_err());
+        assert!(engine.start_recording().is_ok());
+    }
+
+    #[test]
+    fn exclusive_mode_is_refused() {
+        let mut engine = MockEngine::default();
+        engine.use_share_mode(ShareMode::Exclusive);
+
+        assert_eq!(engine.start_recording().err().map(|error| error.to_string()), Some(String::from(EXCLUSIVE_UNSUPPORTED)));
+
+        engine.use_share_mode(ShareMode::Shared);
+        assert!(engine.start_recording().is_ok());
+    }
+
+    #[test]
+    fn packets_reach_the_stream() {
+        let mut engine = MockEngine::default();
+        let stream = engine.start_recording().ok().expect("Recording should have started");
+
+        engine.send(vec![0.5, -0.5]);
+
+        assert_eq!(stream.receive().ok(), Some(vec![0.5, -0.5]));
+    }
+
+    #[test]
+    fn starting_twice_keeps_the_running_recording() {
+        let mut engine = MockEngine::default();
+        let stream = engine.start_recording().ok().expect("Recording should have started");
+
+        assert!(engine.start_recording().is_err());
+
+        engine.send(vec![1.0]);
+        assert_eq!(stream.receive().ok(), Some(vec![1.0]));
+
+        engine.stop_recording();
+        assert!(engine.start_recording().is_ok());
+    }
+
+    #[test]
+    fn preferred_sample_format_is_used_when_supported() {
+        let mut engine = MockEngine::default();
+        engine.use_sample_format(CaptureFormat::I16);
+
+        let stream = engine.start_recording().ok().expect("Recording should have started");
+
+        assert_eq!(stream.sample_format(), SampleFormat::I16);
+    }
+
+    #[test]
+    fn unsupported_sample_format_falls_back_to_the_default() {
+        let mut engine = MockEngine::default();
+        engine.set_supported_formats(vec![SampleFormat::I16, SampleFormat::F32]);
+        engine.use_sample_format(CaptureFormat::I24);
+
+        let stream = engine.start_recording().ok().expect("Recording should have started");
+
+        assert_eq!(stream.sample_format(), SampleFormat::I16);
+    }
+
+    #[test]
+    fn default_device_is_named_like_its_list_entry() {
+        let mut engine = MockEngine::default();
+        engine.set_input_devices(&["USB Audio", "Built-in", "USB Audio"], "USB Audio");
+
+        let devices = engine.get_input_devices();
+        assert_eq!(devices, vec!["USB Audio", "Built-in", "USB Audio #2"]);
+        assert_eq!(engine.get_current_input_device(), Some(String::from("USB Audio")));
+
+        engine.use_input_device(String::from("USB Audio #2"));
+        assert_eq!(engine.get_current_input_device(), Some(String::from("USB Audio #2")));
+
+        // switching hosts selects the default again, by its key
+        engine.use_host(String::from("Mock")).unwrap();
+        assert!(devices.contains(&engine.get_current_input_device().unwrap()));
+    }
+
+    #[test]
+    fn unknown_hosts_are_rejected() {
+        let mut engine = MockEngine::default();
+
+        assert!(engine.use_host(String::from("Nonexistent")).is_err());
+        assert_eq!(engine.get_current_host(), Some(String::from("Mock")));
+        assert!(engine.use_host(String::from("Mock")).is_ok());
+    }
+
+    #[test]
+    fn monitoring_is_retried_and_reopened_after_a_lost_output() {
+        let mut engine = MockEngine::default();
+        engine.use_monitor_retry(MonitorRetry { attempts: 3, delay: Duration::ZERO });
+        let limiter = Arc::new(LimiterControl::default());
+
+        // opens the monitor the way the UI does, one attempt per due retry
+        let open = |engine: &mut MockEngine| {
+            let mut schedule = RetrySchedule::new(engine.get_monitor_retry());
+
+            loop {
+                match engine.start_monitoring(2, 48000, limiter.clone()) {
+                    Ok(_) => return Ok(schedule.attempts()),
+                    Err(error) if schedule.failed(&error, Instant::now()) => assert!(schedule.is_due(Instant::now())),
+                    Err(_) => return Err(schedule.attempts())
+                }
+            }
+        };
+
+        engine.fail_next_monitoring(2);
+        assert_eq!(open(&mut engine), Ok(2));
+
+        engine.fail_next_monitoring(3);
+        assert_eq!(open(&mut engine), Err(3));
+
+        engine.use_output_device(String::from("Mock output"));
+        engine.lose_monitor_output();
+        assert!(engine.monitor_lost());
+        assert_eq!(open(&mut engine), Ok(0));
+        assert!(!engine.monitor_lost());
+        assert_eq!(engine.get_current_output_device(), Some(String::from("Mock output")));
+    }
+}
@@ -1,8 +1,28 @@
-use cpal::{BufferSize, Device, HostId, Stream, StreamConfig, StreamError};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use cpal::{BackendSpecificError, BufferSize, BuildStreamError, Device, FromSample, Host, HostId, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, StreamError, SupportedBufferSize, SupportedStreamConfigRange};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use crossbeam_channel::{Receiver, TryRecvError};
+use crossbeam_channel::{Receiver, Sender, TrySendError, TryRecvError};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::mock::MockEngine;
+use crate::engine::monitor::{self as monitoring, LimiterControl, Monitor, MonitorError, MonitorRetry};
+use crate::engine::watcher::DeviceWatcher;
 
+pub mod analysis;
+pub mod fft;
+pub mod mock;
+pub mod monitor;
 pub mod pipeline;
+pub mod probe;
+pub mod recorder;
+pub mod source;
+pub mod stats;
+pub mod watcher;
 
 pub type AudioHostName = String;
 pub type InputDeviceName = String;
@@ -10,10 +30,17 @@ pub type OutputDeviceName = String;
 pub type SampleType = f32;
 pub type PacketType = Vec<SampleType>;
 
+#[derive(Debug)]
 pub struct AudiaError {
     message: String
 }
 
+impl Display for AudiaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 impl From<String> for AudiaError {
     fn from(message: String) -> Self {
         AudiaError { message }
@@ -26,62 +53,400 @@ impl From<&str> for AudiaError {
     }
 }
 
+impl From<BuildStreamError> for AudiaError {
+    fn from(error: BuildStreamError) -> Self {
+        match error {
+            BuildStreamError::DeviceNotAvailable =>
+                AudiaError::from("Device is not available, it may be in use by another application or disconnected"),
+            BuildStreamError::StreamConfigNotSupported =>
+                AudiaError::from("Device does not support the requested stream configuration"),
+            BuildStreamError::InvalidArgument =>
+                AudiaError::from("Invalid stream configuration"),
+            BuildStreamError::StreamIdOverflow =>
+                AudiaError::from("Too many streams have been opened"),
+            BuildStreamError::BackendSpecific { err: BackendSpecificError { description } } => {
+                let lowercase = description.to_lowercase();
+
+                if ["busy", "in use", "exclusive"].iter().any(|hint| lowercase.contains(hint)) {
+                    AudiaError::from("Device is in use by another application")
+                } else {
+                    AudiaError::from(format!("Audio backend error: {description}"))
+                }
+            }
+        }
+    }
+}
+
 /// Provides an abstraction of the underlying audio systems.
 pub trait Engine {
     // Host operations.
     fn get_available_hosts(&self) -> Vec<AudioHostName>;
     fn get_current_host(&self) -> Option<AudioHostName>;
-    fn use_host(&mut self, host_name: AudioHostName);
+    /// Switches to another host and resets the input and output device to its defaults. Fails
+    /// without changing anything if the host is unknown or can't be opened.
+    fn use_host(&mut self, host_name: AudioHostName) -> Result<(), AudiaError>;
 
     // Input device operations
     fn get_input_devices(&self) -> Vec<InputDeviceName>;
     fn get_current_input_device(&self) -> Option<InputDeviceName>;
     fn use_input_device(&mut self, device_name: InputDeviceName);
+    /// Sample rate the current input device opens with, if it can be queried.
+    fn get_default_sample_rate(&self) -> Option<u32>;
 
     // Output device operations
     fn get_output_devices(&self) -> Vec<OutputDeviceName>;
     fn get_current_output_device(&self) -> Option<OutputDeviceName>;
     fn use_output_device(&mut self, device_name: OutputDeviceName);
 
+    // Stream configuration, applied to the next recording
+    fn get_share_mode(&self) -> ShareMode;
+    fn use_share_mode(&mut self, share_mode: ShareMode);
+    fn get_channel_request(&self) -> ChannelRequest;
+    fn use_channel_request(&mut self, request: ChannelRequest);
+    fn get_sample_format(&self) -> CaptureFormat;
+    fn use_sample_format(&mut self, format: CaptureFormat);
+    fn get_buffer_request(&self) -> BufferRequest;
+    fn use_buffer_request(&mut self, request: BufferRequest);
+
+    /// Watches the current host for devices being added or removed, `None` if the engine can't
+    /// notice changes and the devices have to be refreshed by hand.
+    fn watch_devices(&self) -> Option<DeviceWatcher>;
+
     // Recording operations
+    /// Starts capturing from the current input device. Only one recording can run at a time:
+    /// calling this while a recording is active returns an error and leaves the running
+    /// recording untouched, call `stop_recording` first to restart it.
     fn start_recording(&mut self) -> Result<AudioStream, AudiaError>;
     fn stop_recording(&mut self);
+
+    // Monitoring operations
+    /// Plays the samples pushed into the returned monitor on the current output device. Makes a
+    /// single attempt, the caller retries transient failures as configured with `use_monitor_retry`.
+    fn start_monitoring(&mut self, channels: u16, sample_rate: u32, limiter: Arc<LimiterControl>) -> Result<Monitor, MonitorError>;
+    fn stop_monitoring(&mut self);
+    fn get_monitor_retry(&self) -> MonitorRetry;
+    fn use_monitor_retry(&mut self, retry: MonitorRetry);
+    /// Whether the monitor output stopped because its device went away. Starting the monitoring
+    /// again then opens the default output device.
+    fn monitor_lost(&self) -> bool;
 }
 
 /// CPAL-based audio engine
 pub struct CpalEngine {
     current_host: Option<HostId>,
     current_input_device: Option<Device>,
+    /// Key of the current input device as returned by `get_input_devices`
+    current_input_name: Option<InputDeviceName>,
     current_output_device: Option<Device>,
-    current_stream: Option<Stream>
+    current_output_name: Option<OutputDeviceName>,
+    current_stream: Option<Stream>,
+    monitor_stream: Option<Stream>,
+    monitor_retry: MonitorRetry,
+    /// Set by the monitor stream when its output device is gone
+    monitor_lost: Arc<AtomicBool>,
+    /// Maximum number of packets queued between the audio callback and the consumer
+    channel_capacity: usize,
+    /// The current input device is an output device captured through loopback
+    loopback: bool,
+    share_mode: ShareMode,
+    channel_request: ChannelRequest,
+    sample_format: CaptureFormat,
+    buffer_request: BufferRequest
 }
 
+const ALREADY_RECORDING: &str = "Already recording, stop the current recording first";
+
+/// cpal only opens shared streams, even on hosts like WASAPI that support exclusive access
+const EXCLUSIVE_UNSUPPORTED: &str = "Exclusive mode is not supported, switch to shared mode to record";
+
+/// Marks output devices that are offered as loopback inputs
+const LOOPBACK_SUFFIX: &str = " (loopback)";
+
 impl CpalEngine {
+    /// Starts configuring an engine with a specific host and devices, see `CpalEngineBuilder`.
+    pub fn builder() -> CpalEngineBuilder {
+        CpalEngineBuilder::default()
+    }
+
+    /// Whether the host can capture what an output device is playing. WASAPI supports building an
+    /// input stream on an output device; other hosts may only expose monitor sources as regular
+    /// input devices, if at all.
+    fn supports_loopback(host_id: HostId) -> bool {
+        host_id.name() == "WASAPI"
+    }
+
+    /// Switches to the host's default output device, e.g. after the current one was unplugged.
+    fn use_default_output_device(&mut self) {
+        let Some(host) = self.current_host.and_then(|host_id| cpal::host_from_id(host_id).ok()) else {
+            return;
+        };
+
+        let (name, device) = Self::default_output_device(&host).unzip();
+        log::info!("Switching the monitor output to the default device {}", name.as_deref().unwrap_or("none"));
+
+        self.current_output_name = name;
+        self.current_output_device = device;
+    }
+
+    /// The default input device of `host` with its key among the input devices.
+    fn default_input_device(host: &Host) -> Option<(InputDeviceName, Device)> {
+        let device = host.default_input_device()?;
+
+        Some(keyed_default(Self::input_devices_by_key(host), device_name(&device), device))
+    }
+
+    /// The default output device of `host` with its key among the output devices.
+    fn default_output_device(host: &Host) -> Option<(OutputDeviceName, Device)> {
+        let device = host.default_output_device()?;
+
+        Some(keyed_default(Self::output_devices_by_key(host), device_name(&device), device))
+    }
+
+    /// The input devices of `host` with unique keys, see `disambiguate`.
+    fn input_devices_by_key(host: &Host) -> Vec<(InputDeviceName, Device)> {
+        host.input_devices()
+            .map(|devices| Self::keyed(devices.collect()))
+            .unwrap_or_default()
+    }
+
+    /// The output devices of `host` with unique keys, see `disambiguate`.
+    fn output_devices_by_key(host: &Host) -> Vec<(OutputDeviceName, Device)> {
+        host.output_devices()
+            .map(|devices| Self::keyed(devices.collect()))
+            .unwrap_or_default()
+    }
+
+    /// The configurations `device` can be captured with that have `channels` channels and include
+    /// `sample_rate`.
+    fn matching_configs(device: &Device, loopback: bool, channels: u16, sample_rate: SampleRate) -> Vec<SupportedStreamConfigRange> {
+        let configs: Vec<SupportedStreamConfigRange> = if loopback {
+            device.supported_output_configs().map(Iterator::collect).unwrap_or_default()
+        } else {
+            device.supported_input_configs().map(Iterator::collect).unwrap_or_default()
+        };
+
+        configs.into_iter()
+            .filter(|config| {
+                config.channels() == channels && config.min_sample_rate() <= sample_rate && sample_rate <= config.max_sample_rate()
+            })
+            .collect()
+    }
+
+    /// Whether `device` can be opened with `channels` channels at `sample_rate`.
+    fn supports_channels(device: &Device, loopback: bool, channels: u16, sample_rate: SampleRate) -> bool {
+        !Self::matching_configs(device, loopback, channels, sample_rate).is_empty()
+    }
+
+    /// Builds a capture stream delivering samples of type `T`, which are converted to
+    /// `SampleType` before they are forwarded. The packets are taken from `pool`, so once the
+    /// consumer returns them the callback no longer allocates.
+    fn build_capture_stream<T>(device: &Device, config: &StreamConfig, tx: Sender<PacketType>, pool: PacketPool, dropped: Arc<AtomicUsize>, overload: Arc<AtomicBool>) -> Result<Stream, BuildStreamError>
+    where
+        T: SizedSample,
+        SampleType: FromSample<T>
+    {
+        device.build_input_stream(
+            config,
+            move |data: &[T], _info| {
+                let mut packet = pool.take(data.len());
+                packet.extend(data.iter().map(|sample| sample.to_sample::<SampleType>()));
+                detect_overload(&packet, &overload);
+                forward_packet(&tx, &pool, packet, &dropped)
+            },
+            |err: StreamError| log::error!("An error occurred during reading from the stream: {:?}", err),
+            None)
+    }
+
+    fn keyed(devices: Vec<Device>) -> Vec<(String, Device)> {
+        let names: Vec<String> = devices.iter().map(device_name).collect();
+
+        disambiguate(names).into_iter().zip(devices).collect()
+    }
 }
 
 impl CpalEngine {
 
-    fn run_stream(&mut self, stream: Stream, rx: Receiver<PacketType>) -> Result<AudioStream, AudiaError> {
+    fn run_stream(&mut self, stream: Stream, rx: Receiver<PacketType>, pool: PacketPool, config: &StreamConfig, sample_format: SampleFormat, dropped: Arc<AtomicUsize>, overload: Arc<AtomicBool>) -> Result<AudioStream, AudiaError> {
         if let Err(error) = stream.play() {
             log::error!("Failed to run stream: {error:?}");
             Err(AudiaError::from(format!("Failed to run stream: {error:?}")))
         } else {
             self.current_stream = Some(stream);
             log::info!("Running stream");
-            Ok(AudioStream::new(rx))
+            let buffer_frames = match config.buffer_size {
+                BufferSize::Fixed(frames) => Some(frames),
+                BufferSize::Default => None
+            };
+
+            Ok(AudioStream::new(rx, config.channels, config.sample_rate.0)
+                .with_sample_format(sample_format)
+                .with_buffer_frames(buffer_frames)
+                .with_dropped_samples(dropped)
+                .with_overload(overload)
+                .with_packet_pool(pool))
         }
     }
 }
 
+/// Builds a `CpalEngine` with the given host and devices in one go. Anything left unspecified
+/// falls back to the system default.
+///
+/// ```ignore
+/// let engine = CpalEngine::builder()
+///     .host("ALSA")
+///     .input_device("USB Audio")
+///     .build()?;
+/// ```
+#[derive(Default)]
+pub struct CpalEngineBuilder {
+    host: Option<AudioHostName>,
+    input_device: Option<InputDeviceName>,
+    output_device: Option<OutputDeviceName>,
+    channel_capacity: Option<usize>,
+    share_mode: Option<ShareMode>,
+    channel_request: Option<ChannelRequest>,
+    sample_format: Option<CaptureFormat>,
+    buffer_request: Option<BufferRequest>,
+    monitor_retry: Option<MonitorRetry>
+}
+
+impl CpalEngineBuilder {
+    pub fn host(mut self, host_name: impl Into<AudioHostName>) -> Self {
+        self.host = Some(host_name.into());
+        self
+    }
+
+    pub fn input_device(mut self, device_name: impl Into<InputDeviceName>) -> Self {
+        self.input_device = Some(device_name.into());
+        self
+    }
+
+    pub fn output_device(mut self, device_name: impl Into<OutputDeviceName>) -> Self {
+        self.output_device = Some(device_name.into());
+        self
+    }
+
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = Some(channel_capacity);
+        self
+    }
+
+    pub fn share_mode(mut self, share_mode: ShareMode) -> Self {
+        self.share_mode = Some(share_mode);
+        self
+    }
+
+    pub fn channels(mut self, request: ChannelRequest) -> Self {
+        self.channel_request = Some(request);
+        self
+    }
+
+    pub fn sample_format(mut self, format: CaptureFormat) -> Self {
+        self.sample_format = Some(format);
+        self
+    }
+
+    pub fn buffer_size(mut self, request: BufferRequest) -> Self {
+        self.buffer_request = Some(request);
+        self
+    }
+
+    pub fn monitor_retry(mut self, retry: MonitorRetry) -> Self {
+        self.monitor_retry = Some(retry);
+        self
+    }
+
+    /// Opens the requested host and devices, failing if any of them can't be found.
+    pub fn build(self) -> Result<CpalEngine, AudiaError> {
+        let host_id = match &self.host {
+            Some(host_name) => cpal::available_hosts()
+                .into_iter()
+                .find(|host_id| host_id.name() == host_name.as_str())
+                .ok_or_else(|| AudiaError::from(format!("Unknown audio host: {host_name}")))?,
+            None => cpal::default_host().id()
+        };
+
+        let host = cpal::host_from_id(host_id)
+            .map_err(|error| AudiaError::from(format!("Could not open audio host {}: {error}", host_id.name())))?;
+
+        let input_device = match &self.input_device {
+            Some(device_name) => Some(CpalEngine::input_devices_by_key(&host)
+                .into_iter()
+                .find(|(key, _)| key == device_name)
+                .ok_or_else(|| AudiaError::from(format!("Unknown input device: {device_name}")))?),
+            None => CpalEngine::default_input_device(&host)
+        };
+
+        let output_device = match &self.output_device {
+            Some(device_name) => Some(CpalEngine::output_devices_by_key(&host)
+                .into_iter()
+                .find(|(key, _)| key == device_name)
+                .ok_or_else(|| AudiaError::from(format!("Unknown output device: {device_name}")))?),
+            None => CpalEngine::default_output_device(&host)
+        };
+
+        log::info!("Using CPAL engine with host {}", host_id.name());
+
+        let (current_input_name, current_input_device) = input_device.unzip();
+        let (current_output_name, current_output_device) = output_device.unzip();
+
+        Ok(CpalEngine {
+            current_host: Some(host_id),
+            current_input_device,
+            current_input_name,
+            current_output_device,
+            current_output_name,
+            current_stream: None,
+            monitor_stream: None,
+            monitor_retry: self.monitor_retry.unwrap_or_default(),
+            monitor_lost: Arc::new(AtomicBool::new(false)),
+            channel_capacity: self.channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY),
+            loopback: false,
+            share_mode: self.share_mode.unwrap_or_default(),
+            channel_request: self.channel_request.unwrap_or_default(),
+            sample_format: self.sample_format.unwrap_or_default(),
+            buffer_request: self.buffer_request.unwrap_or_default()
+        })
+    }
+}
+
+fn device_name(device: &Device) -> String {
+    device.name().unwrap_or(String::from("No device name"))
+}
+
+/// Pairs a default device with the key of its entry in the device list. cpal can't tell equally
+/// named devices apart, so the default is taken to be the first device of its name, which is
+/// keyed by the plain name. A default missing from the list keeps its plain name.
+pub(crate) fn keyed_default<D>(devices: Vec<(String, D)>, default_name: String, default: D) -> (String, D) {
+    devices.into_iter()
+        .find(|(key, _)| *key == default_name)
+        .unwrap_or((default_name, default))
+}
+
 impl Default for CpalEngine {
     fn default() -> Self {
         log::info!("Using CPAL engine with default settings");
 
+        let host = cpal::default_host();
+        let (current_input_name, current_input_device) = Self::default_input_device(&host).unzip();
+        let (current_output_name, current_output_device) = Self::default_output_device(&host).unzip();
+
         Self {
-            current_host: Some(cpal::default_host().id()),
-            current_input_device: cpal::default_host().default_input_device(),
-            current_output_device: cpal::default_host().default_output_device(),
-            current_stream: None
+            current_host: Some(host.id()),
+            current_input_device,
+            current_input_name,
+            current_output_device,
+            current_output_name,
+            current_stream: None,
+            monitor_stream: None,
+            monitor_retry: MonitorRetry::default(),
+            monitor_lost: Arc::new(AtomicBool::new(false)),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            loopback: false,
+            share_mode: ShareMode::default(),
+            channel_request: ChannelRequest::default(),
+            sample_format: CaptureFormat::default(),
+            buffer_request: BufferRequest::default()
         }
     }
 }
@@ -99,53 +464,103 @@ impl Engine for CpalEngine {
         self.current_host.map(|h| h.name()).map(String::from)
     }
 
-    fn use_host(&mut self, host_id: AudioHostName) {
-        for host in cpal::available_hosts() {
-            if host.name().eq(host_id.as_str()) {
-                self.current_host = Some(host);
-                log::info!("Switched to audio host {}", host_id);
-            }
-        }
+    fn use_host(&mut self, host_name: AudioHostName) -> Result<(), AudiaError> {
+        let host_id = cpal::available_hosts()
+            .into_iter()
+            .find(|host_id| host_id.name() == host_name.as_str())
+            .ok_or_else(|| AudiaError::from(format!("Unknown audio host: {host_name}")))?;
+
+        let host = cpal::host_from_id(host_id)
+            .map_err(|error| AudiaError::from(format!("Could not open audio host {host_name}: {error}")))?;
+
+        // the devices of the previous host can't be used with the new one
+        let (current_input_name, current_input_device) = Self::default_input_device(&host).unzip();
+        let (current_output_name, current_output_device) = Self::default_output_device(&host).unzip();
+
+        self.current_host = Some(host_id);
+        self.current_input_device = current_input_device;
+        self.current_input_name = current_input_name;
+        self.current_output_device = current_output_device;
+        self.current_output_name = current_output_name;
+        self.loopback = false;
+
+        log::info!("Switched to audio host {} with input {} and output {}", host_name,
+            self.current_input_name.as_deref().unwrap_or("none"), self.current_output_name.as_deref().unwrap_or("none"));
+
+        Ok(())
     }
 
     fn get_input_devices(&self) -> Vec<String> {
         if let Some(host_id) = self.current_host {
             let host = cpal::host_from_id(host_id).expect("Could not open audio host");
-            let devices = host.input_devices().expect("Could not find input devices on host");
 
-            devices.into_iter()
-                .map(|d| d.name().unwrap_or(String::from("No device name")))
-                .collect()
+            let mut device_names: Vec<String> = Self::input_devices_by_key(&host)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+
+            if Self::supports_loopback(host_id) {
+                device_names.extend(Self::output_devices_by_key(&host)
+                    .into_iter()
+                    .map(|(key, _)| key + LOOPBACK_SUFFIX));
+            }
+
+            device_names
         } else {
             vec![]
         }
     }
 
     fn get_current_input_device(&self) -> Option<String> {
-        self.current_input_device.as_ref()
-            .map(|input_device| input_device.name()
-                .unwrap_or(String::from("No device name found")))
+        self.current_input_name.clone()
+            .map(|name| if self.loopback { name + LOOPBACK_SUFFIX } else { name })
+    }
+
+    fn get_default_sample_rate(&self) -> Option<u32> {
+        let device = self.current_input_device.as_ref()?;
+        let default_config = if self.loopback {
+            device.default_output_config()
+        } else {
+            device.default_input_config()
+        };
+
+        default_config.ok().map(|config| config.sample_rate().0)
     }
 
     fn use_input_device(&mut self, device_name: String) {
         if let Some(host_id) = self.current_host {
             let host = cpal::host_from_id(host_id).expect("Could not open audio host");
-            for input_device in host.input_devices().expect("Could not open input devices on host") {
-                if input_device.name().map(|name| name.eq(device_name.as_str())).unwrap_or(false) {
-                    self.current_input_device = Some(input_device);
-                    log::info!("Using input device {}", device_name);
+
+            if let Some(output_name) = device_name.strip_suffix(LOOPBACK_SUFFIX) {
+                if Self::supports_loopback(host_id) {
+                    if let Some((key, output_device)) = Self::output_devices_by_key(&host).into_iter().find(|(key, _)| key == output_name) {
+                        self.current_input_device = Some(output_device);
+                        self.current_input_name = Some(key);
+                        self.loopback = true;
+                        log::info!("Using loopback capture of {}", output_name);
+                        return;
+                    }
+                } else {
+                    log::warn!("Host {} does not support loopback capture", host_id.name());
                 }
             }
+
+            if let Some((key, input_device)) = Self::input_devices_by_key(&host).into_iter().find(|(key, _)| *key == device_name) {
+                self.current_input_device = Some(input_device);
+                self.current_input_name = Some(key);
+                self.loopback = false;
+                log::info!("Using input device {}", device_name);
+            }
         }
     }
 
     fn get_output_devices(&self) -> Vec<OutputDeviceName> {
         if let Some(host_id) = self.current_host {
             let host = cpal::host_from_id(host_id).expect("Could not open audio host");
-            let devices = host.output_devices().expect("Could not find ouput devices on host");
 
-            devices.into_iter()
-                .map(|d| d.name().unwrap_or(String::from("No device name")))
+            Self::output_devices_by_key(&host)
+                .into_iter()
+                .map(|(key, _)| key)
                 .collect()
         } else {
             vec![]
@@ -153,24 +568,77 @@ impl Engine for CpalEngine {
     }
 
     fn get_current_output_device(&self) -> Option<OutputDeviceName> {
-        self.current_output_device.as_ref()
-            .map(|output_device| output_device.name()
-                .unwrap_or(String::from("No device name found")))
+        self.current_output_name.clone()
     }
 
     fn use_output_device(&mut self, device_name: OutputDeviceName) {
         if let Some(host_id) = self.current_host {
             let host = cpal::host_from_id(host_id).expect("Could not open audio host");
-            for output_device in host.output_devices().expect("Could not open output devices on host") {
-                if output_device.name().map(|name| name.eq(device_name.as_str())).unwrap_or(false) {
-                    self.current_output_device = Some(output_device);
-                    log::info!("Using input device {}", device_name);
-                }
+
+            if let Some((key, output_device)) = Self::output_devices_by_key(&host).into_iter().find(|(key, _)| *key == device_name) {
+                self.current_output_device = Some(output_device);
+                self.current_output_name = Some(key);
+                log::info!("Using output device {}", device_name);
             }
         }
     }
 
+    fn get_share_mode(&self) -> ShareMode {
+        self.share_mode
+    }
+
+    fn use_share_mode(&mut self, share_mode: ShareMode) {
+        self.share_mode = share_mode;
+        log::info!("Requesting {} mode for the next stream", share_mode);
+    }
+
+    fn get_channel_request(&self) -> ChannelRequest {
+        self.channel_request
+    }
+
+    fn use_channel_request(&mut self, request: ChannelRequest) {
+        self.channel_request = request;
+        log::info!("Requesting {} for the next stream", request);
+    }
+
+    fn get_sample_format(&self) -> CaptureFormat {
+        self.sample_format
+    }
+
+    fn use_sample_format(&mut self, format: CaptureFormat) {
+        self.sample_format = format;
+        log::info!("Requesting {} samples for the next stream", format);
+    }
+
+    fn get_buffer_request(&self) -> BufferRequest {
+        self.buffer_request
+    }
+
+    fn use_buffer_request(&mut self, request: BufferRequest) {
+        self.buffer_request = request;
+        log::info!("Requesting {} for the next stream", request);
+    }
+
+    fn watch_devices(&self) -> Option<DeviceWatcher> {
+        let host_id = self.current_host?;
+
+        DeviceWatcher::spawn(host_id)
+            .map_err(|error| log::warn!("{}, devices have to be refreshed by hand", error))
+            .ok()
+    }
+
     fn start_recording(&mut self) -> Result<AudioStream, AudiaError> {
+        if self.current_stream.is_some() {
+            log::warn!("Recording has already been started");
+            return Err(AudiaError::from(ALREADY_RECORDING));
+        }
+
+        if self.share_mode == ShareMode::Exclusive {
+            log::warn!("Exclusive mode is not available on host {}",
+                self.current_host.map(|host_id| host_id.name()).unwrap_or("none"));
+            return Err(AudiaError::from(EXCLUSIVE_UNSUPPORTED));
+        }
+
         log::info!("Recording started using {}", self.get_current_input_device().unwrap_or(String::from("No input device name")));
 
         if let Some(device) = &self.current_input_device {
@@ -181,31 +649,63 @@ impl Engine for CpalEngine {
                 }
             }
 
-            if let Ok(default_config) = device.default_input_config() {
-                log::info!("Default input config: {:?}", default_config);
+            // loopback streams have to be opened with the output device's own format
+            let default_config = if self.loopback {
+                device.default_output_config()
+            } else {
+                device.default_input_config()
+            };
 
-                let err_fn = move |err: StreamError| {
-                    log::error!("An error occurred during reading from the stream: {:?}", err);
-                };
+            if let Ok(default_config) = default_config {
+                log::info!("Default input config: {:?}", default_config);
 
-                let (tx, rx) = crossbeam_channel::unbounded::<PacketType>();
+                let default_format = default_config.sample_format();
+                let supported_buffer = *default_config.buffer_size();
+                let (tx, rx) = crossbeam_channel::bounded::<PacketType>(self.channel_capacity);
+                let pool = PacketPool::new(self.channel_capacity);
+                let callback_pool = pool.clone();
+                let dropped = Arc::new(AtomicUsize::new(0));
+                let callback_dropped = dropped.clone();
+                let overload = Arc::new(AtomicBool::new(false));
+                let callback_overload = overload.clone();
 
                 let mut config = StreamConfig::from(default_config);
-                config.buffer_size = BufferSize::Fixed(256);
-
-                let stream_result = device
-                    .build_input_stream(
-                        &config.into(),
-                        move |data: &[SampleType], _info| {
-                            if let Err(error) = tx.send(data.into()) {
-                                log::error!("Failed to send stream data: {error:?}");
-                            }
-                        },
-                        err_fn, None);
+                config.buffer_size = select_buffer_size(self.buffer_request, &supported_buffer);
+
+                if let ChannelRequest::Count(channels) = self.channel_request {
+                    if Self::supports_channels(device, self.loopback, channels, config.sample_rate) {
+                        log::info!("Capturing {} channels", channels);
+                        config.channels = channels;
+                    } else {
+                        log::warn!("The device can't capture {} channels at {} Hz, using {} channels",
+                            channels, config.sample_rate.0, config.channels);
+                    }
+                }
+
+                let supported: Vec<SampleFormat> = Self::matching_configs(device, self.loopback, config.channels, config.sample_rate)
+                    .iter()
+                    .map(SupportedStreamConfigRange::sample_format)
+                    .collect();
+
+                // formats without a conversion here are captured as floats, as before
+                let sample_format = match select_sample_format(self.sample_format, &supported, default_format) {
+                    format @ (SampleFormat::I16 | SampleFormat::I32) => format,
+                    _ => SampleFormat::F32
+                };
+                log::info!("Capturing {:?} samples", sample_format);
+
+                let stream_result = match sample_format {
+                    SampleFormat::I16 => Self::build_capture_stream::<i16>(device, &config, tx, callback_pool, callback_dropped, callback_overload),
+                    SampleFormat::I32 => Self::build_capture_stream::<i32>(device, &config, tx, callback_pool, callback_dropped, callback_overload),
+                    _ => Self::build_capture_stream::<f32>(device, &config, tx, callback_pool, callback_dropped, callback_overload)
+                };
 
                 stream_result
-                    .map_err(|error| AudiaError::from(format!("Failed to create audio stream: {error:?}")))
-                    .and_then(|stream| self.run_stream(stream, rx))
+                    .map_err(|error| {
+                        log::error!("Failed to create audio stream: {error:?}");
+                        AudiaError::from(error)
+                    })
+                    .and_then(|stream| self.run_stream(stream, rx, pool, &config, sample_format, dropped, overload))
             } else {
                 Err(AudiaError::from("Could not find default input config"))
             }
@@ -224,16 +724,361 @@ impl Engine for CpalEngine {
             log::info!("Streaming stopped");
         }
     }
+
+    fn start_monitoring(&mut self, channels: u16, sample_rate: u32, limiter: Arc<LimiterControl>) -> Result<Monitor, MonitorError> {
+        self.stop_monitoring();
+
+        if self.monitor_lost.swap(false, Ordering::Relaxed) {
+            self.use_default_output_device();
+        }
+
+        let device = self.current_output_device.as_ref()
+            .ok_or_else(|| MonitorError { error: AudiaError::from("No output device is selected"), transient: false })?;
+
+        // the output has to run in the capture format, there is no resampling or channel mapping
+        let config = StreamConfig {
+            channels,
+            sample_rate: SampleRate(sample_rate),
+            buffer_size: BufferSize::Default
+        };
+
+        let max_delay = monitoring::delay_samples(monitoring::MAX_MONITOR_DELAY_MS, channels, sample_rate);
+
+        // about 200 ms of audio
+        let (monitor, mut output) = monitoring::monitor_channel(sample_rate as usize * channels as usize / 5, max_delay, limiter);
+        let lost = self.monitor_lost.clone();
+
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [SampleType], _info| output.fill(data),
+                move |err: StreamError| match err {
+                    StreamError::DeviceNotAvailable => {
+                        log::warn!("The monitor output device is no longer available");
+                        lost.store(true, Ordering::Relaxed);
+                    },
+                    err => log::error!("An error occurred during monitoring: {:?}", err)
+                },
+                None)
+            .map_err(|error| {
+                log::error!("Failed to create monitor stream: {error:?}");
+                MonitorError::from(error)
+            })?;
+
+        stream.play()
+            .map_err(|error| MonitorError { error: AudiaError::from(format!("Failed to run monitor stream: {error:?}")), transient: false })?;
+
+        log::info!("Monitoring on {}", self.get_current_output_device().unwrap_or_default());
+        self.monitor_stream = Some(stream);
+
+        Ok(monitor)
+    }
+
+    fn stop_monitoring(&mut self) {
+        if self.monitor_stream.take().is_some() {
+            log::info!("Monitoring stopped");
+        }
+    }
+
+    fn get_monitor_retry(&self) -> MonitorRetry {
+        self.monitor_retry
+    }
+
+    fn use_monitor_retry(&mut self, retry: MonitorRetry) {
+        self.monitor_retry = retry;
+    }
+
+    fn monitor_lost(&self) -> bool {
+        self.monitor_lost.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether opening a stream may succeed when tried again, e.g. after the device was released by
+/// another application. Unsupported configurations fail the same way every time.
+pub(crate) fn is_transient(error: &BuildStreamError) -> bool {
+    matches!(error, BuildStreamError::DeviceNotAvailable | BuildStreamError::BackendSpecific { .. })
+}
+
+/// Samples at or above this magnitude count as an overload of the input
+pub const OVERLOAD_LEVEL: SampleType = 0.999;
+
+/// Sets `overload` when a sample of the packet reaches full scale. Only reads the samples and
+/// stores a flag, so it's safe to call from the audio callback.
+fn detect_overload(data: &[SampleType], overload: &AtomicBool) {
+    if data.iter().any(|sample| sample.abs() >= OVERLOAD_LEVEL) {
+        overload.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Spent packets the consumer hands back to the capture callback, which fills them again instead
+/// of allocating a new one for every callback.
+#[derive(Clone)]
+pub struct PacketPool {
+    tx: Sender<PacketType>,
+    rx: Receiver<PacketType>
+}
+
+impl PacketPool {
+    /// Keeps up to `capacity` spent packets, more are freed.
+    pub fn new(capacity: usize) -> Self {
+        let (tx, rx) = crossbeam_channel::bounded(capacity);
+
+        Self { tx, rx }
+    }
+
+    /// An empty packet with room for `len` samples. Only allocates when no spent packet is
+    /// available or it is too small.
+    pub fn take(&self, len: usize) -> PacketType {
+        match self.rx.try_recv() {
+            Ok(mut packet) => {
+                packet.clear();
+                packet.reserve(len);
+                packet
+            },
+            Err(_) => PacketType::with_capacity(len)
+        }
+    }
+
+    /// Hands a spent packet back, it's freed when the pool is full.
+    pub fn recycle(&self, packet: PacketType) {
+        let _ = self.tx.try_send(packet);
+    }
+}
+
+/// Sends a packet from the audio callback without blocking. When the consumer can't keep up and
+/// the channel is full, the packet is dropped and counted instead, and returned to the pool.
+fn forward_packet(tx: &Sender<PacketType>, pool: &PacketPool, packet: PacketType, dropped: &AtomicUsize) {
+    // some backends occasionally call back without any data
+    if packet.is_empty() {
+        pool.recycle(packet);
+        return;
+    }
+
+    match tx.try_send(packet) {
+        Ok(()) => {},
+        Err(TrySendError::Full(packet)) => {
+            dropped.fetch_add(packet.len(), Ordering::Relaxed);
+            pool.recycle(packet);
+        },
+        Err(TrySendError::Disconnected(_)) => {
+            log::error!("Failed to send stream data: stream has been closed");
+        }
+    }
+}
+
+const DEFAULT_CHANNEL_CAPACITY: usize = 64;
+
+/// Names of the audio hosts built into this binary, e.g. JACK or ASIO when cpal was compiled with
+/// them. Unlike `Engine::get_available_hosts` this doesn't check whether they can be opened.
+pub fn compiled_hosts() -> Vec<AudioHostName> {
+    cpal::ALL_HOSTS.iter()
+        .map(|host_id| String::from(host_id.name()))
+        .collect()
+}
+
+/// The audio backends `AudioSystem` can be built on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EngineKind {
+    #[default]
+    Cpal,
+    /// Hardware-free engine, useful for testing and demos
+    Mock
+}
+
+impl FromStr for EngineKind {
+    type Err = AudiaError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "cpal" => Ok(EngineKind::Cpal),
+            "mock" => Ok(EngineKind::Mock),
+            _ => Err(AudiaError::from(format!("Unknown engine: {value}")))
+        }
+    }
+}
+
+/// Whether a stream shares the device with other applications or takes it over for lower latency.
+/// Recording in exclusive mode is refused, as no engine can open exclusive streams yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ShareMode {
+    #[default]
+    Shared,
+    Exclusive
+}
+
+impl ShareMode {
+    pub const ALL: [ShareMode; 2] = [ShareMode::Shared, ShareMode::Exclusive];
+}
+
+impl Display for ShareMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ShareMode::Shared => "Shared",
+            ShareMode::Exclusive => "Exclusive"
+        })
+    }
+}
+
+/// Number of channels to capture, e.g. mono from a multichannel interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelRequest {
+    /// Whatever the device's default configuration uses
+    #[default]
+    DeviceDefault,
+    Count(u16)
+}
+
+impl ChannelRequest {
+    pub const COMMON: [ChannelRequest; 3] = [ChannelRequest::DeviceDefault, ChannelRequest::Count(1), ChannelRequest::Count(2)];
+}
+
+impl Display for ChannelRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelRequest::DeviceDefault => f.write_str("Default channels"),
+            ChannelRequest::Count(1) => f.write_str("Mono"),
+            ChannelRequest::Count(2) => f.write_str("Stereo"),
+            ChannelRequest::Count(channels) => write!(f, "{channels} channels")
+        }
+    }
+}
+
+/// Preferred sample format of the capture. The analysis always works on `SampleType`, a higher
+/// precision only helps when the device converts internally otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureFormat {
+    #[default]
+    F32,
+    I16,
+    I24
+}
+
+impl CaptureFormat {
+    pub const ALL: [CaptureFormat; 3] = [CaptureFormat::F32, CaptureFormat::I16, CaptureFormat::I24];
+
+    /// The cpal format carrying this format; 24-bit samples arrive in 32-bit containers.
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            CaptureFormat::F32 => SampleFormat::F32,
+            CaptureFormat::I16 => SampleFormat::I16,
+            CaptureFormat::I24 => SampleFormat::I32
+        }
+    }
+}
+
+impl Display for CaptureFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaptureFormat::F32 => "32-bit float",
+            CaptureFormat::I16 => "16-bit",
+            CaptureFormat::I24 => "24-bit"
+        })
+    }
+}
+
+/// Picks the preferred format if the device supports it with the stream's channels and sample
+/// rate, otherwise falls back to the device's default format.
+pub fn select_sample_format(preferred: CaptureFormat, supported: &[SampleFormat], default: SampleFormat) -> SampleFormat {
+    let format = preferred.sample_format();
+
+    if supported.contains(&format) {
+        format
+    } else {
+        log::warn!("The device doesn't support {} samples, using {:?}", preferred, default);
+        default
+    }
+}
+
+/// Frames per device buffer unless another size is requested
+pub const DEFAULT_BUFFER_FRAMES: u32 = 256;
+
+/// Size of the device buffer. A fixed size gives a known, stable latency; larger buffers are
+/// less likely to drop out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferRequest {
+    /// Whatever the host chooses
+    DeviceDefault,
+    /// Frames per buffer
+    Fixed(u32)
+}
+
+impl BufferRequest {
+    pub const COMMON: [BufferRequest; 7] = [
+        BufferRequest::DeviceDefault,
+        BufferRequest::Fixed(64),
+        BufferRequest::Fixed(128),
+        BufferRequest::Fixed(256),
+        BufferRequest::Fixed(512),
+        BufferRequest::Fixed(1024),
+        BufferRequest::Fixed(2048)
+    ];
+}
+
+impl Default for BufferRequest {
+    fn default() -> Self {
+        BufferRequest::Fixed(DEFAULT_BUFFER_FRAMES)
+    }
+}
+
+impl Display for BufferRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BufferRequest::DeviceDefault => f.write_str("Default buffer"),
+            BufferRequest::Fixed(frames) => write!(f, "{frames} frame buffer")
+        }
+    }
+}
+
+/// Uses the requested buffer size if it's within the device's range, otherwise lets the host
+/// choose. Devices that don't report a range get the requested size.
+pub fn select_buffer_size(request: BufferRequest, supported: &SupportedBufferSize) -> BufferSize {
+    match (request, supported) {
+        (BufferRequest::DeviceDefault, _) => BufferSize::Default,
+        (BufferRequest::Fixed(frames), SupportedBufferSize::Range { min, max }) if !(*min..=*max).contains(&frames) => {
+            log::warn!("The device doesn't support {} frame buffers, only {} to {}, using its default", frames, min, max);
+            BufferSize::Default
+        },
+        (BufferRequest::Fixed(frames), _) => BufferSize::Fixed(frames)
+    }
 }
 
 /// Collection of configuration settings required by the audio system
 pub struct AudioSettings {
-
+    pub engine: EngineKind,
+    /// Maximum number of packets buffered between capture and analysis
+    pub channel_capacity: usize,
+    /// Requested access mode; recording fails in exclusive mode
+    pub share_mode: ShareMode,
+    /// Requested channel count; unsupported counts fall back to the device default
+    pub channels: ChannelRequest,
+    /// Preferred sample format; unsupported formats fall back to the device default
+    pub sample_format: CaptureFormat,
+    /// Requested buffer size; unsupported sizes fall back to the device default
+    pub buffer_size: BufferRequest,
+    /// Host to start on, matched by name or by a part of it
+    pub host: Option<AudioHostName>,
+    /// Hosts to try in order when `host` isn't set, the first that opens with an input device is
+    /// used. Matched like `host`
+    pub host_preference: Vec<AudioHostName>,
+    /// Input device to start on, matched by name or by a part of it
+    pub input_device: Option<InputDeviceName>,
+    pub monitor_retry: MonitorRetry
 }
 
 impl Default for AudioSettings {
     fn default() -> Self {
-        Self {}
+        Self {
+            engine: EngineKind::default(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            share_mode: ShareMode::default(),
+            channels: ChannelRequest::default(),
+            sample_format: CaptureFormat::default(),
+            buffer_size: BufferRequest::default(),
+            host: None,
+            host_preference: vec![],
+            input_device: None,
+            monitor_retry: MonitorRetry::default()
+        }
     }
 }
 
@@ -243,28 +1088,391 @@ pub struct AudioSystem {
 }
 
 impl AudioSystem {
-    pub fn new(_settings: AudioSettings) -> Self {
+    pub fn new(settings: AudioSettings) -> Self {
+        let mut engine = Self::create_engine(&settings);
+
+        if let Some(requested) = &settings.host {
+            match resolve_name(requested, &engine.get_available_hosts()) {
+                Some(host_name) => {
+                    log::info!("Resolved host '{}' to {}", requested, host_name);
+
+                    if let Err(error) = engine.use_host(host_name) {
+                        log::warn!("{}, using the default host", error);
+                    }
+                },
+                None => log::warn!("No host matches '{}', using the default host", requested)
+            }
+        } else if !settings.host_preference.is_empty() {
+            use_preferred_host(engine.as_mut(), &settings.host_preference);
+        }
+
+        if let Some(requested) = &settings.input_device {
+            match resolve_name(requested, &engine.get_input_devices()) {
+                Some(device_name) => {
+                    log::info!("Resolved input device '{}' to {}", requested, device_name);
+                    engine.use_input_device(device_name);
+                },
+                None => log::warn!("No input device matches '{}', using the default device", requested)
+            }
+        }
+
         AudioSystem {
-            engine: Box::<CpalEngine>::default(),
+            engine,
             stream: vec![]
         }
     }
+
+    fn create_engine(settings: &AudioSettings) -> Box<dyn Engine> {
+        log::info!("Creating {:?} engine", settings.engine);
+
+        match settings.engine {
+            EngineKind::Cpal => Box::new(CpalEngine {
+                channel_capacity: settings.channel_capacity,
+                share_mode: settings.share_mode,
+                channel_request: settings.channels,
+                sample_format: settings.sample_format,
+                buffer_request: settings.buffer_size,
+                monitor_retry: settings.monitor_retry,
+                ..CpalEngine::default()
+            }),
+            EngineKind::Mock => {
+                let mut engine = MockEngine::default();
+                engine.use_sample_format(settings.sample_format);
+                engine.use_buffer_request(settings.buffer_size);
+                engine.use_monitor_retry(settings.monitor_retry);
+                Box::new(engine)
+            }
+        }
+    }
+}
+
+/// Makes device names usable as keys. cpal doesn't expose stable device identifiers, so repeated
+/// names get their position among the equally named devices appended, e.g. "USB Audio #2". The
+/// first one keeps its plain name, so unique names stay unchanged.
+pub fn disambiguate(names: Vec<String>) -> Vec<String> {
+    let mut keys = Vec::with_capacity(names.len());
+
+    for (index, name) in names.iter().enumerate() {
+        let occurrence = names[..index].iter().filter(|other| *other == name).count() + 1;
+
+        keys.push(if occurrence > 1 { format!("{name} #{occurrence}") } else { name.clone() });
+    }
+
+    keys
+}
+
+/// Finds the name that equals `requested`, or else the first one containing it (ignoring case).
+pub fn resolve_name(requested: &str, available: &[String]) -> Option<String> {
+    let requested_lower = requested.to_lowercase();
+
+    available.iter()
+        .find(|name| name.as_str() == requested)
+        .or_else(|| available.iter().find(|name| name.to_lowercase().contains(&requested_lower)))
+        .cloned()
+}
+
+/// Switches to the first host of `preference` that opens and has an input device, and returns
+/// its name. Hosts that don't match, fail to open or have no input device are skipped. If none is
+/// usable the engine goes back to the host it was on.
+pub fn use_preferred_host(engine: &mut dyn Engine, preference: &[AudioHostName]) -> Option<AudioHostName> {
+    let original_host = engine.get_current_host();
+    let available = engine.get_available_hosts();
+
+    for requested in preference {
+        let Some(host_name) = resolve_name(requested, &available) else {
+            log::info!("No host matches preferred host '{}', trying the next one", requested);
+            continue;
+        };
+
+        if let Err(error) = engine.use_host(host_name.clone()) {
+            log::warn!("{}, trying the next preferred host", error);
+            continue;
+        }
+
+        if engine.get_current_input_device().is_none() {
+            log::warn!("Host {} has no input device, trying the next preferred host", host_name);
+            continue;
+        }
+
+        log::info!("Using preferred host {}", host_name);
+        return Some(host_name);
+    }
+
+    log::warn!("None of the preferred hosts is usable, using the default host");
+
+    if let Some(host_name) = original_host.filter(|host_name| engine.get_current_host().as_ref() != Some(host_name)) {
+        if let Err(error) = engine.use_host(host_name) {
+            log::error!("{}", error);
+        }
+    }
+
+    None
+}
+
+/// What an input device captures. cpal doesn't report it, so it's guessed from the device name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Microphone,
+    Line,
+    /// An output device captured through loopback
+    Loopback,
+    Unknown
+}
+
+impl InputKind {
+    pub fn of(device_name: &str) -> InputKind {
+        if device_name.ends_with(LOOPBACK_SUFFIX) {
+            return InputKind::Loopback;
+        }
+
+        let lowercase = device_name.to_lowercase();
+        let words: Vec<&str> = lowercase.split(|c: char| !c.is_alphanumeric()).collect();
+        let has_word = |candidates: &[&str]| words.iter().any(|word| candidates.contains(word));
+
+        if has_word(&["mic", "mics", "microphone", "microphones", "headset"]) {
+            InputKind::Microphone
+        } else if has_word(&["line", "linein", "aux", "spdif", "optical"]) || lowercase.contains("s/pdif") {
+            InputKind::Line
+        } else {
+            InputKind::Unknown
+        }
+    }
+}
+
+impl Display for InputKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InputKind::Microphone => "Microphone",
+            InputKind::Line => "Line in",
+            InputKind::Loopback => "Loopback",
+            InputKind::Unknown => "Unknown"
+        })
+    }
 }
 
 /// `AudioStream` represents a live recording session from an input device.
 pub struct AudioStream {
-    rx: Receiver<PacketType>
+    rx: Receiver<PacketType>,
+    channels: u16,
+    sample_rate: u32,
+    /// Format the device delivers, before the conversion to `SampleType`
+    sample_format: SampleFormat,
+    dropped: Arc<AtomicUsize>,
+    /// Set by producers that can run out of samples, like a file or a pipe
+    finished: Arc<AtomicBool>,
+    /// Set by the capture callback when a sample reached full scale
+    overload: Arc<AtomicBool>,
+    /// Frames per device buffer, if a fixed size was used
+    buffer_frames: Option<u32>,
+    /// Where spent packets go back to the producer, if it reuses them
+    packet_pool: Option<PacketPool>
 }
 
 impl AudioStream {
 
-    pub fn new(rx: Receiver<Vec<f32>>) -> Self {
+    /// Creates a stream whose packets contain `channels` interleaved channels sampled at
+    /// `sample_rate`.
+    pub fn new(rx: Receiver<Vec<f32>>, channels: u16, sample_rate: u32) -> Self {
         Self {
-            rx
+            rx,
+            channels,
+            sample_rate,
+            sample_format: SampleFormat::F32,
+            dropped: Arc::new(AtomicUsize::new(0)),
+            finished: Arc::new(AtomicBool::new(false)),
+            overload: Arc::new(AtomicBool::new(false)),
+            buffer_frames: None,
+            packet_pool: None
         }
     }
 
+    pub fn with_sample_format(mut self, sample_format: SampleFormat) -> Self {
+        self.sample_format = sample_format;
+        self
+    }
+
+    /// Shares the counter the producer increments whenever it has to drop samples.
+    pub fn with_dropped_samples(mut self, dropped: Arc<AtomicUsize>) -> Self {
+        self.dropped = dropped;
+        self
+    }
+
+    /// Shares the flag the producer sets once it has no more samples.
+    pub fn with_finished(mut self, finished: Arc<AtomicBool>) -> Self {
+        self.finished = finished;
+        self
+    }
+
+    pub fn with_buffer_frames(mut self, buffer_frames: Option<u32>) -> Self {
+        self.buffer_frames = buffer_frames;
+        self
+    }
+
+    pub fn buffer_frames(&self) -> Option<u32> {
+        self.buffer_frames
+    }
+
+    /// Delay added by one device buffer, unknown when the host chose the size.
+    pub fn buffer_latency(&self) -> Option<Duration> {
+        self.buffer_frames.map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64))
+    }
+
+    /// Shares the flag the producer sets when the input reaches full scale.
+    pub fn with_overload(mut self, overload: Arc<AtomicBool>) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Shares the pool the producer takes its packets from.
+    pub fn with_packet_pool(mut self, packet_pool: PacketPool) -> Self {
+        self.packet_pool = Some(packet_pool);
+        self
+    }
+
+    /// The pool received packets should be returned to once they were processed, `None` when
+    /// the producer allocates every packet anyway.
+    pub fn packet_pool(&self) -> Option<PacketPool> {
+        self.packet_pool.clone()
+    }
+
+    /// Whether the input overloaded since the last call.
+    pub fn take_overload(&self) -> bool {
+        self.overload.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether the producer ended and all of its packets have been received.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire) && self.rx.is_empty()
+    }
+
+    /// Number of samples the producer discarded because the stream was full.
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
     pub fn receive(&self) -> Result<PacketType, TryRecvError> {
         self.rx.try_recv()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use cpal::{BufferSize, SupportedBufferSize};
+
+    use crate::engine::{BufferRequest, detect_overload, disambiguate, Engine, forward_packet, InputKind, PacketPool, resolve_name, select_buffer_size, use_preferred_host};
+    use crate::engine::mock::MockEngine;
+
+    #[test]
+    fn full_channel_counts_dropped_samples() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let pool = PacketPool::new(4);
+        let dropped = AtomicUsize::new(0);
+
+        forward_packet(&tx, &pool, vec![0.1, 0.2, 0.3], &dropped);
+        forward_packet(&tx, &pool, vec![0.4, 0.5], &dropped);
+
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(rx.try_recv().ok(), Some(vec![0.1, 0.2, 0.3]));
+
+        forward_packet(&tx, &pool, vec![0.6], &dropped);
+
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(rx.try_recv().ok(), Some(vec![0.6]));
+    }
+
+    #[test]
+    fn empty_packets_are_not_forwarded() {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let pool = PacketPool::new(4);
+        let dropped = AtomicUsize::new(0);
+
+        forward_packet(&tx, &pool, Vec::new(), &dropped);
+
+        assert!(rx.try_recv().is_err());
+        assert_eq!(pool.rx.len(), 1);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn input_kinds_are_guessed_from_the_names() {
+        assert_eq!(InputKind::of("Microphone (Realtek Audio)"), InputKind::Microphone);
+        assert_eq!(InputKind::of("Headset Mic #2"), InputKind::Microphone);
+        assert_eq!(InputKind::of("Line In (USB Audio CODEC)"), InputKind::Line);
+        assert_eq!(InputKind::of("S/PDIF Input"), InputKind::Line);
+        assert_eq!(InputKind::of("Speakers (loopback)"), InputKind::Loopback);
+        assert_eq!(InputKind::of("Scarlett 2i2 USB"), InputKind::Unknown);
+        // "mic" has to be a word of its own
+        assert_eq!(InputKind::of("Dynamic Audio"), InputKind::Unknown);
+    }
+
+    #[test]
+    fn unsupported_buffer_sizes_fall_back_to_the_default() {
+        let range = SupportedBufferSize::Range { min: 64, max: 1024 };
+
+        assert_eq!(select_buffer_size(BufferRequest::Fixed(256), &range), BufferSize::Fixed(256));
+        assert_eq!(select_buffer_size(BufferRequest::Fixed(2048), &range), BufferSize::Default);
+        assert_eq!(select_buffer_size(BufferRequest::DeviceDefault, &range), BufferSize::Default);
+        assert_eq!(select_buffer_size(BufferRequest::Fixed(2048), &SupportedBufferSize::Unknown), BufferSize::Fixed(2048));
+    }
+
+    #[test]
+    fn full_scale_samples_latch_the_overload() {
+        let overload = AtomicBool::new(false);
+
+        detect_overload(&[0.5, -0.9], &overload);
+        assert!(!overload.load(Ordering::Relaxed));
+
+        detect_overload(&[0.1, -1.0, 0.2], &overload);
+        detect_overload(&[0.0], &overload);
+        assert!(overload.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn device_names_resolve_exactly_before_by_substring() {
+        let available = vec![String::from("USB Audio Pro"), String::from("USB Audio"), String::from("Built-in Microphone")];
+
+        assert_eq!(resolve_name("USB Audio", &available), Some(String::from("USB Audio")));
+        assert_eq!(resolve_name("microphone", &available), Some(String::from("Built-in Microphone")));
+        assert_eq!(resolve_name("Line In", &available), None);
+    }
+
+    #[test]
+    fn preferred_hosts_fall_back_to_the_next_usable_one() {
+        let mut engine = MockEngine::default();
+        engine.add_failing_host("JACK");
+        engine.add_inputless_host("PulseAudio");
+        engine.add_host("ALSA");
+
+        let preference = [String::from("jack"), String::from("Nonexistent"), String::from("pulse"), String::from("alsa")];
+
+        assert_eq!(use_preferred_host(&mut engine, &preference), Some(String::from("ALSA")));
+        assert_eq!(engine.get_current_host(), Some(String::from("ALSA")));
+        assert!(engine.get_current_input_device().is_some());
+
+        // nothing usable goes back to the host the engine was on
+        assert_eq!(use_preferred_host(&mut engine, &[String::from("JACK"), String::from("PulseAudio")]), None);
+        assert_eq!(engine.get_current_host(), Some(String::from("ALSA")));
+        assert!(engine.get_current_input_device().is_some());
+    }
+
+    #[test]
+    fn duplicate_device_names_get_an_index() {
+        let names = vec![String::from("USB Audio"), String::from("Built-in"), String::from("USB Audio"), String::from("USB Audio")];
+
+        assert_eq!(disambiguate(names), vec!["USB Audio", "Built-in", "USB Audio #2", "USB Audio #3"]);
+    }
+}
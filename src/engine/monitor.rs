@@ -0,0 +1,380 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use cpal::BuildStreamError;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use crate::engine::{AudiaError, is_transient, SampleType};
+
+/// Default level above which the limiter starts to compress, in dBFS
+pub const DEFAULT_LIMITER_THRESHOLD_DB: f32 = -6.0;
+
+/// Longest delay the monitored audio can be held back by
+pub const MAX_MONITOR_DELAY_MS: f32 = 1000.0;
+
+/// Default number of times opening the monitor output is tried before giving up
+pub const DEFAULT_MONITOR_ATTEMPTS: u32 = 3;
+/// Most attempts that can be configured, the waits double with every one
+pub const MAX_MONITOR_ATTEMPTS: u32 = 8;
+
+/// How often a monitor output that fails to open is tried again, and how long to wait in between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorRetry {
+    /// Attempts in total, including the first one
+    pub attempts: u32,
+    /// Wait after the first failure, doubled after every further one
+    pub delay: Duration
+}
+
+impl MonitorRetry {
+    /// Wait after the failed attempt `attempt`, counted from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.delay * 2u32.saturating_pow(attempt)
+    }
+
+}
+
+impl Default for MonitorRetry {
+    fn default() -> Self {
+        Self { attempts: DEFAULT_MONITOR_ATTEMPTS, delay: Duration::from_millis(50) }
+    }
+}
+
+/// A failed attempt to open the monitor output.
+#[derive(Debug)]
+pub struct MonitorError {
+    pub error: AudiaError,
+    /// The output may open on a later attempt, e.g. once a busy device is released
+    pub transient: bool
+}
+
+impl Display for MonitorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl From<BuildStreamError> for MonitorError {
+    fn from(error: BuildStreamError) -> Self {
+        Self { transient: is_transient(&error), error: AudiaError::from(error) }
+    }
+}
+
+impl From<MonitorError> for AudiaError {
+    fn from(error: MonitorError) -> Self {
+        error.error
+    }
+}
+
+/// Counts the failed attempts to open the monitor output and schedules the next one. The caller
+/// tries again once `is_due`, so it keeps running during the waits.
+pub struct RetrySchedule {
+    retry: MonitorRetry,
+    failed: u32,
+    due: Instant
+}
+
+impl RetrySchedule {
+    pub fn new(retry: MonitorRetry) -> Self {
+        Self { retry, failed: 0, due: Instant::now() }
+    }
+
+    /// Counts a failed attempt at `now` and schedules the next one after its backoff. Returns
+    /// false once the attempts are used up or the failure was permanent.
+    pub fn failed(&mut self, error: &MonitorError, now: Instant) -> bool {
+        self.failed += 1;
+
+        if !error.transient || self.failed >= self.retry.attempts.max(1) {
+            return false;
+        }
+
+        let wait = self.retry.backoff(self.failed - 1);
+        log::warn!("Opening the monitor output failed ({} of {} attempts): {}, retrying in {} ms",
+            self.failed, self.retry.attempts.max(1), error, wait.as_millis());
+        self.due = now + wait;
+
+        true
+    }
+
+    /// Failed attempts so far
+    pub fn attempts(&self) -> u32 {
+        self.failed
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.due
+    }
+}
+
+/// Number of interleaved samples covering `delay_ms`, rounded to whole frames.
+pub fn delay_samples(delay_ms: f32, channels: u16, sample_rate: u32) -> usize {
+    let frames = (delay_ms.max(0.0) / 1000.0 * sample_rate as f32).round() as usize;
+
+    frames * channels as usize
+}
+
+/// Soft clipper that leaves samples below the threshold untouched and bends everything above it
+/// smoothly towards full scale with a tanh curve, so the output can never clip.
+pub fn soft_limit(sample: SampleType, threshold: f32) -> SampleType {
+    let threshold = threshold.clamp(0.0, 0.999);
+    let magnitude = sample.abs();
+
+    if magnitude <= threshold {
+        return sample;
+    }
+
+    let headroom = 1.0 - threshold;
+    let limited = threshold + headroom * ((magnitude - threshold) / headroom).tanh();
+
+    limited.copysign(sample)
+}
+
+/// Limiter settings shared with the output callback, which must not block on a lock.
+pub struct LimiterControl {
+    enabled: AtomicBool,
+    /// Threshold as a linear amplitude, stored as `f32` bits
+    threshold: AtomicU32
+}
+
+impl LimiterControl {
+    pub fn new(enabled: bool, threshold_db: f32) -> Self {
+        let control = Self { enabled: AtomicBool::new(enabled), threshold: AtomicU32::new(0) };
+        control.set_threshold_db(threshold_db);
+        control
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn threshold_db(&self) -> f32 {
+        20.0 * self.threshold().log10()
+    }
+
+    pub fn set_threshold_db(&self, threshold_db: f32) {
+        let threshold = 10f32.powf(threshold_db.min(0.0) / 20.0);
+        self.threshold.store(threshold.to_bits(), Ordering::Relaxed);
+    }
+
+    fn threshold(&self) -> f32 {
+        f32::from_bits(self.threshold.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for LimiterControl {
+    fn default() -> Self {
+        Self::new(true, DEFAULT_LIMITER_THRESHOLD_DB)
+    }
+}
+
+/// Feeds captured audio to a running monitor output stream.
+pub struct Monitor {
+    producer: HeapProducer<SampleType>,
+    limiter: Arc<LimiterControl>,
+    /// Delay in samples, shared with the output
+    delay: Arc<AtomicUsize>,
+    dropped: usize
+}
+
+impl Monitor {
+    pub fn limiter(&self) -> &LimiterControl {
+        &self.limiter
+    }
+
+    /// Holds the monitored audio back by `samples` interleaved samples, e.g. to align it with
+    /// another source. Limited to the delay the monitor was created with.
+    pub fn set_delay(&self, samples: usize) {
+        self.delay.store(samples, Ordering::Relaxed);
+    }
+
+    /// Queues interleaved samples for playback. Samples that don't fit are dropped, which keeps
+    /// the monitoring latency bounded.
+    pub fn push(&mut self, samples: &[SampleType]) {
+        let pushed = self.producer.push_slice(samples);
+        self.dropped += samples.len() - pushed;
+    }
+
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// Circular buffer that delays the samples passing through it by `length` samples. The buffer is
+/// allocated up front, so changing the length doesn't allocate in the output callback.
+struct DelayLine {
+    buffer: Vec<SampleType>,
+    length: usize,
+    position: usize
+}
+
+impl DelayLine {
+    fn new(max_length: usize) -> Self {
+        Self { buffer: vec![0.0; max_length], length: 0, position: 0 }
+    }
+
+    fn set_length(&mut self, length: usize) {
+        let length = length.min(self.buffer.len());
+
+        if length != self.length {
+            // start over with silence rather than replaying stale samples
+            self.length = length;
+            self.position = 0;
+            self.buffer[..length].fill(0.0);
+        }
+    }
+
+    fn process(&mut self, data: &mut [SampleType]) {
+        if self.length == 0 {
+            return;
+        }
+
+        for sample in data {
+            let delayed = self.buffer[self.position];
+            self.buffer[self.position] = *sample;
+            *sample = delayed;
+            self.position = (self.position + 1) % self.length;
+        }
+    }
+}
+
+/// The output callback's end of a monitor.
+pub struct MonitorOutput {
+    consumer: HeapConsumer<SampleType>,
+    limiter: Arc<LimiterControl>,
+    delay: Arc<AtomicUsize>,
+    delay_line: DelayLine
+}
+
+impl MonitorOutput {
+    /// Fills an output buffer with the queued samples, padding with silence on underruns, and
+    /// delays them by the current delay.
+    pub fn fill(&mut self, data: &mut [SampleType]) {
+        let filled = self.consumer.pop_slice(data);
+
+        data[filled..].fill(0.0);
+
+        self.delay_line.set_length(self.delay.load(Ordering::Relaxed));
+        self.delay_line.process(data);
+
+        if self.limiter.is_enabled() {
+            let threshold = self.limiter.threshold();
+            data.iter_mut().for_each(|sample| *sample = soft_limit(*sample, threshold));
+        }
+    }
+}
+
+/// Creates the two ends of a monitor buffering up to `capacity` samples, which can be delayed by
+/// up to `max_delay` samples.
+pub fn monitor_channel(capacity: usize, max_delay: usize, limiter: Arc<LimiterControl>) -> (Monitor, MonitorOutput) {
+    let (producer, consumer) = HeapRb::<SampleType>::new(capacity.max(1)).split();
+    let delay = Arc::new(AtomicUsize::new(0));
+
+    let monitor = Monitor { producer, limiter: limiter.clone(), delay: delay.clone(), dropped: 0 };
+    let output = MonitorOutput { consumer, limiter, delay, delay_line: DelayLine::new(max_delay) };
+
+    (monitor, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use cpal::BuildStreamError;
+
+    use crate::engine::monitor::{delay_samples, LimiterControl, monitor_channel, MonitorError, MonitorRetry, RetrySchedule, soft_limit};
+
+    #[test]
+    fn quiet_samples_pass_unchanged() {
+        assert_eq!(soft_limit(0.3, 0.5), 0.3);
+        assert_eq!(soft_limit(-0.5, 0.5), -0.5);
+    }
+
+    #[test]
+    fn loud_samples_stay_below_full_scale() {
+        for sample in [0.6, 1.0, 2.0, 100.0] {
+            let limited = soft_limit(sample, 0.5);
+
+            assert!(limited > 0.5 && limited <= 1.0);
+            assert_eq!(soft_limit(-sample, 0.5), -limited);
+        }
+
+        // monotonic, so louder input still sounds louder
+        assert!(soft_limit(0.9, 0.5) < soft_limit(1.2, 0.5));
+    }
+
+    #[test]
+    fn output_is_limited_and_padded_with_silence() {
+        let limiter = Arc::new(LimiterControl::new(true, -6.0));
+        let (mut monitor, mut output) = monitor_channel(8, 0, limiter);
+
+        monitor.push(&[0.1, 2.0]);
+
+        let mut data = [1.0; 4];
+        output.fill(&mut data);
+
+        assert_eq!(data[0], 0.1);
+        assert!(data[1] < 1.0);
+        assert_eq!(data[2..], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn transient_failures_are_retried_with_growing_waits() {
+        let retry = MonitorRetry { attempts: 3, delay: Duration::from_millis(10) };
+        assert_eq!(retry.backoff(0), Duration::from_millis(10));
+        assert_eq!(retry.backoff(2), Duration::from_millis(40));
+
+        let busy = MonitorError::from(BuildStreamError::DeviceNotAvailable);
+        let start = Instant::now();
+        let mut schedule = RetrySchedule::new(retry);
+        assert!(schedule.is_due(start));
+
+        // the waits double, nothing blocks in between
+        assert!(schedule.failed(&busy, start));
+        assert!(!schedule.is_due(start + Duration::from_millis(5)));
+        assert!(schedule.is_due(start + Duration::from_millis(10)));
+        assert!(schedule.failed(&busy, start));
+        assert!(!schedule.is_due(start + Duration::from_millis(15)));
+        assert!(schedule.is_due(start + Duration::from_millis(20)));
+
+        // the attempts are used up with the third failure
+        assert!(!schedule.failed(&busy, start));
+        assert_eq!(schedule.attempts(), 3);
+
+        // permanent errors aren't retried
+        let mut schedule = RetrySchedule::new(retry);
+        assert!(!schedule.failed(&MonitorError::from(BuildStreamError::StreamConfigNotSupported), start));
+        assert_eq!(schedule.attempts(), 1);
+    }
+
+    #[test]
+    fn full_monitor_drops_samples() {
+        let (mut monitor, _output) = monitor_channel(4, 0, Arc::new(LimiterControl::default()));
+
+        monitor.push(&[0.0; 6]);
+
+        assert_eq!(monitor.dropped_samples(), 2);
+    }
+
+    #[test]
+    fn delayed_output_starts_with_silence() {
+        let (mut monitor, mut output) = monitor_channel(8, 4, Arc::new(LimiterControl::new(false, 0.0)));
+        // one stereo frame
+        monitor.set_delay(delay_samples(0.5, 2, 2000));
+        monitor.push(&[0.1, 0.2, 0.3, 0.4]);
+
+        let mut data = [1.0; 4];
+        output.fill(&mut data);
+        assert_eq!(data, [0.0, 0.0, 0.1, 0.2]);
+
+        // the rest comes out with the next buffer
+        output.fill(&mut data);
+        assert_eq!(data, [0.3, 0.4, 0.0, 0.0]);
+    }
+}
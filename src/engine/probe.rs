@@ -0,0 +1,146 @@
+use std::fmt::{Display, Formatter};
+
+use cpal::SampleFormat;
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::engine::{AudioHostName, CpalEngine, InputDeviceName};
+
+/// The input capabilities of every device on every available host, for bug reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeReport {
+    pub hosts: Vec<HostProbe>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostProbe {
+    pub host: AudioHostName,
+    /// The devices, or why the host couldn't list them
+    pub devices: Result<Vec<DeviceProbe>, String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceProbe {
+    pub name: InputDeviceName,
+    /// The supported configurations, or why the device couldn't be queried
+    pub configs: Result<Vec<ConfigProbe>, String>
+}
+
+/// One supported input configuration range of a device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfigProbe {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: SampleFormat
+}
+
+/// Queries the input configurations of all devices. A host or device that fails is reported with
+/// its error and the probe continues with the next one.
+pub fn probe_input_devices() -> ProbeReport {
+    let hosts = cpal::available_hosts().into_iter()
+        .map(|host_id| {
+            let devices = cpal::host_from_id(host_id)
+                .map_err(|error| error.to_string())
+                .and_then(|host| host.input_devices().map_err(|error| error.to_string()))
+                .map(|devices| CpalEngine::keyed(devices.collect()).into_iter()
+                    .map(|(name, device)| DeviceProbe {
+                        name,
+                        configs: device.supported_input_configs()
+                            .map(|configs| configs
+                                .map(|config| ConfigProbe {
+                                    channels: config.channels(),
+                                    min_sample_rate: config.min_sample_rate().0,
+                                    max_sample_rate: config.max_sample_rate().0,
+                                    sample_format: config.sample_format()
+                                })
+                                .collect())
+                            .map_err(|error| error.to_string())
+                    })
+                    .collect());
+
+            HostProbe { host: String::from(host_id.name()), devices }
+        })
+        .collect();
+
+    ProbeReport { hosts }
+}
+
+impl Display for ConfigProbe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} channels, {:?}, ", self.channels, self.sample_format)?;
+
+        if self.min_sample_rate == self.max_sample_rate {
+            write!(f, "{} Hz", self.min_sample_rate)
+        } else {
+            write!(f, "{} to {} Hz", self.min_sample_rate, self.max_sample_rate)
+        }
+    }
+}
+
+impl Display for ProbeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Input device probe of {} hosts", self.hosts.len())?;
+
+        for host in &self.hosts {
+            match &host.devices {
+                Ok(devices) => writeln!(f, "{}: {} input devices", host.host, devices.len())?,
+                Err(error) => {
+                    writeln!(f, "{}: failed, {}", host.host, error)?;
+                    continue;
+                }
+            }
+
+            for device in host.devices.iter().flatten() {
+                match &device.configs {
+                    Ok(configs) => {
+                        writeln!(f, "  {}", device.name)?;
+
+                        for config in configs {
+                            writeln!(f, "    {}", config)?;
+                        }
+                    },
+                    Err(error) => writeln!(f, "  {}: failed, {}", device.name, error)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SampleFormat;
+
+    use crate::engine::probe::{ConfigProbe, DeviceProbe, HostProbe, ProbeReport};
+
+    #[test]
+    fn report_lists_failures_next_to_the_probed_devices() {
+        let report = ProbeReport {
+            hosts: vec![
+                HostProbe {
+                    host: String::from("ALSA"),
+                    devices: Ok(vec![
+                        DeviceProbe {
+                            name: String::from("USB Audio"),
+                            configs: Ok(vec![
+                                ConfigProbe { channels: 2, min_sample_rate: 44100, max_sample_rate: 96000, sample_format: SampleFormat::I16 },
+                                ConfigProbe { channels: 1, min_sample_rate: 48000, max_sample_rate: 48000, sample_format: SampleFormat::F32 }
+                            ])
+                        },
+                        DeviceProbe { name: String::from("Busy"), configs: Err(String::from("device is busy")) }
+                    ])
+                },
+                HostProbe { host: String::from("JACK"), devices: Err(String::from("server not running")) }
+            ]
+        };
+
+        assert_eq!(report.to_string(), "Input device probe of 2 hosts\n\
+            ALSA: 2 input devices\n  \
+            USB Audio\n    \
+            2 channels, I16, 44100 to 96000 Hz\n    \
+            1 channels, F32, 48000 Hz\n  \
+            Busy: failed, device is busy\n\
+            JACK: failed, server not running\n");
+    }
+}
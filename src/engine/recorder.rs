@@ -0,0 +1,400 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{AudiaError, SampleType};
+use crate::engine::analysis;
+
+/// Sample formats a WAV recording can be written in, independently of the capture format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavSampleFormat {
+    #[default]
+    F32,
+    I16,
+    I24
+}
+
+impl WavSampleFormat {
+    pub const ALL: [WavSampleFormat; 3] = [WavSampleFormat::F32, WavSampleFormat::I16, WavSampleFormat::I24];
+
+    fn spec(&self, sample_rate: u32, channels: u16) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            WavSampleFormat::F32 => (32, SampleFormat::Float),
+            WavSampleFormat::I16 => (16, SampleFormat::Int),
+            WavSampleFormat::I24 => (24, SampleFormat::Int)
+        };
+
+        WavSpec { channels, sample_rate, bits_per_sample, sample_format }
+    }
+}
+
+impl Display for WavSampleFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WavSampleFormat::F32 => "32-bit float",
+            WavSampleFormat::I16 => "16-bit integer",
+            WavSampleFormat::I24 => "24-bit integer"
+        })
+    }
+}
+
+/// What happens to a running recording when the input switches to a different channel count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChannelChangePolicy {
+    /// Close the current file and continue in a new one with the new channel count
+    #[default]
+    SplitFile,
+    /// Keep the input, switching to a device with a different channel count is refused
+    Reject
+}
+
+impl ChannelChangePolicy {
+    pub const ALL: [ChannelChangePolicy; 2] = [ChannelChangePolicy::SplitFile, ChannelChangePolicy::Reject];
+}
+
+impl Display for ChannelChangePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChannelChangePolicy::SplitFile => "New file on channel change",
+            ChannelChangePolicy::Reject => "Keep input while recording"
+        })
+    }
+}
+
+/// Triangular (TPDF) dither noise in the range of ±1 LSB.
+pub struct Dither {
+    state: u32
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self { state: 0x9e37_79b9 }
+    }
+}
+
+impl Dither {
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        self.state as f32 / u32::MAX as f32 - 0.5
+    }
+
+    pub fn sample(&mut self) -> f32 {
+        self.uniform() + self.uniform()
+    }
+}
+
+/// Quantizes a sample to a signed integer with `bits` of resolution. Out-of-range input is clamped
+/// to full scale so it can never wrap around.
+pub fn quantize(sample: SampleType, bits: u32, dither: Option<&mut Dither>) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    let noise = dither.map(Dither::sample).unwrap_or(0.0);
+    let sample = if sample.is_finite() { sample.clamp(-1.0, 1.0) } else { 0.0 };
+
+    (sample * max + noise).round().clamp(-max - 1.0, max) as i32
+}
+
+/// Writes captured samples into a WAV file in the requested sample format.
+pub struct WavRecorder {
+    writer: WavWriter<BufWriter<File>>,
+    format: WavSampleFormat,
+    dither: Dither,
+    channels: usize,
+    /// Frames after which nothing more is written
+    max_frames: Option<u32>
+}
+
+impl WavRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, format: WavSampleFormat) -> Result<Self, AudiaError> {
+        let writer = WavWriter::create(path.as_ref(), format.spec(sample_rate, channels))
+            .map_err(|error| AudiaError::from(format!("Could not create {}: {error}", path.as_ref().display())))?;
+
+        log::info!("Recording to {} as {}", path.as_ref().display(), format);
+
+        Ok(Self { writer, format, dither: Dither::default(), channels: channels.max(1) as usize, max_frames: None })
+    }
+
+    /// Limits the recording to `max_frames` frames, the rest of the input is ignored.
+    pub fn with_max_frames(mut self, max_frames: Option<u32>) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    pub fn write(&mut self, samples: &[SampleType]) -> Result<(), AudiaError> {
+        let samples = match self.remaining_frames() {
+            Some(remaining) => &samples[..samples.len().min(remaining as usize * self.channels)],
+            None => samples
+        };
+
+        for sample in samples {
+            let result = match self.format {
+                WavSampleFormat::F32 => self.writer.write_sample(*sample),
+                WavSampleFormat::I16 => self.writer.write_sample(quantize(*sample, 16, Some(&mut self.dither)) as i16),
+                WavSampleFormat::I24 => self.writer.write_sample(quantize(*sample, 24, Some(&mut self.dither)))
+            };
+
+            result.map_err(|error| AudiaError::from(format!("Could not write recording: {error}")))?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of frames (samples per channel) written so far.
+    pub fn frames(&self) -> u32 {
+        self.writer.duration()
+    }
+
+    /// Frames left until the maximum duration, `None` without a limit.
+    pub fn remaining_frames(&self) -> Option<u32> {
+        self.max_frames.map(|max_frames| max_frames.saturating_sub(self.frames()))
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.writer.spec().sample_rate
+    }
+
+    /// Whether a stream with `channels` interleaved channels at `sample_rate` fits the file.
+    pub fn accepts(&self, channels: u16, sample_rate: u32) -> bool {
+        channels.max(1) as usize == self.channels && sample_rate == self.sample_rate()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.remaining_frames() == Some(0)
+    }
+
+    pub fn finalize(self) -> Result<(), AudiaError> {
+        self.writer.finalize()
+            .map_err(|error| AudiaError::from(format!("Could not finalize recording: {error}")))
+    }
+}
+
+/// Writes one CSV row per analyzed frame, to be correlated with a recording of the raw input.
+pub struct AnalysisLog {
+    writer: BufWriter<File>
+}
+
+impl AnalysisLog {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, AudiaError> {
+        let file = File::create(path.as_ref())
+            .map_err(|error| AudiaError::from(format!("Could not create {}: {error}", path.as_ref().display())))?;
+
+        let mut log = Self { writer: BufWriter::new(file) };
+        log.write_line("time_s,peak_frequency_hz,peak_amplitude")?;
+
+        log::info!("Logging analysis to {}", path.as_ref().display());
+
+        Ok(log)
+    }
+
+    /// `time` is the position in the recording in seconds.
+    pub fn write(&mut self, time: f64, peak_freq: f32, peak_amp: f32) -> Result<(), AudiaError> {
+        self.write_line(&format!("{time:.6},{peak_freq:.3},{peak_amp:.6}"))
+    }
+
+    pub fn finish(mut self) -> Result<(), AudiaError> {
+        self.writer.flush()
+            .map_err(|error| AudiaError::from(format!("Could not finish analysis log: {error}")))
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<(), AudiaError> {
+        writeln!(self.writer, "{line}")
+            .map_err(|error| AudiaError::from(format!("Could not write analysis log: {error}")))
+    }
+}
+
+/// Settings of the sound-activated recording.
+#[derive(Debug, Clone, Copy)]
+pub struct ActivationSettings {
+    /// RMS level that starts a recording
+    pub threshold_dbfs: f32,
+    /// How far below the threshold the level has to drop before it counts as silence
+    pub hysteresis_db: f32,
+    /// How long the signal has to stay silent before the recording is stopped
+    pub silence_timeout: Duration
+}
+
+impl Default for ActivationSettings {
+    fn default() -> Self {
+        Self {
+            threshold_dbfs: -40.0,
+            hysteresis_db: 6.0,
+            silence_timeout: Duration::from_secs(2)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationEvent {
+    SignalStarted,
+    SignalEnded
+}
+
+/// Detects the presence of a signal to start and stop recordings automatically. Separate start and
+/// release levels keep the gate from chattering around the threshold.
+pub struct SignalGate {
+    pub settings: ActivationSettings,
+    sample_rate: u32,
+    open: bool,
+    silent_frames: usize
+}
+
+impl SignalGate {
+    pub fn new(settings: ActivationSettings, sample_rate: u32) -> Self {
+        Self { settings, sample_rate, open: false, silent_frames: 0 }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Feeds an interleaved packet with `channels` channels through the gate.
+    pub fn process(&mut self, packet: &[SampleType], channels: u16) -> Option<ActivationEvent> {
+        let level = 20.0 * analysis::rms(packet).max(1e-10).log10();
+
+        if !self.open {
+            if level >= self.settings.threshold_dbfs {
+                self.open = true;
+                self.silent_frames = 0;
+                return Some(ActivationEvent::SignalStarted);
+            }
+        } else if level < self.settings.threshold_dbfs - self.settings.hysteresis_db {
+            self.silent_frames += packet.len() / channels.max(1) as usize;
+
+            let timeout_frames = (self.settings.silence_timeout.as_secs_f32() * self.sample_rate as f32) as usize;
+
+            if self.silent_frames >= timeout_frames {
+                self.open = false;
+                return Some(ActivationEvent::SignalEnded);
+            }
+        } else {
+            self.silent_frames = 0;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::engine::recorder::{ActivationEvent, ActivationSettings, Dither, quantize, SignalGate, WavRecorder, WavSampleFormat};
+
+    #[test]
+    fn full_scale_maps_to_integer_peaks() {
+        assert_eq!(quantize(1.0, 16, None), i16::MAX as i32);
+        assert_eq!(quantize(-1.0, 16, None), -(i16::MAX as i32));
+        assert_eq!(quantize(1.0, 24, None), 8_388_607);
+        assert_eq!(quantize(-1.0, 24, None), -8_388_607);
+    }
+
+    #[test]
+    fn out_of_range_samples_do_not_wrap() {
+        assert_eq!(quantize(1.5, 16, None), i16::MAX as i32);
+        assert_eq!(quantize(-3.0, 16, None), -(i16::MAX as i32));
+        assert_eq!(quantize(f32::NAN, 24, None), 0);
+    }
+
+    #[test]
+    fn dithered_full_scale_stays_in_range() {
+        let mut dither = Dither::default();
+
+        for _ in 0..1000 {
+            let value = quantize(1.0, 16, Some(&mut dither));
+            assert!((i16::MAX as i32 - 1..=i16::MAX as i32).contains(&value));
+
+            let value = quantize(-1.0, 24, Some(&mut dither));
+            assert!((-8_388_608..=-8_388_606).contains(&value));
+        }
+    }
+
+    #[test]
+    fn recording_stops_at_the_maximum_duration() {
+        let path = std::env::temp_dir().join("audia-limited-recording.wav");
+        let mut recorder = WavRecorder::create(&path, 48000, 2, WavSampleFormat::I16).unwrap()
+            .with_max_frames(Some(4));
+
+        recorder.write(&[0.1; 6]).unwrap();
+        assert_eq!(recorder.remaining_frames(), Some(1));
+        assert!(!recorder.is_full());
+
+        recorder.write(&[0.1; 6]).unwrap();
+        assert_eq!(recorder.frames(), 4);
+        assert!(recorder.is_full());
+
+        recorder.finalize().unwrap();
+        assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 4);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn recording_only_accepts_its_stream_format() {
+        let path = std::env::temp_dir().join("audia-channel-recording.wav");
+        let recorder = WavRecorder::create(&path, 48000, 2, WavSampleFormat::F32).unwrap();
+
+        assert_eq!(recorder.channels(), 2);
+        assert!(recorder.accepts(2, 48000));
+        assert!(!recorder.accepts(1, 48000));
+        assert!(!recorder.accepts(4, 48000));
+        assert!(!recorder.accepts(2, 44100));
+
+        recorder.finalize().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn signal_gate_opens_and_closes_with_hysteresis() {
+        let settings = ActivationSettings {
+            threshold_dbfs: -20.0,
+            hysteresis_db: 6.0,
+            silence_timeout: Duration::from_millis(10)
+        };
+        // 100 frames per 10 ms
+        let mut gate = SignalGate::new(settings, 10000);
+
+        let loud = vec![0.5; 50];
+        // about -23 dBFS: below the threshold but within the hysteresis
+        let quiet = vec![0.07; 50];
+        let silence = vec![0.0; 50];
+
+        assert_eq!(gate.process(&silence, 1), None);
+        assert_eq!(gate.process(&loud, 1), Some(ActivationEvent::SignalStarted));
+        assert_eq!(gate.process(&loud, 1), None);
+
+        for _ in 0..10 {
+            assert_eq!(gate.process(&quiet, 1), None);
+        }
+        assert!(gate.is_open());
+
+        assert_eq!(gate.process(&silence, 1), None);
+        assert_eq!(gate.process(&silence, 1), Some(ActivationEvent::SignalEnded));
+        assert!(!gate.is_open());
+    }
+
+    #[test]
+    fn short_pauses_do_not_close_the_gate() {
+        let settings = ActivationSettings {
+            threshold_dbfs: -20.0,
+            hysteresis_db: 6.0,
+            silence_timeout: Duration::from_millis(10)
+        };
+        let mut gate = SignalGate::new(settings, 10000);
+
+        assert_eq!(gate.process(&[0.5; 50], 1), Some(ActivationEvent::SignalStarted));
+        assert_eq!(gate.process(&[0.0; 50], 1), None);
+        assert_eq!(gate.process(&[0.5; 50], 1), None);
+        assert_eq!(gate.process(&[0.0; 50], 1), None);
+        assert!(gate.is_open());
+    }
+}
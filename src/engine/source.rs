@@ -0,0 +1,370 @@
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::engine::{AudiaError, AudioStream, PacketType, SampleType};
+
+/// Provides samples from something other than an audio device, e.g. a signal generator.
+pub trait SampleSource: Send {
+    fn sample_rate(&self) -> u32;
+
+    /// Number of interleaved channels in each packet.
+    fn channels(&self) -> u16;
+
+    /// Returns the next packet of samples or `None` once the source is exhausted.
+    fn next_packet(&mut self) -> Option<PacketType>;
+}
+
+/// Runs `source` on a background thread and exposes its packets as an `AudioStream`. When `paced`
+/// is set, packets are released at the rate a real device would deliver them.
+pub fn spawn_source(mut source: Box<dyn SampleSource>, paced: bool) -> AudioStream {
+    let (tx, rx) = crossbeam_channel::unbounded::<PacketType>();
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let finished = Arc::new(AtomicBool::new(false));
+    let source_finished = finished.clone();
+
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut samples_sent = 0;
+
+        while let Some(packet) = source.next_packet() {
+            samples_sent += packet.len();
+
+            if tx.send(packet).is_err() {
+                // the stream has been dropped on the receiving side
+                break;
+            }
+
+            if paced {
+                let due = Duration::from_secs_f64(samples_sent as f64 / source.sample_rate() as f64);
+
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+        }
+
+        log::info!("Sample source finished");
+        source_finished.store(true, Ordering::Release);
+    });
+
+    AudioStream::new(rx, channels, sample_rate).with_finished(finished)
+}
+
+/// The kinds of test signals `SyntheticSource` can generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestSignalKind {
+    Sine,
+    Noise,
+    Sweep,
+    Multitone
+}
+
+impl TestSignalKind {
+    pub const ALL: [TestSignalKind; 4] = [TestSignalKind::Sine, TestSignalKind::Noise, TestSignalKind::Sweep, TestSignalKind::Multitone];
+}
+
+impl Display for TestSignalKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TestSignalKind::Sine => "Sine",
+            TestSignalKind::Noise => "Noise",
+            TestSignalKind::Sweep => "Sweep",
+            TestSignalKind::Multitone => "Multitone"
+        })
+    }
+}
+
+const SINE_FREQUENCY: f32 = 440.0;
+const MULTITONE_FREQUENCIES: [f32; 4] = [220.0, 440.0, 880.0, 1320.0];
+pub const SWEEP_RANGE: (f32, f32) = (20.0, 2000.0);
+pub const SWEEP_SECONDS: f32 = 5.0;
+const AMPLITUDE: f32 = 0.5;
+
+/// Generates a synthetic test signal for exercising the analysis without audio hardware.
+pub struct SyntheticSource {
+    kind: TestSignalKind,
+    sample_rate: u32,
+    packet_size: usize,
+    position: u64,
+    phases: Vec<f32>,
+    noise_state: u32
+}
+
+impl SyntheticSource {
+    pub fn new(kind: TestSignalKind, sample_rate: u32, packet_size: usize) -> Self {
+        let tones = match kind {
+            TestSignalKind::Multitone => MULTITONE_FREQUENCIES.len(),
+            _ => 1
+        };
+
+        Self {
+            kind,
+            sample_rate,
+            packet_size,
+            position: 0,
+            phases: vec![0.0; tones],
+            noise_state: 0x2545_f491
+        }
+    }
+
+    fn advance_phase(&mut self, tone: usize, freq: f32) -> SampleType {
+        let sample = self.phases[tone].sin();
+        self.phases[tone] = (self.phases[tone] + 2.0 * PI * freq / self.sample_rate as f32) % (2.0 * PI);
+        sample
+    }
+
+    fn next_sample(&mut self) -> SampleType {
+        let sample = match self.kind {
+            TestSignalKind::Sine => AMPLITUDE * self.advance_phase(0, SINE_FREQUENCY),
+            TestSignalKind::Noise => {
+                // xorshift32 is plenty for audible white noise
+                self.noise_state ^= self.noise_state << 13;
+                self.noise_state ^= self.noise_state >> 17;
+                self.noise_state ^= self.noise_state << 5;
+
+                AMPLITUDE * (2.0 * (self.noise_state as f32 / u32::MAX as f32) - 1.0)
+            },
+            TestSignalKind::Sweep => {
+                let elapsed = (self.position as f32 / self.sample_rate as f32) % SWEEP_SECONDS;
+                let (from, to) = SWEEP_RANGE;
+                let freq = from * (to / from).powf(elapsed / SWEEP_SECONDS);
+
+                AMPLITUDE * self.advance_phase(0, freq)
+            },
+            TestSignalKind::Multitone => {
+                let mut sum = 0.0;
+                for (tone, freq) in MULTITONE_FREQUENCIES.iter().enumerate() {
+                    sum += self.advance_phase(tone, *freq);
+                }
+
+                AMPLITUDE * sum / MULTITONE_FREQUENCIES.len() as f32
+            }
+        };
+
+        self.position += 1;
+        sample
+    }
+
+    /// Generates the next `count` samples.
+    pub fn generate(&mut self, count: usize) -> PacketType {
+        (0..count).map(|_| self.next_sample()).collect()
+    }
+}
+
+impl SampleSource for SyntheticSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn next_packet(&mut self) -> Option<PacketType> {
+        Some(self.generate(self.packet_size))
+    }
+}
+
+/// Encodings of raw PCM input, little-endian and interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawSampleFormat {
+    #[default]
+    F32,
+    I16
+}
+
+impl RawSampleFormat {
+    fn bytes_per_sample(&self) -> usize {
+        match self {
+            RawSampleFormat::F32 => 4,
+            RawSampleFormat::I16 => 2
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> SampleType {
+        match self {
+            RawSampleFormat::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            RawSampleFormat::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0
+        }
+    }
+}
+
+impl FromStr for RawSampleFormat {
+    type Err = AudiaError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "f32" | "f32le" => Ok(RawSampleFormat::F32),
+            "i16" | "s16" | "s16le" => Ok(RawSampleFormat::I16),
+            _ => Err(AudiaError::from(format!("Unknown raw sample format: {value}")))
+        }
+    }
+}
+
+/// Where raw PCM input is read from and how it is laid out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawInput {
+    /// File or named pipe to read, stdin when missing
+    pub path: Option<PathBuf>,
+    pub format: RawSampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16
+}
+
+impl RawInput {
+    /// A source reading `packet_frames` frames per packet. The input is only opened once the
+    /// source runs, as opening a named pipe blocks until the writer connects.
+    pub fn source(&self, packet_frames: usize) -> RawSource {
+        let path = self.path.clone();
+        let open = move || -> std::io::Result<Box<dyn Read + Send>> {
+            match &path {
+                Some(path) => Ok(Box::new(File::open(path)?)),
+                None => Ok(Box::new(std::io::stdin()))
+            }
+        };
+
+        RawSource {
+            reader: None,
+            open: Some(Box::new(open)),
+            format: self.format,
+            sample_rate: self.sample_rate,
+            channels: self.channels.max(1),
+            packet_frames
+        }
+    }
+}
+
+impl Display for RawInput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}", path.display()),
+            None => f.write_str("stdin")
+        }
+    }
+}
+
+type OpenReader = Box<dyn FnOnce() -> std::io::Result<Box<dyn Read + Send>> + Send>;
+
+/// Reads raw PCM, e.g. piped from ffmpeg or sox, until the end of the input.
+pub struct RawSource {
+    reader: Option<Box<dyn Read + Send>>,
+    open: Option<OpenReader>,
+    format: RawSampleFormat,
+    sample_rate: u32,
+    channels: u16,
+    packet_frames: usize
+}
+
+impl RawSource {
+    pub fn from_reader(reader: impl Read + Send + 'static, format: RawSampleFormat, sample_rate: u32, channels: u16, packet_frames: usize) -> Self {
+        Self {
+            reader: Some(Box::new(reader)),
+            open: None,
+            format,
+            sample_rate,
+            channels: channels.max(1),
+            packet_frames
+        }
+    }
+
+    /// Fills as much of `buffer` as the input provides, returning the number of bytes read.
+    fn read_up_to(reader: &mut dyn Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let mut filled = 0;
+
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error)
+            }
+        }
+
+        Ok(filled)
+    }
+}
+
+impl SampleSource for RawSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn next_packet(&mut self) -> Option<PacketType> {
+        if let Some(open) = self.open.take() {
+            match open() {
+                Ok(reader) => self.reader = Some(reader),
+                Err(error) => log::error!("Could not open the raw input: {}", error)
+            }
+        }
+
+        let reader = self.reader.as_mut()?;
+        let frame_bytes = self.format.bytes_per_sample() * self.channels as usize;
+        let mut buffer = vec![0; frame_bytes * self.packet_frames.max(1)];
+
+        let read = match Self::read_up_to(reader.as_mut(), &mut buffer) {
+            Ok(read) => read,
+            Err(error) => {
+                log::error!("Could not read the raw input: {}", error);
+                0
+            }
+        };
+
+        // a partial frame at the end of the input is dropped
+        let complete = read / frame_bytes * frame_bytes;
+
+        if complete == 0 {
+            log::info!("Raw input ended");
+            self.reader = None;
+            return None;
+        }
+
+        Some(buffer[..complete]
+            .chunks_exact(self.format.bytes_per_sample())
+            .map(|bytes| self.format.decode(bytes))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::engine::source::{RawSampleFormat, RawSource, SampleSource};
+
+    #[test]
+    fn raw_input_is_decoded_until_the_end() {
+        let bytes: Vec<u8> = [0i16, 16384, -32768, 8192, 1]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let mut source = RawSource::from_reader(Cursor::new(bytes), RawSampleFormat::I16, 48000, 2, 1);
+
+        assert_eq!(source.next_packet(), Some(vec![0.0, 0.5]));
+        assert_eq!(source.next_packet(), Some(vec![-1.0, 0.25]));
+        // the last sample is only half a frame
+        assert_eq!(source.next_packet(), None);
+    }
+
+    #[test]
+    fn raw_float_input_keeps_the_samples() {
+        let bytes: Vec<u8> = [0.25f32, -0.75, 1.0].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let mut source = RawSource::from_reader(Cursor::new(bytes), RawSampleFormat::F32, 44100, 1, 4);
+
+        assert_eq!(source.next_packet(), Some(vec![0.25, -0.75, 1.0]));
+        assert_eq!(source.next_packet(), None);
+        assert_eq!("s16le".parse::<RawSampleFormat>().unwrap(), RawSampleFormat::I16);
+    }
+}
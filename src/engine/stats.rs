@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::engine::SampleType;
+use crate::engine::analysis;
+
+/// Running aggregates over everything analyzed since the stream started.
+pub struct SessionStats {
+    sample_rate: u32,
+    samples: u64,
+    /// Sum of the squared samples, for the average level over the whole session
+    energy: f64,
+    /// Quietest and loudest packet RMS in dBFS
+    min_level_db: f32,
+    max_level_db: f32,
+    clips: usize,
+    /// How often the peak fell into each frequency bin
+    peak_counts: HashMap<u32, usize>,
+    resolution: f32
+}
+
+/// What a finished session looked like, see `SessionStats::summary`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    pub duration: Duration,
+    /// The frequency that was the peak most often
+    pub dominant_freq: Option<f32>,
+    pub min_level_db: Option<f32>,
+    pub max_level_db: Option<f32>,
+    pub average_level_db: Option<f32>,
+    /// Packets that clipped after the gain was applied
+    pub clips: usize
+}
+
+impl SessionStats {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: 0,
+            energy: 0.0,
+            min_level_db: f32::INFINITY,
+            max_level_db: f32::NEG_INFINITY,
+            clips: 0,
+            peak_counts: HashMap::new(),
+            resolution: 1.0
+        }
+    }
+
+    pub fn add_packet(&mut self, mono: &[SampleType], clipped: bool) {
+        if mono.is_empty() {
+            return;
+        }
+
+        let level_db = 20.0 * analysis::rms(mono).max(1e-10).log10();
+        self.min_level_db = self.min_level_db.min(level_db);
+        self.max_level_db = self.max_level_db.max(level_db);
+        self.energy += mono.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>();
+        self.samples += mono.len() as u64;
+
+        if clipped {
+            self.clips += 1;
+        }
+    }
+
+    /// Counts `freq` as the peak of a frame, in bins of `resolution` Hz. A changed resolution
+    /// starts the count over, the old bins would no longer line up.
+    pub fn add_peak(&mut self, freq: f32, resolution: f32) {
+        if resolution <= 0.0 {
+            return;
+        }
+
+        if resolution != self.resolution {
+            self.resolution = resolution;
+            self.peak_counts.clear();
+        }
+
+        *self.peak_counts.entry((freq / resolution).round() as u32).or_default() += 1;
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        let analyzed = self.samples > 0;
+
+        SessionSummary {
+            duration: Duration::from_secs_f64(self.samples as f64 / self.sample_rate.max(1) as f64),
+            dominant_freq: self.peak_counts.iter()
+                .max_by_key(|(bin, count)| (**count, std::cmp::Reverse(**bin)))
+                .map(|(bin, _)| *bin as f32 * self.resolution),
+            min_level_db: analyzed.then_some(self.min_level_db),
+            max_level_db: analyzed.then_some(self.max_level_db),
+            average_level_db: analyzed.then(|| (10.0 * (self.energy / self.samples as f64).max(1e-20).log10()) as f32),
+            clips: self.clips
+        }
+    }
+}
+
+impl Display for SessionSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1} s", self.duration.as_secs_f32())?;
+
+        if let Some(freq) = self.dominant_freq {
+            write!(f, ", dominant {:.1} Hz", freq)?;
+        }
+
+        if let (Some(min), Some(max), Some(average)) = (self.min_level_db, self.max_level_db, self.average_level_db) {
+            write!(f, ", level {:.1} to {:.1} dBFS, average {:.1} dBFS", min, max, average)?;
+        }
+
+        write!(f, ", {} clipped packets", self.clips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::engine::stats::SessionStats;
+
+    #[test]
+    fn summary_aggregates_the_whole_session() {
+        let mut stats = SessionStats::new(1000);
+        assert_eq!(stats.summary().average_level_db, None);
+
+        stats.add_packet(&[0.5; 500], false);
+        stats.add_packet(&[0.05; 500], true);
+        for _ in 0..3 {
+            stats.add_peak(1001.0, 10.0);
+        }
+        stats.add_peak(500.0, 10.0);
+
+        let summary = stats.summary();
+        assert_eq!(summary.duration, Duration::from_secs(1));
+        assert_eq!(summary.dominant_freq, Some(1000.0));
+        assert_eq!(summary.clips, 1);
+        assert!((summary.max_level_db.unwrap() + 6.02).abs() < 0.01);
+        assert!((summary.min_level_db.unwrap() + 26.02).abs() < 0.01);
+        // the mean square is (0.25 + 0.0025) / 2
+        assert!((summary.average_level_db.unwrap() + 8.98).abs() < 0.01);
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use cpal::{Host, HostId};
+use cpal::traits::{DeviceTrait, HostTrait};
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::engine::AudiaError;
+
+/// How often the device lists are compared
+pub const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+static NEXT_WATCHER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Reports devices being plugged in or removed on an audio host. cpal doesn't pass on the change
+/// notifications of WASAPI or CoreAudio, so a background thread compares the device names
+/// periodically instead. The thread stops when the watcher is dropped.
+pub struct DeviceWatcher {
+    id: u64,
+    changes: Receiver<()>,
+    stop: Arc<AtomicBool>
+}
+
+impl DeviceWatcher {
+    pub fn spawn(host_id: HostId) -> Result<Self, AudiaError> {
+        // a pending change already makes the UI re-enumerate, so one slot is enough
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        thread::Builder::new()
+            .name(String::from("device-watcher"))
+            .spawn(move || watch(host_id, tx, thread_stop))
+            .map_err(|error| AudiaError::from(format!("Could not start the device watcher: {error}")))?;
+
+        Ok(Self { id: NEXT_WATCHER_ID.fetch_add(1, Ordering::Relaxed), changes: rx, stop })
+    }
+
+    /// Tells the watchers apart, also of the same host
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Receives a message for every change. The sender is dropped once the watcher stops, which
+    /// ends a blocking `recv`.
+    pub fn changes(&self) -> Receiver<()> {
+        self.changes.clone()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn watch(host_id: HostId, changes: Sender<()>, stop: Arc<AtomicBool>) {
+    let host = match cpal::host_from_id(host_id) {
+        Ok(host) => host,
+        Err(error) => {
+            log::warn!("Not watching the devices of {}: {}", host_id.name(), error);
+            return;
+        }
+    };
+
+    let mut known = device_names(&host);
+
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(DEVICE_POLL_INTERVAL);
+
+        let current = device_names(&host);
+
+        if current != known {
+            log::info!("Audio devices of {} changed", host_id.name());
+            known = current;
+            let _ = changes.try_send(());
+        }
+    }
+}
+
+fn device_names(host: &Host) -> Vec<String> {
+    host.devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
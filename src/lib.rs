@@ -0,0 +1,3 @@
+//! Audio capture and analysis engine of Audia, shared by the application and the integration tests.
+
+pub mod engine;
@@ -1,44 +1,229 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use fast_log::Config;
 use fast_log::consts::LogSize;
 use fast_log::filter::ModuleFilter;
-use iced::{Application, Error, Settings};
+use fast_log::plugin::file_split::RollingType;
+use fast_log::plugin::packer::LogPacker;
+use iced::{Application, Error, Settings, window};
 use log::LevelFilter;
-use crate::engine::{AudioSettings, AudioSystem};
+use crate::engine::{AudioSettings, AudioSystem, EngineKind};
+use crate::engine::source::{RawInput, RawSampleFormat, TestSignalKind};
+use crate::ui::control::DEFAULT_CONTROL_PORT;
+use crate::ui::settings::{SETTINGS_FILE, UserSettings};
 use crate::ui::UIParams;
 
+use audia::engine;
+
 mod data;
-mod engine;
 mod ui;
 
 pub const APP_NAME: &str = "audia";
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Sample rate of the raw input unless given with `--raw-rate`
+const DEFAULT_RAW_SAMPLE_RATE: u32 = 48000;
+
+/// Smallest window that still fits the controls
+const MIN_WINDOW_SIZE: (u32, u32) = (640, 480);
+
+/// Provide configuration for the application window.
+struct AppConfig {
+    window_size: (u32, u32)
+}
+
+impl AppConfig {
+    /// The window opens at the size it was left at, unless the command line asks for another one.
+    fn new(settings: &UserSettings, cli_args: &CliArgs) -> Self {
+        let (width, height) = cli_args.window_size.unwrap_or(settings.window_size);
+
+        Self {
+            window_size: (width.max(MIN_WINDOW_SIZE.0), height.max(MIN_WINDOW_SIZE.1))
+        }
+    }
+}
+
+/// Options accepted on the command line.
+#[derive(Default)]
+struct CliArgs {
+    test_signal: Option<TestSignalKind>,
+    engine: Option<EngineKind>,
+    host: Option<String>,
+    input_device: Option<String>,
+    control_port: Option<u16>,
+    tray: bool,
+    raw_input: Option<RawInput>,
+    autostart: bool,
+    /// Logs the capabilities of all input devices before the window opens
+    probe: bool,
+    /// Largest boost or cut the input gain slider offers, in dB
+    max_gain_db: Option<f32>,
+    window_size: Option<(u32, u32)>
+}
+
+impl CliArgs {
+    fn parse(args: impl Iterator<Item = String>) -> Self {
+        let mut cli_args = Self::default();
+        // the first argument is the executable itself
+        let mut args = args.skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--test-signal" => {
+                    let name = args.next().unwrap_or_default();
+                    let kind = TestSignalKind::ALL.into_iter()
+                        .find(|kind| kind.to_string().eq_ignore_ascii_case(name.as_str()))
+                        .unwrap_or_else(|| {
+                            log::warn!("Unknown test signal '{}', using a sine instead", name);
+                            TestSignalKind::Sine
+                        });
+
+                    cli_args.test_signal = Some(kind);
+                },
+                "--engine" => {
+                    match args.next().unwrap_or_default().parse::<EngineKind>() {
+                        Ok(engine) => cli_args.engine = Some(engine),
+                        Err(error) => log::warn!("{}, using the default engine", error)
+                    }
+                },
+                "--host" => cli_args.host = args.next(),
+                "--input-device" => cli_args.input_device = args.next(),
+                "--autostart" => cli_args.autostart = true,
+                "--tray" => cli_args.tray = true,
+                "--probe" => cli_args.probe = true,
+                "--control-port" => {
+                    let port = args.next().unwrap_or_default();
+                    cli_args.control_port = Some(port.parse().unwrap_or_else(|_| {
+                        log::warn!("Invalid control port '{}', using {}", port, DEFAULT_CONTROL_PORT);
+                        DEFAULT_CONTROL_PORT
+                    }));
+                },
+                "--max-gain-db" => {
+                    let gain = args.next().unwrap_or_default();
+                    match gain.parse::<f32>() {
+                        Ok(gain) if gain.is_finite() && gain > 0.0 => cli_args.max_gain_db = Some(gain),
+                        _ => log::warn!("Invalid maximum gain '{}', using the default", gain)
+                    }
+                },
+                "--window-size" => {
+                    // e.g. 1280x800
+                    let size = args.next().unwrap_or_default();
+                    match size.split_once('x').map(|(width, height)| (width.parse(), height.parse())) {
+                        Some((Ok(width), Ok(height))) => cli_args.window_size = Some((width, height)),
+                        _ => log::warn!("Invalid window size '{}', expected WIDTHxHEIGHT", size)
+                    }
+                },
+                "--raw-input" => {
+                    // "-" reads from stdin
+                    let path = args.next().filter(|path| path != "-").map(PathBuf::from);
+                    cli_args.raw_input_mut().path = path;
+                },
+                "--raw-format" => {
+                    match args.next().unwrap_or_default().parse::<RawSampleFormat>() {
+                        Ok(format) => cli_args.raw_input_mut().format = format,
+                        Err(error) => log::warn!("{}, using f32", error)
+                    }
+                },
+                "--raw-rate" => {
+                    let rate = args.next().unwrap_or_default();
+                    match rate.parse() {
+                        Ok(rate) if rate > 0 => cli_args.raw_input_mut().sample_rate = rate,
+                        _ => log::warn!("Invalid raw sample rate '{}'", rate)
+                    }
+                },
+                "--raw-channels" => {
+                    let channels = args.next().unwrap_or_default();
+                    match channels.parse() {
+                        Ok(channels) if channels > 0 => cli_args.raw_input_mut().channels = channels,
+                        _ => log::warn!("Invalid raw channel count '{}'", channels)
+                    }
+                },
+                _ => log::warn!("Ignoring unknown argument {}", arg)
+            }
+        }
+
+        cli_args
+    }
 
-struct AppConfig {}
+    /// The raw input options, created with the defaults by the first `--raw-*` argument.
+    fn raw_input_mut(&mut self) -> &mut RawInput {
+        self.raw_input.get_or_insert_with(|| RawInput {
+            path: None,
+            format: RawSampleFormat::default(),
+            sample_rate: DEFAULT_RAW_SAMPLE_RATE,
+            channels: 1
+        })
+    }
+}
 
 /// Provide configuration for the global logger, such as log levels, log file name, etc.
-struct LogConfig<'a> {
-    log_file_name: &'a str,
+struct LogConfig {
+    log_file_name: String,
     max_log_size_mb: usize,
-    log_level: &'a str,
+    /// Number of rotated log files to keep next to the active one
+    max_log_files: usize,
+    /// Upper bound for the combined size of the rotated log files
+    max_total_log_size_mb: usize,
+    log_level: String,
+    /// Logs the version and the audio setup at startup
+    banner: bool
 }
 
-impl<'a> Default for LogConfig<'a> {
+impl Default for LogConfig {
     fn default() -> Self {
         Self {
             // It's important to define the log file name as a path
-            log_file_name: "./audia.log",
+            log_file_name: String::from("./audia.log"),
             max_log_size_mb: 1,
-            log_level: "info"
+            max_log_files: 5,
+            max_total_log_size_mb: 10,
+            log_level: String::from("info"),
+            banner: true
+        }
+    }
+}
+
+impl LogConfig {
+    /// Overrides the defaults with the `AUDIA_LOG_*` environment variables, if present.
+    fn from_env() -> Self {
+        let defaults = Self::default();
+
+        Self {
+            log_file_name: env_or("AUDIA_LOG_FILE", defaults.log_file_name),
+            max_log_size_mb: env_or("AUDIA_LOG_MAX_SIZE_MB", defaults.max_log_size_mb),
+            max_log_files: env_or("AUDIA_LOG_MAX_FILES", defaults.max_log_files),
+            max_total_log_size_mb: env_or("AUDIA_LOG_MAX_TOTAL_SIZE_MB", defaults.max_total_log_size_mb),
+            log_level: env_or("AUDIA_LOG_LEVEL", defaults.log_level),
+            banner: env_or("AUDIA_LOG_BANNER", defaults.banner)
         }
     }
+
+    /// The number of rotated files to retain so that neither the file count nor the total size
+    /// limit is exceeded. At least one rotated file is always kept.
+    fn retained_log_files(&self) -> usize {
+        let files_within_size_cap = self.max_total_log_size_mb / self.max_log_size_mb.max(1);
+
+        self.max_log_files.min(files_within_size_cap).max(1)
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
 }
 
 fn init_logger(config: &LogConfig) {
     let log_config = Config::new()
-        .file_loop(config.log_file_name, LogSize::MB(config.max_log_size_mb))
+        .file_split(
+            config.log_file_name.as_str(),
+            LogSize::MB(config.max_log_size_mb),
+            RollingType::KeepNum(config.retained_log_files() as i64),
+            LogPacker {})
         .console()
-        .level(LevelFilter::from_str(config.log_level).unwrap_or(LevelFilter::Info))
+        .level(LevelFilter::from_str(config.log_level.as_str()).unwrap_or(LevelFilter::Info))
         .filter(ModuleFilter::new_include(vec![ String::from(APP_NAME) ]))
         // Providing a channel length will make the log queue bounded
         .chan_len(Some(65536));
@@ -46,16 +231,86 @@ fn init_logger(config: &LogConfig) {
     fast_log::init(log_config).expect("Could not initialize logger");
 }
 
+/// Logs the version, the build and the audio setup the application starts with, for bug reports.
+fn log_startup_banner(audio_system: &AudioSystem, engine_kind: EngineKind) {
+    if !log::log_enabled!(log::Level::Info) {
+        return;
+    }
+
+    let engine = &audio_system.engine;
+    let build = if cfg!(debug_assertions) { "debug" } else { "release" };
+
+    log::info!("{} {} ({} build, {}/{})", APP_NAME, APP_VERSION, build, std::env::consts::OS, std::env::consts::ARCH);
+    log::info!("  engine: {:?}, host: {}", engine_kind, engine.get_current_host().unwrap_or(String::from("none")));
+    log::info!("  input device: {}, sample rate: {}",
+        engine.get_current_input_device().unwrap_or(String::from("none")),
+        engine.get_default_sample_rate().map(|rate| format!("{rate} Hz")).unwrap_or(String::from("unknown")));
+    log::info!("  compiled hosts: {}", engine::compiled_hosts().join(", "));
+}
+
+/// Logs what every input device of every host supports. Probing can take a moment with many
+/// devices, devices that fail are listed with their error.
+fn log_probe_report() {
+    let report = engine::probe::probe_input_devices();
+
+    for line in report.to_string().lines() {
+        log::info!("{}", line);
+    }
+}
+
 fn main() -> Result<(), Error> {
+    let log_config = LogConfig::from_env();
     // initialise logger
-    init_logger(&LogConfig::default());
+    init_logger(&log_config);
 
     log::info!("Initializing application");
 
-    let audio_system = AudioSystem::new(AudioSettings::default());
+    let cli_args = CliArgs::parse(std::env::args());
+    let settings = UserSettings::load_or_default(SETTINGS_FILE);
+    let app_config = AppConfig::new(&settings, &cli_args);
+
+    let audio_settings = AudioSettings {
+        engine: cli_args.engine.unwrap_or_default(),
+        // the command line takes precedence over the persisted settings
+        host: cli_args.host.or_else(|| settings.host.clone().filter(|_| settings.host_preference.is_empty())),
+        host_preference: settings.host_preference.clone(),
+        input_device: cli_args.input_device.or_else(|| settings.input_device.clone()),
+        share_mode: settings.share_mode,
+        ..AudioSettings::default()
+    };
 
-    let ui_params = UIParams::new(audio_system);
+    if cli_args.probe || settings.probe_devices {
+        log_probe_report();
+    }
+
+    let engine_kind = audio_settings.engine;
+    let audio_system = AudioSystem::new(audio_settings);
+
+    if log_config.banner {
+        log_startup_banner(&audio_system, engine_kind);
+    }
+
+    let antialiasing = settings.antialiasing;
+    let mut ui_params = UIParams::new(audio_system);
+    ui_params.test_signal = cli_args.test_signal;
+    ui_params.settings = settings;
+    ui_params.control_port = cli_args.control_port;
+    ui_params.tray = cli_args.tray;
+    ui_params.raw_input = cli_args.raw_input;
+    ui_params.autostart = cli_args.autostart;
+    if let Some(max_gain_db) = cli_args.max_gain_db {
+        ui_params.gain_range_db = -max_gain_db..=max_gain_db;
+    }
 
     // Note: the UI must run on the main thread
-    ui::Audia::run(Settings::with_flags(ui_params))
+    ui::Audia::run(Settings {
+        window: window::Settings {
+            size: app_config.window_size,
+            ..window::Settings::default()
+        },
+        // with a tray icon, closing the window minimizes it to the tray
+        exit_on_close_request: !cli_args.tray,
+        antialiasing,
+        ..Settings::with_flags(ui_params)
+    })
 }
@@ -0,0 +1,168 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Sender};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::engine::AudiaError;
+
+/// Port of the control server when it's enabled without an explicit port
+pub const DEFAULT_CONTROL_PORT: u16 = 7878;
+
+/// How long a connection waits for the UI to answer a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Commands accepted by the control server, one JSON object per line, e.g.
+/// `{"id": 1, "method": "select_device", "params": {"name": "USB Audio"}}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Start,
+    Stop,
+    SelectDevice { name: String },
+    Status,
+    Peak,
+    Spectrum
+}
+
+/// A command waiting for the UI, which answers with the result or an error message.
+#[derive(Debug, Clone)]
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<Result<Value, String>>
+}
+
+impl ControlRequest {
+    pub fn reply(self, result: Result<Value, String>) {
+        // the client may have given up already
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Listens on `address` on a background thread and forwards the commands of all connections to
+/// the returned channel. Returns the bound address, which tells the port when binding to port 0.
+pub fn spawn_server(address: &str) -> Result<(SocketAddr, Receiver<ControlRequest>), AudiaError> {
+    let listener = TcpListener::bind(address)
+        .map_err(|error| AudiaError::from(format!("Could not start the control server on {address}: {error}")))?;
+    let local_address = listener.local_addr()
+        .map_err(|error| AudiaError::from(format!("Control server has no address: {error}")))?;
+
+    let (tx, rx) = crossbeam_channel::unbounded::<ControlRequest>();
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            match connection {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    thread::spawn(move || serve(stream, tx));
+                },
+                Err(error) => log::warn!("Control connection failed: {}", error)
+            }
+        }
+    });
+
+    log::info!("Control server listening on {}", local_address);
+
+    Ok((local_address, rx))
+}
+
+fn serve(stream: TcpStream, requests: Sender<ControlRequest>) {
+    let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(error) => {
+            log::warn!("Could not serve control client {}: {}", peer, error);
+            return;
+        }
+    };
+
+    log::info!("Control client {} connected", peer);
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = handle_line(&line, &requests);
+
+        if writeln!(writer, "{response}").is_err() {
+            break;
+        }
+    }
+
+    log::info!("Control client {} disconnected", peer);
+}
+
+fn handle_line(line: &str, requests: &Sender<ControlRequest>) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => return json!({ "id": null, "error": format!("Invalid request: {error}") })
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+
+    let command = match ControlCommand::deserialize(request) {
+        Ok(command) => command,
+        Err(error) => return json!({ "id": id, "error": format!("Invalid request: {error}") })
+    };
+
+    let (reply, answer) = crossbeam_channel::bounded(1);
+
+    if requests.send(ControlRequest { command, reply }).is_err() {
+        return json!({ "id": id, "error": "The application is shutting down" });
+    }
+
+    match answer.recv_timeout(REPLY_TIMEOUT) {
+        Ok(Ok(result)) => json!({ "id": id, "result": result }),
+        Ok(Err(error)) => json!({ "id": id, "error": error }),
+        Err(_) => json!({ "id": id, "error": "No answer from the application" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use crate::ui::control::{ControlCommand, spawn_server};
+
+    #[test]
+    fn commands_are_forwarded_and_answered() {
+        let (address, requests) = spawn_server("127.0.0.1:0").unwrap();
+
+        let ui = thread::spawn(move || {
+            let request = requests.recv().unwrap();
+            assert_eq!(request.command, ControlCommand::SelectDevice { name: String::from("USB Audio") });
+            request.reply(Ok(json!("USB Audio")));
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        writeln!(stream, r#"{{"id": 7, "method": "select_device", "params": {{"name": "USB Audio"}}}}"#).unwrap();
+
+        let mut response = String::new();
+        BufReader::new(&stream).read_line(&mut response).unwrap();
+        ui.join().unwrap();
+
+        assert_eq!(serde_json::from_str::<Value>(&response).unwrap(), json!({ "id": 7, "result": "USB Audio" }));
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let (address, _requests) = spawn_server("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        writeln!(stream, r#"{{"method": "explode"}}"#).unwrap();
+
+        let mut response = String::new();
+        BufReader::new(&stream).read_line(&mut response).unwrap();
+
+        assert!(serde_json::from_str::<Value>(&response).unwrap()["error"].is_string());
+    }
+}
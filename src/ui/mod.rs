@@ -1,27 +1,167 @@
-use std::time::Duration;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use iced::{Alignment, Application, Command, Element, executor, Subscription, Theme};
+use iced::{Alignment, Application, Color, Command, Element, Event, executor, Length, Subscription, subscription, Theme, window};
+use iced::futures::{future, SinkExt};
+use iced::futures::executor::block_on;
 use iced::time as iced_time;
-use iced::widget::{button, Column, pick_list, Row, text};
-use spectrum_analyzer::{FrequencyLimit, samples_fft_to_spectrum};
-use spectrum_analyzer::scaling::{divide_by_N, divide_by_N_sqrt};
-use spectrum_analyzer::windows::hann_window;
+use iced::widget::{Column, progress_bar, Row, slider, text_input};
+use crossbeam_channel::Receiver;
+use serde_json::{json, Value};
+use spectrum_analyzer::FrequencyLimit;
 
-use crate::engine::{AudioHostName, AudioStream, AudioSystem, InputDeviceName, PacketType};
-use crate::ui::spectrogram::Spectrogram;
+use crate::engine::analysis::{self, BandWidth, ChannelChain, ChannelView, Decimator, EnvelopeFollower, FeedbackDetector, HighPass, PendingClip, Warmup, Welch};
+use crate::engine::fft::{FftBackend, SpectrumBackend};
+use crate::engine::monitor::{self as monitoring, DEFAULT_LIMITER_THRESHOLD_DB, LimiterControl, MAX_MONITOR_ATTEMPTS, MAX_MONITOR_DELAY_MS, Monitor, MonitorRetry, RetrySchedule};
+use crate::engine::{AudiaError, AudioHostName, AudioStream, AudioSystem, BufferRequest, CaptureFormat, ChannelRequest, InputDeviceName, InputKind, OutputDeviceName, PacketType, ShareMode};
+use crate::engine::recorder::{ActivationEvent, ActivationSettings, AnalysisLog, ChannelChangePolicy, SignalGate, WavRecorder, WavSampleFormat};
+use crate::engine::source::{self, RawInput, SyntheticSource, TestSignalKind};
+use crate::engine::stats::{SessionStats, SessionSummary};
+use crate::engine::watcher::DeviceWatcher;
+use crate::ui::control::{ControlCommand, ControlRequest};
+use crate::ui::response::{ResponseView, SweepMeasurement};
+use crate::ui::settings::{DEFAULT_DISPLAY_FPS, DEFAULT_METER_ATTACK_MS, DEFAULT_METER_RELEASE_MS, Preset, PRESET_DIR, UserSettings};
+use crate::ui::spectrogram::{ChartStyle, Spectrogram};
+use crate::ui::style::{DisplayStyle, TracePalette};
+use crate::ui::tray::{Tray, TrayAction, TrayStatus};
+use crate::ui::units::{self, DB_FLOOR, DbRange, IN_TUNE_CENTS, MAX_DB_CEILING, MAX_TILT_DB_PER_OCTAVE, MIN_DB_SPAN, Tilt, to_dbfs, UnitMode, XAxisUnit};
+use crate::ui::waterfall::{SESSION_DIR, Waterfall, WaterfallDirection, WaterfallSession};
+use crate::ui::waveform::{Trigger, Waveform, WINDOW_SIZES};
 
+pub mod control;
+mod response;
+pub mod settings;
 mod spectrogram;
+mod style;
+mod tray;
+mod units;
+mod waterfall;
+mod waveform;
 
-// this needs to be a power of two
+/// Samples per packet of the test signal and the raw input
 const RECEIVE_PACKET_SIZE: usize = 256;
 
+const MIN_FFT_WINDOW_MS: f32 = 1.0;
+const MAX_FFT_WINDOW_MS: f32 = 500.0;
+
+/// Used until a stream reports its actual sample rate
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const MAX_FREQUENCY: f32 = 2200.0;
+
+const DEFAULT_FEEDBACK_SENSITIVITY_DB: f32 = 20.0;
+const DEFAULT_FEEDBACK_PERSISTENCE: usize = 20;
+
+const DEFAULT_WELCH_SEGMENTS: usize = 8;
+const DEFAULT_WELCH_OVERLAP: f32 = 0.5;
+const MAX_WELCH_SEGMENTS: usize = 32;
+
+const MAX_WARMUP_FRAMES: usize = 32;
+
+const MAX_QUEUED_FRAMES: usize = 16;
+
+const MAX_DISPLAY_POINTS: usize = 4096;
+
+/// Redraw rate while power saving and the window is unfocused
+const POWER_SAVING_FPS: u32 = 5;
+
+const MIN_FOLLOW_SPAN_HZ: f32 = 50.0;
+const MAX_FOLLOW_SPAN_HZ: f32 = 2000.0;
+
+const MAX_PEAK_COUNT: usize = 20;
+
+const MAX_IGNORED_LOW_BINS: usize = 16;
+
+const MAX_CREST_SMOOTHING: f32 = 0.99;
+
+const MIN_PEAK_CAP_DECAY_DB: f32 = 0.1;
+const MAX_PEAK_CAP_DECAY_DB: f32 = 6.0;
+
+const MAX_LINE_WIDTH: u32 = 8;
+
+const MIN_PAD_FILL: f32 = 0.1;
+const MAX_PAD_FILL: f32 = 0.9;
+
+const MAX_PERSISTENCE_FRAMES: usize = 32;
+const MIN_PERSISTENCE_DECAY: f32 = 0.3;
+const MAX_PERSISTENCE_DECAY: f32 = 0.95;
+
+/// Peaks of the peak table are at least this many bins apart
+const PEAK_SEPARATION_BINS: f32 = 3.0;
+const PEAK_TABLE_WIDTH: f32 = 280.0;
+
+const DEFAULT_MAX_RECORDING: Duration = Duration::from_secs(10 * 60);
+const MAX_RECORDING_MINUTES: f32 = 240.0;
+
+const MAX_LOG_SNAPSHOT_INTERVAL_S: f32 = 60.0;
+
+const MIN_AUTO_SAVE_INTERVAL_S: f32 = 5.0;
+const MAX_AUTO_SAVE_INTERVAL_S: f32 = 3600.0;
+
+const MAX_OVERLOAD_HOLD_S: f32 = 10.0;
+
+const MIN_LOW_CUT_HZ: f32 = 1.0;
+const MAX_LOW_CUT_HZ: f32 = 200.0;
+
+const MAX_SILENCE_GATE_DB: f32 = -20.0;
+
+/// Start of the error shown for a target frequency that can't be parsed
+const INVALID_TARGET_FREQ: &str = "Invalid target frequency";
+
+/// Pseudo input device that feeds the analysis from a signal generator
+const TEST_SIGNAL_DEVICE: &str = "Test signal";
+
+/// Pseudo input device for the raw PCM input given on the command line
+const RAW_INPUT_DEVICE: &str = "Raw input";
+
 pub struct UIParams {
-    pub audio_system: AudioSystem
+    pub audio_system: AudioSystem,
+    pub test_signal: Option<TestSignalKind>,
+    /// Raw PCM input from stdin or a named pipe, used instead of a device when present
+    pub raw_input: Option<RawInput>,
+    /// Range of the input gain slider in dB
+    pub gain_range_db: RangeInclusive<f32>,
+    /// Settings to start with; the devices are expected to be applied to the audio system already
+    pub settings: UserSettings,
+    pub settings_path: PathBuf,
+    /// Starts the local control server on this port when present
+    pub control_port: Option<u16>,
+    /// Shows an icon in the system tray, which keeps the app reachable while the window is hidden
+    pub tray: bool,
+    /// Starts streaming on launch even if the settings don't ask for it
+    pub autostart: bool
 }
 
 impl UIParams {
     pub fn new(audio_system: AudioSystem) -> Self {
-        Self { audio_system }
+        Self {
+            audio_system,
+            test_signal: None,
+            raw_input: None,
+            gain_range_db: -24.0..=24.0,
+            settings: UserSettings::default(),
+            settings_path: PathBuf::from(settings::SETTINGS_FILE),
+            control_port: None,
+            tray: false,
+            autostart: false
+        }
+    }
+}
+
+/// An entry of the input device list, labelled with the kind of input when it is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InputChoice(InputDeviceName);
+
+impl Display for InputChoice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match InputKind::of(&self.0) {
+            InputKind::Unknown => f.write_str(&self.0),
+            kind => write!(f, "{} - {}", self.0, kind)
+        }
     }
 }
 
@@ -30,16 +170,332 @@ pub enum UIMessage {
     HostChanged(String),
     InputDeviceChanged(String),
     OutputDeviceChanged(String),
+    ShareModeChanged(ShareMode),
+    ChannelRequestChanged(ChannelRequest),
+    BufferRequestChanged(BufferRequest),
+    SampleFormatChanged(CaptureFormat),
+    ChannelWeightsChanged(String),
+    ChannelViewChanged(ChannelView),
     StartStreaming,
+    AutostartToggled(bool),
+    ProbeDevicesToggled(bool),
     StopStreaming,
+    /// Captures and analyzes a single window, then stops
+    SingleShot,
     StreamTick,
+    /// Time to redraw the charts with the latest analysis results
+    RedrawTick,
+    DisplayFpsChanged(u8),
+    PowerSavingToggled(bool),
+    WindowFocusChanged(bool),
+    WindowResized(u32, u32),
+    /// The window was asked to close or was minimized, it goes to the tray when there is one
+    WindowCloseRequested,
+    WindowMinimized,
+    LogSnapshotsToggled(bool),
+    LogSnapshotIntervalChanged(f32),
+    SaveChartPng,
+    AutoSaveToggled(bool),
+    AutoSaveIntervalChanged(f32),
+    AutoSaveDirChanged(String),
+    AutoSaveTick,
+    /// A chart image was written, with its path or the error
+    ChartSaved(Result<String, String>),
+    LogSnapshot,
+    OverloadHoldChanged(f32),
+    WarmupFramesChanged(u8),
+    MaxQueuedFramesChanged(u8),
+    DecimationToggled(bool),
+    FftWindowChanged(f32),
+    ZeroPaddingToggled(bool),
+    PadMinFillChanged(f32),
+    LowCutToggled(bool),
+    LowCutChanged(f32),
+    SilenceGateToggled(bool),
+    SilenceGateChanged(f32),
+    AnalysisToggled(bool),
+    FreezeOnClipToggled(bool),
+    TestSignalChanged(TestSignalKind),
+    UnitModeChanged(UnitMode),
+    DbFloorChanged(f32),
+    DbCeilingChanged(f32),
+    XAxisUnitChanged(XAxisUnit),
+    AveragingToggled(bool),
+    ResetAverage,
+    LeqToggled(bool),
+    ResetLeq,
+    ChartHover(Option<f32>),
+    MarkersToggled(bool),
+    SetMarker(usize, f32),
+    ClearMarkers,
+    RecordToFileToggled(bool),
+    RecordAnalysisToggled(bool),
+    RecordFormatChanged(WavSampleFormat),
+    ChannelChangePolicyChanged(ChannelChangePolicy),
+    SoundActivationToggled(bool),
+    MaxRecordingToggled(bool),
+    /// Maximum recording duration in minutes
+    MaxRecordingChanged(f32),
+    StopStreamingAtLimitToggled(bool),
+    /// A recording was finalized because it reached the maximum duration
+    RecordingLimitReached,
+    ActivationThresholdChanged(f32),
+    SilenceTimeoutChanged(f32),
+    GainChanged(f32),
+    MonitoringToggled(bool),
+    LimiterToggled(bool),
+    LimiterThresholdChanged(f32),
+    MonitorDelayChanged(f32),
+    SnapshotLabelChanged(String),
+    TakeSnapshot,
+    ToggleSnapshot(usize),
+    RemoveSnapshot(usize),
+    ClearSnapshots,
+    ChartStyleChanged(ChartStyle),
+    PeakCapDecayChanged(f32),
+    TiltChanged(Tilt),
+    CustomTiltChanged(f32),
+    LineWidthToggled(bool),
+    LineWidthChanged(u32),
+    AntialiasingToggled(bool),
+    PersistenceFramesChanged(usize),
+    PersistenceDecayChanged(f32),
+    DisplayStyleChanged(DisplayStyle),
+    TracePaletteChanged(TracePalette),
+    ToggleSplitView,
+    ToggleFollowPeak,
+    FollowPeakSpanChanged(f32),
+    FullSpectrumToggled(bool),
+    MaxDisplayPointsChanged(usize),
+    PeakTableToggled(bool),
+    PeakCountChanged(u8),
+    PeakThresholdChanged(f32),
+    IgnoreDcToggled(bool),
+    IgnoredLowBinsChanged(u8),
+    HoldPeakFreqMaxToggled(bool),
+    ResetPeakFreqMax,
+    DismissSessionSummary,
+    TargetFreqChanged(String),
+    WaterfallToggled(bool),
+    WaterfallSmoothingChanged(f32),
+    WaterfallFramesPerRowChanged(u8),
+    WaterfallDirectionChanged(WaterfallDirection),
+    ExportWaterfallPng,
+    SaveWaterfallSession,
+    OpenWaterfallSession(String),
+    ScopeToggled(bool),
+    ScopeWindowChanged(usize),
+    TriggerChanged(Trigger),
+    TriggerLevelChanged(f32),
+    MeasureResponse,
+    ResponseToggled(bool),
+    StoreReferenceCurve,
+    ClearReferenceCurve,
+    FeedbackDetectionToggled(bool),
+    FeedbackSensitivityChanged(f32),
+    FeedbackPersistenceChanged(u8),
+    FftBackendChanged(FftBackend),
+    WelchToggled(bool),
+    WelchSegmentsChanged(u8),
+    WelchOverlapChanged(f32),
+    MeterAttackChanged(f32),
+    MeterReleaseChanged(f32),
+    CrestFactorToggled(bool),
+    CrestSmoothingChanged(f32),
+    SaveSettings,
+    ReloadSettings,
+    PresetNameChanged(String),
+    SavePreset,
+    LoadPreset(String),
+    /// Periodic check whether the settings file was changed externally
+    SettingsFileTick,
+    /// Re-enumerates the devices, sent by the watcher or the refresh button
+    DevicesChanged,
+    /// A request of the control server waits for its answer
+    ControlRequested(ControlRequest),
+    /// An entry of the tray menu was chosen
+    TrayMenu(TrayAction),
+    BandAnalyzerToggled(bool),
+    BandWidthChanged(BandWidth),
     DebugEvent
 }
 
 pub struct Audia {
     spectrogram: Spectrogram,
     audio_system: AudioSystem,
-    current_stream: Option<AudioStream>
+    /// Devices of the current host, enumerated again when they change
+    input_devices: Vec<InputDeviceName>,
+    output_devices: Vec<OutputDeviceName>,
+    device_watcher: Option<DeviceWatcher>,
+    current_stream: Option<AudioStream>,
+    /// The stream stops after the next analyzed window, which stays on screen
+    single_shot: bool,
+    decimator: Option<Decimator>,
+    /// Analyzed window length as set by the user
+    fft_window_ms: f32,
+    /// FFT size derived from the window length at the analysis sample rate
+    fft_size: usize,
+    /// Removes DC offset and rumble from the analyzed signal when present
+    low_cut: Option<HighPass>,
+    low_cut_hz: f32,
+    /// Blanks the spectrum while the input is quieter than `silence_gate_db` dBFS
+    silence_gate: bool,
+    silence_gate_db: f32,
+    error_message: Option<String>,
+    analyze: bool,
+    /// Pauses the analysis when the input overloads or the gain clips
+    freeze_on_clip: bool,
+    /// The analysis was paused by a clip and shows the spectrum it happened in
+    frozen_on_clip: bool,
+    /// A clip happened and the analysis freezes once its window was analyzed
+    pending_clip: PendingClip,
+    test_signal: Option<TestSignalKind>,
+    raw_input: Option<RawInput>,
+    /// The raw input is selected instead of a device or the test signal
+    use_raw_input: bool,
+    record_to_file: bool,
+    /// Log the analysis results to a CSV file next to each WAV recording
+    record_analysis: bool,
+    analysis_log: Option<AnalysisLog>,
+    record_format: WavSampleFormat,
+    recorder: Option<WavRecorder>,
+    /// What a channel count change does to a running recording
+    channel_change_policy: ChannelChangePolicy,
+    /// Recording taken over from the previous input while switching devices, with its log
+    carried_recording: Option<(WavRecorder, Option<AnalysisLog>)>,
+    /// Tells why the recording continued in a new file
+    recording_notice: Option<String>,
+    /// Starts and stops file recordings based on the signal level when present
+    recording_gate: Option<SignalGate>,
+    /// Recordings are finalized after this duration when present
+    max_recording: Option<Duration>,
+    /// Also stop streaming once a recording reached the maximum duration
+    stop_streaming_at_limit: bool,
+    /// Set by the recording path, answered with `RecordingLimitReached` after the update
+    recording_limit_reached: bool,
+    gain_db: f32,
+    gain_range_db: RangeInclusive<f32>,
+    /// Weights for mixing the channels into the analyzed signal, empty for a flat downmix
+    channel_weights: Vec<f32>,
+    channel_weights_input: String,
+    channel_view: ChannelView,
+    /// Filters the right channel for the second trace of the dual view
+    second_channel: Option<ChannelChain>,
+    /// Plays the input back on the output device while streaming
+    monitoring: bool,
+    monitor: Option<Monitor>,
+    /// Downmixed samples of a stream tick and of the second channel, kept for their allocation
+    mixed: PacketType,
+    second_mixed: PacketType,
+    /// The monitor output failed to open and is tried again on a later tick
+    monitor_retry: Option<RetrySchedule>,
+    monitor_delay_ms: f32,
+    /// Keeps the monitoring output from clipping, shared with the output stream
+    limiter: Arc<LimiterControl>,
+    /// Sample rate of the running (or last) stream
+    sample_rate: u32,
+    /// Shows a full-range overview below the zoomed chart
+    split_view: bool,
+    /// Analyzes and shows the whole spectrum up to Nyquist instead of the range up to `MAX_FREQUENCY`
+    full_spectrum: bool,
+    waterfall: Waterfall,
+    show_waterfall: bool,
+    /// Saved waterfall sessions, the newest first, and the one that was opened last
+    sessions: Vec<String>,
+    current_session: Option<String>,
+    waveform: Waveform,
+    /// Shows the oscilloscope instead of the spectrum
+    show_scope: bool,
+    /// Plays the sweep on the output while a frequency response is measured
+    sweep: Option<SweepMeasurement>,
+    response: ResponseView,
+    /// Shows the measured frequency response instead of the spectrum
+    show_response: bool,
+    /// Follows the mean square of the input, the meter shows its square root
+    rms_meter: EnvelopeFollower,
+    peak_meter: EnvelopeFollower,
+    crest_factor: bool,
+    /// Smoothed crest factor of the latest packets, None while silent
+    crest_factor_db: Option<f32>,
+    /// Share of the previous crest factor kept per packet
+    crest_smoothing: f32,
+    /// Lists the strongest peaks of each frame next to the charts
+    peak_table: bool,
+    peak_count: usize,
+    /// Peaks below this level in dBFS are not listed, nor held as the maximum frequency
+    peak_threshold_db: f32,
+    /// Leaves DC and the lowest bins out of the peak detection
+    ignore_dc: bool,
+    ignored_low_bins: usize,
+    /// Looks for acoustic feedback in each analyzed frame when present
+    feedback_detector: Option<FeedbackDetector>,
+    /// Averages overlapping segments with Welch's method instead of analyzing each frame alone
+    welch: Option<Welch>,
+    fft_backend: FftBackend,
+    /// Computes the spectrum of each frame with `fft_backend`
+    fft: Box<dyn SpectrumBackend>,
+    /// High contrast and text size of the widgets and charts
+    display_style: DisplayStyle,
+    /// Smooths the chart lines, iced only applies it when the window is created
+    antialiasing: bool,
+    /// Current size of the window, saved so the next launch opens at the same size
+    window_size: (u32, u32),
+    /// Chart redraws per second while streaming, independently of the stream tick
+    display_fps: u32,
+    /// Redraws less often while the window is unfocused
+    power_saving: bool,
+    focused: bool,
+    /// Logs the peak frequency and level while streaming, every `log_snapshot_interval_s`
+    log_snapshots: bool,
+    log_snapshot_interval_s: f32,
+    /// Saves the chart periodically while streaming
+    auto_save: bool,
+    auto_save_interval_s: f32,
+    auto_save_dir: String,
+    /// A chart image is still being written, the next one waits for it
+    saving_chart: bool,
+    /// Keeps the unsettled first frames after a start off the charts
+    warmup: Warmup,
+    /// Queued FFT windows above which only the latest is analyzed, 0 analyzes all of them
+    max_queued_frames: usize,
+    /// Windows skipped since the stream started
+    skipped_frames: usize,
+    /// Analyzes the collected part of a window padded with zeros, once `pad_min_fill` of it is there
+    zero_padding: bool,
+    pad_min_fill: f32,
+    /// Share of the window the displayed spectrum was computed from, when it was padded
+    padded_fill: Option<f32>,
+    settings_path: PathBuf,
+    /// Modification time of the settings file when it was last loaded or saved
+    settings_modified: Option<SystemTime>,
+    /// Name the next preset is saved under
+    preset_name: String,
+    presets: Vec<String>,
+    current_preset: Option<String>,
+    /// Reference frequency the peak is tuned against
+    target_freq: Option<f32>,
+    target_freq_input: String,
+    /// Label of the next snapshot, a numbered default when empty
+    snapshot_label: String,
+    /// Requests from the control server, when it's running
+    control: Option<Receiver<ControlRequest>>,
+    tray: Option<Tray>,
+    window_hidden: bool,
+    /// Persisted choice to start streaming on launch
+    autostart: bool,
+    /// Persisted choice to log a device capability report on launch
+    probe_devices: bool,
+    /// Hosts tried on launch, kept to be saved again
+    host_preference: Vec<AudioHostName>,
+    /// Set when the gain pushed samples of the latest packets past full scale
+    clipping: bool,
+    /// Aggregates of the running stream
+    session_stats: Option<SessionStats>,
+    /// Shown after the stream stopped until dismissed
+    session_summary: Option<SessionSummary>,
+    /// The overload indicator is lit until then, after the captured input reached full scale
+    overload_until: Option<Instant>,
+    overload_hold: Duration
 }
 
 impl Audia {
@@ -47,12 +503,56 @@ impl Audia {
         log::info!("Start streaming");
 
         if self.current_stream.is_none() {
-            match self.audio_system.engine.start_recording() {
+            let result = match (&self.raw_input, self.test_signal) {
+                (Some(raw_input), _) if self.use_raw_input => {
+                    log::info!("Reading raw input from {}", raw_input);
+                    Ok(source::spawn_source(Box::new(raw_input.source(RECEIVE_PACKET_SIZE)), true))
+                },
+                (_, Some(kind)) => {
+                    log::info!("Generating {} test signal", kind);
+                    let generator = SyntheticSource::new(kind, DEFAULT_SAMPLE_RATE, RECEIVE_PACKET_SIZE);
+                    Ok(source::spawn_source(Box::new(generator), true))
+                },
+                _ => self.audio_system.engine.start_recording()
+            };
+
+            match result {
                 Ok(stream) => {
+                    self.error_message = None;
+                    self.use_sample_rate(stream.sample_rate());
+                    self.spectrogram.reset_average();
+                    self.spectrogram.reset_peak_freq_max();
+                    self.spectrogram.current_buf.clear();
+                    self.reset_second_channel();
+                    if let Some(welch) = &mut self.welch {
+                        welch.reset();
+                    }
+                    self.warmup.restart();
+                    self.pending_clip.clear();
+                    self.skipped_frames = 0;
+                    self.session_stats = Some(SessionStats::new(stream.sample_rate()));
+                    self.session_summary = None;
+
+                    if let Some((recorder, analysis_log)) = self.carried_recording.take() {
+                        if !self.resume_recording(recorder, analysis_log, stream.channels(), stream.sample_rate()) {
+                            // the stream is dropped, the caller switches back to the previous input
+                            self.audio_system.engine.stop_recording();
+                            return;
+                        }
+                    } else if self.record_to_file && self.recording_gate.is_none() {
+                        // sound-activated recordings are started by the gate instead
+                        self.start_file_recording(stream.channels());
+                    }
+
+                    if self.monitoring {
+                        self.start_monitoring(stream.channels());
+                    }
+
                     self.current_stream = Some(stream);
                 },
                 Err(error) => {
-                    log::error!("Error jaj");
+                    log::error!("Could not start streaming: {}", error);
+                    self.error_message = Some(error.to_string());
                 }
             };
 
@@ -61,153 +561,2646 @@ impl Audia {
         }
     }
 
+    /// Streams until one window was analyzed, see `update_state`. The warm-up frames are
+    /// skipped as usual, so the shot shows a settled spectrum.
+    fn start_single_shot(&mut self) {
+        if self.current_stream.is_some() {
+            log::info!("Single shot needs a stopped stream");
+            return;
+        }
+
+        log::info!("Capturing a single shot");
+        self.start_streaming();
+        self.single_shot = self.current_stream.is_some();
+    }
+
     fn stop_streaming(&mut self) {
         log::info!("Stop streaming");
 
+        self.single_shot = false;
+
         if self.current_stream.is_some() {
             self.current_stream = None;
+            self.overload_until = None;
+            self.stop_file_recording();
+            self.stop_sweep();
+            self.stop_monitoring();
+            self.audio_system.engine.stop_recording();
+
+            if let Some(stats) = self.session_stats.take() {
+                let summary = stats.summary();
+                log::info!("Session summary: {}", summary);
+                self.session_summary = Some(summary);
+            }
+            self.show_tray_status();
         } else {
             log::info!("Stream has not been stopped");
         }
     }
 
-    fn stream_update(&mut self) {
-        if let Some(stream) = &self.current_stream {
-            if let Ok(mut packet) = stream.receive() {
-                self.update_state(&mut packet);
-            } else {
-                // There was no audio data in the stream, ignore
+    fn start_monitoring(&mut self, channels: u16) {
+        let schedule = RetrySchedule::new(self.audio_system.engine.get_monitor_retry());
+        self.try_monitoring(channels, schedule);
+    }
+
+    /// Makes an attempt to open the monitor output. A transient failure is tried again once
+    /// `schedule` is due, see `retry_monitoring`, the monitoring is turned off when none is left.
+    fn try_monitoring(&mut self, channels: u16, mut schedule: RetrySchedule) {
+        match self.audio_system.engine.start_monitoring(channels, self.sample_rate, self.limiter.clone()) {
+            Ok(monitor) => {
+                monitor.set_delay(monitoring::delay_samples(self.monitor_delay_ms, channels, self.sample_rate));
+                self.monitor = Some(monitor);
+                self.monitor_retry = None;
+            },
+            Err(error) if schedule.failed(&error, Instant::now()) => self.monitor_retry = Some(schedule),
+            Err(error) => {
+                log::error!("Could not start monitoring: {}", error);
+                self.error_message = Some(format!("Could not open the monitor output after {} attempts: {}", schedule.attempts(), error));
+                self.monitor_retry = None;
+                self.monitoring = false;
             }
-        } else {
-            log::info!("Stream update request but no stream :(");
         }
     }
 
-    fn update_state(&mut self, packet: &mut PacketType) {
-        self.spectrogram.current_buf.append(packet);
+    /// Makes the next attempt to open the monitor output once its wait has passed.
+    fn retry_monitoring(&mut self, channels: u16) {
+        if self.monitor_retry.as_ref().is_some_and(|schedule| schedule.is_due(Instant::now())) {
+            if let Some(schedule) = self.monitor_retry.take() {
+                self.try_monitoring(channels, schedule);
+            }
+        }
+    }
+
+    /// Starts the monitoring again after its output device went away, which opens the default
+    /// output device.
+    fn reopen_monitoring(&mut self, channels: u16) {
+        log::warn!("Monitor output was lost, reopening it on the default output device");
+        self.monitor = None;
+        self.start_monitoring(channels);
+    }
 
-        while self.spectrogram.current_buf.len() >= RECEIVE_PACKET_SIZE {
-            let current_packet: PacketType = self.spectrogram.current_buf.drain(0..RECEIVE_PACKET_SIZE).collect();
+    fn set_monitor_delay(&mut self, delay_ms: f32) {
+        self.monitor_delay_ms = delay_ms.clamp(0.0, MAX_MONITOR_DELAY_MS);
 
-            self.spectrogram.user_data += RECEIVE_PACKET_SIZE;
+        let channels = self.current_stream.as_ref().map(AudioStream::channels).unwrap_or(0);
+        if let Some(monitor) = &self.monitor {
+            monitor.set_delay(monitoring::delay_samples(self.monitor_delay_ms, channels, self.sample_rate));
+        }
+    }
 
-            self.spectrogram.freq_data.clear();
+    fn take_snapshot(&mut self) {
+        let taken_at_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or(0);
+        let label = match self.snapshot_label.trim() {
+            "" => format!("Snapshot {}", self.spectrogram.snapshots.len() + 1),
+            label => label.to_string()
+        };
 
-            let hann_window = hann_window(current_packet.as_slice());
-            let spectrum = samples_fft_to_spectrum(
-                &hann_window,
+        self.spectrogram.take_snapshot(label, taken_at_ms);
+        self.snapshot_label.clear();
+    }
 
-                48000,
-                FrequencyLimit::Max(2200.0),
-                Some(&divide_by_N_sqrt))
-                .expect("Could not extract frequency spectrum");
+    fn stop_monitoring(&mut self) {
+        self.monitor_retry = None;
 
-            let points: Vec<(i32, f32)> = spectrum.data()
-                .iter()
-                .map(|(freq, amp)| {
-                    (freq.val() as i32, amp.val() * 2048.0)
-                }).collect();
+        if self.monitor.take().is_some() {
+            self.audio_system.engine.stop_monitoring();
+        }
+    }
 
+    /// Plays the built-in sweep on the output device and compares it with the captured input.
+    fn start_response_measurement(&mut self) {
+        let Some(stream) = &self.current_stream else {
+            self.error_message = Some(String::from("Start streaming to measure the frequency response"));
+            return;
+        };
 
-            self.spectrogram.peak_freq = points.iter().fold((0, 0.0), |a, b| {
-                if a.1 >= b.1 {
-                    a
-                } else {
-                    *b
+        if !self.analyze {
+            self.error_message = Some(String::from("Enable the analysis to measure the frequency response"));
+            return;
+        }
+
+        let channels = stream.channels();
+
+        // the sweep takes over the output from the live monitoring
+        self.stop_monitoring();
+        self.stop_sweep();
+
+        let limiter = Arc::new(LimiterControl::new(false, DEFAULT_LIMITER_THRESHOLD_DB));
+
+        match self.audio_system.engine.start_monitoring(channels, self.sample_rate, limiter) {
+            Ok(monitor) => {
+                log::info!("Measuring the frequency response");
+                self.sweep = Some(SweepMeasurement::new(monitor, channels, self.sample_rate,
+                    self.decimator.as_ref().map(Decimator::factor), self.fft_size, MAX_FREQUENCY));
+                self.error_message = None;
+            },
+            Err(error) => {
+                log::error!("Could not play the sweep: {}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    fn stop_sweep(&mut self) {
+        if self.sweep.take().is_some() {
+            self.audio_system.engine.stop_monitoring();
+        }
+    }
+
+    fn finish_response_measurement(&mut self) {
+        if let Some(sweep) = self.sweep.take() {
+            self.audio_system.engine.stop_monitoring();
+            self.response.response = sweep.finish();
+            self.show_response = true;
+
+            if self.response.response.is_empty() {
+                self.error_message = Some(String::from("No response was captured, is the output connected to the input?"));
+            }
+        }
+
+        if self.monitoring {
+            if let Some(channels) = self.current_stream.as_ref().map(AudioStream::channels) {
+                self.start_monitoring(channels);
+            }
+        }
+    }
+
+    fn set_monitoring(&mut self, enabled: bool) {
+        self.monitoring = enabled;
+
+        match (enabled, &self.current_stream) {
+            (true, Some(stream)) => {
+                let channels = stream.channels();
+                self.start_monitoring(channels);
+            },
+            (false, _) => self.stop_monitoring(),
+            _ => {}
+        }
+    }
+
+    fn start_file_recording(&mut self, channels: u16) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        let path = format!("audia-{}.wav", timestamp);
+
+        let max_frames = self.max_recording.map(|max| (max.as_secs_f64() * self.sample_rate as f64) as u32);
+
+        match WavRecorder::create(path, self.sample_rate, channels, self.record_format) {
+            Ok(recorder) => self.recorder = Some(recorder.with_max_frames(max_frames)),
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+                return;
+            }
+        }
+
+        if self.record_analysis {
+            match AnalysisLog::create(format!("audia-{}.csv", timestamp)) {
+                Ok(analysis_log) => self.analysis_log = Some(analysis_log),
+                Err(error) => {
+                    log::error!("{}", error);
+                    self.error_message = Some(error.to_string());
                 }
-            }).0 as f32;
-            //self.spectrogram.peak_freq = points.iter().fold(0.0, |a, b| a.max(b.0 as f32));
-            self.spectrogram.freq_data = points;
+            }
         }
     }
-}
 
-impl Application for Audia {
-    type Executor = executor::Default;
-    type Message = UIMessage;
-    type Theme = Theme;
-    type Flags = UIParams;
+    /// Continues a recording carried over from the previous input on a new stream. A stream with
+    /// a different channel count or sample rate doesn't fit the file: it is closed and recording
+    /// continues in a new one, or under `ChannelChangePolicy::Reject` the recording is carried
+    /// back and `false` is returned to refuse the new stream.
+    fn resume_recording(&mut self, recorder: WavRecorder, analysis_log: Option<AnalysisLog>, channels: u16, sample_rate: u32) -> bool {
+        if recorder.accepts(channels, sample_rate) {
+            self.recorder = Some(recorder);
+            self.analysis_log = analysis_log;
+            return true;
+        }
 
-    fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
-        let audio_system = flags.audio_system;
+        let change = format!("{} channels at {} Hz to {} channels at {} Hz", recorder.channels(), recorder.sample_rate(), channels, sample_rate);
 
-        (Self {
-            spectrogram: Spectrogram::new(),
-            current_stream: None,
-            audio_system
-        }, Command::none())
+        match self.channel_change_policy {
+            ChannelChangePolicy::SplitFile => {
+                self.recorder = Some(recorder);
+                self.analysis_log = analysis_log;
+                self.stop_file_recording();
+
+                let notice = format!("Input changed from {}, recording continues in a new file", change);
+                log::info!("{}", notice);
+                self.recording_notice = Some(notice);
+                self.start_file_recording(channels);
+                true
+            },
+            ChannelChangePolicy::Reject => {
+                let error = format!("Input changed from {}, stop the recording to switch to it", change);
+                log::error!("{}", error);
+                self.error_message = Some(error);
+                self.carried_recording = Some((recorder, analysis_log));
+                false
+            }
+        }
     }
 
-    fn title(&self) -> String {
-        String::from("Audia")
+    fn stop_file_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(error) = recorder.finalize() {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+
+        if let Some(analysis_log) = self.analysis_log.take() {
+            if let Err(error) = analysis_log.finish() {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
     }
 
-    fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
-        match message {
-            UIMessage::HostChanged(new_host) => self.audio_system.engine.use_host(AudioHostName::from(new_host.as_str())),
-            UIMessage::InputDeviceChanged(new_device) => self.audio_system.engine.use_input_device(InputDeviceName::from(new_device.as_str())),
-            UIMessage::StartStreaming => self.start_streaming(),
-            UIMessage::StopStreaming => self.stop_streaming(),
-            UIMessage::StreamTick => self.stream_update(),
-            _ => {
-                log::info!("Unknown event: {:?}", message);
+    /// Time covered by one waterfall row in seconds.
+    fn waterfall_row_duration(&self) -> f32 {
+        (self.waterfall.frames_per_row * self.fft_size) as f32 / self.analysis_sample_rate() as f32
+    }
+
+    fn export_waterfall(&mut self) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        if let Err(error) = self.waterfall.export_png(format!("audia-waterfall-{}.png", timestamp), self.waterfall_row_duration()) {
+            log::error!("{}", error);
+            self.error_message = Some(error.to_string());
+        }
+    }
+
+    /// Renders the chart and writes it to a timestamped PNG in `dir` in the background. Skipped
+    /// while the previous image is still being written.
+    fn save_chart(&mut self, dir: PathBuf) -> Command<UIMessage> {
+        if self.saving_chart {
+            log::warn!("Not saving the chart, the previous image is still being written");
+            return Command::none();
+        }
+
+        let max_freq = self.full_spectrum.then(|| (self.analysis_sample_rate() / 2) as i32);
+        let image = match self.spectrogram.render(max_freq) {
+            Ok(image) => image,
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+                return Command::none();
+            }
+        };
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        let path = dir.join(format!("audia-spectrum-{}.png", timestamp));
+        self.saving_chart = true;
+
+        Command::perform(async move {
+            std::fs::create_dir_all(&dir)
+                .map_err(|error| format!("Could not create {}: {error}", dir.display()))?;
+            image.save_png(&path).map_err(|error| error.to_string())?;
+            Ok::<String, String>(path.display().to_string())
+        }, UIMessage::ChartSaved)
+    }
+
+    fn save_waterfall_session(&mut self) {
+        let saved_at_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or(0);
+        match self.waterfall.session(saved_at_ms, self.analysis_sample_rate(), self.waterfall_row_duration()).save(SESSION_DIR) {
+            Ok(name) => {
+                self.sessions = WaterfallSession::list(SESSION_DIR);
+                self.current_session = Some(name);
+            },
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
             }
         }
-        Command::none()
     }
 
-    fn view(&self) -> Element<Self::Message> {
-        let stream_button = if self.current_stream.is_none() {
-            button("Start streaming").on_press(UIMessage::StartStreaming)
+    /// Stops the live capture and shows a saved session for review.
+    fn open_waterfall_session(&mut self, name: String) {
+        match WaterfallSession::load(SESSION_DIR, &name) {
+            Ok(session) => {
+                log::info!("Opened waterfall session {} with {} rows", name, session.rows.len());
+                self.stop_streaming();
+
+                // the main chart shows the newest row of the session
+                self.spectrogram.freq_data = session.rows.first()
+                    .map(|row| session.frequencies.iter().copied().zip(row.iter().copied()).collect())
+                    .unwrap_or_default();
+                self.spectrogram.redraw();
+
+                self.waterfall.restore(session);
+                self.show_waterfall = true;
+                self.current_session = Some(name);
+            },
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Writes the latest peak to the log file, a lightweight record of a session.
+    fn log_snapshot(&self) {
+        if self.analyze {
+            log::info!("Peak {:.2} Hz at {:.1} dBFS", self.spectrogram.peak_freq, to_dbfs(self.spectrogram.peak_amp));
         } else {
-            button("Stop streaming").on_press(UIMessage::StopStreaming)
+            log::info!("Analysis paused");
+        }
+    }
+
+    /// Logs the latest analysis results against the position in the WAV recording.
+    fn log_analysis(&mut self) {
+        let time = match &self.recorder {
+            Some(recorder) => recorder.frames() as f64 / self.sample_rate as f64,
+            None => return
         };
 
-        Column::new()
-            .push(
-                Row::new()
-                .push(text("Audio host: "))
-                    .push(
-                        pick_list(
-                            self.audio_system.engine.get_available_hosts(),
-                            self.audio_system.engine.get_current_host().map(|e| e.into()),
-                            UIMessage::HostChanged)
-                            .placeholder("Choose an audio host")))
-            .push(
-                Row::new()
-                    .spacing(5)
-                    .push(text("Input device"))
-                    .push(
-                        pick_list(
-                            self.audio_system.engine.get_input_devices(),
-                            self.audio_system.engine.get_current_input_device(),
-                            UIMessage::InputDeviceChanged)
-                            .placeholder("Choose an input device")))
-            .push(
-                Row::new()
-                    .spacing(5)
-                    .push(text("Output device"))
-                    .push(
-                        pick_list(
-                            self.audio_system.engine.get_output_devices(),
-                            self.audio_system.engine.get_current_output_device(),
-                            UIMessage::OutputDeviceChanged)
-                            .placeholder("Choose an output device")))
-            .push(stream_button)
-            .push(self.spectrogram.view())
-            .push(text(format!("{:3.2}Hz {}", self.spectrogram.peak_freq, self.spectrogram.user_data)))
-            .padding(20)
-            .spacing(10)
-            .align_items(Alignment::Center)
-            .into()
+        let result = match &mut self.analysis_log {
+            Some(analysis_log) => analysis_log.write(time, self.spectrogram.peak_freq, self.spectrogram.peak_amp),
+            None => return
+        };
+
+        if let Err(error) = result {
+            log::error!("{}", error);
+            self.error_message = Some(error.to_string());
+            self.analysis_log = None;
+        }
     }
 
-    fn subscription(&self) -> Subscription<Self::Message> {
-        if self.current_stream.is_some() {
-            let duration = Duration::from_millis(5);
-            iced_time::every(duration).map(|_instant| UIMessage::StreamTick)
+    fn set_sound_activation(&mut self, enabled: bool) {
+        // an open gate started the running recording, which would never end without it
+        if !enabled && self.recording_gate.as_ref().is_some_and(SignalGate::is_open) {
+            self.stop_file_recording();
+        }
+
+        self.recording_gate = enabled.then(|| SignalGate::new(ActivationSettings::default(), self.sample_rate));
+    }
+
+    fn update_activation_settings(&mut self, update: impl FnOnce(&mut ActivationSettings)) {
+        if let Some(gate) = &mut self.recording_gate {
+            update(&mut gate.settings);
+        }
+    }
+
+    fn record(&mut self, packet: &[f32], channels: u16) {
+        if self.record_to_file {
+            let event = self.recording_gate.as_mut().and_then(|gate| gate.process(packet, channels));
+
+            match event {
+                // every event goes into a file of its own
+                Some(ActivationEvent::SignalStarted) => self.start_file_recording(channels),
+                Some(ActivationEvent::SignalEnded) => self.stop_file_recording(),
+                None => {}
+            }
+        }
+
+        let result = match &mut self.recorder {
+            Some(recorder) => recorder.write(packet).map(|()| recorder.is_full()),
+            None => return
+        };
+
+        match result {
+            Ok(false) => {},
+            Ok(true) => {
+                log::info!("Maximum recording duration reached");
+                self.stop_file_recording();
+                // keeps the gate from starting the next file
+                self.record_to_file = false;
+                self.recording_limit_reached = true;
+            },
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+                self.stop_file_recording();
+            }
+        }
+    }
+
+    fn current_settings(&self) -> UserSettings {
+        UserSettings {
+            host: self.audio_system.engine.get_current_host(),
+            host_preference: self.host_preference.clone(),
+            input_device: self.current_input_name(),
+            share_mode: self.audio_system.engine.get_share_mode(),
+            autostart: self.autostart,
+            probe_devices: self.probe_devices,
+            decimation: self.decimator.is_some(),
+            fft_window_ms: self.fft_window_ms,
+            zero_padding: self.zero_padding,
+            pad_min_fill: self.pad_min_fill,
+            low_cut: self.low_cut.is_some(),
+            low_cut_hz: self.low_cut_hz,
+            silence_gate: self.silence_gate,
+            silence_gate_db: self.silence_gate_db,
+            freeze_on_clip: self.freeze_on_clip,
+            unit_mode: self.spectrogram.unit_mode,
+            db_floor: self.spectrogram.db_range.floor,
+            db_ceiling: self.spectrogram.db_range.ceiling,
+            x_unit: self.spectrogram.x_unit,
+            tilt: self.spectrogram.tilt,
+            custom_tilt_db: self.spectrogram.custom_tilt_db,
+            averaging: self.spectrogram.averaging,
+            chart_style: self.spectrogram.chart_style,
+            display_style: self.display_style,
+            trace_palette: self.spectrogram.palette,
+            fft_backend: self.fft_backend,
+            band_width: self.spectrogram.band_width,
+            target_freq: self.target_freq,
+            split_view: self.split_view,
+            follow_peak: self.spectrogram.follow_peak,
+            follow_peak_span_hz: self.spectrogram.follow_span,
+            full_spectrum: self.full_spectrum,
+            max_display_points: self.spectrogram.max_display_points,
+            peak_table: self.peak_table,
+            peak_count: self.peak_count,
+            peak_threshold_db: self.peak_threshold_db,
+            ignore_dc: self.ignore_dc,
+            ignored_low_bins: self.ignored_low_bins,
+            hold_peak_freq_max: self.spectrogram.hold_peak_freq_max,
+            markers: self.spectrogram.markers_enabled,
+            gain_db: self.gain_db,
+            waterfall: self.show_waterfall,
+            waterfall_smoothing: self.waterfall.smoothing,
+            waterfall_frames_per_row: self.waterfall.frames_per_row as u8,
+            waterfall_direction: self.waterfall.direction,
+            meter_attack_ms: self.rms_meter.attack(),
+            meter_release_ms: self.rms_meter.release(),
+            crest_factor: self.crest_factor,
+            crest_smoothing: self.crest_smoothing,
+            display_fps: self.display_fps,
+            power_saving: self.power_saving,
+            log_snapshots: self.log_snapshots,
+            log_snapshot_interval_s: self.log_snapshot_interval_s,
+            auto_save: self.auto_save,
+            auto_save_interval_s: self.auto_save_interval_s,
+            auto_save_dir: self.auto_save_dir.clone(),
+            overload_hold_s: self.overload_hold.as_secs_f32(),
+            warmup_frames: self.warmup.frames(),
+            max_queued_frames: self.max_queued_frames,
+            channel_weights: self.channel_weights.clone(),
+            channel_view: self.channel_view,
+            scope: self.show_scope,
+            scope_window: self.waveform.window(),
+            trigger: self.waveform.trigger,
+            trigger_level: self.waveform.trigger_level,
+            limiter: self.limiter.is_enabled(),
+            limiter_threshold_db: self.limiter.threshold_db(),
+            monitor_delay_ms: self.monitor_delay_ms,
+            channel_change_policy: self.channel_change_policy,
+            monitor_attempts: self.audio_system.engine.get_monitor_retry().attempts,
+            peak_cap_decay_db: self.spectrogram.peak_cap_decay_db,
+            persistence_frames: self.spectrogram.persistence_frames,
+            persistence_decay: self.spectrogram.persistence_decay,
+            line_width: self.spectrogram.line_width,
+            antialiasing: self.antialiasing,
+            window_size: self.window_size
+        }
+    }
+
+    fn save_settings(&mut self) {
+        match self.current_settings().save(&self.settings_path) {
+            Ok(()) => self.settings_modified = settings::modified_time(&self.settings_path),
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    fn reload_settings(&mut self) {
+        // remember the attempt so a broken file is not re-read every second
+        self.settings_modified = settings::modified_time(&self.settings_path);
+
+        match UserSettings::load(&self.settings_path) {
+            Ok(settings) => self.apply_settings(settings),
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    fn save_preset(&mut self) {
+        let name = self.preset_name.trim();
+
+        if name.is_empty() {
+            self.error_message = Some(String::from("Enter a name for the preset"));
+            return;
+        }
+
+        match Preset::new(name, self.current_settings()).save(PRESET_DIR) {
+            Ok(listed_name) => {
+                self.presets = Preset::list(PRESET_DIR);
+                self.current_preset = Some(listed_name);
+            },
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    fn load_preset(&mut self, name: String) {
+        match Preset::load(PRESET_DIR, &name) {
+            Ok(preset) => {
+                log::info!("Loaded preset {}", preset.name);
+                self.apply_analysis_settings(&preset.settings);
+                self.preset_name = preset.name;
+                self.current_preset = Some(name);
+            },
+            Err(error) => {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Applies settings while running. A running stream is restarted if the device changes.
+    fn apply_settings(&mut self, settings: UserSettings) {
+        let current = self.current_settings();
+        let host_changed = settings.host.is_some() && settings.host != current.host;
+        let device_changed = settings.input_device.is_some() && settings.input_device != current.input_device;
+        let restart = (host_changed || device_changed) && self.current_stream.is_some();
+
+        if restart {
+            self.stop_streaming();
+        }
+
+        if let Some(host) = settings.host.clone().filter(|_| host_changed) {
+            if let Err(error) = self.audio_system.engine.use_host(host) {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+            self.watch_devices();
+        }
+
+        if let Some(device) = settings.input_device.clone().filter(|_| host_changed || device_changed) {
+            if let Err(error) = self.use_input_device(device) {
+                log::error!("{}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+
+        self.audio_system.engine.use_share_mode(settings.share_mode);
+        self.apply_analysis_settings(&settings);
+
+        if restart {
+            self.start_streaming();
+        }
+
+        log::info!("Applied settings");
+    }
+
+    fn apply_analysis_settings(&mut self, settings: &UserSettings) {
+        if settings.decimation != self.decimator.is_some() {
+            self.set_decimation(settings.decimation);
+        }
+        self.set_fft_window(settings.fft_window_ms);
+        self.set_zero_padding(settings.zero_padding);
+        self.freeze_on_clip = settings.freeze_on_clip;
+        self.pad_min_fill = settings.pad_min_fill.clamp(MIN_PAD_FILL, MAX_PAD_FILL);
+
+        self.set_low_cut(settings.low_cut, settings.low_cut_hz);
+        self.set_silence_gate(settings.silence_gate);
+        self.silence_gate_db = settings.silence_gate_db.clamp(DB_FLOOR, MAX_SILENCE_GATE_DB);
+
+        if settings.averaging != self.spectrogram.averaging {
+            self.spectrogram.averaging = settings.averaging;
+            self.spectrogram.reset_average();
+        }
+
+        if settings.band_width != self.spectrogram.band_width {
+            self.set_band_width(settings.band_width);
+        }
+
+        self.target_freq = settings.target_freq.filter(|freq| *freq > 0.0);
+        self.target_freq_input = self.target_freq.map(|freq| freq.to_string()).unwrap_or_default();
+
+        self.spectrogram.unit_mode = settings.unit_mode;
+        self.set_db_range(settings.db_floor, settings.db_ceiling);
+        self.spectrogram.x_unit = settings.x_unit;
+        self.spectrogram.tilt = settings.tilt;
+        self.spectrogram.custom_tilt_db = settings.custom_tilt_db.clamp(-MAX_TILT_DB_PER_OCTAVE, MAX_TILT_DB_PER_OCTAVE);
+        self.spectrogram.chart_style = settings.chart_style;
+        self.spectrogram.peak_cap_decay_db = settings.peak_cap_decay_db.clamp(MIN_PEAK_CAP_DECAY_DB, MAX_PEAK_CAP_DECAY_DB);
+        self.spectrogram.persistence_frames = settings.persistence_frames.min(MAX_PERSISTENCE_FRAMES);
+        self.spectrogram.persistence_decay = settings.persistence_decay.clamp(MIN_PERSISTENCE_DECAY, MAX_PERSISTENCE_DECAY);
+        self.spectrogram.clear_persistence();
+        self.spectrogram.line_width = settings.line_width.map(|width| width.clamp(1, MAX_LINE_WIDTH));
+        self.antialiasing = settings.antialiasing;
+        self.set_display_style(settings.display_style);
+        self.set_trace_palette(settings.trace_palette);
+        self.set_fft_backend(settings.fft_backend);
+        self.split_view = settings.split_view;
+        self.spectrogram.follow_peak = settings.follow_peak;
+        self.spectrogram.follow_span = settings.follow_peak_span_hz.clamp(MIN_FOLLOW_SPAN_HZ, MAX_FOLLOW_SPAN_HZ);
+        self.set_full_spectrum(settings.full_spectrum);
+        self.spectrogram.max_display_points = settings.max_display_points.min(MAX_DISPLAY_POINTS);
+        self.set_peak_table(settings.peak_table);
+        self.peak_count = settings.peak_count.clamp(1, MAX_PEAK_COUNT);
+        self.peak_threshold_db = settings.peak_threshold_db.clamp(DB_FLOOR, 0.0);
+        self.ignore_dc = settings.ignore_dc;
+        self.ignored_low_bins = settings.ignored_low_bins.clamp(1, MAX_IGNORED_LOW_BINS);
+        self.spectrogram.hold_peak_freq_max = settings.hold_peak_freq_max;
+        self.set_markers(settings.markers);
+        self.show_waterfall = settings.waterfall;
+        self.waterfall.smoothing = settings.waterfall_smoothing;
+        self.waterfall.frames_per_row = settings.waterfall_frames_per_row.max(1) as usize;
+        self.waterfall.direction = settings.waterfall_direction;
+        self.set_meter_times(settings.meter_attack_ms, settings.meter_release_ms);
+        self.crest_factor = settings.crest_factor;
+        self.crest_smoothing = settings.crest_smoothing.clamp(0.0, MAX_CREST_SMOOTHING);
+        self.display_fps = settings.display_fps.clamp(1, 60);
+        self.power_saving = settings.power_saving;
+        self.log_snapshots = settings.log_snapshots;
+        self.log_snapshot_interval_s = settings.log_snapshot_interval_s.clamp(1.0, MAX_LOG_SNAPSHOT_INTERVAL_S);
+        self.auto_save = settings.auto_save;
+        self.auto_save_interval_s = settings.auto_save_interval_s.clamp(MIN_AUTO_SAVE_INTERVAL_S, MAX_AUTO_SAVE_INTERVAL_S);
+        self.auto_save_dir = settings.auto_save_dir.clone();
+        self.overload_hold = Duration::from_secs_f32(settings.overload_hold_s.clamp(0.0, MAX_OVERLOAD_HOLD_S));
+        self.warmup.set_frames(settings.warmup_frames.min(MAX_WARMUP_FRAMES));
+        self.max_queued_frames = settings.max_queued_frames.min(MAX_QUEUED_FRAMES);
+        self.gain_db = settings.gain_db.clamp(*self.gain_range_db.start(), *self.gain_range_db.end());
+        self.channel_weights = settings.channel_weights.clone();
+        self.channel_weights_input = settings.channel_weights.iter()
+            .map(f32::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.set_channel_view(settings.channel_view);
+        self.show_scope = settings.scope;
+        self.waveform.set_window(settings.scope_window);
+        self.waveform.trigger = settings.trigger;
+        self.waveform.trigger_level = settings.trigger_level.clamp(-1.0, 1.0);
+        self.limiter.set_enabled(settings.limiter);
+        self.limiter.set_threshold_db(settings.limiter_threshold_db);
+        self.set_monitor_delay(settings.monitor_delay_ms);
+        self.channel_change_policy = settings.channel_change_policy;
+        self.audio_system.engine.use_monitor_retry(MonitorRetry {
+            attempts: settings.monitor_attempts.clamp(1, MAX_MONITOR_ATTEMPTS),
+            ..MonitorRetry::default()
+        });
+    }
+
+    fn set_analysis(&mut self, enabled: bool) {
+        log::info!("Analysis {}", if enabled { "resumed" } else { "bypassed" });
+
+        self.analyze = enabled;
+        self.frozen_on_clip = false;
+        self.pending_clip.clear();
+        // drop the partial frame left over from before the bypass
+        self.spectrogram.current_buf.clear();
+        self.reset_second_channel();
+    }
+
+    /// Pauses the analysis with the window that contained the clip on screen.
+    fn freeze_after_clip(&mut self) {
+        log::warn!("Clip detected, analysis frozen with the peak at {:.1} Hz, {}",
+            self.spectrogram.peak_freq, self.spectrogram.unit_mode.format(self.spectrogram.peak_amp));
+        self.set_analysis(false);
+        self.frozen_on_clip = true;
+    }
+
+    /// Lights the overload indicator for the hold time, or turns it off once that has passed.
+    fn update_overload(&mut self, overloaded: bool) {
+        let now = Instant::now();
+
+        if overloaded {
+            self.overload_until = Some(now + self.overload_hold);
+        } else if self.overload_until.is_some_and(|until| until <= now) {
+            self.overload_until = None;
+        }
+    }
+
+    fn stream_update(&mut self) {
+        let (packets, channels, overloaded, packet_pool) = if let Some(stream) = &self.current_stream {
+            // keep the channel drained so capture can continue without piling up packets
+            let packets: Vec<PacketType> = std::iter::from_fn(|| stream.receive().ok()).collect();
+
+            (packets, stream.channels(), stream.take_overload(), stream.packet_pool())
+        } else {
+            log::info!("Stream update request but no stream :(");
+            return;
+        };
+
+        self.update_overload(overloaded);
+
+        if self.monitor.is_some() && self.audio_system.engine.monitor_lost() {
+            self.reopen_monitoring(channels);
+        }
+        self.retry_monitoring(channels);
+
+        self.clipping = false;
+        let (weights, second_weights) = self.channel_view.weights(&self.channel_weights, channels as usize);
+        // the buffers of the last tick are reused, their samples were moved on to the analysis
+        let mut analyzed = std::mem::take(&mut self.mixed);
+        let mut second = std::mem::take(&mut self.second_mixed);
+
+        for mut packet in packets {
+            let clipped = analysis::apply_gain(&mut packet, self.gain_db);
+            self.clipping |= clipped;
+            self.record(&packet, channels);
+
+            if let Some(monitor) = &mut self.monitor {
+                monitor.push(&packet);
+            }
+
+            if let Some(sweep) = &mut self.sweep {
+                sweep.play(packet.len() / channels.max(1) as usize);
+            }
+
+            let start = analyzed.len();
+            analysis::weighted_downmix_into(&packet, &weights, &mut analyzed);
+
+            // the session statistics cover the whole stream, also while the analysis is bypassed
+            if let Some(stats) = &mut self.session_stats {
+                stats.add_packet(&analyzed[start..], clipped);
+            }
+
+            if self.analyze {
+                self.update_meters(&analyzed[start..]);
+
+                if let Some(second_weights) = &second_weights {
+                    analysis::weighted_downmix_into(&packet, second_weights, &mut second);
+                }
+            } else {
+                analyzed.truncate(start);
+            }
+
+            // the capture callback fills it again instead of allocating a new packet
+            if let Some(pool) = &packet_pool {
+                pool.recycle(packet);
+            }
+        }
+
+        // all windows of the tick are queued together, so a backlog can be coalesced
+        let analyzed_before = self.spectrogram.user_data;
+        self.update_state(&mut analyzed);
+        self.update_second_channel(&mut second);
+        analyzed.clear();
+        second.clear();
+        self.mixed = analyzed;
+        self.second_mixed = second;
+
+        if self.freeze_on_clip && self.analyze && self.sweep.is_none() {
+            let analyzed_samples = self.spectrogram.user_data - analyzed_before;
+
+            // samples of the tick that are still buffered wait for the rest of their window
+            if self.clipping || overloaded {
+                self.pending_clip.clip(self.spectrogram.current_buf.len() + analyzed_samples);
+            }
+
+            if self.pending_clip.analyzed(analyzed_samples) {
+                self.freeze_after_clip();
+            }
+        }
+
+        if self.sweep.as_ref().is_some_and(SweepMeasurement::is_finished) {
+            self.finish_response_measurement();
+        }
+
+        if self.current_stream.as_ref().is_some_and(AudioStream::is_finished) {
+            log::info!("The input ended, stopping the stream");
+            self.stop_streaming();
+        }
+    }
+
+    fn set_channel_view(&mut self, view: ChannelView) {
+        self.channel_view = view;
+        self.reset_second_channel();
+    }
+
+    /// Rebuilds the filters of the second trace so they match the main signal again.
+    fn reset_second_channel(&mut self) {
+        self.second_channel = (self.channel_view == ChannelView::Dual).then(|| ChannelChain::new(
+            self.low_cut.is_some().then(|| HighPass::new(self.low_cut_hz, self.sample_rate)),
+            self.decimator.as_ref().map(|decimator| Decimator::new(decimator.factor()))));
+        self.spectrogram.second_freq_data.clear();
+    }
+
+    /// Only the latest frame of the second trace is analyzed, it is drawn as is without averaging.
+    fn update_second_channel(&mut self, packet: &mut PacketType) {
+        let sample_rate = self.analysis_sample_rate();
+        let frequency_limit = self.frequency_limit();
+        let fft_size = self.fft_size;
+
+        let frame = match self.second_channel.as_mut().and_then(|chain| chain.process(packet, fft_size)) {
+            Some(frame) => frame,
+            None => return
+        };
+
+        match self.fft.spectrum(frame, sample_rate, frequency_limit) {
+            Ok(points) => {
+                self.spectrogram.second_freq_data = points.iter()
+                    .map(|(freq, amp)| (*freq as i32, *amp))
+                    .collect();
+            },
+            // errors of the main signal are already reported, the frame is skipped
+            Err(error) => log::debug!("Skipped a frame of the second channel: {}", error)
+        }
+    }
+
+    fn set_channel_weights(&mut self, input: String) {
+        match analysis::parse_weights(&input) {
+            Ok(weights) => {
+                self.channel_weights = weights;
+                self.error_message = None;
+            },
+            Err(error) => self.error_message = Some(error.to_string())
+        }
+
+        self.channel_weights_input = input;
+    }
+
+    fn set_target_freq(&mut self, input: String) {
+        let trimmed = input.trim();
+        let target_freq = match trimmed.parse::<f32>() {
+            _ if trimmed.is_empty() => Ok(None),
+            Ok(freq) if freq > 0.0 && freq.is_finite() => Ok(Some(freq)),
+            _ => Err(format!("{INVALID_TARGET_FREQ}: {trimmed}"))
+        };
+
+        match target_freq {
+            Ok(freq) => {
+                self.target_freq = freq;
+                // errors of other controls stay until they are resolved
+                if self.error_message.as_deref().is_some_and(|message| message.starts_with(INVALID_TARGET_FREQ)) {
+                    self.error_message = None;
+                }
+            },
+            Err(error) => self.error_message = Some(error)
+        }
+
+        self.target_freq_input = input;
+    }
+
+    /// Deviation of the peak from the target frequency in cents, None without a target or a peak
+    /// above the threshold.
+    fn tuning_cents(&self) -> Option<f32> {
+        let threshold = 10f32.powf(self.peak_threshold_db / 20.0);
+
+        if !self.analyze || self.spectrogram.silent || self.spectrogram.peak_amp < threshold {
+            return None;
+        }
+
+        units::cents_from(self.spectrogram.peak_freq, self.target_freq?)
+    }
+
+    fn tuning_status(&self) -> String {
+        let Some(target) = self.target_freq else {
+            return String::new();
+        };
+
+        match self.tuning_cents() {
+            Some(cents) if cents.abs() <= IN_TUNE_CENTS => format!("{cents:+.1} cents, in tune with {target:.2} Hz"),
+            Some(cents) => format!("{cents:+.1} cents, {} {target:.2} Hz", if cents > 0.0 { "above" } else { "below" }),
+            None => format!("No signal to tune against {target:.2} Hz")
+        }
+    }
+
+    /// Warns when the channel weights don't match the channel count of the running stream.
+    fn channel_weights_warning(&self) -> String {
+        let channels = self.current_stream.as_ref().map(AudioStream::channels).unwrap_or(0) as usize;
+
+        if channels == 0 || self.channel_view != ChannelView::Downmix || self.channel_weights.is_empty() || self.channel_weights.len() == channels {
+            String::new()
+        } else {
+            format!("{} weights for {} channels, missing weights count as 0", self.channel_weights.len(), channels)
+        }
+    }
+
+    /// Carries out a chosen tray menu entry.
+    fn handle_tray_action(&mut self, action: TrayAction) -> Command<UIMessage> {
+        let command = match action {
+            TrayAction::ToggleWindow => self.show_window(self.window_hidden),
+            TrayAction::StartStreaming => {
+                self.start_streaming();
+                Command::none()
+            },
+            TrayAction::StopStreaming => {
+                self.stop_streaming();
+                Command::none()
+            },
+            TrayAction::Quit => window::close()
+        };
+
+        self.show_tray_status();
+        command
+    }
+
+    /// Shows or hides the window, which stays reachable from the tray while hidden.
+    fn show_window(&mut self, shown: bool) -> Command<UIMessage> {
+        self.window_hidden = !shown;
+        window::change_mode(if shown { window::Mode::Windowed } else { window::Mode::Hidden })
+    }
+
+    /// Hides the window instead of closing or minimizing it while there is a tray icon, the
+    /// capture keeps running in the background.
+    fn minimize_to_tray(&mut self, close: bool) -> Command<UIMessage> {
+        match (&self.tray, close) {
+            (Some(_), _) => {
+                log::info!("Minimized to the tray");
+                self.show_window(false)
+            },
+            (None, true) => window::close(),
+            (None, false) => Command::none()
+        }
+    }
+
+    /// Shows the current level on the tray icon.
+    fn show_tray_status(&mut self) {
+        let status = TrayStatus {
+            peak_db: self.current_stream.is_some().then(|| to_dbfs(self.peak_meter.level())),
+            clipping: self.clipping || self.overload_until.is_some()
+        };
+        if let Some(tray) = &mut self.tray {
+            tray.show(status);
+        }
+    }
+
+    fn handle_control_request(&mut self, request: ControlRequest) {
+        log::info!("Control command: {:?}", request.command);
+        let result = self.run_control_command(&request.command);
+        request.reply(result);
+    }
+
+    fn run_control_command(&mut self, command: &ControlCommand) -> Result<Value, String> {
+        match command {
+            ControlCommand::Start => {
+                self.start_streaming();
+
+                if let (None, Some(error)) = (&self.current_stream, &self.error_message) {
+                    return Err(error.clone());
+                }
+            },
+            ControlCommand::Stop => self.stop_streaming(),
+            ControlCommand::SelectDevice { name } => {
+                let pseudo_device = name == TEST_SIGNAL_DEVICE || (name == RAW_INPUT_DEVICE && self.raw_input.is_some());
+
+                if !pseudo_device && !self.audio_system.engine.get_input_devices().contains(name) {
+                    return Err(format!("Unknown input device: {name}"));
+                }
+
+                self.use_input_device(name.clone()).map_err(|error| error.to_string())?;
+            },
+            ControlCommand::Status => {},
+            // a silent input has no peak
+            ControlCommand::Peak if self.spectrogram.silent => return Ok(json!({
+                "frequency": null,
+                "amplitude": null
+            })),
+            ControlCommand::Peak => return Ok(json!({
+                "frequency": self.spectrogram.peak_freq,
+                "amplitude": self.spectrogram.peak_amp
+            })),
+            ControlCommand::Spectrum => return Ok(json!({
+                "sample_rate": self.analysis_sample_rate(),
+                "points": self.spectrogram.freq_data
+            }))
+        }
+
+        Ok(self.control_status())
+    }
+
+    fn control_status(&self) -> Value {
+        json!({
+            "streaming": self.current_stream.is_some(),
+            "host": self.audio_system.engine.get_current_host(),
+            "input_device": self.current_input_name(),
+            "sample_rate": self.sample_rate,
+            "channels": self.current_stream.as_ref().map(AudioStream::channels)
+        })
+    }
+
+    fn use_host(&mut self, host_name: AudioHostName) {
+        // the running stream belongs to a device of the old host
+        let restart = self.current_stream.is_some() && self.test_signal.is_none() && !self.use_raw_input;
+
+        if restart {
+            self.stop_streaming();
+        }
+
+        match self.audio_system.engine.use_host(host_name) {
+            Ok(()) => self.error_message = None,
+            Err(error) => {
+                log::error!("Could not switch hosts: {}", error);
+                self.error_message = Some(error.to_string());
+            }
+        }
+        self.watch_devices();
+
+        if restart {
+            self.start_streaming();
+        }
+    }
+
+    /// Delivers everything `receiver` gets as a message, without polling. The receiver blocks, so
+    /// it's waited on in a thread of its own that ends with the sender or the subscription.
+    fn forward<T: Send + 'static>(id: impl Hash + 'static, receiver: Receiver<T>, message: fn(T) -> UIMessage) -> Subscription<UIMessage> {
+        subscription::channel(id, 1, move |mut output| async move {
+            thread::spawn(move || {
+                while let Ok(value) = receiver.recv() {
+                    if block_on(output.send(message(value))).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            future::pending().await
+        })
+    }
+
+    /// Watches the devices of the current host, replacing the watcher of the previous one.
+    fn watch_devices(&mut self) {
+        // dropping the old watcher stops its thread
+        self.device_watcher = self.audio_system.engine.watch_devices();
+        self.refresh_devices();
+    }
+
+    fn refresh_devices(&mut self) {
+        self.input_devices = self.audio_system.engine.get_input_devices();
+        self.output_devices = self.audio_system.engine.get_output_devices();
+
+        if let Some(current) = self.audio_system.engine.get_current_input_device() {
+            if !self.input_devices.contains(&current) {
+                log::warn!("Input device {} is no longer available", current);
+            }
+        }
+    }
+
+    /// The selected input as shown to the user, including the pseudo devices.
+    fn current_input_name(&self) -> Option<InputDeviceName> {
+        if self.use_raw_input {
+            Some(InputDeviceName::from(RAW_INPUT_DEVICE))
+        } else if self.test_signal.is_some() {
+            Some(InputDeviceName::from(TEST_SIGNAL_DEVICE))
+        } else {
+            self.audio_system.engine.get_current_input_device()
+        }
+    }
+
+    /// Switches the input. A running recording carries over to the new input, see
+    /// `resume_recording`; when the policy refuses the new input the previous one is restored.
+    fn use_input_device(&mut self, device_name: InputDeviceName) -> Result<(), AudiaError> {
+        let recording = self.recorder.is_some() && self.current_stream.is_some();
+        let previous_input = self.current_input_name();
+
+        self.select_input(device_name.clone());
+
+        if !recording {
+            return Ok(());
+        }
+
+        self.carried_recording = self.recorder.take().map(|recorder| (recorder, self.analysis_log.take()));
+        self.stop_streaming();
+        self.start_streaming();
+
+        if self.carried_recording.is_none() {
+            return Ok(());
+        }
+
+        // the new input was refused or couldn't be opened, the recording goes on with the old one
+        let error = self.error_message.take().unwrap_or_else(|| format!("Could not switch to {}", device_name));
+
+        if let Some(previous_input) = previous_input {
+            self.select_input(previous_input);
+        }
+        self.start_streaming();
+
+        // a recording that doesn't fit the previous input anymore has to end
+        if let Some((recorder, analysis_log)) = self.carried_recording.take() {
+            self.recorder = Some(recorder);
+            self.analysis_log = analysis_log;
+            self.stop_file_recording();
+        }
+
+        Err(AudiaError::from(error))
+    }
+
+    fn select_input(&mut self, device_name: InputDeviceName) {
+        // the raw input only exists when it was given on the command line
+        if device_name == RAW_INPUT_DEVICE && self.raw_input.is_none() {
+            log::warn!("No raw input was given, keeping the current input");
+            return;
+        }
+
+        self.use_raw_input = device_name == RAW_INPUT_DEVICE;
+
+        if self.use_raw_input {
+            self.test_signal = None;
+        } else if device_name == TEST_SIGNAL_DEVICE {
+            self.test_signal = Some(self.test_signal.unwrap_or(TestSignalKind::Sine));
+        } else {
+            self.test_signal = None;
+            self.audio_system.engine.use_input_device(device_name);
+        }
+    }
+
+    fn use_test_signal(&mut self, kind: TestSignalKind) {
+        self.test_signal = Some(kind);
+
+        // restart a running generator so the new signal takes effect immediately
+        if self.current_stream.is_some() {
+            self.stop_streaming();
+            self.start_streaming();
+        }
+    }
+
+    fn use_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+
+        log::info!("Stream runs at {} Hz", sample_rate);
+        self.sample_rate = sample_rate;
+
+        // the decimation factor and the silence timeout depend on the sample rate
+        self.set_decimation(self.decimator.is_some());
+        self.recording_gate = self.recording_gate.as_ref().map(|gate| SignalGate::new(gate.settings, sample_rate));
+        self.rms_meter.set_rate(sample_rate);
+        self.peak_meter.set_rate(sample_rate);
+        self.waveform.set_sample_rate(sample_rate);
+        self.set_low_cut(self.low_cut.is_some(), self.low_cut_hz);
+    }
+
+    fn set_markers(&mut self, enabled: bool) {
+        self.spectrogram.markers_enabled = enabled;
+
+        if !enabled {
+            self.spectrogram.clear_markers();
+        }
+    }
+
+    /// Positions of the delta markers and the difference between them.
+    fn marker_readout(&self) -> String {
+        let [first, second] = self.spectrogram.marker_points();
+        let position = |point: Option<(i32, f32)>| point
+            .map(|(freq, amp)| format!("{} Hz, {}", freq, self.spectrogram.unit_mode.format(amp)))
+            .unwrap_or(String::from("-"));
+
+        match self.spectrogram.marker_delta() {
+            Some((delta_freq, delta_db)) => format!("M1 {}, M2 {}, ΔFreq {:.0} Hz, ΔdB {:+.1} dB",
+                position(first), position(second), delta_freq, delta_db),
+            None if first.is_none() && second.is_none() => String::from("Left click places M1, right click M2"),
+            None => format!("M1 {}, M2 {}", position(first), position(second))
+        }
+    }
+
+    fn set_peak_table(&mut self, enabled: bool) {
+        self.peak_table = enabled;
+        self.spectrogram.peaks.clear();
+    }
+
+    /// Applies a dB range loaded from the settings, an invalid one is reported and not applied.
+    fn set_db_range(&mut self, floor: f32, ceiling: f32) {
+        match DbRange::new(floor, ceiling) {
+            Ok(range) => self.spectrogram.db_range = range,
+            Err(error) => {
+                log::warn!("{error}");
+                self.error_message = Some(error.to_string());
+            }
+        }
+    }
+
+    fn set_fft_backend(&mut self, backend: FftBackend) {
+        if backend != self.fft_backend {
+            log::info!("Computing spectra with {}", backend);
+            self.fft_backend = backend;
+            self.fft = backend.create();
+        }
+    }
+
+    fn set_trace_palette(&mut self, palette: TracePalette) {
+        self.spectrogram.palette = palette;
+        self.response.palette = palette;
+    }
+
+    fn set_display_style(&mut self, style: DisplayStyle) {
+        self.display_style = style;
+        self.spectrogram.style = style;
+        self.waterfall.style = style;
+        self.waveform.style = style;
+        self.response.style = style;
+    }
+
+    fn set_meter_times(&mut self, attack: f32, release: f32) {
+        self.rms_meter.set_times(attack, release);
+        self.peak_meter.set_times(attack, release);
+    }
+
+    fn update_meters(&mut self, mono: &[f32]) {
+        for sample in mono {
+            self.rms_meter.process(sample * sample);
+            self.peak_meter.process(sample.abs());
+        }
+
+        if self.crest_factor {
+            self.crest_factor_db = match (analysis::crest_factor_db(mono), self.crest_factor_db) {
+                (Some(current), Some(previous)) => Some(self.crest_smoothing * previous + (1.0 - self.crest_smoothing) * current),
+                (current, _) => current
+            };
+        }
+    }
+
+    fn set_decimation(&mut self, enabled: bool) {
+        self.decimator = if enabled {
+            let factor = analysis::decimation_factor(self.sample_rate, MAX_FREQUENCY);
+            log::info!("Decimating analysis input by {}", factor);
+            Some(Decimator::new(factor))
+        } else {
+            None
+        };
+
+        // samples collected at the previous rate would distort the next frame
+        self.spectrogram.current_buf.clear();
+        self.reset_second_channel();
+        if let Some(welch) = &mut self.welch {
+            welch.reset();
+        }
+        self.spectrogram.reset_average();
+        self.set_band_width(self.spectrogram.band_width);
+        self.update_fft_size();
+    }
+
+    fn set_fft_window(&mut self, window_ms: f32) {
+        self.fft_window_ms = window_ms.clamp(MIN_FFT_WINDOW_MS, MAX_FFT_WINDOW_MS);
+        self.update_fft_size();
+    }
+
+    /// Derives the FFT size from the window length again, e.g. after the sample rate or the
+    /// decimation changed.
+    fn update_fft_size(&mut self) {
+        let fft_size = analysis::fft_size(self.fft_window_ms, self.analysis_sample_rate());
+
+        if fft_size == self.fft_size {
+            return;
+        }
+
+        log::info!("Analyzing {} ms as a {} point FFT", self.fft_window_ms, fft_size);
+        self.fft_size = fft_size;
+
+        // the sweep and the collected frames rely on the previous size
+        if self.sweep.is_some() {
+            log::warn!("The FFT size changed, stopping the response measurement");
+            self.stop_sweep();
+        }
+        self.spectrogram.current_buf.clear();
+        self.reset_second_channel();
+        if let Some(welch) = &mut self.welch {
+            welch.reset();
+        }
+        self.spectrogram.reset_average();
+    }
+
+    fn set_full_spectrum(&mut self, enabled: bool) {
+        self.full_spectrum = enabled;
+
+        // decimation would cut the spectrum down to the default range again
+        if enabled && self.decimator.is_some() {
+            self.set_decimation(false);
+        }
+
+        self.set_band_width(self.spectrogram.band_width);
+        self.spectrogram.reset_average();
+    }
+
+    /// Upper end of the analyzed range.
+    fn max_frequency(&self) -> f32 {
+        if self.full_spectrum {
+            self.analysis_sample_rate() as f32 / 2.0
+        } else {
+            MAX_FREQUENCY
+        }
+    }
+
+    fn set_low_cut(&mut self, enabled: bool, cutoff: f32) {
+        self.low_cut_hz = cutoff.clamp(MIN_LOW_CUT_HZ, MAX_LOW_CUT_HZ);
+        self.low_cut = enabled.then(|| HighPass::new(self.low_cut_hz, self.sample_rate));
+        self.reset_second_channel();
+    }
+
+    fn set_silence_gate(&mut self, enabled: bool) {
+        self.silence_gate = enabled;
+        // the blanked display would otherwise stay until the next frame
+        self.spectrogram.silent = false;
+    }
+
+    fn set_band_width(&mut self, band_width: Option<BandWidth>) {
+        self.spectrogram.band_width = band_width;
+        self.spectrogram.bands = band_width
+            .map(|width| analysis::bands(width, self.max_frequency()))
+            .unwrap_or_default();
+        self.spectrogram.band_levels.clear();
+    }
+
+    fn analysis_sample_rate(&self) -> u32 {
+        let factor = self.decimator.as_ref().map(Decimator::factor).unwrap_or(1);
+
+        self.sample_rate / factor as u32
+    }
+
+    fn frequency_limit(&self) -> FrequencyLimit {
+        // the overview needs the spectrum up to Nyquist
+        if self.split_view || self.full_spectrum { FrequencyLimit::All } else { FrequencyLimit::Max(MAX_FREQUENCY) }
+    }
+
+    /// Width of one FFT bin at the analysis sample rate.
+    fn frequency_resolution(&self) -> f32 {
+        self.analysis_sample_rate() as f32 / self.fft_size as f32
+    }
+
+    /// Padded frames would be counted like complete ones by the averages, Welch's method and the
+    /// response measurement, so padding is off while one of them runs.
+    fn set_zero_padding(&mut self, enabled: bool) {
+        self.zero_padding = enabled;
+        self.padded_fill = None;
+    }
+
+    fn padding_possible(&self) -> bool {
+        self.welch.is_none() && self.sweep.is_none() && !self.spectrogram.averaging && !self.spectrogram.leq
+    }
+
+    /// Whether queued windows may be skipped. The response measurement, averaging, Leq, the
+    /// waterfall and the analysis log need every window, skipping would compress their time. A
+    /// freeze on clip needs the window with the clip, which may not be the latest.
+    fn coalescing_possible(&self) -> bool {
+        self.sweep.is_none() && !self.spectrogram.averaging && !self.spectrogram.leq && !self.show_waterfall
+            && self.analysis_log.is_none() && !self.freeze_on_clip
+    }
+
+    fn update_state(&mut self, packet: &mut PacketType) {
+        if packet.is_empty() {
+            return;
+        }
+
+        if let Some(low_cut) = &mut self.low_cut {
+            low_cut.process(packet);
+        }
+
+        if self.show_scope {
+            self.waveform.push(packet);
+        }
+
+        if let Some(decimator) = &mut self.decimator {
+            *packet = decimator.process(packet);
+        }
+
+        // the frames are analyzed in place, the buffer keeps its allocation across ticks
+        let mut buffer = std::mem::take(&mut self.spectrogram.current_buf);
+        buffer.append(packet);
+
+        // only complete frames are analyzed, the rest waits for the next packet
+        let complete = buffer.len() / self.fft_size;
+        let max_queued = if self.coalescing_possible() { self.max_queued_frames } else { 0 };
+        let (skipped, complete_frames) = analysis::queued_frames(&buffer, self.fft_size, max_queued);
+
+        if skipped > 0 {
+            log::debug!("Skipped {} queued FFT windows", skipped);
+            self.skipped_frames += skipped;
+            self.spectrogram.user_data += skipped * self.fft_size;
+            self.warmup.skip(skipped);
+        }
+
+        // while no complete window arrived, the collected part is shown padded with zeros. Its
+        // samples are analyzed again with the complete window, so padded frames are only displayed
+        // and left out of every measurement.
+        let fill = (buffer.len() - complete * self.fft_size) as f32 / self.fft_size as f32;
+        let padded = self.zero_padding && complete == 0 && fill >= self.pad_min_fill && self.padding_possible();
+        let padded_frame = padded.then(|| analysis::zero_padded_frame(&buffer, self.fft_size));
+
+        if padded {
+            self.padded_fill = Some(fill);
+        } else if complete > 0 {
+            self.padded_fill = None;
+        }
+
+        let frames = complete_frames.chain(padded_frame.as_deref());
+
+        for current_packet in frames {
+            if padded {
+                if !self.warmup.is_primed() {
+                    continue;
+                }
+            } else {
+                self.spectrogram.user_data += self.fft_size;
+
+                if !self.warmup.next_frame() {
+                    continue;
+                }
+            }
+
+            let frequency_limit = self.frequency_limit();
+            let sample_rate = self.analysis_sample_rate();
+            // a padded frame is only displayed, the hooks are handed the spectra of complete frames
+            let result = if padded {
+                self.fft.spectrum(current_packet, sample_rate, frequency_limit)
+            } else {
+                analysis::analyze_frame(self.fft.as_mut(), self.welch.as_mut(), current_packet, sample_rate, frequency_limit)
+            };
+
+            // the frame is skipped and the previous spectrum stays on screen
+            let spectrum_points = match result {
+                Ok(spectrum_points) => spectrum_points,
+                Err(error) => {
+                    let message = error.to_string();
+
+                    // the same error tends to repeat for every frame
+                    if self.error_message.as_ref() != Some(&message) {
+                        log::error!("{}", message);
+                        self.error_message = Some(message);
+                    }
+                    continue;
+                }
+            };
+
+            let points: Vec<(i32, f32)> = spectrum_points
+                .iter()
+                .map(|(freq, amp)| (*freq as i32, *amp))
+                .collect();
+
+            // the gate only blanks the display, the measurements still see every frame
+            let silent = self.silence_gate && analysis::is_silent(current_packet, self.silence_gate_db);
+
+            if let Some(detector) = self.feedback_detector.as_mut().filter(|_| !padded) {
+                let detected = detector.process(&spectrum_points);
+
+                if !detected.is_empty() && detected != self.spectrogram.feedback_freqs {
+                    log::warn!("Possible feedback at {:?} Hz", detected);
+                }
+                self.spectrogram.feedback_freqs = detected;
+            }
+
+            // the peak and the bands only cover the analyzed range, even when the overview is shown
+            let max_frequency = self.max_frequency();
+            let analyzed = &spectrum_points[..spectrum_points.partition_point(|(freq, _)| *freq <= max_frequency)];
+
+            if !silent {
+                if self.spectrogram.band_width.is_some() {
+                    self.spectrogram.band_levels = analysis::band_levels(analyzed, &self.spectrogram.bands);
+                }
+
+                let searched = analysis::without_low_bins(analyzed, if self.ignore_dc { self.ignored_low_bins } else { 0 });
+                (self.spectrogram.peak_freq, self.spectrogram.peak_amp) = analysis::peak(searched).unwrap_or((0.0, 0.0));
+
+                let threshold = 10f32.powf(self.peak_threshold_db / 20.0);
+                if let Some(stats) = self.session_stats.as_mut().filter(|_| !padded) {
+                    if self.spectrogram.peak_amp >= threshold {
+                        stats.add_peak(self.spectrogram.peak_freq, self.frequency_resolution());
+                    }
+                }
+                if !padded {
+                    self.spectrogram.hold_peak_freq(threshold);
+                    self.spectrogram.follow(threshold);
+                }
+
+                if self.peak_table {
+                    let separation = PEAK_SEPARATION_BINS * self.frequency_resolution();
+                    self.spectrogram.peaks = analysis::top_peaks(searched, self.peak_count, threshold, separation);
+                }
+            }
+
+            if let Some(sweep) = &mut self.sweep {
+                // the sweep only covers the default range
+                sweep.add_captured(&spectrum_points[..spectrum_points.partition_point(|(freq, _)| *freq <= MAX_FREQUENCY)]);
+            }
+            if self.spectrogram.leq {
+                let frame_duration = self.fft_size as f64 / self.analysis_sample_rate() as f64;
+                self.spectrogram.accumulate_energy(&points, frame_duration);
+            }
+
+            if silent {
+                self.spectrogram.show_silence(&points);
+            } else {
+                self.spectrogram.silent = false;
+                self.spectrogram.persist_frame();
+                self.spectrogram.freq_data = if self.spectrogram.averaging {
+                    self.spectrogram.accumulate(points)
+                } else {
+                    points
+                };
+                self.spectrogram.update_peak_caps();
+            }
+
+            if padded {
+                continue;
+            }
+
+            if self.show_waterfall {
+                self.waterfall.row_duration = self.waterfall_row_duration();
+                self.waterfall.push(&self.spectrogram.freq_data);
+            }
+
+            self.log_analysis();
+
+            if self.single_shot {
+                log::info!("Single shot captured, peak at {:.1} Hz", self.spectrogram.peak_freq);
+                self.stop_streaming();
+                break;
+            }
+        }
+
+        buffer.drain(..complete * self.fft_size);
+        self.spectrogram.current_buf = buffer;
+    }
+}
+
+impl Application for Audia {
+    type Executor = executor::Default;
+    type Message = UIMessage;
+    type Theme = Theme;
+    type Flags = UIParams;
+
+    fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
+        let audio_system = flags.audio_system;
+        let test_signal = flags.test_signal.or_else(|| {
+            (flags.settings.input_device.as_deref() == Some(TEST_SIGNAL_DEVICE)).then_some(TestSignalKind::Sine)
+        });
+        let gain_range_db = flags.gain_range_db;
+        let settings_modified = settings::modified_time(&flags.settings_path);
+
+        let (control, control_error) = match flags.control_port.map(|port| control::spawn_server(&format!("127.0.0.1:{port}"))) {
+            Some(Ok((_address, requests))) => (Some(requests), None),
+            Some(Err(error)) => {
+                log::error!("{}", error);
+                (None, Some(error.to_string()))
+            },
+            None => (None, None)
+        };
+
+        let (tray, tray_error) = if flags.tray {
+            match Tray::spawn() {
+                Ok(tray) => (Some(tray), None),
+                Err(error) => {
+                    log::error!("{}", error);
+                    (None, Some(error.to_string()))
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        let mut audia = Self {
+            spectrogram: Spectrogram::new(),
+            current_stream: None,
+            single_shot: false,
+            audio_system,
+            input_devices: vec![],
+            output_devices: vec![],
+            device_watcher: None,
+            decimator: None,
+            fft_window_ms: settings::DEFAULT_FFT_WINDOW_MS,
+            fft_size: analysis::fft_size(settings::DEFAULT_FFT_WINDOW_MS, DEFAULT_SAMPLE_RATE),
+            low_cut: None,
+            low_cut_hz: settings::DEFAULT_LOW_CUT_HZ,
+            silence_gate: false,
+            silence_gate_db: settings::DEFAULT_SILENCE_GATE_DB,
+            error_message: control_error.or(tray_error),
+            analyze: true,
+            freeze_on_clip: false,
+            frozen_on_clip: false,
+            pending_clip: PendingClip::default(),
+            test_signal,
+            use_raw_input: flags.raw_input.is_some(),
+            raw_input: flags.raw_input,
+            record_to_file: false,
+            record_analysis: false,
+            analysis_log: None,
+            record_format: WavSampleFormat::default(),
+            recorder: None,
+            channel_change_policy: ChannelChangePolicy::default(),
+            carried_recording: None,
+            recording_notice: None,
+            recording_gate: None,
+            max_recording: None,
+            stop_streaming_at_limit: false,
+            recording_limit_reached: false,
+            gain_db: 0.0,
+            gain_range_db,
+            channel_weights: vec![],
+            channel_weights_input: String::new(),
+            channel_view: ChannelView::default(),
+            second_channel: None,
+            monitoring: false,
+            monitor: None,
+            mixed: PacketType::new(),
+            second_mixed: PacketType::new(),
+            monitor_retry: None,
+            monitor_delay_ms: 0.0,
+            limiter: Arc::new(LimiterControl::default()),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            split_view: false,
+            full_spectrum: false,
+            waterfall: Waterfall::new(waterfall::DEFAULT_HISTORY_ROWS),
+            show_waterfall: false,
+            sessions: WaterfallSession::list(SESSION_DIR),
+            current_session: None,
+            waveform: Waveform::new(waveform::DEFAULT_WINDOW, DEFAULT_SAMPLE_RATE),
+            show_scope: false,
+            sweep: None,
+            response: ResponseView::new(MAX_FREQUENCY),
+            show_response: false,
+            rms_meter: EnvelopeFollower::new(DEFAULT_METER_ATTACK_MS, DEFAULT_METER_RELEASE_MS, DEFAULT_SAMPLE_RATE),
+            peak_meter: EnvelopeFollower::new(DEFAULT_METER_ATTACK_MS, DEFAULT_METER_RELEASE_MS, DEFAULT_SAMPLE_RATE),
+            crest_factor: false,
+            crest_factor_db: None,
+            crest_smoothing: settings::DEFAULT_CREST_SMOOTHING,
+            peak_table: false,
+            peak_count: settings::DEFAULT_PEAK_COUNT,
+            peak_threshold_db: settings::DEFAULT_PEAK_THRESHOLD_DB,
+            ignore_dc: true,
+            ignored_low_bins: settings::DEFAULT_IGNORED_LOW_BINS,
+            feedback_detector: None,
+            welch: None,
+            fft_backend: FftBackend::default(),
+            fft: FftBackend::default().create(),
+            display_style: DisplayStyle::default(),
+            antialiasing: false,
+            window_size: flags.settings.window_size,
+            display_fps: DEFAULT_DISPLAY_FPS,
+            power_saving: false,
+            focused: true,
+            log_snapshots: false,
+            log_snapshot_interval_s: settings::DEFAULT_LOG_SNAPSHOT_INTERVAL_S,
+            auto_save: false,
+            auto_save_interval_s: settings::DEFAULT_AUTO_SAVE_INTERVAL_S,
+            auto_save_dir: String::from(settings::DEFAULT_AUTO_SAVE_DIR),
+            saving_chart: false,
+            warmup: Warmup::new(settings::DEFAULT_WARMUP_FRAMES),
+            max_queued_frames: settings::DEFAULT_MAX_QUEUED_FRAMES,
+            skipped_frames: 0,
+            zero_padding: false,
+            pad_min_fill: settings::DEFAULT_PAD_MIN_FILL,
+            padded_fill: None,
+            settings_path: flags.settings_path,
+            settings_modified,
+            preset_name: String::new(),
+            target_freq: None,
+            target_freq_input: String::new(),
+            snapshot_label: String::new(),
+            presets: Preset::list(PRESET_DIR),
+            current_preset: None,
+            control,
+            tray,
+            window_hidden: false,
+            autostart: flags.settings.autostart,
+            probe_devices: flags.settings.probe_devices,
+            host_preference: flags.settings.host_preference.clone(),
+            clipping: false,
+            session_stats: None,
+            session_summary: None,
+            overload_until: None,
+            overload_hold: Duration::from_secs_f32(settings::DEFAULT_OVERLOAD_HOLD_S)
+        };
+        audia.apply_analysis_settings(&flags.settings);
+        audia.watch_devices();
+
+        let command = if !(flags.autostart || audia.autostart) {
+            Command::none()
+        } else if audia.current_input_name().is_some() {
+            log::info!("Starting the stream on launch");
+            Command::perform(async {}, |()| UIMessage::StartStreaming)
+        } else {
+            log::warn!("Not starting the stream on launch, no input device is selected");
+            audia.error_message = Some(String::from("Choose an input device to start streaming"));
+            Command::none()
+        };
+
+        (audia, command)
+    }
+
+    fn title(&self) -> String {
+        let mut title = String::from("Audia");
+
+        if let Some(device) = self.current_input_name() {
+            title.push_str(" — ");
+            title.push_str(device.as_str());
+        }
+
+        // streaming alone doesn't write anything, only a running WAV recording does
+        if self.recorder.is_some() {
+            title.push_str(" [REC]");
+        } else if self.current_stream.is_some() {
+            title.push_str(" [LIVE]");
+        }
+
+        title
+    }
+
+    fn theme(&self) -> Theme {
+        self.display_style.theme()
+    }
+
+    fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
+        // new audio alone only shows up with the next redraw tick, anything else is shown right away
+        let redraw = !matches!(message, UIMessage::StreamTick | UIMessage::ControlRequested(_));
+
+        match message {
+            UIMessage::HostChanged(new_host) => self.use_host(AudioHostName::from(new_host.as_str())),
+            UIMessage::InputDeviceChanged(new_device) => {
+                if let Err(error) = self.use_input_device(InputDeviceName::from(new_device.as_str())) {
+                    log::error!("{}", error);
+                    self.error_message = Some(error.to_string());
+                }
+            },
+            UIMessage::ShareModeChanged(share_mode) => self.audio_system.engine.use_share_mode(share_mode),
+            UIMessage::ChannelRequestChanged(request) => self.audio_system.engine.use_channel_request(request),
+            UIMessage::BufferRequestChanged(request) => self.audio_system.engine.use_buffer_request(request),
+            UIMessage::SampleFormatChanged(format) => self.audio_system.engine.use_sample_format(format),
+            UIMessage::ChannelWeightsChanged(input) => self.set_channel_weights(input),
+            UIMessage::ChannelViewChanged(view) => self.set_channel_view(view),
+            UIMessage::StartStreaming => self.start_streaming(),
+            UIMessage::AutostartToggled(enabled) => self.autostart = enabled,
+            UIMessage::ProbeDevicesToggled(enabled) => self.probe_devices = enabled,
+            UIMessage::StopStreaming => self.stop_streaming(),
+            UIMessage::SingleShot => self.start_single_shot(),
+            UIMessage::StreamTick => self.stream_update(),
+            UIMessage::RedrawTick => self.show_tray_status(),
+            UIMessage::DisplayFpsChanged(fps) => self.display_fps = fps as u32,
+            UIMessage::PowerSavingToggled(enabled) => self.power_saving = enabled,
+            UIMessage::WindowFocusChanged(focused) => {
+                if self.power_saving && focused != self.focused {
+                    log::info!("Window {}, {} the redraw rate", if focused { "focused" } else { "unfocused" }, if focused { "restoring" } else { "lowering" });
+                }
+                self.focused = focused;
+            },
+            UIMessage::WindowResized(width, height) => self.window_size = (width, height),
+            UIMessage::WindowCloseRequested => return self.minimize_to_tray(true),
+            UIMessage::WindowMinimized => return self.minimize_to_tray(false),
+            UIMessage::LogSnapshotsToggled(enabled) => self.log_snapshots = enabled,
+            UIMessage::LogSnapshotIntervalChanged(seconds) => self.log_snapshot_interval_s = seconds,
+            UIMessage::LogSnapshot => self.log_snapshot(),
+            UIMessage::SaveChartPng => return self.save_chart(PathBuf::from(".")),
+            UIMessage::AutoSaveToggled(enabled) => self.auto_save = enabled,
+            UIMessage::AutoSaveIntervalChanged(seconds) => self.auto_save_interval_s = seconds,
+            UIMessage::AutoSaveDirChanged(dir) => self.auto_save_dir = dir,
+            UIMessage::AutoSaveTick => return self.save_chart(PathBuf::from(&self.auto_save_dir)),
+            UIMessage::ChartSaved(result) => {
+                self.saving_chart = false;
+                match result {
+                    Ok(path) => log::info!("Saved the chart to {}", path),
+                    Err(error) => {
+                        log::error!("{}", error);
+                        self.error_message = Some(error);
+                    }
+                }
+            },
+            UIMessage::OverloadHoldChanged(seconds) => self.overload_hold = Duration::from_secs_f32(seconds),
+            UIMessage::WarmupFramesChanged(frames) => self.warmup.set_frames(frames as usize),
+            UIMessage::MaxQueuedFramesChanged(frames) => self.max_queued_frames = frames as usize,
+            UIMessage::DecimationToggled(enabled) => self.set_decimation(enabled),
+            UIMessage::FftWindowChanged(window_ms) => self.set_fft_window(window_ms),
+            UIMessage::ZeroPaddingToggled(enabled) => self.set_zero_padding(enabled),
+            UIMessage::PadMinFillChanged(fill) => self.pad_min_fill = fill,
+            UIMessage::LowCutToggled(enabled) => self.set_low_cut(enabled, self.low_cut_hz),
+            UIMessage::LowCutChanged(cutoff) => self.set_low_cut(self.low_cut.is_some(), cutoff),
+            UIMessage::SilenceGateToggled(enabled) => self.set_silence_gate(enabled),
+            UIMessage::SilenceGateChanged(threshold_db) => self.silence_gate_db = threshold_db,
+            UIMessage::AnalysisToggled(enabled) => self.set_analysis(enabled),
+            UIMessage::FreezeOnClipToggled(enabled) => {
+                self.freeze_on_clip = enabled;
+                self.pending_clip.clear();
+            }
+            UIMessage::TestSignalChanged(kind) => self.use_test_signal(kind),
+            UIMessage::UnitModeChanged(mode) => self.spectrogram.unit_mode = mode,
+            // a dragged limit stops short of the other one
+            UIMessage::DbFloorChanged(floor) => self.spectrogram.db_range = self.spectrogram.db_range.with_floor(floor),
+            UIMessage::DbCeilingChanged(ceiling) => self.spectrogram.db_range = self.spectrogram.db_range.with_ceiling(ceiling),
+            UIMessage::XAxisUnitChanged(unit) => self.spectrogram.x_unit = unit,
+            UIMessage::AveragingToggled(enabled) => {
+                self.spectrogram.averaging = enabled;
+                self.spectrogram.reset_average();
+            },
+            UIMessage::ResetAverage => self.spectrogram.reset_average(),
+            UIMessage::LeqToggled(enabled) => {
+                self.spectrogram.leq = enabled;
+                self.spectrogram.reset_energy();
+            },
+            UIMessage::ResetLeq => self.spectrogram.reset_energy(),
+            UIMessage::SnapshotLabelChanged(label) => self.snapshot_label = label,
+            UIMessage::TakeSnapshot => self.take_snapshot(),
+            UIMessage::ToggleSnapshot(index) => self.spectrogram.toggle_snapshot(index),
+            UIMessage::RemoveSnapshot(index) => self.spectrogram.remove_snapshot(index),
+            UIMessage::ClearSnapshots => self.spectrogram.clear_snapshots(),
+            UIMessage::ChartHover(freq) => self.spectrogram.hover_freq = freq,
+            UIMessage::MarkersToggled(enabled) => self.set_markers(enabled),
+            UIMessage::SetMarker(index, freq) => self.spectrogram.set_marker(index, freq),
+            UIMessage::ClearMarkers => self.spectrogram.clear_markers(),
+            UIMessage::RecordToFileToggled(enabled) => {
+                self.record_to_file = enabled;
+                self.recording_notice = None;
+            },
+            UIMessage::RecordAnalysisToggled(enabled) => self.record_analysis = enabled,
+            UIMessage::RecordFormatChanged(format) => self.record_format = format,
+            UIMessage::ChannelChangePolicyChanged(policy) => self.channel_change_policy = policy,
+            UIMessage::SoundActivationToggled(enabled) => self.set_sound_activation(enabled),
+            UIMessage::MaxRecordingToggled(enabled) => self.max_recording = enabled.then_some(DEFAULT_MAX_RECORDING),
+            UIMessage::MaxRecordingChanged(minutes) => self.max_recording = Some(Duration::from_secs_f32(minutes * 60.0)),
+            UIMessage::StopStreamingAtLimitToggled(enabled) => self.stop_streaming_at_limit = enabled,
+            UIMessage::RecordingLimitReached => {
+                if self.stop_streaming_at_limit {
+                    self.stop_streaming();
+                }
+            },
+            UIMessage::ActivationThresholdChanged(threshold) =>
+                self.update_activation_settings(|settings| settings.threshold_dbfs = threshold),
+            UIMessage::GainChanged(gain_db) =>
+                self.gain_db = gain_db.clamp(*self.gain_range_db.start(), *self.gain_range_db.end()),
+            UIMessage::MonitoringToggled(enabled) => self.set_monitoring(enabled),
+            UIMessage::LimiterToggled(enabled) => self.limiter.set_enabled(enabled),
+            UIMessage::LimiterThresholdChanged(threshold_db) => self.limiter.set_threshold_db(threshold_db),
+            UIMessage::MonitorDelayChanged(delay_ms) => self.set_monitor_delay(delay_ms),
+            UIMessage::SilenceTimeoutChanged(seconds) =>
+                self.update_activation_settings(|settings| settings.silence_timeout = Duration::from_secs_f32(seconds)),
+            UIMessage::ChartStyleChanged(style) => self.spectrogram.chart_style = style,
+            UIMessage::PeakCapDecayChanged(decay_db) => self.spectrogram.peak_cap_decay_db = decay_db,
+            UIMessage::TiltChanged(tilt) => self.spectrogram.tilt = tilt,
+            UIMessage::CustomTiltChanged(db_per_octave) => self.spectrogram.custom_tilt_db = db_per_octave,
+            UIMessage::LineWidthToggled(enabled) => {
+                self.spectrogram.line_width = enabled.then(|| self.display_style.line_width());
+            },
+            UIMessage::LineWidthChanged(width) => self.spectrogram.line_width = Some(width),
+            UIMessage::AntialiasingToggled(enabled) => {
+                self.antialiasing = enabled;
+                log::info!("Anti-aliasing {} after a restart", if enabled { "is on" } else { "is off" });
+            },
+            UIMessage::PersistenceFramesChanged(frames) => self.spectrogram.set_persistence_frames(frames),
+            UIMessage::PersistenceDecayChanged(decay) => self.spectrogram.persistence_decay = decay,
+            UIMessage::DisplayStyleChanged(style) => self.set_display_style(style),
+            UIMessage::TracePaletteChanged(palette) => self.set_trace_palette(palette),
+            UIMessage::ToggleSplitView => self.split_view = !self.split_view,
+            UIMessage::ToggleFollowPeak => self.spectrogram.follow_peak = !self.spectrogram.follow_peak,
+            UIMessage::FollowPeakSpanChanged(span) => self.spectrogram.follow_span = span,
+            UIMessage::FullSpectrumToggled(enabled) => self.set_full_spectrum(enabled),
+            UIMessage::MaxDisplayPointsChanged(points) => self.spectrogram.max_display_points = points,
+            UIMessage::PeakTableToggled(enabled) => self.set_peak_table(enabled),
+            UIMessage::PeakCountChanged(count) => self.peak_count = count as usize,
+            UIMessage::PeakThresholdChanged(threshold_db) => self.peak_threshold_db = threshold_db,
+            UIMessage::IgnoreDcToggled(enabled) => self.ignore_dc = enabled,
+            UIMessage::IgnoredLowBinsChanged(bins) => self.ignored_low_bins = bins as usize,
+            UIMessage::HoldPeakFreqMaxToggled(enabled) => {
+                self.spectrogram.hold_peak_freq_max = enabled;
+                self.spectrogram.reset_peak_freq_max();
+            },
+            UIMessage::ResetPeakFreqMax => self.spectrogram.reset_peak_freq_max(),
+            UIMessage::DismissSessionSummary => self.session_summary = None,
+            UIMessage::TargetFreqChanged(input) => self.set_target_freq(input),
+            UIMessage::WaterfallToggled(enabled) => {
+                self.show_waterfall = enabled;
+                self.waterfall.clear();
+            },
+            UIMessage::WaterfallSmoothingChanged(smoothing) => self.waterfall.smoothing = smoothing,
+            UIMessage::WaterfallFramesPerRowChanged(frames) => self.waterfall.frames_per_row = frames as usize,
+            UIMessage::WaterfallDirectionChanged(direction) => self.waterfall.direction = direction,
+            UIMessage::ExportWaterfallPng => self.export_waterfall(),
+            UIMessage::SaveWaterfallSession => self.save_waterfall_session(),
+            UIMessage::OpenWaterfallSession(name) => self.open_waterfall_session(name),
+            UIMessage::ScopeToggled(enabled) => {
+                self.show_scope = enabled;
+                self.waveform.clear();
+            },
+            UIMessage::ScopeWindowChanged(window) => self.waveform.set_window(window),
+            UIMessage::TriggerChanged(trigger) => self.waveform.trigger = trigger,
+            UIMessage::TriggerLevelChanged(level) => self.waveform.trigger_level = level,
+            UIMessage::MeasureResponse => self.start_response_measurement(),
+            UIMessage::ResponseToggled(enabled) => self.show_response = enabled,
+            UIMessage::StoreReferenceCurve => self.response.store_reference(),
+            UIMessage::ClearReferenceCurve => self.response.reference = None,
+            UIMessage::FeedbackDetectionToggled(enabled) => {
+                self.feedback_detector = enabled.then(|| FeedbackDetector::new(DEFAULT_FEEDBACK_SENSITIVITY_DB, DEFAULT_FEEDBACK_PERSISTENCE));
+                self.spectrogram.feedback_freqs.clear();
+            },
+            UIMessage::FeedbackSensitivityChanged(sensitivity_db) => {
+                if let Some(detector) = &mut self.feedback_detector {
+                    detector.sensitivity_db = sensitivity_db;
+                }
+            },
+            UIMessage::FeedbackPersistenceChanged(frames) => {
+                if let Some(detector) = &mut self.feedback_detector {
+                    detector.persistence = frames as usize;
+                }
+            },
+            UIMessage::FftBackendChanged(backend) => self.set_fft_backend(backend),
+            UIMessage::WelchToggled(enabled) => {
+                self.welch = enabled.then(|| Welch::new(DEFAULT_WELCH_SEGMENTS, DEFAULT_WELCH_OVERLAP));
+                self.spectrogram.reset_average();
+            },
+            UIMessage::WelchSegmentsChanged(segments) => {
+                if let Some(welch) = &mut self.welch {
+                    welch.segments = segments as usize;
+                }
+            },
+            UIMessage::WelchOverlapChanged(overlap) => {
+                if let Some(welch) = &mut self.welch {
+                    welch.overlap = overlap;
+                    // segments at the old spacing don't line up with the new ones
+                    welch.reset();
+                }
+            },
+            UIMessage::MeterAttackChanged(attack) => self.set_meter_times(attack, self.rms_meter.release()),
+            UIMessage::MeterReleaseChanged(release) => self.set_meter_times(self.rms_meter.attack(), release),
+            UIMessage::CrestFactorToggled(enabled) => {
+                self.crest_factor = enabled;
+                self.crest_factor_db = None;
+            },
+            UIMessage::CrestSmoothingChanged(smoothing) => self.crest_smoothing = smoothing,
+            UIMessage::SaveSettings => self.save_settings(),
+            UIMessage::ReloadSettings => self.reload_settings(),
+            UIMessage::PresetNameChanged(name) => self.preset_name = name,
+            UIMessage::SavePreset => self.save_preset(),
+            UIMessage::LoadPreset(name) => self.load_preset(name),
+            UIMessage::SettingsFileTick => {
+                let modified = settings::modified_time(&self.settings_path);
+
+                if modified.is_some() && modified != self.settings_modified {
+                    log::info!("Settings file changed, reloading");
+                    self.reload_settings();
+                }
+            },
+            UIMessage::ControlRequested(request) => self.handle_control_request(request),
+            UIMessage::TrayMenu(action) => return self.handle_tray_action(action),
+            UIMessage::DevicesChanged => self.refresh_devices(),
+            UIMessage::BandAnalyzerToggled(enabled) => self.set_band_width(enabled.then(BandWidth::default)),
+            UIMessage::BandWidthChanged(width) => self.set_band_width(Some(width)),
+            _ => {
+                log::info!("Unknown event: {:?}", message);
+            }
+        }
+
+        if redraw {
+            self.spectrogram.redraw();
+            self.waterfall.redraw();
+            self.waveform.redraw();
+            self.response.redraw();
+        }
+
+        if std::mem::take(&mut self.recording_limit_reached) {
+            return Command::perform(async {}, |()| UIMessage::RecordingLimitReached);
+        }
+
+        Command::none()
+    }
+
+    fn view(&self) -> Element<Self::Message> {
+        let style = self.display_style;
+        let mut input_devices = self.input_devices.clone();
+        input_devices.push(InputDeviceName::from(TEST_SIGNAL_DEVICE));
+
+        if self.raw_input.is_some() {
+            input_devices.push(InputDeviceName::from(RAW_INPUT_DEVICE));
+        }
+
+        let input_choices: Vec<InputChoice> = input_devices.into_iter().map(InputChoice).collect();
+        let current_input_device = self.current_input_name().map(InputChoice);
+
+        let mut input_row = Row::new()
+            .spacing(5)
+            .push(style.text("Input device"))
+            .push(
+                style.pick_list(
+                    input_choices,
+                    current_input_device,
+                    |choice| UIMessage::InputDeviceChanged(choice.0))
+                    .placeholder("Choose an input device"));
+
+        if self.device_watcher.is_none() {
+            // without a watcher new devices only show up after a refresh
+            input_row = input_row.push(style.button("Refresh devices").on_press(UIMessage::DevicesChanged));
+        }
+
+        if let Some(kind) = self.test_signal {
+            input_row = input_row.push(style.pick_list(TestSignalKind::ALL.to_vec(), Some(kind), UIMessage::TestSignalChanged));
+        }
+
+        let stream_button = if self.current_stream.is_none() {
+            style.button("Start streaming").on_press(UIMessage::StartStreaming)
+        } else {
+            style.button("Stop streaming").on_press(UIMessage::StopStreaming)
+        };
+
+        let single_shot_button = match (self.single_shot, self.current_stream.is_none()) {
+            (true, _) => style.button("Capturing..."),
+            (false, true) => style.button("Single shot").on_press(UIMessage::SingleShot),
+            (false, false) => style.button("Single shot")
+        };
+
+        let mut analyze_row = Row::new()
+            .spacing(5)
+            .align_items(Alignment::Center)
+            .push(style.checkbox("Analyze", self.analyze, UIMessage::AnalysisToggled))
+            .push(style.checkbox("Freeze on clip", self.freeze_on_clip, UIMessage::FreezeOnClipToggled));
+
+        if self.frozen_on_clip {
+            analyze_row = analyze_row
+                .push(style.text("Frozen on a clip").style(style.theme().palette().danger))
+                .push(style.button("Resume").on_press(UIMessage::AnalysisToggled(true)));
+        }
+
+        let mut padding_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Zero-pad partial windows", self.zero_padding, UIMessage::ZeroPaddingToggled));
+
+        if self.zero_padding {
+            padding_row = padding_row
+                .push(style.text(format!("from {:.0}% collected", self.pad_min_fill * 100.0)))
+                .push(slider(MIN_PAD_FILL..=MAX_PAD_FILL, self.pad_min_fill, UIMessage::PadMinFillChanged).step(0.05).width(120))
+                .push(style.text(match self.padded_fill {
+                    Some(fill) => format!("Padded: showing {:.0}% of the window at lower resolution", fill * 100.0),
+                    None if !self.padding_possible() => String::from("Off while averaging, Leq, Welch or a sweep runs"),
+                    None => String::new()
+                }));
+        }
+
+        let mut recording_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Record to WAV", self.record_to_file, UIMessage::RecordToFileToggled))
+            .push(style.checkbox("with analysis CSV", self.record_analysis, UIMessage::RecordAnalysisToggled))
+            .push(style.pick_list(WavSampleFormat::ALL.to_vec(), Some(self.record_format), UIMessage::RecordFormatChanged))
+            .push(style.pick_list(ChannelChangePolicy::ALL.to_vec(), Some(self.channel_change_policy), UIMessage::ChannelChangePolicyChanged))
+            .push(style.checkbox("Sound activated", self.recording_gate.is_some(), UIMessage::SoundActivationToggled));
+
+        if let Some(gate) = &self.recording_gate {
+            recording_row = recording_row
+                .push(style.text(format!("Threshold {:.0} dBFS", gate.settings.threshold_dbfs)))
+                .push(slider(-80.0..=0.0, gate.settings.threshold_dbfs, UIMessage::ActivationThresholdChanged).width(120))
+                .push(style.text(format!("Stop after {:.1}s of silence", gate.settings.silence_timeout.as_secs_f32())))
+                .push(slider(0.5..=30.0, gate.settings.silence_timeout.as_secs_f32(), UIMessage::SilenceTimeoutChanged).step(0.5).width(120));
+        }
+
+        recording_row = recording_row.push(style.checkbox("Limit duration", self.max_recording.is_some(), UIMessage::MaxRecordingToggled));
+
+        if let Some(max_recording) = self.max_recording {
+            let minutes = max_recording.as_secs_f32() / 60.0;
+
+            recording_row = recording_row
+                .push(style.text(format!("{:.0} min", minutes)))
+                .push(slider(1.0..=MAX_RECORDING_MINUTES, minutes, UIMessage::MaxRecordingChanged).step(1.0).width(120))
+                .push(style.checkbox("then stop streaming", self.stop_streaming_at_limit, UIMessage::StopStreamingAtLimitToggled));
+
+            if let Some(remaining) = self.recorder.as_ref().and_then(WavRecorder::remaining_frames) {
+                let seconds = remaining / self.sample_rate.max(1);
+                recording_row = recording_row.push(style.text(format!("{}:{:02} left", seconds / 60, seconds % 60)));
+            }
+        }
+
+        if let Some(notice) = &self.recording_notice {
+            recording_row = recording_row.push(style.text(notice));
+        }
+
+        let rms_level = self.rms_meter.level().sqrt();
+
+        let buffer_info = match self.current_stream.as_ref().map(|stream| (stream.buffer_frames(), stream.buffer_latency())) {
+            Some((Some(frames), Some(latency))) => format!(", buffer: {} frames ({:.1} ms)", frames, latency.as_secs_f64() * 1000.0),
+            Some(_) => String::from(", buffer: device default"),
+            None => String::new()
+        };
+
+        let nyquist = (self.analysis_sample_rate() / 2) as i32;
+
+        let mut charts = Column::new()
+            .spacing(10)
+            .height(Length::Fill)
+            .push(if self.show_response {
+                self.response.view()
+            } else if self.show_scope {
+                self.waveform.view()
+            } else if self.full_spectrum {
+                self.spectrogram.view_full(nyquist)
+            } else {
+                self.spectrogram.view()
+            });
+
+        // the full spectrum already shows what the overview would
+        if self.split_view && !self.full_spectrum && !self.show_scope && !self.show_response && self.spectrogram.band_width.is_none() {
+            charts = charts.push(self.spectrogram.view_overview(nyquist));
+        }
+
+        if self.show_waterfall {
+            charts = charts.push(self.waterfall.view());
+        }
+
+        let mut chart_area = Row::new()
+            .spacing(10)
+            .height(Length::Fill)
+            .push(charts.width(Length::Fill));
+
+        if self.peak_table {
+            let mut table = Column::new()
+                .spacing(2)
+                .width(PEAK_TABLE_WIDTH)
+                .push(Row::new()
+                    .spacing(10)
+                    .push(style.text("#").width(30))
+                    .push(style.text("Frequency").width(110))
+                    .push(style.text("Level")));
+
+            for (rank, (freq, amp)) in self.spectrogram.peaks.iter().enumerate() {
+                table = table.push(Row::new()
+                    .spacing(10)
+                    .push(style.text(rank + 1).width(30))
+                    .push(style.text(format!("{freq:.1} Hz")).width(110))
+                    .push(style.text(self.spectrogram.unit_mode.format(*amp))));
+            }
+
+            chart_area = chart_area.push(table);
+        }
+
+        let mut scope_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Oscilloscope", self.show_scope, UIMessage::ScopeToggled));
+
+        if self.show_scope {
+            scope_row = scope_row
+                .push(style.pick_list(WINDOW_SIZES.to_vec(), Some(self.waveform.window()), UIMessage::ScopeWindowChanged))
+                .push(style.text("samples, trigger"))
+                .push(style.pick_list(Trigger::ALL.to_vec(), Some(self.waveform.trigger), UIMessage::TriggerChanged))
+                .push(style.text(format!("Level {:+.2}", self.waveform.trigger_level)))
+                .push(slider(-1.0..=1.0, self.waveform.trigger_level, UIMessage::TriggerLevelChanged).step(0.01).width(120));
+        }
+
+        let mut response_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Frequency response", self.show_response, UIMessage::ResponseToggled));
+
+        response_row = if let Some(sweep) = &self.sweep {
+            response_row
+                .push(style.text("Measuring"))
+                .push(progress_bar(0.0..=1.0, sweep.progress(self.sample_rate)).width(150))
+        } else {
+            response_row.push(style.button("Measure response").on_press(UIMessage::MeasureResponse))
+        };
+
+        response_row = response_row
+            .push(style.button("Store as reference").on_press(UIMessage::StoreReferenceCurve))
+            .push(style.button("Clear reference").on_press(UIMessage::ClearReferenceCurve));
+
+        let mut summary_row = Row::new().spacing(5);
+
+        if let Some(summary) = &self.session_summary {
+            summary_row = summary_row
+                .push(style.text(format!("Last session: {summary}")))
+                .push(style.button("Dismiss").on_press(UIMessage::DismissSessionSummary));
+        }
+
+        let mut snapshot_row = Row::new()
+            .spacing(5)
+            .push(text_input("Snapshot label", &self.snapshot_label).size(style.text_size())
+                .on_input(UIMessage::SnapshotLabelChanged)
+                .on_submit(UIMessage::TakeSnapshot)
+                .width(150))
+            .push(style.button("Take snapshot").on_press(UIMessage::TakeSnapshot));
+
+        if !self.spectrogram.snapshots.is_empty() {
+            snapshot_row = snapshot_row.push(style.button("Clear snapshots").on_press(UIMessage::ClearSnapshots));
+        }
+
+        for (index, snapshot) in self.spectrogram.snapshots.iter().enumerate() {
+            let seconds = snapshot.taken_at_ms / 1000 % 86_400;
+            snapshot_row = snapshot_row
+                .push(style.checkbox(&format!("{} ({:02}:{:02}:{:02} UTC)", snapshot.label, seconds / 3600, seconds / 60 % 60, seconds % 60),
+                    snapshot.visible, move |_| UIMessage::ToggleSnapshot(index)))
+                .push(style.button("Remove").on_press(UIMessage::RemoveSnapshot(index)));
+        }
+
+        let mut peak_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Peak table", self.peak_table, UIMessage::PeakTableToggled));
+
+        if self.peak_table {
+            peak_row = peak_row
+                .push(style.text(format!("Top {}", self.peak_count)))
+                .push(slider(1..=MAX_PEAK_COUNT as u8, self.peak_count as u8, UIMessage::PeakCountChanged).width(100));
+        }
+
+        peak_row = peak_row
+            .push(style.checkbox("Ignore DC", self.ignore_dc, UIMessage::IgnoreDcToggled));
+
+        if self.ignore_dc {
+            peak_row = peak_row
+                .push(style.text(format!("lowest {} bins", self.ignored_low_bins)))
+                .push(slider(1..=MAX_IGNORED_LOW_BINS as u8, self.ignored_low_bins as u8, UIMessage::IgnoredLowBinsChanged).width(80));
+        }
+
+        peak_row = peak_row
+            .push(style.checkbox("Hold max frequency", self.spectrogram.hold_peak_freq_max, UIMessage::HoldPeakFreqMaxToggled));
+
+        if self.spectrogram.hold_peak_freq_max {
+            peak_row = peak_row
+                .push(style.button("Reset max").on_press(UIMessage::ResetPeakFreqMax));
+        }
+
+        if self.peak_table || self.spectrogram.hold_peak_freq_max {
+            peak_row = peak_row
+                .push(style.text(format!("above {:.0} dBFS", self.peak_threshold_db)))
+                .push(slider(DB_FLOOR..=0.0, self.peak_threshold_db, UIMessage::PeakThresholdChanged).step(1.0).width(120));
+        }
+
+        let mut marker_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Delta markers", self.spectrogram.markers_enabled, UIMessage::MarkersToggled));
+
+        if self.spectrogram.markers_enabled {
+            marker_row = marker_row
+                .push(style.text(self.marker_readout()))
+                .push(style.button("Clear markers").on_press(UIMessage::ClearMarkers));
+        }
+
+        let in_tune = self.tuning_cents().is_some_and(|cents| cents.abs() <= IN_TUNE_CENTS);
+        let tuning_row = Row::new()
+            .spacing(5)
+            .push(style.text("Tune to"))
+            .push(text_input("Target Hz, e.g. 432", &self.target_freq_input).size(style.text_size())
+                .on_input(UIMessage::TargetFreqChanged)
+                .width(150))
+            .push(style.text(self.tuning_status()).style(if in_tune {
+                style.theme().palette().success
+            } else {
+                style.theme().palette().text
+            }));
+
+        let mut feedback_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Detect feedback", self.feedback_detector.is_some(), UIMessage::FeedbackDetectionToggled));
+
+        if let Some(detector) = &self.feedback_detector {
+            feedback_row = feedback_row
+                .push(style.text(format!("Sensitivity {:.0} dB", detector.sensitivity_db)))
+                .push(slider(6.0..=40.0, detector.sensitivity_db, UIMessage::FeedbackSensitivityChanged).width(100))
+                .push(style.text(format!("Persistence {} frames", detector.persistence)))
+                .push(slider(2..=100, detector.persistence as u8, UIMessage::FeedbackPersistenceChanged).width(100))
+                .push(style.text(if self.spectrogram.feedback_freqs.is_empty() {
+                    String::new()
+                } else {
+                    let freqs: Vec<String> = self.spectrogram.feedback_freqs.iter().map(|freq| format!("{freq:.0} Hz")).collect();
+                    format!("Feedback: {}", freqs.join(", "))
+                }));
+        }
+
+        let mut welch_row = Row::new()
+            .spacing(5)
+            .push(style.pick_list(FftBackend::ALL.to_vec(), Some(self.fft_backend), UIMessage::FftBackendChanged))
+            .push(style.checkbox("Welch averaging", self.welch.is_some(), UIMessage::WelchToggled));
+
+        if let Some(welch) = &self.welch {
+            welch_row = welch_row
+                .push(style.text(format!("{} segments", welch.segments)))
+                .push(slider(2..=MAX_WELCH_SEGMENTS as u8, welch.segments as u8, UIMessage::WelchSegmentsChanged).width(100))
+                .push(style.text(format!("Overlap {:.0}%", welch.overlap * 100.0)))
+                .push(slider(0.0..=0.75, welch.overlap, UIMessage::WelchOverlapChanged).step(0.05).width(100));
+        }
+
+        let mut units_row = Row::new()
+            .spacing(5)
+            .push(style.text("Amplitude units"))
+            .push(style.pick_list(UnitMode::ALL.to_vec(), Some(self.spectrogram.unit_mode), UIMessage::UnitModeChanged));
+
+        if self.spectrogram.unit_mode == UnitMode::DbFs {
+            let range = self.spectrogram.db_range;
+            units_row = units_row
+                .push(style.text(format!("Floor {:.0} dB", range.floor)))
+                .push(slider(DB_FLOOR..=MAX_DB_CEILING - MIN_DB_SPAN, range.floor, UIMessage::DbFloorChanged).step(1.0).width(100))
+                .push(style.text(format!("Ceiling {:.0} dB", range.ceiling)))
+                .push(slider(DB_FLOOR + MIN_DB_SPAN..=MAX_DB_CEILING, range.ceiling, UIMessage::DbCeilingChanged).step(1.0).width(100));
+        }
+
+        let mut crest_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Crest factor", self.crest_factor, UIMessage::CrestFactorToggled));
+
+        if self.crest_factor {
+            crest_row = crest_row
+                .push(style.text(match self.crest_factor_db {
+                    Some(crest_db) => format!("{crest_db:.1} dB"),
+                    None => String::from("-")
+                }))
+                .push(style.text(format!("smoothing {:.2}", self.crest_smoothing)))
+                .push(slider(0.0..=MAX_CREST_SMOOTHING, self.crest_smoothing, UIMessage::CrestSmoothingChanged).step(0.01).width(100));
+        }
+
+        let mut chart_style_row = Row::new()
+            .spacing(5)
+            .push(style.text("Chart style"))
+            .push(style.pick_list(ChartStyle::ALL.to_vec(), Some(self.spectrogram.chart_style), UIMessage::ChartStyleChanged));
+
+        if self.spectrogram.chart_style == ChartStyle::Line {
+            chart_style_row = chart_style_row
+                .push(style.text(match self.spectrogram.persistence_frames {
+                    0 => String::from("No persistence"),
+                    frames => format!("Persist {frames} frames")
+                }))
+                .push(slider(0..=MAX_PERSISTENCE_FRAMES as u8, self.spectrogram.persistence_frames as u8,
+                    |frames| UIMessage::PersistenceFramesChanged(frames as usize)).width(100));
+
+            if self.spectrogram.persistence_frames > 0 {
+                chart_style_row = chart_style_row
+                    .push(style.text(format!("fade {:.2}", self.spectrogram.persistence_decay)))
+                    .push(slider(MIN_PERSISTENCE_DECAY..=MAX_PERSISTENCE_DECAY, self.spectrogram.persistence_decay, UIMessage::PersistenceDecayChanged).step(0.05).width(100));
+            }
+        }
+
+        if self.spectrogram.chart_style == ChartStyle::Bars {
+            chart_style_row = chart_style_row
+                .push(style.text(format!("Peaks fall {:.1} dB/frame", self.spectrogram.peak_cap_decay_db)))
+                .push(slider(MIN_PEAK_CAP_DECAY_DB..=MAX_PEAK_CAP_DECAY_DB, self.spectrogram.peak_cap_decay_db, UIMessage::PeakCapDecayChanged).step(0.1).width(100));
+        }
+
+        chart_style_row = chart_style_row.push(style.checkbox("Line width", self.spectrogram.line_width.is_some(), UIMessage::LineWidthToggled));
+
+        if let Some(width) = self.spectrogram.line_width {
+            chart_style_row = chart_style_row
+                .push(style.text(format!("{width} px")))
+                .push(slider(1..=MAX_LINE_WIDTH, width, UIMessage::LineWidthChanged).width(80));
+        }
+
+        chart_style_row = chart_style_row.push(style.checkbox("Anti-aliasing (after restart)", self.antialiasing, UIMessage::AntialiasingToggled));
+
+        let mut tilt_row = Row::new()
+            .spacing(5)
+            .push(style.pick_list(Tilt::ALL.to_vec(), Some(self.spectrogram.tilt), UIMessage::TiltChanged));
+
+        if self.spectrogram.tilt == Tilt::Custom {
+            tilt_row = tilt_row
+                .push(style.text(format!("{:+.1} dB/oct", self.spectrogram.custom_tilt_db)))
+                .push(slider(-MAX_TILT_DB_PER_OCTAVE..=MAX_TILT_DB_PER_OCTAVE, self.spectrogram.custom_tilt_db, UIMessage::CustomTiltChanged).step(0.5).width(100));
+        }
+
+        let mut waterfall_row = Row::new()
+            .spacing(5)
+            .push(style.checkbox("Waterfall", self.show_waterfall, UIMessage::WaterfallToggled));
+
+        if self.show_waterfall {
+            waterfall_row = waterfall_row
+                .push(style.text(format!("Smoothing {:.0}%", self.waterfall.smoothing * 100.0)))
+                .push(slider(0.0..=0.95, self.waterfall.smoothing, UIMessage::WaterfallSmoothingChanged).step(0.05).width(120))
+                .push(style.text(format!("{} frames per row", self.waterfall.frames_per_row)))
+                .push(slider(1..=16, self.waterfall.frames_per_row as u8, UIMessage::WaterfallFramesPerRowChanged).width(120))
+                .push(style.pick_list(WaterfallDirection::ALL.to_vec(), Some(self.waterfall.direction), UIMessage::WaterfallDirectionChanged))
+                .push(style.button("Export PNG").on_press(UIMessage::ExportWaterfallPng))
+                .push(style.button("Save session").on_press(UIMessage::SaveWaterfallSession));
+        }
+
+        waterfall_row = waterfall_row
+            .push(style.pick_list(self.sessions.clone(), self.current_session.clone(), UIMessage::OpenWaterfallSession)
+                .placeholder("Open session"));
+
+        Column::new()
+            .push(
+                Row::new()
+                .push(style.text("Audio host: "))
+                    .push(
+                        style.pick_list(
+                            self.audio_system.engine.get_available_hosts(),
+                            self.audio_system.engine.get_current_host().map(|e| e.into()),
+                            UIMessage::HostChanged)
+                            .placeholder("Choose an audio host")))
+            .push(input_row)
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.text("Output device"))
+                    .push(
+                        style.pick_list(
+                            self.output_devices.clone(),
+                            self.audio_system.engine.get_current_output_device(),
+                            UIMessage::OutputDeviceChanged)
+                            .placeholder("Choose an output device")))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(stream_button)
+                    .push(single_shot_button)
+                    .push(style.text("Overload").style(if self.overload_until.is_some() {
+                        style.theme().palette().danger
+                    } else {
+                        Color::from_rgb(0.5, 0.5, 0.5)
+                    }))
+                    .push(style.text(format!("hold {:.1} s", self.overload_hold.as_secs_f32())))
+                    .push(slider(0.0..=MAX_OVERLOAD_HOLD_S, self.overload_hold.as_secs_f32(), UIMessage::OverloadHoldChanged).step(0.5).width(80))
+                    .push(style.checkbox("Start on launch", self.autostart, UIMessage::AutostartToggled))
+                    .push(style.checkbox("Probe devices on launch", self.probe_devices, UIMessage::ProbeDevicesToggled))
+                    .push(style.pick_list(ShareMode::ALL.to_vec(), Some(self.audio_system.engine.get_share_mode()), UIMessage::ShareModeChanged))
+                    .push(style.pick_list(ChannelRequest::COMMON.to_vec(), Some(self.audio_system.engine.get_channel_request()), UIMessage::ChannelRequestChanged))
+                    .push(style.pick_list(CaptureFormat::ALL.to_vec(), Some(self.audio_system.engine.get_sample_format()), UIMessage::SampleFormatChanged))
+                    .push(style.pick_list(BufferRequest::COMMON.to_vec(), Some(self.audio_system.engine.get_buffer_request()), UIMessage::BufferRequestChanged))
+                    .push(text_input("Channel weights, e.g. 1, -1", &self.channel_weights_input).size(style.text_size())
+                        .on_input(UIMessage::ChannelWeightsChanged)
+                        .width(180))
+                    .push(style.pick_list(ChannelView::ALL.to_vec(), Some(self.channel_view), UIMessage::ChannelViewChanged))
+                    .push(style.button("Save settings").on_press(UIMessage::SaveSettings))
+                    .push(style.button("Reload settings").on_press(UIMessage::ReloadSettings))
+                    .push(text_input("Preset name", &self.preset_name).size(style.text_size()).on_input(UIMessage::PresetNameChanged).width(150))
+                    .push(style.button("Save preset").on_press(UIMessage::SavePreset))
+                    .push(style.pick_list(self.presets.clone(), self.current_preset.clone(), UIMessage::LoadPreset)
+                        .placeholder("Load preset")))
+            .push(style.text(self.error_message.as_deref().unwrap_or("")))
+            .push(summary_row)
+            .push(style.text(self.channel_weights_warning()))
+            .push(style.text(match self.current_stream.as_ref().map(AudioStream::dropped_samples) {
+                Some(dropped) if dropped > 0 => format!("Warning, dropped: {} samples", dropped),
+                _ => String::new()
+            }))
+            .push(style.text(if self.skipped_frames > 0 {
+                format!("Skipped {} FFT windows to keep up", self.skipped_frames)
+            } else {
+                String::new()
+            }))
+            .push(style.checkbox("Decimate before analysis", self.decimator.is_some(), UIMessage::DecimationToggled))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.text(format!("FFT window {:.0} ms, {} samples, {:.2} Hz/bin",
+                        self.fft_window_ms, self.fft_size, self.frequency_resolution())))
+                    .push(slider(MIN_FFT_WINDOW_MS..=MAX_FFT_WINDOW_MS, self.fft_window_ms, UIMessage::FftWindowChanged).step(1.0).width(200)))
+            .push(padding_row)
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.checkbox("Low cut", self.low_cut.is_some(), UIMessage::LowCutToggled))
+                    .push(style.text(format!("{:.0} Hz", self.low_cut_hz)))
+                    .push(slider(MIN_LOW_CUT_HZ..=MAX_LOW_CUT_HZ, self.low_cut_hz, UIMessage::LowCutChanged).step(1.0).width(150))
+                    .push(style.checkbox("Silence gate", self.silence_gate, UIMessage::SilenceGateToggled))
+                    .push(style.text(format!("below {:.0} dBFS", self.silence_gate_db)))
+                    .push(slider(DB_FLOOR..=MAX_SILENCE_GATE_DB, self.silence_gate_db, UIMessage::SilenceGateChanged).step(1.0).width(120)))
+            .push(analyze_row)
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.text(format!("Gain {:+.1} dB", self.gain_db)))
+                    .push(slider(self.gain_range_db.clone(), self.gain_db, UIMessage::GainChanged).step(0.5).width(200))
+                    .push(style.text(if self.clipping { "Clipping!" } else { "" })))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.checkbox("Monitor", self.monitoring, UIMessage::MonitoringToggled))
+                    .push(style.checkbox("Limiter", self.limiter.is_enabled(), UIMessage::LimiterToggled))
+                    .push(style.text(format!("Threshold {:.1} dBFS", self.limiter.threshold_db())))
+                    .push(slider(-12.0..=0.0, self.limiter.threshold_db(), UIMessage::LimiterThresholdChanged).step(0.5).width(150))
+                    .push(style.text(format!("Delay {:.0} ms", self.monitor_delay_ms)))
+                    .push(slider(0.0..=MAX_MONITOR_DELAY_MS, self.monitor_delay_ms, UIMessage::MonitorDelayChanged).step(5.0).width(150))
+                    .push(style.text(match &self.monitor {
+                        Some(monitor) if monitor.dropped_samples() > 0 =>
+                            format!("Monitor dropped {} samples", monitor.dropped_samples()),
+                        _ => String::new()
+                    })))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.text("Level"))
+                    .push(progress_bar(DB_FLOOR..=0.0, to_dbfs(rms_level)).width(200))
+                    .push(style.text(format!("RMS {}, peak {}",
+                        self.spectrogram.unit_mode.format(rms_level), self.spectrogram.unit_mode.format(self.peak_meter.level()))))
+                    .push(style.text(format!("Attack {:.0} ms", self.rms_meter.attack())))
+                    .push(slider(1.0..=500.0, self.rms_meter.attack(), UIMessage::MeterAttackChanged).width(100))
+                    .push(style.text(format!("Release {:.0} ms", self.rms_meter.release())))
+                    .push(slider(10.0..=3000.0, self.rms_meter.release(), UIMessage::MeterReleaseChanged).step(10.0).width(100))
+                    .push(crest_row))
+            .push(recording_row)
+            .push(
+                units_row
+                    .push(style.text("Frequency"))
+                    .push(style.pick_list(XAxisUnit::ALL.to_vec(), Some(self.spectrogram.x_unit), UIMessage::XAxisUnitChanged))
+                    .push(tilt_row)
+                    .push(chart_style_row)
+                    .push(style.pick_list(DisplayStyle::ALL.to_vec(), Some(style), UIMessage::DisplayStyleChanged))
+                    .push(style.pick_list(TracePalette::ALL.to_vec(), Some(self.spectrogram.palette), UIMessage::TracePaletteChanged))
+                    .push(style.checkbox("Split view", self.split_view, |_| UIMessage::ToggleSplitView))
+                    .push(style.checkbox(&format!("Follow peak, {:.0} Hz wide", self.spectrogram.follow_span), self.spectrogram.follow_peak, |_| UIMessage::ToggleFollowPeak))
+                    .push(slider(MIN_FOLLOW_SPAN_HZ..=MAX_FOLLOW_SPAN_HZ, self.spectrogram.follow_span, UIMessage::FollowPeakSpanChanged).step(50.0).width(100))
+                    .push(style.checkbox("Full spectrum", self.full_spectrum, UIMessage::FullSpectrumToggled))
+                    .push(style.text(match self.spectrogram.max_display_points {
+                        0 => String::from("Draw all points"),
+                        points => format!("Draw {points} points")
+                    }))
+                    .push(slider(0.0..=MAX_DISPLAY_POINTS as f32, self.spectrogram.max_display_points as f32,
+                        |points| UIMessage::MaxDisplayPointsChanged(points as usize)).step(256.0).width(100))
+                    .push(style.text(format!("{} fps", self.display_fps)))
+                    .push(slider(5..=60, self.display_fps as u8, UIMessage::DisplayFpsChanged).width(100))
+                    .push(style.checkbox("Power saving when unfocused", self.power_saving, UIMessage::PowerSavingToggled))
+                    .push(style.checkbox(&format!("Log peak every {:.0} s", self.log_snapshot_interval_s), self.log_snapshots, UIMessage::LogSnapshotsToggled))
+                    .push(slider(1.0..=MAX_LOG_SNAPSHOT_INTERVAL_S, self.log_snapshot_interval_s, UIMessage::LogSnapshotIntervalChanged).step(1.0).width(100))
+                    .push(style.button("Save chart").on_press(UIMessage::SaveChartPng))
+                    .push(style.checkbox(&format!("Auto-save every {:.0} s to", self.auto_save_interval_s), self.auto_save, UIMessage::AutoSaveToggled))
+                    .push(text_input("Directory", &self.auto_save_dir).size(style.text_size()).on_input(UIMessage::AutoSaveDirChanged).width(120))
+                    .push(slider(MIN_AUTO_SAVE_INTERVAL_S..=MAX_AUTO_SAVE_INTERVAL_S, self.auto_save_interval_s, UIMessage::AutoSaveIntervalChanged).step(5.0).width(100))
+                    .push(style.text(format!("Skip {} frames on start", self.warmup.frames())))
+                    .push(slider(0..=MAX_WARMUP_FRAMES as u8, self.warmup.frames() as u8, UIMessage::WarmupFramesChanged).width(100))
+                    .push(style.text(match self.max_queued_frames {
+                        0 => String::from("Analyze all windows"),
+                        frames => format!("Skip windows above {frames} queued")
+                    }))
+                    .push(slider(0..=MAX_QUEUED_FRAMES as u8, self.max_queued_frames as u8, UIMessage::MaxQueuedFramesChanged).width(100)))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.checkbox("Band analyzer", self.spectrogram.band_width.is_some(), UIMessage::BandAnalyzerToggled))
+                    .push(style.pick_list(BandWidth::ALL.to_vec(), self.spectrogram.band_width, UIMessage::BandWidthChanged)))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(style.checkbox("Average", self.spectrogram.averaging, UIMessage::AveragingToggled))
+                    .push(style.button("Reset").on_press(UIMessage::ResetAverage))
+                    .push(style.text(if self.spectrogram.averaging {
+                        format!("{} averages", self.spectrogram.average_count)
+                    } else {
+                        String::new()
+                    }))
+                    .push(style.checkbox("Leq", self.spectrogram.leq, UIMessage::LeqToggled))
+                    .push(style.button("Reset Leq").on_press(UIMessage::ResetLeq))
+                    .push(style.text(if self.spectrogram.leq {
+                        format!("Measured {:.1} s", self.spectrogram.measured_time)
+                    } else {
+                        String::new()
+                    })))
+            .push(peak_row)
+            .push(marker_row)
+            .push(tuning_row)
+            .push(feedback_row)
+            .push(welch_row)
+            .push(waterfall_row)
+            .push(scope_row)
+            .push(response_row)
+            .push(snapshot_row)
+            .push(chart_area)
+            .push(style.text(format!("Sample rate: {} Hz, analyzed at {} Hz, resolution: {:.2} Hz/bin ({} point FFT over {:.1} ms){}",
+                self.sample_rate, self.analysis_sample_rate(), self.frequency_resolution(), self.fft_size,
+                self.fft_size as f32 * 1000.0 / self.analysis_sample_rate() as f32, buffer_info)))
+            .push(if self.analyze {
+                let held_max = if self.spectrogram.hold_peak_freq_max {
+                    format!(", max {:3.2}Hz", self.spectrogram.peak_freq_max)
+                } else {
+                    String::new()
+                };
+
+                let peak = if self.spectrogram.silent {
+                    String::from("Silence, no peak")
+                } else {
+                    format!("{:3.2}Hz at {}", self.spectrogram.peak_freq, self.spectrogram.unit_mode.format(self.spectrogram.peak_amp))
+                };
+
+                style.text(format!("{}{} {} ({} channels)", peak, held_max, self.spectrogram.user_data,
+                    self.current_stream.as_ref().map(AudioStream::channels).unwrap_or(0)))
+            } else if self.frozen_on_clip {
+                style.text("Analysis frozen on a clip")
+            } else {
+                style.text("Analysis paused")
+            })
+            .padding(20)
+            .spacing(10)
+            .align_items(Alignment::Center)
+            .into()
+    }
+
+    fn subscription(&self) -> Subscription<Self::Message> {
+        let settings_watch = iced_time::every(Duration::from_secs(1)).map(|_instant| UIMessage::SettingsFileTick);
+        let control = match &self.control {
+            Some(control) => Self::forward("control", control.clone(), UIMessage::ControlRequested),
+            None => Subscription::none()
+        };
+
+        let tray = match &self.tray {
+            Some(tray) => Self::forward("tray", tray.actions(), UIMessage::TrayMenu),
+            None => Subscription::none()
+        };
+
+        let devices = match &self.device_watcher {
+            Some(watcher) => Self::forward(("devices", watcher.id()), watcher.changes(), |_| UIMessage::DevicesChanged),
+            None => Subscription::none()
+        };
+
+        let focus = subscription::events_with(|event, _status| match event {
+            Event::Window(window::Event::Focused) => Some(UIMessage::WindowFocusChanged(true)),
+            Event::Window(window::Event::Unfocused) => Some(UIMessage::WindowFocusChanged(false)),
+            Event::Window(window::Event::CloseRequested) => Some(UIMessage::WindowCloseRequested),
+            // there is no minimize event, a minimized window is resized to nothing
+            Event::Window(window::Event::Resized { width: 0, .. } | window::Event::Resized { height: 0, .. }) => Some(UIMessage::WindowMinimized),
+            Event::Window(window::Event::Resized { width, height }) => Some(UIMessage::WindowResized(width, height)),
+            _ => None
+        });
+
+        if self.current_stream.is_some() {
+            let saving_power = self.power_saving && !self.focused;
+            let fps = if saving_power { self.display_fps.min(POWER_SAVING_FPS) } else { self.display_fps };
+            let redraw_interval = Duration::from_secs_f32(1.0 / fps.max(1) as f32);
+            let snapshots = if self.log_snapshots {
+                iced_time::every(Duration::from_secs_f32(self.log_snapshot_interval_s)).map(|_instant| UIMessage::LogSnapshot)
+            } else {
+                Subscription::none()
+            };
+            let auto_save = if self.auto_save {
+                iced_time::every(Duration::from_secs_f32(self.auto_save_interval_s)).map(|_instant| UIMessage::AutoSaveTick)
+            } else {
+                Subscription::none()
+            };
+
+            Subscription::batch([
+                // the stream is polled at full rate, so no audio is dropped while redraws are slowed
+                iced_time::every(Duration::from_millis(5)).map(|_instant| UIMessage::StreamTick),
+                iced_time::every(redraw_interval).map(|_instant| UIMessage::RedrawTick),
+                snapshots,
+                auto_save,
+                settings_watch,
+                control,
+                tray,
+                devices,
+                focus
+            ])
         } else {
-            Subscription::none()
+            Subscription::batch([settings_watch, control, tray, devices, focus])
         }
     }
 
@@ -0,0 +1,226 @@
+use iced::{Element, Length, Size};
+use iced::widget::canvas::{Cache, Frame, Geometry};
+use plotters::backend::DrawingBackend;
+use plotters::chart::ChartBuilder;
+use plotters::series::LineSeries;
+use plotters::style::{Color, RGBColor};
+use plotters_iced::{Chart, ChartWidget, Renderer};
+use spectrum_analyzer::FrequencyLimit;
+
+use crate::engine::analysis::{self, Decimator, ResponseMeasurement, SpectrumPoint};
+use crate::engine::monitor::Monitor;
+use crate::engine::PacketType;
+use crate::engine::source::{SWEEP_SECONDS, SyntheticSource, TestSignalKind};
+use crate::ui::UIMessage;
+use crate::ui::style::{DisplayStyle, TracePalette};
+
+/// Silence played after the sweep, so its end still arrives through the latency of the devices
+const TAIL_SECONDS: f32 = 0.5;
+
+/// Smoothing applied to a finished response, in octaves
+const RESPONSE_SMOOTHING: f32 = 1.0 / 6.0;
+
+const REFERENCE_COLOR: RGBColor = RGBColor(150, 150, 150);
+
+/// Plays the built-in sweep on the output while the input is captured. The sweep goes through
+/// the same decimation and FFT as the captured signal, which gives the reference spectrum.
+pub struct SweepMeasurement {
+    generator: SyntheticSource,
+    monitor: Monitor,
+    channels: usize,
+    decimator: Option<Decimator>,
+    buffer: PacketType,
+    frame_size: usize,
+    analysis_sample_rate: u32,
+    max_frequency: f32,
+    /// Sweep samples still to be played, followed by the silent tail
+    remaining_sweep: usize,
+    remaining_tail: usize,
+    measurement: ResponseMeasurement
+}
+
+impl SweepMeasurement {
+    /// Prepares a measurement analyzed like the input: `frame_size` samples per frame, decimated
+    /// by `decimation` if given and limited to `max_frequency`.
+    pub fn new(monitor: Monitor, channels: u16, sample_rate: u32, decimation: Option<usize>, frame_size: usize, max_frequency: f32) -> Self {
+        let factor = decimation.unwrap_or(1);
+
+        Self {
+            generator: SyntheticSource::new(TestSignalKind::Sweep, sample_rate, frame_size),
+            monitor,
+            channels: channels.max(1) as usize,
+            decimator: decimation.map(Decimator::new),
+            buffer: vec![],
+            frame_size,
+            analysis_sample_rate: sample_rate / factor as u32,
+            max_frequency,
+            remaining_sweep: (SWEEP_SECONDS * sample_rate as f32) as usize,
+            remaining_tail: (TAIL_SECONDS * sample_rate as f32) as usize,
+            measurement: ResponseMeasurement::default()
+        }
+    }
+
+    /// Plays the next `frames` frames of the sweep, as many as were just captured, and adds them
+    /// to the reference.
+    pub fn play(&mut self, frames: usize) {
+        let sweep_frames = frames.min(self.remaining_sweep);
+        let tail_frames = (frames - sweep_frames).min(self.remaining_tail);
+        self.remaining_sweep -= sweep_frames;
+        self.remaining_tail -= tail_frames;
+
+        let mut sweep = self.generator.generate(sweep_frames);
+        sweep.resize(sweep_frames + tail_frames, 0.0);
+
+        let interleaved: PacketType = sweep.iter()
+            .flat_map(|sample| std::iter::repeat(*sample).take(self.channels))
+            .collect();
+        self.monitor.push(&interleaved);
+
+        if let Some(decimator) = &mut self.decimator {
+            sweep = decimator.process(&sweep);
+        }
+        self.buffer.append(&mut sweep);
+
+        for frame in analysis::take_frames(&mut self.buffer, self.frame_size) {
+            match analysis::spectrum(&frame, self.analysis_sample_rate, FrequencyLimit::Max(self.max_frequency)) {
+                Ok(spectrum) => self.measurement.add_reference(&spectrum),
+                Err(error) => log::error!("{}", error)
+            }
+        }
+    }
+
+    pub fn add_captured(&mut self, spectrum: &[SpectrumPoint]) {
+        self.measurement.add_captured(spectrum);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.remaining_sweep == 0 && self.remaining_tail == 0
+    }
+
+    /// Progress from 0 to 1
+    pub fn progress(&self, sample_rate: u32) -> f32 {
+        let total = (SWEEP_SECONDS + TAIL_SECONDS) * sample_rate as f32;
+
+        1.0 - (self.remaining_sweep + self.remaining_tail) as f32 / total
+    }
+
+    /// The smoothed response in dB.
+    pub fn finish(self) -> Vec<SpectrumPoint> {
+        self.measurement.response(RESPONSE_SMOOTHING)
+    }
+}
+
+/// Chart of a measured frequency response, with an optional stored reference curve.
+pub struct ResponseView {
+    pub response: Vec<SpectrumPoint>,
+    pub reference: Option<Vec<SpectrumPoint>>,
+    max_frequency: f32,
+    pub style: DisplayStyle,
+    pub palette: TracePalette,
+    cache: Cache
+}
+
+impl ResponseView {
+    pub fn new(max_frequency: f32) -> Self {
+        Self {
+            response: vec![],
+            reference: None,
+            max_frequency,
+            style: DisplayStyle::default(),
+            palette: TracePalette::default(),
+            cache: Cache::new()
+        }
+    }
+
+    pub fn view(&self) -> Element<UIMessage> {
+        ChartWidget::new(self)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    /// Marks the chart as outdated so it is rendered again on the next frame.
+    pub fn redraw(&self) {
+        self.cache.clear();
+    }
+
+    /// Keeps the current response to compare the next measurements with.
+    pub fn store_reference(&mut self) {
+        if !self.response.is_empty() {
+            self.reference = Some(self.response.clone());
+        }
+    }
+}
+
+impl Chart<UIMessage> for ResponseView {
+    type State = ();
+
+    fn draw<R: Renderer, F: Fn(&mut Frame)>(&self, renderer: &R, bounds: Size, draw_fn: F) -> Geometry {
+        renderer.draw_cache(&self.cache, bounds, draw_fn)
+    }
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut builder: ChartBuilder<DB>) {
+        let mut chart = builder
+            .set_all_label_area_size(self.style.label_area_size())
+            .build_cartesian_2d(0.0..self.max_frequency, -40.0f32..20.0)
+            .expect("Failed to build chart");
+
+        self.style.fill_background(&chart);
+
+        let mut mesh = chart.configure_mesh();
+        self.style.apply(&mut mesh);
+        mesh.x_desc("Frequency (Hz)")
+            .y_desc("Response (dB)")
+            .draw()
+            .expect("Failed to draw mesh");
+
+        if let Some(reference) = &self.reference {
+            let color = match self.palette {
+                TracePalette::Standard => REFERENCE_COLOR,
+                TracePalette::ColorBlindSafe => self.palette.color(self.style, 0)
+            };
+            self.palette.dash(0).draw(&mut chart, reference.clone(), color.stroke_width(self.style.line_width() + 1));
+        }
+
+        chart.draw_series(LineSeries::new(self.response.iter().copied(), self.style.line_color().stroke_width(self.style.line_width() + 1)))
+            .expect("Failed to draw response");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use spectrum_analyzer::FrequencyLimit;
+
+    use crate::engine::analysis;
+    use crate::engine::monitor::{self, LimiterControl};
+    use crate::ui::response::SweepMeasurement;
+
+    #[test]
+    fn direct_loopback_has_a_flat_response() {
+        let (monitor, mut output) = monitor::monitor_channel(48000, 0, Arc::new(LimiterControl::new(false, 0.0)));
+        let mut sweep = SweepMeasurement::new(monitor, 1, 48000, None, 1024, 2200.0);
+
+        // the captured signal lags behind the output, as it would with real devices
+        let mut captured = vec![0.0; 300];
+
+        while !sweep.is_finished() {
+            sweep.play(1024);
+
+            let mut data = vec![0.0; 1024];
+            output.fill(&mut data);
+            captured.extend(data);
+
+            for frame in analysis::take_frames(&mut captured, 1024) {
+                sweep.add_captured(&analysis::spectrum(&frame, 48000, FrequencyLimit::Max(2200.0)).unwrap());
+            }
+        }
+
+        let response = sweep.finish();
+        let swept: Vec<_> = response.iter().filter(|(freq, _)| (100.0..1900.0).contains(freq)).collect();
+
+        assert!(!swept.is_empty());
+        assert!(swept.iter().all(|(_, db)| db.abs() < 1.0), "{swept:?}");
+    }
+}
@@ -0,0 +1,448 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{AudiaError, AudioHostName, InputDeviceName, ShareMode};
+use crate::engine::analysis::{BandWidth, ChannelView};
+use crate::engine::fft::FftBackend;
+use crate::engine::monitor::{DEFAULT_LIMITER_THRESHOLD_DB, DEFAULT_MONITOR_ATTEMPTS};
+use crate::engine::recorder::ChannelChangePolicy;
+use crate::ui::spectrogram::{ChartStyle, DEFAULT_FOLLOW_SPAN_HZ, DEFAULT_MAX_DISPLAY_POINTS, DEFAULT_PEAK_CAP_DECAY_DB, DEFAULT_PERSISTENCE_DECAY};
+use crate::ui::style::{DisplayStyle, TracePalette};
+use crate::ui::units::{DB_FLOOR, PINK_TILT_DB_PER_OCTAVE, Tilt, UnitMode, XAxisUnit};
+use crate::ui::waterfall::WaterfallDirection;
+use crate::ui::waveform::{DEFAULT_WINDOW, Trigger};
+
+pub const DEFAULT_METER_ATTACK_MS: f32 = 10.0;
+pub const DEFAULT_METER_RELEASE_MS: f32 = 300.0;
+
+pub const DEFAULT_DISPLAY_FPS: u32 = 30;
+
+pub const DEFAULT_LOG_SNAPSHOT_INTERVAL_S: f32 = 1.0;
+
+pub const DEFAULT_OVERLOAD_HOLD_S: f32 = 2.0;
+
+pub const DEFAULT_AUTO_SAVE_INTERVAL_S: f32 = 60.0;
+pub const DEFAULT_AUTO_SAVE_DIR: &str = "captures";
+
+pub const DEFAULT_LOW_CUT_HZ: f32 = 20.0;
+
+/// About 256 samples at 48 kHz
+pub const DEFAULT_FFT_WINDOW_MS: f32 = 5.0;
+
+/// Share of the window collected before it is analyzed with zero-padding
+pub const DEFAULT_PAD_MIN_FILL: f32 = 0.5;
+
+pub const DEFAULT_SILENCE_GATE_DB: f32 = -70.0;
+
+/// Frames skipped after the stream starts, about 20 ms at 48 kHz
+pub const DEFAULT_WARMUP_FRAMES: usize = 4;
+
+/// FFT windows that may queue up before all but the latest are skipped, 0 analyzes all of them
+pub const DEFAULT_MAX_QUEUED_FRAMES: usize = 0;
+
+/// Share of the previous crest factor kept per packet
+pub const DEFAULT_CREST_SMOOTHING: f32 = 0.9;
+
+pub const DEFAULT_PEAK_COUNT: usize = 5;
+pub const DEFAULT_PEAK_THRESHOLD_DB: f32 = -60.0;
+
+/// DC and the window's leakage of it into the next bin
+pub const DEFAULT_IGNORED_LOW_BINS: usize = 2;
+
+/// Size the window opens at when no size was saved yet
+pub const DEFAULT_WINDOW_SIZE: (u32, u32) = (1024, 768);
+
+/// Where the settings are persisted, relative to the working directory
+pub const SETTINGS_FILE: &str = "./audia.toml";
+
+/// Directory holding one file per preset, relative to the working directory
+pub const PRESET_DIR: &str = "./presets";
+
+/// Settings that are persisted between runs. Missing entries fall back to their defaults, so the
+/// file can be edited by hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserSettings {
+    pub host: Option<AudioHostName>,
+    /// Hosts tried in order on launch, the first that opens with an input device is used. When
+    /// set it takes the place of the persisted `host`
+    pub host_preference: Vec<AudioHostName>,
+    pub input_device: Option<InputDeviceName>,
+    /// Access mode requested for the input device, recording fails in exclusive mode
+    pub share_mode: ShareMode,
+    /// Starts streaming from the input device on launch
+    pub autostart: bool,
+    /// Logs the capabilities of all input devices on launch
+    pub probe_devices: bool,
+    pub decimation: bool,
+    /// Length of the analyzed window, rounded to a power-of-two FFT size at the analysis rate
+    pub fft_window_ms: f32,
+    /// Shows partial windows padded with zeros until a complete one arrived
+    pub zero_padding: bool,
+    pub pad_min_fill: f32,
+    /// High-pass filter before the analysis
+    pub low_cut: bool,
+    pub low_cut_hz: f32,
+    /// Blanks the spectrum while the input RMS is below `silence_gate_db` dBFS
+    pub silence_gate: bool,
+    pub silence_gate_db: f32,
+    /// Pauses the analysis on the first clip, so the spectrum of the clip stays on screen
+    pub freeze_on_clip: bool,
+    pub unit_mode: UnitMode,
+    /// Bottom of the amplitude axis in dBFS mode, must lie below `db_ceiling`
+    pub db_floor: f32,
+    pub db_ceiling: f32,
+    pub x_unit: XAxisUnit,
+    /// Slope added to the drawn spectrum, e.g. pink to show pink noise flat
+    pub tilt: Tilt,
+    /// Slope of the custom tilt in dB per octave
+    pub custom_tilt_db: f32,
+    pub averaging: bool,
+    pub chart_style: ChartStyle,
+    /// High contrast with larger text for low vision
+    pub display_style: DisplayStyle,
+    /// Colors of the second channel, snapshots and reference curves
+    pub trace_palette: TracePalette,
+    pub fft_backend: FftBackend,
+    pub band_width: Option<BandWidth>,
+    /// Reference the peak is tuned against, in Hz
+    pub target_freq: Option<f32>,
+    pub split_view: bool,
+    /// Centers the frequency axis on the peak, `follow_peak_span_hz` wide
+    pub follow_peak: bool,
+    pub follow_peak_span_hz: f32,
+    /// Analyze up to Nyquist instead of the default range
+    pub full_spectrum: bool,
+    /// Points drawn per spectrum chart at most, 0 draws every bin
+    pub max_display_points: usize,
+    /// Table of the strongest peaks next to the charts
+    pub peak_table: bool,
+    pub peak_count: usize,
+    /// Peaks below this level in dBFS are not listed
+    pub peak_threshold_db: f32,
+    /// Leaves DC and the lowest `ignored_low_bins` bins out of the peak detection
+    pub ignore_dc: bool,
+    pub ignored_low_bins: usize,
+    /// Shows the highest peak frequency since the last reset
+    pub hold_peak_freq_max: bool,
+    /// Lets clicks on the chart place two markers and shows the difference between them
+    pub markers: bool,
+    pub gain_db: f32,
+    pub waterfall: bool,
+    pub waterfall_smoothing: f32,
+    pub waterfall_frames_per_row: u8,
+    pub waterfall_direction: WaterfallDirection,
+    /// Meter attack time constant in ms
+    pub meter_attack_ms: f32,
+    /// Meter release time constant in ms
+    pub meter_release_ms: f32,
+    /// Shows the crest factor next to the level meter
+    pub crest_factor: bool,
+    pub crest_smoothing: f32,
+    /// How often the charts are redrawn while streaming
+    pub display_fps: u32,
+    /// Lowers the redraw rate while the window is unfocused
+    pub power_saving: bool,
+    /// Writes the peak frequency and level to the log file at `log_snapshot_interval_s`
+    pub log_snapshots: bool,
+    pub log_snapshot_interval_s: f32,
+    /// Saves the chart as a PNG to `auto_save_dir` every `auto_save_interval_s` while streaming
+    pub auto_save: bool,
+    pub auto_save_interval_s: f32,
+    pub auto_save_dir: String,
+    /// How long the overload indicator stays lit after the input reached full scale
+    pub overload_hold_s: f32,
+    /// Analyzed frames that aren't shown after the stream starts
+    pub warmup_frames: usize,
+    /// Queued FFT windows above which only the latest is analyzed, 0 analyzes all of them
+    pub max_queued_frames: usize,
+    /// Weights for mixing the input channels into the analyzed signal, empty for a flat downmix
+    pub channel_weights: Vec<f32>,
+    /// Downmix, a single channel, or left and right together
+    pub channel_view: ChannelView,
+    /// Oscilloscope instead of the spectrum
+    pub scope: bool,
+    /// Samples shown by the oscilloscope
+    pub scope_window: usize,
+    pub trigger: Trigger,
+    pub trigger_level: f32,
+    /// Soft limiter on the monitoring output
+    pub limiter: bool,
+    pub limiter_threshold_db: f32,
+    /// Holds the monitored audio back, e.g. to align it with another source
+    pub monitor_delay_ms: f32,
+    /// Times opening the monitor output is tried before giving up
+    pub monitor_attempts: u32,
+    /// What switching to an input with a different channel count does to a running recording
+    pub channel_change_policy: ChannelChangePolicy,
+    /// Fall of the peak caps in the bar chart in dB per frame
+    pub peak_cap_decay_db: f32,
+    /// Previous frames fading out behind the line chart
+    pub persistence_frames: usize,
+    pub persistence_decay: f32,
+    /// Width of the chart traces in pixels, `None` follows the display style
+    pub line_width: Option<u32>,
+    /// Smooth chart lines, read when the window is created
+    pub antialiasing: bool,
+    /// Size of the window when it was last resized, read when the window is created
+    pub window_size: (u32, u32)
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            host: None,
+            host_preference: vec![],
+            input_device: None,
+            share_mode: ShareMode::default(),
+            autostart: false,
+            probe_devices: false,
+            decimation: false,
+            fft_window_ms: DEFAULT_FFT_WINDOW_MS,
+            zero_padding: false,
+            pad_min_fill: DEFAULT_PAD_MIN_FILL,
+            low_cut: false,
+            low_cut_hz: DEFAULT_LOW_CUT_HZ,
+            silence_gate: false,
+            silence_gate_db: DEFAULT_SILENCE_GATE_DB,
+            freeze_on_clip: false,
+            unit_mode: UnitMode::default(),
+            db_floor: DB_FLOOR,
+            db_ceiling: 0.0,
+            x_unit: XAxisUnit::default(),
+            tilt: Tilt::default(),
+            custom_tilt_db: PINK_TILT_DB_PER_OCTAVE,
+            averaging: false,
+            chart_style: ChartStyle::default(),
+            display_style: DisplayStyle::default(),
+            trace_palette: TracePalette::default(),
+            fft_backend: FftBackend::default(),
+            band_width: None,
+            target_freq: None,
+            split_view: false,
+            follow_peak: false,
+            follow_peak_span_hz: DEFAULT_FOLLOW_SPAN_HZ,
+            full_spectrum: false,
+            max_display_points: DEFAULT_MAX_DISPLAY_POINTS,
+            peak_table: false,
+            peak_count: DEFAULT_PEAK_COUNT,
+            peak_threshold_db: DEFAULT_PEAK_THRESHOLD_DB,
+            ignore_dc: true,
+            ignored_low_bins: DEFAULT_IGNORED_LOW_BINS,
+            hold_peak_freq_max: false,
+            markers: false,
+            gain_db: 0.0,
+            waterfall: false,
+            waterfall_smoothing: 0.0,
+            waterfall_frames_per_row: 1,
+            waterfall_direction: WaterfallDirection::default(),
+            meter_attack_ms: DEFAULT_METER_ATTACK_MS,
+            meter_release_ms: DEFAULT_METER_RELEASE_MS,
+            crest_factor: false,
+            crest_smoothing: DEFAULT_CREST_SMOOTHING,
+            display_fps: DEFAULT_DISPLAY_FPS,
+            power_saving: false,
+            log_snapshots: false,
+            log_snapshot_interval_s: DEFAULT_LOG_SNAPSHOT_INTERVAL_S,
+            auto_save: false,
+            auto_save_interval_s: DEFAULT_AUTO_SAVE_INTERVAL_S,
+            auto_save_dir: String::from(DEFAULT_AUTO_SAVE_DIR),
+            overload_hold_s: DEFAULT_OVERLOAD_HOLD_S,
+            warmup_frames: DEFAULT_WARMUP_FRAMES,
+            max_queued_frames: DEFAULT_MAX_QUEUED_FRAMES,
+            channel_weights: vec![],
+            channel_view: ChannelView::default(),
+            scope: false,
+            scope_window: DEFAULT_WINDOW,
+            trigger: Trigger::default(),
+            trigger_level: 0.0,
+            limiter: true,
+            limiter_threshold_db: DEFAULT_LIMITER_THRESHOLD_DB,
+            monitor_delay_ms: 0.0,
+            monitor_attempts: DEFAULT_MONITOR_ATTEMPTS,
+            channel_change_policy: ChannelChangePolicy::default(),
+            peak_cap_decay_db: DEFAULT_PEAK_CAP_DECAY_DB,
+            persistence_frames: 0,
+            persistence_decay: DEFAULT_PERSISTENCE_DECAY,
+            line_width: None,
+            antialiasing: false,
+            window_size: DEFAULT_WINDOW_SIZE
+        }
+    }
+}
+
+impl UserSettings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, AudiaError> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|error| AudiaError::from(format!("Could not read {}: {error}", path.as_ref().display())))?;
+
+        toml::from_str(&contents)
+            .map_err(|error| AudiaError::from(format!("Invalid settings in {}: {error}", path.as_ref().display())))
+    }
+
+    /// Loads the settings, falling back to the defaults if the file is missing or invalid.
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        if !path.as_ref().exists() {
+            return Self::default();
+        }
+
+        Self::load(path).unwrap_or_else(|error| {
+            log::warn!("{}, using the default settings", error);
+            Self::default()
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), AudiaError> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|error| AudiaError::from(format!("Could not serialize settings: {error}")))?;
+
+        std::fs::write(path.as_ref(), contents)
+            .map_err(|error| AudiaError::from(format!("Could not write {}: {error}", path.as_ref().display())))?;
+
+        log::info!("Saved settings to {}", path.as_ref().display());
+
+        Ok(())
+    }
+}
+
+/// A named analysis setup. Presets leave the devices alone, so they can be shared between
+/// machines and switched without interrupting the stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: UserSettings
+}
+
+impl Preset {
+    pub fn new(name: &str, settings: UserSettings) -> Self {
+        Self {
+            name: String::from(name),
+            settings: UserSettings { host: None, host_preference: vec![], input_device: None, ..settings }
+        }
+    }
+
+    /// The name of the preset file, which `list` returns.
+    fn file_name(name: &str) -> String {
+        // keep the name from escaping the preset directory
+        name.chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ' ' { c } else { '_' })
+            .collect()
+    }
+
+    fn path<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+        dir.as_ref().join(format!("{}.toml", Self::file_name(name)))
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P, name: &str) -> Result<Self, AudiaError> {
+        let path = Self::path(dir, name);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|error| AudiaError::from(format!("Could not read preset {}: {error}", path.display())))?;
+
+        toml::from_str(&contents)
+            .map_err(|error| AudiaError::from(format!("Invalid preset in {}: {error}", path.display())))
+    }
+
+    /// Writes the preset to `dir` and returns the name it's listed and loaded by.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<String, AudiaError> {
+        std::fs::create_dir_all(dir.as_ref())
+            .map_err(|error| AudiaError::from(format!("Could not create {}: {error}", dir.as_ref().display())))?;
+
+        let path = Self::path(dir, &self.name);
+        let contents = toml::to_string_pretty(self)
+            .map_err(|error| AudiaError::from(format!("Could not serialize preset: {error}")))?;
+
+        std::fs::write(&path, contents)
+            .map_err(|error| AudiaError::from(format!("Could not write {}: {error}", path.display())))?;
+
+        log::info!("Saved preset {} to {}", self.name, path.display());
+
+        Ok(Self::file_name(&self.name))
+    }
+
+    /// Names of the presets in `dir`, sorted alphabetically.
+    pub fn list<P: AsRef<Path>>(dir: P) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .map(|entries| entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map(|extension| extension == "toml").unwrap_or(false))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .collect())
+            .unwrap_or_default();
+
+        names.sort();
+        names
+    }
+}
+
+/// Last modification time of the settings file, used to pick up external edits.
+pub fn modified_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::settings::{Preset, UserSettings};
+    use crate::ui::units::UnitMode;
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let settings = UserSettings {
+            input_device: Some(String::from("USB Audio")),
+            unit_mode: UnitMode::DbFs,
+            split_view: true,
+            gain_db: -6.0,
+            ..UserSettings::default()
+        };
+
+        let contents = toml::to_string_pretty(&settings).unwrap();
+
+        assert_eq!(toml::from_str::<UserSettings>(&contents).unwrap(), settings);
+    }
+
+    #[test]
+    fn missing_entries_use_defaults() {
+        let settings: UserSettings = toml::from_str("averaging = true").unwrap();
+
+        assert!(settings.averaging);
+        assert_eq!(settings.input_device, None);
+        assert_eq!(settings.unit_mode, UnitMode::default());
+    }
+
+    #[test]
+    fn presets_leave_out_the_devices() {
+        let settings = UserSettings {
+            host: Some(String::from("ALSA")),
+            host_preference: vec![String::from("JACK"), String::from("ALSA")],
+            input_device: Some(String::from("USB Audio")),
+            averaging: true,
+            ..UserSettings::default()
+        };
+
+        let preset = Preset::new("Room", settings);
+        let contents = toml::to_string_pretty(&preset).unwrap();
+        let loaded: Preset = toml::from_str(&contents).unwrap();
+
+        assert_eq!(loaded.name, "Room");
+        assert!(loaded.settings.averaging);
+        assert_eq!(loaded.settings.input_device, None);
+        assert_eq!(loaded.settings.host, None);
+        assert!(loaded.settings.host_preference.is_empty());
+    }
+
+    #[test]
+    fn saved_presets_are_listed_by_their_file_name() {
+        let dir = std::env::temp_dir().join("audia-test-presets");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let name = Preset::new("Room/Stage", UserSettings::default()).save(&dir).unwrap();
+        assert_eq!(name, "Room_Stage");
+        assert_eq!(Preset::list(&dir), vec![name.clone()]);
+        assert_eq!(Preset::load(&dir, &name).unwrap().name, "Room/Stage");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,60 +1,961 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
 use std::ops::Range;
-use iced::{Element, Length};
-use plotters::backend::DrawingBackend;
-use plotters::chart::ChartBuilder;
-use plotters::series::LineSeries;
-use plotters::style::BLACK;
-use plotters_iced::{Chart, ChartWidget};
-use crate::engine::PacketType;
+use std::path::Path;
+use iced::{Element, Length, mouse, Rectangle, Size};
+use iced::event::Status;
+use iced::widget::canvas::{Cache, Event, Frame, Geometry};
+use plotters::backend::{BitMapBackend, DrawingBackend};
+use plotters::chart::{ChartBuilder, SeriesLabelPosition};
+use plotters::drawing::IntoDrawingArea;
+use plotters::element::{Circle, EmptyElement, PathElement, Rectangle as PlotRectangle, Text};
+use plotters::series::{AreaSeries, LineSeries};
+use plotters::style::{Color, IntoFont, RED, ShapeStyle};
+use plotters_iced::{Chart, ChartWidget, Renderer};
+use serde::{Deserialize, Serialize};
+use crate::engine::{AudiaError, PacketType};
+use crate::engine::analysis::{Band, BandWidth};
 use crate::ui::UIMessage;
+use crate::ui::style::{DisplayStyle, TracePalette};
+use crate::ui::units::{self, DbRange, PINK_TILT_DB_PER_OCTAVE, Tilt, UnitMode, XAxisUnit};
+
+/// Frequency range of the default (zoomed) chart
+pub const LOW_FREQUENCY_RANGE: Range<i32> = 0..2000;
+
+/// Size of exported chart images in pixels
+pub const EXPORT_SIZE: (u32, u32) = (1200, 700);
+
+/// Default number of points drawn per spectrum chart
+pub const DEFAULT_MAX_DISPLAY_POINTS: usize = 1024;
+
+/// Default width of the frequency axis while it follows the peak
+pub const DEFAULT_FOLLOW_SPAN_HZ: f32 = 400.0;
+
+/// Default fall of the bar peak caps in dB per frame
+pub const DEFAULT_PEAK_CAP_DECAY_DB: f32 = 0.5;
+
+/// Default opacity of a persisted trace relative to the next newer one
+pub const DEFAULT_PERSISTENCE_DECAY: f32 = 0.7;
+
+/// How far the followed center moves towards the current peak per frame
+const FOLLOW_SMOOTHING: f32 = 0.2;
+
+/// A frozen copy of the spectrum for comparing against later measurements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub label: String,
+    /// Milliseconds since the Unix epoch when the snapshot was taken
+    pub taken_at_ms: u64,
+    pub points: Vec<(i32, f32)>,
+    pub visible: bool
+}
+
+/// A chart rendered to RGB pixels, which can be written to a file away from the UI thread.
+pub struct ChartImage {
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32
+}
+
+impl ChartImage {
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), AudiaError> {
+        image::save_buffer(path.as_ref(), &self.pixels, self.width, self.height, image::ColorType::Rgb8)
+            .map_err(|error| AudiaError::from(format!("Could not save {}: {error}", path.as_ref().display())))
+    }
+}
+
+/// Reduces the points to at most `target_points` for rendering by keeping the strongest point of
+/// each run of neighbours, so narrow peaks survive. A `target_points` of 0 keeps all points.
+pub fn decimate_for_display(points: &[(i32, f32)], target_points: usize) -> Vec<(i32, f32)> {
+    if target_points == 0 || points.len() <= target_points {
+        return points.to_vec();
+    }
+
+    let chunk_size = points.len().div_ceil(target_points);
+
+    points.chunks(chunk_size)
+        .filter_map(|chunk| chunk.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1)))
+        .collect()
+}
+
+/// How the spectrum is rendered on the chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChartStyle {
+    #[default]
+    Line,
+    Bars,
+    Filled
+}
+
+impl ChartStyle {
+    pub const ALL: [ChartStyle; 3] = [ChartStyle::Line, ChartStyle::Bars, ChartStyle::Filled];
+}
+
+impl Display for ChartStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChartStyle::Line => "Line",
+            ChartStyle::Bars => "Bars",
+            ChartStyle::Filled => "Filled"
+        })
+    }
+}
 
 pub struct Spectrogram {
     pub user_data: usize,
     pub current_buf: PacketType,
     pub peak_freq: f32,
-    pub freq_data: Vec<(i32, f32)>
+    pub peak_amp: f32,
+    /// The latest frame was below the silence gate, so there is no peak
+    pub silent: bool,
+    /// Latches the highest peak frequency seen since the last reset
+    pub hold_peak_freq_max: bool,
+    /// Highest peak frequency since the last reset, 0 if none was above the threshold yet
+    pub peak_freq_max: f32,
+    /// Centers the frequency axis of the main chart on the peak
+    pub follow_peak: bool,
+    /// Width of the frequency axis while following the peak
+    pub follow_span: f32,
+    /// Smoothed peak frequency the axis is centered on
+    follow_center: f32,
+    /// Strongest peaks of the latest frame for the peak table, strongest first
+    pub peaks: Vec<(f32, f32)>,
+    /// Amplitudes relative to full scale per frequency
+    pub freq_data: Vec<(i32, f32)>,
+    pub unit_mode: UnitMode,
+    /// Floor and ceiling of the amplitude axis in dBFS mode
+    pub db_range: DbRange,
+    pub x_unit: XAxisUnit,
+    /// Slope added to the drawn spectrum, the bands and the readouts stay untilted
+    pub tilt: Tilt,
+    /// Slope of `Tilt::Custom` in dB per octave
+    pub custom_tilt_db: f32,
+    pub averaging: bool,
+    /// Number of frames folded into the running average since the last reset
+    pub average_count: usize,
+    average_power: Vec<f32>,
+    /// Shows the energy average over the whole measurement (Leq) instead of the latest frame
+    pub leq: bool,
+    /// Power per bin summed since the measurement started
+    energy: Vec<(i32, f64)>,
+    energy_frames: usize,
+    /// Audio time covered by the measurement in seconds
+    pub measured_time: f64,
+    /// Frequency under the mouse cursor, if it is over the plotting area
+    pub hover_freq: Option<f32>,
+    /// Lets clicks on the chart place the two delta markers
+    pub markers_enabled: bool,
+    /// Frequencies of the two delta markers, placed with the left and the right mouse button
+    pub markers: [Option<f32>; 2],
+    pub chart_style: ChartStyle,
+    /// Bands shown instead of the raw spectrum when the band analyzer is enabled
+    pub band_width: Option<BandWidth>,
+    pub bands: Vec<Band>,
+    pub band_levels: Vec<f32>,
+    /// Frequencies flagged by the feedback detector
+    pub feedback_freqs: Vec<f32>,
+    /// Spectrum of the right channel, drawn over the left one in the dual channel view
+    pub second_freq_data: Vec<(i32, f32)>,
+    /// Frozen spectra drawn over the live one while visible
+    pub snapshots: Vec<Snapshot>,
+    /// Recent peak per bin, drawn as caps on top of the bars
+    peak_caps: Vec<(i32, f32)>,
+    /// How fast the peak caps fall in dB per frame
+    pub peak_cap_decay_db: f32,
+    /// Previous frames fading out behind the line chart, newest first
+    persistence: VecDeque<Vec<(i32, f32)>>,
+    /// Number of previous frames kept for the persistence, 0 turns it off
+    pub persistence_frames: usize,
+    /// Opacity of each persisted frame relative to the next newer one
+    pub persistence_decay: f32,
+    pub style: DisplayStyle,
+    pub palette: TracePalette,
+    /// Width of the traces in pixels, `None` follows the display style
+    pub line_width: Option<u32>,
+    /// Points drawn per chart at most, 0 draws every bin
+    pub max_display_points: usize,
+    /// Rendered charts, only redrawn after `redraw` was called
+    zoom_cache: Cache,
+    overview_cache: Cache
 }
 
 impl Spectrogram {
     pub fn view(&self) -> Element<UIMessage> {
-        ChartWidget::new(self)
+        self.view_range(self.followed_range(LOW_FREQUENCY_RANGE), &self.zoom_cache)
+    }
+
+    /// The main chart from 0 Hz up to `max_freq`, for the full spectrum.
+    pub fn view_full(&self, max_freq: i32) -> Element<UIMessage> {
+        self.view_range(self.followed_range(0..max_freq), &self.zoom_cache)
+    }
+
+    /// Renders the main chart as it is shown, up to `max_freq` for the full spectrum.
+    pub fn render(&self, max_freq: Option<i32>) -> Result<ChartImage, AudiaError> {
+        let x_range = self.followed_range(max_freq.map_or(LOW_FREQUENCY_RANGE, |max_freq| 0..max_freq));
+        let (width, height) = EXPORT_SIZE;
+        let mut pixels = vec![0; (width * height * 3) as usize];
+        let to_error = |error| AudiaError::from(format!("Could not render the chart: {error:?}"));
+
+        {
+            let root = BitMapBackend::with_buffer(&mut pixels, EXPORT_SIZE).into_drawing_area();
+            root.fill(&self.style.chart_background()).map_err(to_error)?;
+
+            let chart = SpectrumChart { spectrogram: self, x_range, cache: &self.zoom_cache };
+            chart.build_chart(&false, ChartBuilder::on(&root.margin(10, 10, 10, 10)));
+            root.present().map_err(to_error)?;
+        }
+
+        Ok(ChartImage { pixels, width, height })
+    }
+
+    /// Moves the followed center towards the current peak, if it is louder than `min_amp`.
+    pub fn follow(&mut self, min_amp: f32) {
+        if !self.follow_peak || self.peak_amp < min_amp {
+            return;
+        }
+
+        if self.follow_center <= 0.0 {
+            self.follow_center = self.peak_freq;
+        } else {
+            self.follow_center += (self.peak_freq - self.follow_center) * FOLLOW_SMOOTHING;
+        }
+    }
+
+    /// The part of `bounds` shown while following the peak: `follow_span` wide around the
+    /// smoothed peak, shifted to stay inside `bounds`.
+    fn followed_range(&self, bounds: Range<i32>) -> Range<i32> {
+        let span = self.follow_span.round() as i32;
+
+        if !self.follow_peak || self.follow_center <= 0.0 || span >= bounds.end - bounds.start {
+            return bounds;
+        }
+
+        let start = (self.follow_center.round() as i32 - span / 2).clamp(bounds.start, bounds.end - span);
+
+        start..start + span
+    }
+
+    /// The same data from 0 Hz up to `max_freq`.
+    pub fn view_overview(&self, max_freq: i32) -> Element<UIMessage> {
+        self.view_range(0..max_freq, &self.overview_cache)
+    }
+
+    fn view_range<'a>(&'a self, x_range: Range<i32>, cache: &'a Cache) -> Element<'a, UIMessage> {
+        ChartWidget::new(SpectrumChart { spectrogram: self, x_range, cache })
             .width(Length::Fill)
             .height(Length::Fill)
             .into()
     }
 
+    /// Marks the charts as outdated so they are rendered again on the next frame.
+    pub fn redraw(&self) {
+        self.zoom_cache.clear();
+        self.overview_cache.clear();
+    }
+
     pub fn new() -> Self {
         Self {
             user_data: 0,
             current_buf: vec![],
             peak_freq: 0.0,
-            freq_data: vec![]
+            peak_amp: 0.0,
+            silent: false,
+            hold_peak_freq_max: false,
+            peak_freq_max: 0.0,
+            follow_peak: false,
+            follow_span: DEFAULT_FOLLOW_SPAN_HZ,
+            follow_center: 0.0,
+            peaks: vec![],
+            freq_data: vec![],
+            unit_mode: UnitMode::default(),
+            db_range: DbRange::default(),
+            x_unit: XAxisUnit::default(),
+            tilt: Tilt::default(),
+            custom_tilt_db: PINK_TILT_DB_PER_OCTAVE,
+            averaging: false,
+            average_count: 0,
+            average_power: vec![],
+            leq: false,
+            energy: vec![],
+            energy_frames: 0,
+            measured_time: 0.0,
+            hover_freq: None,
+            markers_enabled: false,
+            markers: [None; 2],
+            chart_style: ChartStyle::default(),
+            band_width: None,
+            bands: vec![],
+            band_levels: vec![],
+            feedback_freqs: vec![],
+            second_freq_data: vec![],
+            snapshots: vec![],
+            peak_caps: vec![],
+            peak_cap_decay_db: DEFAULT_PEAK_CAP_DECAY_DB,
+            persistence: VecDeque::new(),
+            persistence_frames: 0,
+            persistence_decay: DEFAULT_PERSISTENCE_DECAY,
+            style: DisplayStyle::default(),
+            palette: TracePalette::default(),
+            line_width: None,
+            max_display_points: DEFAULT_MAX_DISPLAY_POINTS,
+            zoom_cache: Cache::new(),
+            overview_cache: Cache::new()
+        }
+    }
+
+    /// The data point closest to the hovered frequency.
+    fn hovered_point(&self, x_range: &Range<i32>) -> Option<(i32, f32)> {
+        let hover_freq = self.hover_freq?;
+
+        self.displayed_points().iter()
+            .filter(|(freq, _)| x_range.contains(freq))
+            .min_by(|a, b| (a.0 as f32 - hover_freq).abs().total_cmp(&(b.0 as f32 - hover_freq).abs()))
+            .copied()
+    }
+
+    /// Places marker `index`, 0 or 1, at `freq`.
+    pub fn set_marker(&mut self, index: usize, freq: f32) {
+        if let Some(marker) = self.markers.get_mut(index) {
+            *marker = Some(freq.max(0.0));
+        }
+    }
+
+    pub fn clear_markers(&mut self) {
+        self.markers = [None; 2];
+    }
+
+    /// The displayed points closest to the two markers.
+    pub fn marker_points(&self) -> [Option<(i32, f32)>; 2] {
+        let points = self.displayed_points();
+
+        self.markers.map(|marker| {
+            let marker = marker?;
+
+            points.iter()
+                .min_by(|a, b| (a.0 as f32 - marker).abs().total_cmp(&(b.0 as f32 - marker).abs()))
+                .copied()
+        })
+    }
+
+    /// Frequency and level of the second marker relative to the first, in Hz and dB.
+    pub fn marker_delta(&self) -> Option<(f32, f32)> {
+        let [Some((first_freq, first_amp)), Some((second_freq, second_amp))] = self.marker_points() else {
+            return None;
+        };
+
+        Some(((second_freq - first_freq) as f32, units::to_dbfs(second_amp) - units::to_dbfs(first_amp)))
+    }
+
+    /// The latest spectrum, or the Leq spectrum once a measurement is running.
+    fn displayed_points(&self) -> Vec<(i32, f32)> {
+        if self.leq && self.energy_frames > 0 {
+            let frames = self.energy_frames as f64;
+
+            self.energy.iter()
+                .map(|(freq, energy)| (*freq, (energy / frames).sqrt() as f32))
+                .collect()
+        } else {
+            self.freq_data.clone()
+        }
+    }
+
+    /// Freezes the displayed spectrum as a new visible snapshot.
+    pub fn take_snapshot(&mut self, label: String, taken_at_ms: u64) {
+        let points = self.displayed_points();
+        self.snapshots.push(Snapshot { label, taken_at_ms, points, visible: true });
+    }
+
+    pub fn toggle_snapshot(&mut self, index: usize) {
+        if let Some(snapshot) = self.snapshots.get_mut(index) {
+            snapshot.visible = !snapshot.visible;
+        }
+    }
+
+    pub fn remove_snapshot(&mut self, index: usize) {
+        if index < self.snapshots.len() {
+            self.snapshots.remove(index);
+        }
+    }
+
+    pub fn clear_snapshots(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Raises the peak caps to the current spectrum and lets the others fall exponentially.
+    pub fn update_peak_caps(&mut self) {
+        let same_bins = self.peak_caps.len() == self.freq_data.len()
+            && self.peak_caps.iter().zip(&self.freq_data).all(|(cap, point)| cap.0 == point.0);
+
+        if !same_bins {
+            self.peak_caps = self.freq_data.clone();
+            return;
+        }
+
+        let decay = 10f32.powf(-self.peak_cap_decay_db / 20.0);
+
+        for (cap, (_, amp)) in self.peak_caps.iter_mut().zip(&self.freq_data) {
+            cap.1 = amp.max(cap.1 * decay);
+        }
+    }
+
+    /// Keeps the outgoing frame for the persistence, to be called before `freq_data` is replaced.
+    pub fn persist_frame(&mut self) {
+        if self.persistence_frames > 0 && !self.freq_data.is_empty() {
+            self.persistence.push_front(self.freq_data.clone());
+        }
+        self.persistence.truncate(self.persistence_frames);
+    }
+
+    pub fn set_persistence_frames(&mut self, frames: usize) {
+        self.persistence_frames = frames;
+        self.persistence.truncate(frames);
+    }
+
+    pub fn clear_persistence(&mut self) {
+        self.persistence.clear();
+    }
+
+    /// Width of the traces, the chosen one or the display style's.
+    pub fn line_width(&self) -> u32 {
+        self.line_width.unwrap_or(self.style.line_width())
+    }
+
+    /// Position of an amplitude on the amplitude axis, tilted by its frequency.
+    fn scaled(&self, freq: i32, amp: f32) -> f32 {
+        self.unit_mode.scale(units::apply_tilt(freq as f32, amp, self.tilt.db_per_octave(self.custom_tilt_db)), self.db_range)
+    }
+
+    /// The persisted frames with their opacity, oldest (faintest) first.
+    fn persistence_traces(&self) -> impl Iterator<Item = (&Vec<(i32, f32)>, f64)> {
+        self.persistence.iter()
+            .enumerate()
+            .rev()
+            .map(|(index, frame)| (frame, self.persistence_decay.powi(index as i32 + 1) as f64))
+    }
+
+    pub fn reset_energy(&mut self) {
+        self.energy.clear();
+        self.energy_frames = 0;
+        self.measured_time = 0.0;
+    }
+
+    /// Adds a frame covering `duration` seconds of audio to the Leq measurement. Unlike the
+    /// running average nothing is forgotten, every frame weighs the same.
+    pub fn accumulate_energy(&mut self, points: &[(i32, f32)], duration: f64) {
+        if self.energy.len() != points.len() {
+            self.reset_energy();
+            self.energy = points.iter().map(|(freq, _)| (*freq, 0.0)).collect();
+        }
+
+        for ((_, energy), (_, amp)) in self.energy.iter_mut().zip(points) {
+            *energy += (*amp as f64) * (*amp as f64);
+        }
+
+        self.energy_frames += 1;
+        self.measured_time += duration;
+    }
+
+    /// Blanks the display for a frame below the silence gate: a flat line without a peak.
+    pub fn show_silence(&mut self, points: &[(i32, f32)]) {
+        self.silent = true;
+        self.freq_data = points.iter().map(|(freq, _)| (*freq, 0.0)).collect();
+        self.band_levels = vec![0.0; self.bands.len()];
+        self.peak_freq = 0.0;
+        self.peak_amp = 0.0;
+        self.peaks.clear();
+    }
+
+    pub fn reset_peak_freq_max(&mut self) {
+        self.peak_freq_max = 0.0;
+    }
+
+    /// Raises the held maximum to the current peak if it is louder than `min_amp`, so the noise
+    /// floor of quiet frames doesn't latch a random frequency.
+    pub fn hold_peak_freq(&mut self, min_amp: f32) {
+        if self.hold_peak_freq_max && self.peak_amp >= min_amp && self.peak_freq > self.peak_freq_max {
+            self.peak_freq_max = self.peak_freq;
         }
     }
+
+    pub fn reset_average(&mut self) {
+        self.average_count = 0;
+        self.average_power.clear();
+    }
+
+    /// Folds a new frame into the running (power) average and returns the averaged spectrum. The
+    /// average restarts whenever the number of bins changes.
+    pub fn accumulate(&mut self, points: Vec<(i32, f32)>) -> Vec<(i32, f32)> {
+        if self.average_power.len() != points.len() {
+            self.reset_average();
+            self.average_power = vec![0.0; points.len()];
+        }
+
+        self.average_count += 1;
+        let weight = 1.0 / self.average_count as f32;
+
+        points.into_iter()
+            .zip(self.average_power.iter_mut())
+            .map(|((freq, amp), power)| {
+                *power += (amp * amp - *power) * weight;
+                (freq, power.sqrt())
+            })
+            .collect()
+    }
 }
 
-impl Chart<UIMessage> for Spectrogram {
-    type State = u64;
+impl Spectrogram {
+    /// Draws one bar per fractional-octave band, labelled with the nominal center frequencies.
+    fn build_band_chart<DB: DrawingBackend>(&self, mut builder: ChartBuilder<DB>) {
+        let band_count = self.bands.len();
+        // band i is centered on x = i so the labels line up with the bars
+        let x_range: Range<f32> = -0.5..(band_count as f32 - 0.5);
+        let y_range: Range<f32> = self.unit_mode.range(self.db_range);
+        let baseline = y_range.start;
+
+        let mut chart = builder
+            .set_all_label_area_size(self.style.label_area_size())
+            .build_cartesian_2d(x_range, y_range)
+            .expect("Failed to build chart");
+
+        self.style.fill_background(&chart);
+
+        let mut mesh = chart.configure_mesh();
+        self.style.apply(&mut mesh);
+        mesh.x_labels(band_count)
+            .x_label_formatter(&|value| {
+                let index = value.round();
+
+                if (value - index).abs() < 0.01 && index >= 0.0 {
+                    self.bands.get(index as usize).map(Band::label).unwrap_or_default()
+                } else {
+                    String::new()
+                }
+            })
+            .y_label_formatter(&|value| self.unit_mode.format_scaled(*value))
+            .draw()
+            .expect("Failed to draw mesh");
+
+        chart.draw_series(self.band_levels.iter().enumerate().map(|(index, level)| {
+            let x = index as f32;
+            PlotRectangle::new([(x - 0.4, baseline), (x + 0.4, self.unit_mode.scale(*level, self.db_range))], self.style.line_color().filled())
+        }))
+            .expect("Failed to draw bands");
+    }
+}
+
+/// A view of the spectrogram over one frequency range.
+struct SpectrumChart<'a> {
+    spectrogram: &'a Spectrogram,
+    x_range: Range<i32>,
+    cache: &'a Cache
+}
+
+impl Chart<UIMessage> for SpectrumChart<'_> {
+    /// Whether the cursor was over this chart's plotting area
+    type State = bool;
 
     fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut builder: ChartBuilder<DB>) {
-        let x_range: Range<i32> = 0..2000;
-        let y_range: Range<f32> = 0.0..2048.0;
+        let spectrogram = self.spectrogram;
+
+        if spectrogram.band_width.is_some() {
+            return spectrogram.build_band_chart(builder);
+        }
+
+        let x_range: Range<i32> = self.x_range.clone();
+        let y_range: Range<f32> = spectrogram.unit_mode.range(spectrogram.db_range);
+        let style = spectrogram.style;
+        let line_width = spectrogram.line_width();
+        let line = style.line_color().stroke_width(line_width);
 
         let mut chart = builder
-            .set_all_label_area_size(40)
+            .set_all_label_area_size(style.label_area_size())
             .build_cartesian_2d(x_range, y_range)
             .expect("Failed to build chart");
 
-        // TODO try to avoid cloning here
-        let series = LineSeries::new(self.freq_data.clone(), &BLACK);
+        style.fill_background(&chart);
 
-        chart.configure_mesh()
+        let mut mesh = chart.configure_mesh();
+        style.apply(&mut mesh);
+        mesh.x_labels(spectrogram.x_unit.label_count())
+            .x_label_formatter(&|value| spectrogram.x_unit.format(*value as f32))
+            .y_label_formatter(&|value| spectrogram.unit_mode.format_scaled(*value))
             .draw()
             .expect("Failed to draw mesh");
 
-        chart.draw_series(series)
-            .expect("Failed to draw series");
+        let displayed: Vec<(i32, f32)> = spectrogram.displayed_points().into_iter()
+            .filter(|(freq, _)| self.x_range.contains(freq))
+            .collect();
+        let points = decimate_for_display(&displayed, spectrogram.max_display_points).into_iter()
+            .map(|(freq, amp)| (freq, spectrogram.scaled(freq, amp)));
+        let baseline = spectrogram.unit_mode.range(spectrogram.db_range).start;
+        let top = spectrogram.unit_mode.range(spectrogram.db_range).end;
+
+        match spectrogram.chart_style {
+            ChartStyle::Line => {
+                for (frame, opacity) in spectrogram.persistence_traces() {
+                    let shown: Vec<(i32, f32)> = frame.iter()
+                        .filter(|(freq, _)| self.x_range.contains(freq))
+                        .copied()
+                        .collect();
+                    let faded = decimate_for_display(&shown, spectrogram.max_display_points).into_iter()
+                        .map(|(freq, amp)| (freq, spectrogram.scaled(freq, amp)));
+
+                    chart.draw_series(LineSeries::new(faded, style.line_color().mix(opacity).stroke_width(line_width)))
+                        .expect("Failed to draw persistence");
+                }
+
+                chart.draw_series(LineSeries::new(points, line))
+                    .expect("Failed to draw series");
+            },
+            ChartStyle::Bars => {
+                // leave a one pixel-ish gap between neighbouring bins
+                let bin_width = match spectrogram.freq_data.as_slice() {
+                    [first, second, ..] => second.0 - first.0,
+                    _ => 1
+                };
+                let half_width = (bin_width / 2 - 1).max(1);
+
+                chart.draw_series(points.map(|(freq, value)| {
+                    PlotRectangle::new([(freq - half_width, baseline), (freq + half_width, value)], style.line_color().filled())
+                }))
+                    .expect("Failed to draw series");
+
+                let caps: Vec<(i32, f32)> = spectrogram.peak_caps.iter()
+                    .filter(|(freq, _)| self.x_range.contains(freq))
+                    .copied()
+                    .collect();
+                let cap_height = (top - baseline) / 100.0;
+
+                chart.draw_series(decimate_for_display(&caps, spectrogram.max_display_points).into_iter().map(|(freq, amp)| {
+                    let value = spectrogram.scaled(freq, amp);
+                    PlotRectangle::new([(freq - half_width, value), (freq + half_width, value + cap_height)], style.foreground().filled())
+                }))
+                    .expect("Failed to draw peak caps");
+            },
+            ChartStyle::Filled => {
+                chart.draw_series(AreaSeries::new(points, baseline, style.line_color().mix(0.2)).border_style(line))
+                    .expect("Failed to draw series");
+            }
+        }
+
+        let mut has_snapshots = false;
+
+        // the second channel is the first overlay, the snapshots follow
+        for (index, snapshot) in spectrogram.snapshots.iter().enumerate().filter(|(_, snapshot)| snapshot.visible) {
+            let color = spectrogram.palette.color(style, index + 1);
+            let shown: Vec<(i32, f32)> = snapshot.points.iter()
+                .filter(|(freq, _)| self.x_range.contains(freq))
+                .copied()
+                .collect();
+            let snapshot_points = decimate_for_display(&shown, spectrogram.max_display_points).into_iter()
+                .map(|(freq, amp)| (freq, spectrogram.scaled(freq, amp)))
+                .collect();
+
+            spectrogram.palette.dash(index + 1).draw(&mut chart, snapshot_points, color.stroke_width(line_width))
+                .label(snapshot.label.as_str())
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 16, y)], color.stroke_width(2)));
+            has_snapshots = true;
+        }
+
+        if has_snapshots {
+            chart.configure_series_labels()
+                .position(SeriesLabelPosition::UpperRight)
+                .background_style(style.chart_background().mix(0.8))
+                .border_style(style.foreground())
+                .label_font(("sans-serif", style.label_font_size() + 2).into_font().color(&style.foreground()))
+                .draw()
+                .expect("Failed to draw snapshot legend");
+        }
+
+        if !spectrogram.second_freq_data.is_empty() {
+            let second: Vec<(i32, f32)> = spectrogram.second_freq_data.iter()
+                .filter(|(freq, _)| self.x_range.contains(freq))
+                .copied()
+                .collect();
+            let second_points = decimate_for_display(&second, spectrogram.max_display_points).into_iter()
+                .map(|(freq, amp)| (freq, spectrogram.scaled(freq, amp)))
+                .collect();
+            let second_color = spectrogram.palette.color(style, 0);
+
+            spectrogram.palette.dash(0).draw(&mut chart, second_points, second_color.stroke_width(line_width));
+
+            let font_size = style.label_font_size() + 2;
+            let corner = (self.x_range.start, top);
+            chart.draw_series(std::iter::once(EmptyElement::at(corner)
+                + Text::new("Left", (8, 4), ("sans-serif", font_size).into_font().color(&style.line_color()))
+                + Text::new("Right", (8 + 4 * font_size as i32, 4), ("sans-serif", font_size).into_font().color(&second_color))))
+                .expect("Failed to draw legend");
+        }
+
+        for freq in spectrogram.feedback_freqs.iter().map(|freq| freq.round() as i32).filter(|freq| self.x_range.contains(freq)) {
+            chart.draw_series(std::iter::once(PathElement::new(vec![(freq, baseline), (freq, top)], RED.stroke_width(2))))
+                .expect("Failed to draw feedback marker");
+            chart.draw_series(std::iter::once(Text::new(format!("Feedback {} Hz", freq), (freq, top), ("sans-serif", style.label_font_size() + 2).into_font().color(&RED))))
+                .expect("Failed to draw feedback marker");
+        }
+
+        if spectrogram.markers_enabled {
+            let markers = spectrogram.markers.iter()
+                .enumerate()
+                .filter_map(|(index, marker)| marker.map(|freq| (index, freq.round() as i32)))
+                .filter(|(_, freq)| self.x_range.contains(freq));
+
+            for (index, freq) in markers {
+                chart.draw_series(std::iter::once(PathElement::new(vec![(freq, baseline), (freq, top)], style.foreground().stroke_width(1))))
+                    .expect("Failed to draw marker");
+                chart.draw_series(std::iter::once(Text::new(format!("M{}", index + 1), (freq, top), ("sans-serif", style.label_font_size() + 2).into_font().color(&style.foreground()))))
+                    .expect("Failed to draw marker");
+            }
+        }
+
+        if spectrogram.silent {
+            let center = ((self.x_range.start + self.x_range.end) / 2, (baseline + top) / 2.0);
+            chart.draw_series(std::iter::once(Text::new("Silence", center, ("sans-serif", style.label_font_size() * 2).into_font().color(&style.foreground()))))
+                .expect("Failed to draw silence label");
+        }
+
+        if let Some((freq, amp)) = spectrogram.hovered_point(&self.x_range) {
+            let tooltip = EmptyElement::at((freq, spectrogram.scaled(freq, amp)))
+                + Circle::new((0, 0), 2 + line_width, ShapeStyle::from(&style.foreground()).filled())
+                + Text::new(format!("{} Hz{}, {}", freq,
+                    if spectrogram.x_unit == XAxisUnit::Note { format!(" ({})", units::note_name(freq as f32)) } else { String::new() },
+                    spectrogram.unit_mode.format(amp)), (8, -16), ("sans-serif", style.label_font_size() + 2).into_font().color(&style.foreground()));
+
+            chart.draw_series(std::iter::once(tooltip))
+                .expect("Failed to draw tooltip");
+        }
+    }
+
+    fn draw<R: Renderer, F: Fn(&mut Frame)>(&self, renderer: &R, bounds: Size, draw_fn: F) -> Geometry {
+        renderer.draw_cache(self.cache, bounds, draw_fn)
+    }
+
+    fn update(&self, hovered: &mut Self::State, event: Event, bounds: Rectangle, cursor: mouse::Cursor) -> (Status, Option<UIMessage>) {
+        match event {
+            Event::Mouse(mouse::Event::CursorMoved { .. }) | Event::Mouse(mouse::Event::CursorLeft) => {
+                let hover_freq = self.freq_at(bounds, cursor);
 
+                // with several charts only the one under the cursor (or just left by it) reports
+                if hover_freq.is_none() && !*hovered {
+                    return (Status::Ignored, None);
+                }
+                *hovered = hover_freq.is_some();
+
+                (Status::Ignored, Some(UIMessage::ChartHover(hover_freq)))
+            },
+            Event::Mouse(mouse::Event::ButtonPressed(button @ (mouse::Button::Left | mouse::Button::Right)))
+                if self.spectrogram.markers_enabled && self.spectrogram.band_width.is_none() => {
+                match self.freq_at(bounds, cursor) {
+                    Some(freq) => (Status::Captured, Some(UIMessage::SetMarker(usize::from(button == mouse::Button::Right), freq))),
+                    None => (Status::Ignored, None)
+                }
+            },
+            _ => (Status::Ignored, None)
+        }
+    }
+}
+
+impl SpectrumChart<'_> {
+    /// Frequency under the cursor, if it is over the plotting area.
+    fn freq_at(&self, bounds: Rectangle, cursor: mouse::Cursor) -> Option<f32> {
+        let label_area = self.spectrogram.style.label_area_size() as f32;
+        let plot_width = bounds.width - 2.0 * label_area;
+        let plot_height = bounds.height - 2.0 * label_area;
+
+        cursor.position_in(bounds)
+            .filter(|position| {
+                position.x >= label_area && position.x <= label_area + plot_width
+                    && position.y >= label_area && position.y <= label_area + plot_height
+            })
+            .map(|position| {
+                let x_range = &self.x_range;
+                let ratio = (position.x - label_area) / plot_width;
+
+                x_range.start as f32 + ratio * (x_range.end - x_range.start) as f32
+            })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::ui::spectrogram::{decimate_for_display, Spectrogram};
+    use crate::ui::style::DisplayStyle;
+
+    #[test]
+    fn leq_averages_energy_over_the_whole_measurement() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.leq = true;
+
+        spectrogram.accumulate_energy(&[(100, 1.0)], 0.5);
+        for _ in 0..3 {
+            spectrogram.accumulate_energy(&[(100, 0.0)], 0.5);
+        }
+
+        // a quarter of the energy, so half the amplitude
+        assert_eq!(spectrogram.displayed_points(), vec![(100, 0.5)]);
+        assert_eq!(spectrogram.measured_time, 2.0);
+
+        spectrogram.reset_energy();
+        assert_eq!(spectrogram.measured_time, 0.0);
+    }
+
+    #[test]
+    fn display_decimation_keeps_the_peaks() {
+        let mut points: Vec<(i32, f32)> = (0..4096).map(|freq| (freq, 0.01)).collect();
+        points[1234].1 = 0.9;
+        points[4095].1 = 0.5;
+
+        let decimated = decimate_for_display(&points, 1000);
+
+        assert!(decimated.len() <= 1000);
+        assert!(decimated.contains(&(1234, 0.9)));
+        assert!(decimated.contains(&(4095, 0.5)));
+        assert_eq!(decimate_for_display(&points[..10], 1000), points[..10].to_vec());
+        assert_eq!(decimate_for_display(&points, 0).len(), 4096);
+    }
+
+    #[test]
+    fn followed_range_is_centered_on_the_smoothed_peak() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.follow_peak = true;
+        spectrogram.follow_span = 400.0;
+
+        (spectrogram.peak_freq, spectrogram.peak_amp) = (1000.0, 0.5);
+        spectrogram.follow(0.01);
+        assert_eq!(spectrogram.followed_range(0..2000), 800..1200);
+
+        // a jump only moves the center part of the way
+        spectrogram.peak_freq = 1500.0;
+        spectrogram.follow(0.01);
+        assert_eq!(spectrogram.followed_range(0..2000), 900..1300);
+
+        // quiet frames don't move it, and the range stays inside the bounds
+        (spectrogram.peak_freq, spectrogram.peak_amp) = (100.0, 0.001);
+        spectrogram.follow(0.01);
+        assert_eq!(spectrogram.followed_range(0..1200), 800..1200);
+        assert_eq!(spectrogram.followed_range(0..300), 0..300);
+    }
+
+    #[test]
+    fn held_peak_frequency_latches_the_maximum_of_a_sweep() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.hold_peak_freq_max = true;
+
+        // up to 1800 Hz and back down, followed by a louder noise peak below the threshold
+        for freq in (100..=1800).step_by(100).chain((100..1800).rev().step_by(100)) {
+            (spectrogram.peak_freq, spectrogram.peak_amp) = (freq as f32, 0.5);
+            spectrogram.hold_peak_freq(0.01);
+        }
+        (spectrogram.peak_freq, spectrogram.peak_amp) = (1950.0, 0.001);
+        spectrogram.hold_peak_freq(0.01);
+
+        assert_eq!(spectrogram.peak_freq_max, 1800.0);
+
+        spectrogram.reset_peak_freq_max();
+        assert_eq!(spectrogram.peak_freq_max, 0.0);
+    }
+
+    #[test]
+    fn peak_caps_rise_instantly_and_fall_exponentially() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.peak_cap_decay_db = 6.0;
+
+        spectrogram.freq_data = vec![(100, 0.5), (200, 0.1)];
+        spectrogram.update_peak_caps();
+        spectrogram.freq_data = vec![(100, 0.0), (200, 0.8)];
+        spectrogram.update_peak_caps();
+
+        assert!((spectrogram.peak_caps[0].1 - 0.25).abs() < 0.01);
+        assert_eq!(spectrogram.peak_caps[1], (200, 0.8));
+
+        // a new bin layout starts over
+        spectrogram.freq_data = vec![(50, 0.1)];
+        spectrogram.update_peak_caps();
+        assert_eq!(spectrogram.peak_caps, vec![(50, 0.1)]);
+    }
+
+    #[test]
+    fn persistence_keeps_the_latest_frames_fading_with_age() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.persistence_frames = 2;
+        spectrogram.persistence_decay = 0.5;
+
+        for amp in [0.1, 0.2, 0.3, 0.4] {
+            spectrogram.persist_frame();
+            spectrogram.freq_data = vec![(100, amp)];
+        }
+
+        let traces: Vec<(Vec<(i32, f32)>, f64)> = spectrogram.persistence_traces()
+            .map(|(frame, opacity)| (frame.clone(), opacity))
+            .collect();
+        assert_eq!(traces, vec![(vec![(100, 0.2)], 0.25), (vec![(100, 0.3)], 0.5)]);
+
+        spectrogram.persistence_frames = 0;
+        spectrogram.persist_frame();
+        assert_eq!(spectrogram.persistence_traces().count(), 0);
+    }
+
+    #[test]
+    fn snapshots_keep_the_spectrum_they_were_taken_from() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.freq_data = vec![(100, 0.5)];
+        spectrogram.take_snapshot(String::from("Before"), 1000);
+        spectrogram.freq_data = vec![(100, 0.25)];
+        spectrogram.take_snapshot(String::from("After"), 2000);
+        spectrogram.freq_data = vec![(100, 0.1)];
+
+        assert_eq!(spectrogram.snapshots[0].points, vec![(100, 0.5)]);
+        assert_eq!(spectrogram.snapshots[1].points, vec![(100, 0.25)]);
+
+        spectrogram.toggle_snapshot(0);
+        assert!(!spectrogram.snapshots[0].visible);
+
+        spectrogram.remove_snapshot(0);
+        assert_eq!(spectrogram.snapshots[0].label, "After");
+        spectrogram.remove_snapshot(5);
+        assert_eq!(spectrogram.snapshots.len(), 1);
+
+        spectrogram.clear_snapshots();
+        assert!(spectrogram.snapshots.is_empty());
+    }
+
+    #[test]
+    fn markers_measure_the_difference_between_the_nearest_bins() {
+        let mut spectrogram = Spectrogram::new();
+        spectrogram.freq_data = vec![(100, 0.5), (200, 0.05), (300, 0.25), (400, 0.01)];
+
+        spectrogram.set_marker(0, 104.0);
+        assert_eq!(spectrogram.marker_delta(), None);
+
+        spectrogram.set_marker(1, 290.0);
+        assert_eq!(spectrogram.marker_points(), [Some((100, 0.5)), Some((300, 0.25))]);
+
+        let (delta_freq, delta_db) = spectrogram.marker_delta().unwrap();
+        assert_eq!(delta_freq, 200.0);
+        // half the amplitude
+        assert!((delta_db + 6.02).abs() < 0.01);
+
+        // a marker below the first one gives negative deltas
+        spectrogram.set_marker(1, 0.0);
+        assert_eq!(spectrogram.marker_delta().map(|(delta_freq, _)| delta_freq), Some(0.0));
+        spectrogram.set_marker(0, 395.0);
+        let (delta_freq, delta_db) = spectrogram.marker_delta().unwrap();
+        assert_eq!(delta_freq, -300.0);
+        assert!((delta_db - 33.98).abs() < 0.01);
+
+        spectrogram.clear_markers();
+        assert_eq!(spectrogram.marker_points(), [None, None]);
+    }
+
+    #[test]
+    fn line_width_follows_the_display_style_unless_chosen() {
+        let mut spectrogram = Spectrogram::new();
+        assert_eq!(spectrogram.line_width(), 1);
+
+        spectrogram.style = DisplayStyle::HighContrast;
+        assert_eq!(spectrogram.line_width(), 3);
+
+        spectrogram.line_width = Some(5);
+        assert_eq!(spectrogram.line_width(), 5);
+    }
+}
@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use iced::{Color, Theme};
+use iced::theme::Palette;
+use iced::widget::{button, Button, checkbox, Checkbox, pick_list, PickList, text, Text};
+use plotters::backend::DrawingBackend;
+use plotters::chart::{ChartContext, MeshStyle, SeriesAnno};
+use plotters::coord::CoordTranslate;
+use plotters::coord::ranged1d::{Ranged, ValueFormatter};
+use plotters::series::{DashedLineSeries, LineSeries};
+use plotters::style::{BLACK, Color as PlotColor, CYAN, IntoFont, RGBAColor, RGBColor, ShapeStyle, TextStyle, WHITE, YELLOW};
+use serde::{Deserialize, Serialize};
+
+use crate::ui::UIMessage;
+
+/// Look of the widgets and the charts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DisplayStyle {
+    /// Light theme
+    #[default]
+    Standard,
+    Dark,
+    /// Bright, thick lines on a black background and larger text for low vision
+    HighContrast
+}
+
+impl DisplayStyle {
+    pub const ALL: [DisplayStyle; 3] = [DisplayStyle::Standard, DisplayStyle::Dark, DisplayStyle::HighContrast];
+
+    pub fn theme(&self) -> Theme {
+        match self {
+            DisplayStyle::Standard => Theme::Light,
+            DisplayStyle::Dark => Theme::Dark,
+            DisplayStyle::HighContrast => Theme::custom(Palette {
+                background: Color::BLACK,
+                text: Color::WHITE,
+                primary: Color::from_rgb(1.0, 0.85, 0.0),
+                success: Color::from_rgb(0.0, 1.0, 0.4),
+                danger: Color::from_rgb(1.0, 0.3, 0.3)
+            })
+        }
+    }
+
+    /// Size of the widget text in logical pixels
+    pub fn text_size(&self) -> f32 {
+        match self {
+            DisplayStyle::Standard | DisplayStyle::Dark => 16.0,
+            DisplayStyle::HighContrast => 22.0
+        }
+    }
+
+    pub fn text<'a>(&self, content: impl ToString) -> Text<'a> {
+        text(content).size(self.text_size())
+    }
+
+    pub fn button<'a>(&self, label: &str) -> Button<'a, UIMessage> {
+        button(self.text(label))
+    }
+
+    pub fn checkbox<'a>(&self, label: &str, checked: bool, f: impl Fn(bool) -> UIMessage + 'a) -> Checkbox<'a, UIMessage> {
+        checkbox(label, checked, f).text_size(self.text_size())
+    }
+
+    pub fn pick_list<'a, T>(&self, options: impl Into<Cow<'a, [T]>>, selected: Option<T>, f: impl Fn(T) -> UIMessage + 'a) -> PickList<'a, T, UIMessage>
+        where T: ToString + Eq + Clone + 'static {
+        pick_list(options, selected, f).text_size(self.text_size())
+    }
+
+    /// Text and axes of the charts
+    pub fn foreground(&self) -> RGBColor {
+        match self {
+            DisplayStyle::Standard => BLACK,
+            DisplayStyle::Dark => RGBColor(220, 220, 220),
+            DisplayStyle::HighContrast => WHITE
+        }
+    }
+
+    /// Plotting area of the charts, matching the background of the theme
+    pub fn chart_background(&self) -> RGBColor {
+        match self {
+            DisplayStyle::Standard => WHITE,
+            // the background of iced's dark palette
+            DisplayStyle::Dark => RGBColor(32, 34, 37),
+            DisplayStyle::HighContrast => BLACK
+        }
+    }
+
+    /// Major gridlines, the minor ones are fainter
+    pub fn grid_color(&self) -> RGBAColor {
+        match self {
+            DisplayStyle::Standard => BLACK.mix(0.2),
+            DisplayStyle::Dark => WHITE.mix(0.15),
+            DisplayStyle::HighContrast => WHITE.mix(0.35)
+        }
+    }
+
+    /// The plotted signal
+    pub fn line_color(&self) -> RGBColor {
+        match self {
+            DisplayStyle::Standard => BLACK,
+            DisplayStyle::Dark => RGBColor(110, 200, 255),
+            DisplayStyle::HighContrast => YELLOW
+        }
+    }
+
+    pub fn line_width(&self) -> u32 {
+        match self {
+            DisplayStyle::Standard | DisplayStyle::Dark => 1,
+            DisplayStyle::HighContrast => 3
+        }
+    }
+
+    pub fn label_font_size(&self) -> u32 {
+        match self {
+            DisplayStyle::Standard | DisplayStyle::Dark => 12,
+            DisplayStyle::HighContrast => 20
+        }
+    }
+
+    /// Space reserved for the axis labels on every side of a plotting area
+    pub fn label_area_size(&self) -> u32 {
+        match self {
+            DisplayStyle::Standard | DisplayStyle::Dark => 40,
+            DisplayStyle::HighContrast => 60
+        }
+    }
+
+    pub fn label_style<'a>(&self) -> TextStyle<'a> {
+        ("sans-serif", self.label_font_size()).into_font().color(&self.foreground())
+    }
+
+    /// Fills the plotting area of a chart, before anything is drawn on it.
+    pub fn fill_background<DB: DrawingBackend, CT: CoordTranslate>(&self, chart: &ChartContext<'_, DB, CT>) {
+        chart.plotting_area().fill(&self.chart_background())
+            .expect("Failed to fill chart background");
+    }
+
+    /// Applies the colors and label sizes to the mesh of a chart.
+    pub fn apply<X, Y, DB>(&self, mesh: &mut MeshStyle<'_, '_, X, Y, DB>)
+        where X: Ranged + ValueFormatter<X::ValueType>, Y: Ranged + ValueFormatter<Y::ValueType>, DB: DrawingBackend {
+        let grid = self.grid_color();
+
+        mesh.axis_style(self.foreground())
+            .bold_line_style(grid)
+            .light_line_style(grid.mix(0.3))
+            .label_style(self.label_style())
+            .axis_desc_style(self.label_style());
+    }
+}
+
+impl Display for DisplayStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DisplayStyle::Standard => "Light",
+            DisplayStyle::Dark => "Dark",
+            DisplayStyle::HighContrast => "High contrast"
+        })
+    }
+}
+
+/// Colors of the traces drawn over the main one, e.g. the second channel, snapshots and the
+/// reference curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TracePalette {
+    #[default]
+    Standard,
+    /// Okabe-Ito colors, which stay apart with the common kinds of color blindness
+    ColorBlindSafe
+}
+
+impl TracePalette {
+    pub const ALL: [TracePalette; 2] = [TracePalette::Standard, TracePalette::ColorBlindSafe];
+
+    /// Color of the `index`th overlaid trace. The standard colors are the second channel's for
+    /// the first trace and the snapshot colors after it.
+    pub fn color(&self, style: DisplayStyle, index: usize) -> RGBColor {
+        let (colors, index) = match (self, style, index) {
+            (TracePalette::Standard, DisplayStyle::Standard, 0) => return RGBColor(200, 40, 40),
+            (TracePalette::Standard, DisplayStyle::Dark, 0) => return RGBColor(255, 160, 80),
+            (TracePalette::Standard, DisplayStyle::HighContrast, 0) => return CYAN,
+            (TracePalette::Standard, DisplayStyle::Standard, _) =>
+                ([RGBColor(0, 114, 178), RGBColor(213, 94, 0), RGBColor(0, 158, 115), RGBColor(204, 121, 167), RGBColor(230, 159, 0)], index - 1),
+            (TracePalette::Standard, DisplayStyle::Dark | DisplayStyle::HighContrast, _) =>
+                ([RGBColor(86, 180, 233), RGBColor(230, 159, 0), RGBColor(0, 158, 115), RGBColor(204, 121, 167), RGBColor(240, 228, 66)], index - 1),
+            (TracePalette::ColorBlindSafe, DisplayStyle::Standard, _) =>
+                ([RGBColor(213, 94, 0), RGBColor(0, 114, 178), RGBColor(0, 158, 115), RGBColor(204, 121, 167), RGBColor(230, 159, 0)], index),
+            // the main trace is already yellow or light blue on dark backgrounds
+            (TracePalette::ColorBlindSafe, DisplayStyle::Dark | DisplayStyle::HighContrast, _) =>
+                ([RGBColor(230, 159, 0), RGBColor(0, 158, 115), RGBColor(204, 121, 167), RGBColor(213, 94, 0), WHITE], index)
+        };
+
+        colors[index % colors.len()]
+    }
+
+    /// Dash pattern of the `index`th overlaid trace. The color-blind safe traces differ by more
+    /// than color, the standard ones are solid.
+    pub fn dash(&self, index: usize) -> LineDash {
+        match self {
+            TracePalette::Standard => LineDash::Solid,
+            TracePalette::ColorBlindSafe => [LineDash::Dashed, LineDash::Dotted, LineDash::Solid][index % 3]
+        }
+    }
+}
+
+impl Display for TracePalette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TracePalette::Standard => "Standard colors",
+            TracePalette::ColorBlindSafe => "Color-blind safe colors"
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineDash {
+    Solid,
+    Dashed,
+    Dotted
+}
+
+impl LineDash {
+    /// Draws the points as a line with this dash pattern.
+    pub fn draw<'a, 'b, DB, CT>(&self, chart: &'b mut ChartContext<'a, DB, CT>, points: Vec<CT::From>, line: ShapeStyle) -> &'b mut SeriesAnno<'a, DB>
+        where DB: DrawingBackend + 'a, CT: CoordTranslate, CT::From: Clone + 'static {
+        let result = match self {
+            LineDash::Solid => chart.draw_series(LineSeries::new(points, line)),
+            LineDash::Dashed => chart.draw_series(DashedLineSeries::new(points, 8, 4, line)),
+            LineDash::Dotted => chart.draw_series(DashedLineSeries::new(points, 2, 4, line))
+        };
+
+        result.expect("Failed to draw trace")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plotters::style::RGBColor;
+
+    use crate::ui::style::{DisplayStyle, LineDash, TracePalette};
+
+    #[test]
+    fn standard_palette_keeps_the_solid_traces() {
+        // the second channel and the first snapshot
+        assert_eq!(TracePalette::Standard.color(DisplayStyle::Standard, 0), RGBColor(200, 40, 40));
+        assert_eq!(TracePalette::Standard.color(DisplayStyle::Standard, 1), RGBColor(0, 114, 178));
+        assert_eq!(TracePalette::Standard.dash(0), LineDash::Solid);
+        assert_eq!(TracePalette::Standard.dash(4), LineDash::Solid);
+
+        assert_eq!(TracePalette::ColorBlindSafe.dash(0), LineDash::Dashed);
+        assert_eq!(TracePalette::ColorBlindSafe.dash(1), LineDash::Dotted);
+        assert_eq!(TracePalette::ColorBlindSafe.dash(2), LineDash::Solid);
+    }
+}
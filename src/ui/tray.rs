@@ -0,0 +1,320 @@
+use std::fmt::{Display, Formatter};
+
+use crossbeam_channel::Receiver;
+
+use crate::engine::AudiaError;
+#[cfg(any(feature = "tray", test))]
+use crate::ui::units::DB_FLOOR;
+
+/// Width and height of the generated tray icon in pixels
+#[cfg(any(feature = "tray", test))]
+const ICON_SIZE: u32 = 16;
+
+/// Entries of the tray menu. Without the tray feature no menu creates them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "tray"), allow(dead_code))]
+pub enum TrayAction {
+    ToggleWindow,
+    StartStreaming,
+    StopStreaming,
+    Quit
+}
+
+#[cfg(feature = "tray")]
+impl TrayAction {
+    pub const ALL: [TrayAction; 4] = [TrayAction::ToggleWindow, TrayAction::StartStreaming, TrayAction::StopStreaming, TrayAction::Quit];
+}
+
+impl Display for TrayAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TrayAction::ToggleWindow => "Show/hide window",
+            TrayAction::StartStreaming => "Start streaming",
+            TrayAction::StopStreaming => "Stop streaming",
+            TrayAction::Quit => "Quit"
+        })
+    }
+}
+
+/// What the tray icon shows at a glance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrayStatus {
+    /// Peak level in dBFS, None while not streaming
+    pub peak_db: Option<f32>,
+    pub clipping: bool
+}
+
+// only the tray backend draws the status
+#[cfg(any(feature = "tray", test))]
+impl TrayStatus {
+    pub fn tooltip(&self) -> String {
+        match self.peak_db {
+            None => String::from("audia: not streaming"),
+            Some(_) if self.clipping => String::from("audia: clipping!"),
+            Some(peak_db) => format!("audia: peak {:.1} dBFS", peak_db)
+        }
+    }
+
+    /// A level bar growing from the bottom, red while clipping, as RGBA rows.
+    fn icon_rgba(&self) -> Vec<u8> {
+        let size = ICON_SIZE as usize;
+        let fill = self.peak_db.map_or(0.0, |peak_db| (1.0 - peak_db / DB_FLOOR).clamp(0.0, 1.0));
+        let filled_rows = (fill * size as f32).round() as usize;
+        let color = if self.clipping { [220, 30, 30, 255] } else { [40, 180, 60, 255] };
+        let frame = [128, 128, 128, 255];
+
+        let mut rgba = Vec::with_capacity(size * size * 4);
+
+        for y in 0..size {
+            for x in 0..size {
+                let pixel = if x == 0 || y == 0 || x == size - 1 || y == size - 1 {
+                    frame
+                } else if y >= size - filled_rows && (4..size - 4).contains(&x) {
+                    color
+                } else {
+                    [0, 0, 0, 0]
+                };
+                rgba.extend_from_slice(&pixel);
+            }
+        }
+
+        rgba
+    }
+}
+
+/// Icon in the system tray with a menu to control the app while its window is hidden. The chosen
+/// menu entries arrive on the receiver of `actions`.
+pub struct Tray {
+    backend: backend::Handle,
+    last_status: TrayStatus
+}
+
+impl Tray {
+    pub fn spawn() -> Result<Self, AudiaError> {
+        let status = TrayStatus { peak_db: None, clipping: false };
+
+        Ok(Self { backend: backend::spawn(&status)?, last_status: status })
+    }
+
+    /// Receives the chosen menu entries.
+    pub fn actions(&self) -> Receiver<TrayAction> {
+        self.backend.actions()
+    }
+
+    pub fn show(&mut self, status: TrayStatus) {
+        if status != self.last_status {
+            self.backend.show(&status);
+            self.last_status = status;
+        }
+    }
+}
+
+#[cfg(feature = "tray")]
+mod backend {
+    use crossbeam_channel::Receiver;
+    use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+    use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem};
+
+    use crate::engine::AudiaError;
+    use crate::ui::tray::{ICON_SIZE, TrayAction, TrayStatus};
+
+    fn build(status: &TrayStatus) -> Result<(TrayIcon, Vec<(MenuId, TrayAction)>), AudiaError> {
+        let menu = Menu::new();
+        let mut items = Vec::with_capacity(TrayAction::ALL.len());
+
+        for action in TrayAction::ALL {
+            let item = MenuItem::new(action.to_string(), true, None);
+            menu.append(&item)
+                .map_err(|error| AudiaError::from(format!("Could not build the tray menu: {error}")))?;
+            items.push((item.id().clone(), action));
+        }
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip(status.tooltip())
+            .with_icon(icon(status)?)
+            .build()
+            .map_err(|error| AudiaError::from(format!("Could not create the tray icon: {error}")))?;
+
+        Ok((icon, items))
+    }
+
+    fn icon(status: &TrayStatus) -> Result<Icon, AudiaError> {
+        Icon::from_rgba(status.icon_rgba(), ICON_SIZE, ICON_SIZE)
+            .map_err(|error| AudiaError::from(format!("Invalid tray icon: {error}")))
+    }
+
+    fn show(tray_icon: &TrayIcon, status: &TrayStatus) {
+        if let Err(error) = tray_icon.set_tooltip(Some(status.tooltip())) {
+            log::warn!("Could not update the tray tooltip: {}", error);
+        }
+
+        match icon(status) {
+            Ok(icon) => {
+                if let Err(error) = tray_icon.set_icon(Some(icon)) {
+                    log::warn!("Could not update the tray icon: {}", error);
+                }
+            },
+            Err(error) => log::warn!("{}", error)
+        }
+    }
+
+    /// Maps the menu events to the actions of their entries, on a thread of its own so the UI
+    /// can wait for them.
+    fn forward_actions(items: Vec<(MenuId, TrayAction)>) -> Result<Receiver<TrayAction>, AudiaError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+
+        std::thread::Builder::new()
+            .name(String::from("tray-menu"))
+            .spawn(move || {
+                for event in MenuEvent::receiver().iter() {
+                    if let Some((_, action)) = items.iter().find(|(id, _)| *id == event.id) {
+                        if tx.send(*action).is_err() {
+                            break;
+                        }
+                    }
+                }
+            })
+            .map_err(|error| AudiaError::from(format!("Could not start the tray menu thread: {error}")))?;
+
+        Ok(rx)
+    }
+
+    /// GTK has to run its own main loop for the tray on Linux, so the icon lives on a thread of
+    /// its own and gets the status over a channel.
+    /// How often the tray thread picks up a new icon
+    #[cfg(target_os = "linux")]
+    const TRAY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+    #[cfg(target_os = "linux")]
+    pub struct Handle {
+        status: crossbeam_channel::Sender<TrayStatus>,
+        actions: Receiver<TrayAction>
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn spawn(status: &TrayStatus) -> Result<Handle, AudiaError> {
+        let (ready_tx, ready_rx) = crossbeam_channel::bounded(1);
+        let (status_tx, status_rx) = crossbeam_channel::unbounded::<TrayStatus>();
+        let status = status.clone();
+
+        std::thread::Builder::new()
+            .name(String::from("tray"))
+            .spawn(move || {
+                if let Err(error) = gtk::init() {
+                    let _ = ready_tx.send(Err(AudiaError::from(format!("Could not initialize GTK for the tray: {error}"))));
+                    return;
+                }
+
+                match build(&status) {
+                    Ok((tray_icon, items)) => {
+                        let _ = ready_tx.send(Ok(items));
+
+                        gtk::glib::timeout_add_local(TRAY_POLL_INTERVAL, move || {
+                            if let Some(status) = status_rx.try_iter().last() {
+                                show(&tray_icon, &status);
+                            }
+                            gtk::glib::ControlFlow::Continue
+                        });
+                        gtk::main();
+                    },
+                    Err(error) => {
+                        let _ = ready_tx.send(Err(error));
+                    }
+                }
+            })
+            .map_err(|error| AudiaError::from(format!("Could not start the tray thread: {error}")))?;
+
+        let items = ready_rx.recv()
+            .map_err(|_| AudiaError::from(String::from("The tray thread stopped")))??;
+
+        Ok(Handle { status: status_tx, actions: forward_actions(items)? })
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Handle {
+        pub fn show(&self, status: &TrayStatus) {
+            let _ = self.status.send(status.clone());
+        }
+
+        pub fn actions(&self) -> Receiver<TrayAction> {
+            self.actions.clone()
+        }
+    }
+
+    /// Elsewhere the icon is driven by the event loop of the window, on the main thread.
+    #[cfg(not(target_os = "linux"))]
+    pub struct Handle {
+        tray_icon: TrayIcon,
+        actions: Receiver<TrayAction>
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn spawn(status: &TrayStatus) -> Result<Handle, AudiaError> {
+        let (tray_icon, items) = build(status)?;
+
+        Ok(Handle { tray_icon, actions: forward_actions(items)? })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    impl Handle {
+        pub fn show(&self, status: &TrayStatus) {
+            show(&self.tray_icon, status);
+        }
+
+        pub fn actions(&self) -> Receiver<TrayAction> {
+            self.actions.clone()
+        }
+    }
+}
+
+#[cfg(not(feature = "tray"))]
+mod backend {
+    use crossbeam_channel::Receiver;
+
+    use crate::engine::AudiaError;
+    use crate::ui::tray::{TrayAction, TrayStatus};
+
+    pub struct Handle;
+
+    pub fn spawn(_status: &TrayStatus) -> Result<Handle, AudiaError> {
+        Err(AudiaError::from(String::from("This build has no tray support, it needs the tray feature")))
+    }
+
+    impl Handle {
+        pub fn show(&self, _status: &TrayStatus) {}
+
+        pub fn actions(&self) -> Receiver<TrayAction> {
+            crossbeam_channel::never()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::tray::{ICON_SIZE, TrayStatus};
+    use crate::ui::units::DB_FLOOR;
+
+    #[test]
+    fn icon_shows_the_level_and_clipping() {
+        let pixel = |rgba: &[u8], x: u32, y: u32| {
+            let index = ((y * ICON_SIZE + x) * 4) as usize;
+            rgba[index..index + 4].to_vec()
+        };
+        let bottom = ICON_SIZE - 2;
+        let center = ICON_SIZE / 2;
+
+        let stopped = TrayStatus { peak_db: None, clipping: false };
+        assert_eq!(pixel(&stopped.icon_rgba(), center, bottom), vec![0, 0, 0, 0]);
+        assert_eq!(stopped.tooltip(), "audia: not streaming");
+
+        let clipping = TrayStatus { peak_db: Some(0.0), clipping: true };
+        assert_eq!(pixel(&clipping.icon_rgba(), center, 1), vec![220, 30, 30, 255]);
+        assert_eq!(clipping.tooltip(), "audia: clipping!");
+
+        // half way up the meter range
+        let half = TrayStatus { peak_db: Some(DB_FLOOR / 2.0), clipping: false };
+        assert_eq!(pixel(&half.icon_rgba(), center, bottom), vec![40, 180, 60, 255]);
+        assert_eq!(pixel(&half.icon_rgba(), center, 2), vec![0, 0, 0, 0]);
+    }
+}
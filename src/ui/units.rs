@@ -0,0 +1,312 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::AudiaError;
+
+/// Lowest level shown in dBFS mode; anything quieter is clamped to this value.
+pub const DB_FLOOR: f32 = -100.0;
+/// Highest ceiling the dBFS axis can be raised to, leaving headroom above full scale.
+pub const MAX_DB_CEILING: f32 = 20.0;
+/// Smallest distance between the floor and the ceiling of the dBFS axis.
+pub const MIN_DB_SPAN: f32 = 10.0;
+
+/// The part of the dBFS scale shown on the amplitude axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DbRange {
+    pub floor: f32,
+    pub ceiling: f32
+}
+
+impl DbRange {
+    /// Checks that the floor lies below the ceiling and both are within the displayable scale.
+    pub fn new(floor: f32, ceiling: f32) -> Result<Self, AudiaError> {
+        if !(DB_FLOOR..=MAX_DB_CEILING).contains(&floor) || !(DB_FLOOR..=MAX_DB_CEILING).contains(&ceiling) {
+            return Err(AudiaError::from(format!(
+                "dB range {floor:.0}..{ceiling:.0} must lie within {DB_FLOOR:.0}..{MAX_DB_CEILING:.0} dB")));
+        }
+        if ceiling - floor < MIN_DB_SPAN {
+            return Err(AudiaError::from(format!(
+                "dB floor {floor:.0} must be at least {MIN_DB_SPAN:.0} dB below the ceiling {ceiling:.0}")));
+        }
+        Ok(Self { floor, ceiling })
+    }
+
+    /// The range with a new floor, kept at least `MIN_DB_SPAN` below the ceiling.
+    pub fn with_floor(&self, floor: f32) -> Self {
+        Self { floor: floor.clamp(DB_FLOOR, self.ceiling - MIN_DB_SPAN), ceiling: self.ceiling }
+    }
+
+    /// The range with a new ceiling, kept at least `MIN_DB_SPAN` above the floor.
+    pub fn with_ceiling(&self, ceiling: f32) -> Self {
+        Self { floor: self.floor, ceiling: ceiling.clamp(self.floor + MIN_DB_SPAN, MAX_DB_CEILING) }
+    }
+
+    /// Clamps a dBFS value to the visible range.
+    pub fn clamp(&self, db: f32) -> f32 {
+        db.clamp(self.floor, self.ceiling)
+    }
+}
+
+impl Default for DbRange {
+    fn default() -> Self {
+        Self { floor: DB_FLOOR, ceiling: 0.0 }
+    }
+}
+
+/// Converts an amplitude relative to full scale into dBFS, clamped to `DB_FLOOR`.
+pub fn to_dbfs(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        DB_FLOOR
+    } else {
+        (20.0 * amplitude.log10()).max(DB_FLOOR)
+    }
+}
+
+/// Units used on the amplitude axis of the chart and in the numeric readouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum UnitMode {
+    #[default]
+    Linear,
+    DbFs,
+    Percent
+}
+
+impl UnitMode {
+    pub const ALL: [UnitMode; 3] = [UnitMode::Linear, UnitMode::DbFs, UnitMode::Percent];
+
+    /// Maps an amplitude relative to full scale (1.0) to the value plotted on the y axis.
+    /// dBFS values are clamped to `db`.
+    pub fn scale(&self, amplitude: f32, db: DbRange) -> f32 {
+        match self {
+            UnitMode::Linear => amplitude,
+            UnitMode::DbFs => db.clamp(to_dbfs(amplitude)),
+            UnitMode::Percent => amplitude * 100.0
+        }
+    }
+
+    /// The y axis range covering silence up to full scale, or `db` in dBFS mode.
+    pub fn range(&self, db: DbRange) -> Range<f32> {
+        match self {
+            UnitMode::Linear => 0.0..1.0,
+            UnitMode::DbFs => db.floor..db.ceiling,
+            UnitMode::Percent => 0.0..100.0
+        }
+    }
+
+    /// Formats a value that has already been scaled, e.g. an axis label.
+    pub fn format_scaled(&self, value: f32) -> String {
+        match self {
+            UnitMode::Linear => format!("{value:.2}"),
+            UnitMode::DbFs => format!("{value:.0} dB"),
+            UnitMode::Percent => format!("{value:.0}%")
+        }
+    }
+
+    /// Formats an amplitude relative to full scale for the numeric readouts.
+    pub fn format(&self, amplitude: f32) -> String {
+        match self {
+            UnitMode::Linear => format!("{amplitude:.4}"),
+            UnitMode::DbFs => format!("{:.1} dBFS", to_dbfs(amplitude)),
+            UnitMode::Percent => format!("{:.1}%", amplitude * 100.0)
+        }
+    }
+}
+
+impl Display for UnitMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            UnitMode::Linear => "Linear",
+            UnitMode::DbFs => "dBFS",
+            UnitMode::Percent => "Percent"
+        })
+    }
+}
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Frequency of A4 that the note names are tuned to
+const CONCERT_PITCH: f32 = 440.0;
+
+/// The name of the equal-tempered note closest to `freq`, e.g. "A4" for 440 Hz.
+pub fn note_name(freq: f32) -> String {
+    if freq <= 0.0 {
+        return String::from("-");
+    }
+
+    // MIDI note numbers: A4 is 69, C-1 is 0
+    let note = (69.0 + 12.0 * (freq / CONCERT_PITCH).log2()).round() as i32;
+    let octave = note.div_euclid(12) - 1;
+
+    format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], octave)
+}
+
+/// Deviations up to this many cents count as in tune
+pub const IN_TUNE_CENTS: f32 = 5.0;
+
+/// Deviation of `freq` from `target` in cents, None without a signal or a target.
+pub fn cents_from(freq: f32, target: f32) -> Option<f32> {
+    if freq <= 0.0 || target <= 0.0 {
+        return None;
+    }
+
+    Some(1200.0 * (freq / target).log2())
+}
+
+/// Frequency the tilt pivots around, amplitudes there are shown unchanged
+pub const TILT_PIVOT_HZ: f32 = 1000.0;
+/// Slope that shows pink noise flat on a spectrum of equally wide bins
+pub const PINK_TILT_DB_PER_OCTAVE: f32 = 3.0;
+/// Steepest custom tilt, up or down
+pub const MAX_TILT_DB_PER_OCTAVE: f32 = 6.0;
+
+/// Slope added to the displayed spectrum so broadband signals look flat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Tilt {
+    #[default]
+    Flat,
+    Pink,
+    Custom
+}
+
+impl Tilt {
+    pub const ALL: [Tilt; 3] = [Tilt::Flat, Tilt::Pink, Tilt::Custom];
+
+    /// The slope in dB per octave, `custom` is the one chosen for `Tilt::Custom`.
+    pub fn db_per_octave(&self, custom: f32) -> f32 {
+        match self {
+            Tilt::Flat => 0.0,
+            Tilt::Pink => PINK_TILT_DB_PER_OCTAVE,
+            Tilt::Custom => custom
+        }
+    }
+}
+
+impl Display for Tilt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Tilt::Flat => "Flat",
+            Tilt::Pink => "Pink tilt",
+            Tilt::Custom => "Custom tilt"
+        })
+    }
+}
+
+/// Raises `amp` by `db_per_octave` for every octave above `TILT_PIVOT_HZ` and lowers it by as much
+/// for every octave below. The DC bin lies no number of octaves away and is left as it is.
+pub fn apply_tilt(freq: f32, amp: f32, db_per_octave: f32) -> f32 {
+    if freq <= 0.0 || db_per_octave == 0.0 {
+        return amp;
+    }
+
+    amp * 10f32.powf(db_per_octave * (freq / TILT_PIVOT_HZ).log2() / 20.0)
+}
+
+/// Units used on the frequency axis of the chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum XAxisUnit {
+    #[default]
+    Hz,
+    KHz,
+    Note
+}
+
+impl XAxisUnit {
+    pub const ALL: [XAxisUnit; 3] = [XAxisUnit::Hz, XAxisUnit::KHz, XAxisUnit::Note];
+
+    pub fn format(&self, freq: f32) -> String {
+        match self {
+            XAxisUnit::Hz => format!("{freq:.0}"),
+            XAxisUnit::KHz => format!("{:.1}k", freq / 1000.0),
+            XAxisUnit::Note => note_name(freq)
+        }
+    }
+
+    /// How many labels fit on the axis without overlapping.
+    pub fn label_count(&self) -> usize {
+        match self {
+            XAxisUnit::Hz | XAxisUnit::KHz => 10,
+            // note names are wider, and neighbouring ticks may round to the same note
+            XAxisUnit::Note => 6
+        }
+    }
+}
+
+impl Display for XAxisUnit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            XAxisUnit::Hz => "Hz",
+            XAxisUnit::KHz => "kHz",
+            XAxisUnit::Note => "Note"
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::units::{apply_tilt, cents_from, DbRange, MIN_DB_SPAN, note_name, PINK_TILT_DB_PER_OCTAVE, TILT_PIVOT_HZ, to_dbfs, UnitMode, XAxisUnit};
+
+    #[test]
+    fn frequencies_map_to_the_nearest_note() {
+        assert_eq!(note_name(440.0), "A4");
+        assert_eq!(note_name(261.63), "C4");
+        assert_eq!(note_name(27.5), "A0");
+        // a quarter tone below A4 rounds to the closer G#
+        assert_eq!(note_name(425.0), "G#4");
+        assert_eq!(note_name(0.0), "-");
+    }
+
+    #[test]
+    fn cents_are_measured_against_the_exact_target() {
+        assert_eq!(cents_from(432.0, 432.0), Some(0.0));
+        assert!((cents_from(864.0, 432.0).unwrap() - 1200.0).abs() < 1e-3);
+        // 440 Hz is about 32 cents above a 432 Hz reference, not in tune with it
+        assert!((cents_from(440.0, 432.0).unwrap() - 31.77).abs() < 0.01);
+        assert_eq!(cents_from(0.0, 432.0), None);
+        assert_eq!(cents_from(440.0, 0.0), None);
+    }
+
+    #[test]
+    fn tilt_rises_by_its_slope_per_octave() {
+        let db_at = |freq: f32| to_dbfs(apply_tilt(freq, 0.01, PINK_TILT_DB_PER_OCTAVE));
+
+        assert!((db_at(TILT_PIVOT_HZ) + 40.0).abs() < 1e-3);
+        assert!((db_at(2000.0) - db_at(1000.0) - 3.0).abs() < 1e-3);
+        assert!((db_at(8000.0) - db_at(125.0) - 18.0).abs() < 1e-3);
+        assert!((to_dbfs(apply_tilt(500.0, 0.01, -4.5)) - to_dbfs(0.01) - 4.5).abs() < 1e-3);
+        // the DC bin and a flat tilt keep the amplitude
+        assert_eq!(apply_tilt(0.0, 0.01, PINK_TILT_DB_PER_OCTAVE), 0.01);
+        assert_eq!(apply_tilt(8000.0, 0.01, 0.0), 0.01);
+    }
+
+    #[test]
+    fn kilohertz_labels_are_scaled() {
+        assert_eq!(XAxisUnit::KHz.format(1500.0), "1.5k");
+        assert_eq!(XAxisUnit::Hz.format(1500.0), "1500");
+    }
+
+    #[test]
+    fn db_range_requires_floor_below_ceiling() {
+        assert!(DbRange::new(-60.0, -20.0).is_ok());
+        assert!(DbRange::new(-20.0, -20.0).is_err());
+        assert!(DbRange::new(-10.0, -60.0).is_err());
+        assert!(DbRange::new(-200.0, 0.0).is_err());
+
+        let range = DbRange::new(-60.0, -20.0).unwrap();
+        assert_eq!(UnitMode::DbFs.range(range), -60.0..-20.0);
+        // full scale is clamped to the ceiling, silence to the floor
+        assert_eq!(UnitMode::DbFs.scale(1.0, range), -20.0);
+        assert_eq!(UnitMode::DbFs.scale(0.0, range), -60.0);
+    }
+
+    #[test]
+    fn dragged_db_limits_keep_the_minimum_span() {
+        let range = DbRange::new(-60.0, -20.0).unwrap();
+
+        assert_eq!(range.with_floor(-10.0).floor, -20.0 - MIN_DB_SPAN);
+        assert_eq!(range.with_floor(-80.0).floor, -80.0);
+        assert_eq!(range.with_ceiling(-70.0).ceiling, -60.0 + MIN_DB_SPAN);
+        assert_eq!(range.with_ceiling(-10.0).ceiling, -10.0);
+    }
+}
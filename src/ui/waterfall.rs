@@ -0,0 +1,470 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use iced::{Element, Length, Size};
+use iced::widget::canvas::{Cache, Frame, Geometry};
+use plotters::backend::{BitMapBackend, DrawingBackend};
+use plotters::chart::ChartBuilder;
+use plotters::drawing::IntoDrawingArea;
+use plotters::element::Rectangle as PlotRectangle;
+use plotters::style::{Color, HSLColor, WHITE};
+use plotters_iced::{Chart, ChartWidget, Renderer};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::AudiaError;
+use crate::ui::UIMessage;
+use crate::ui::style::DisplayStyle;
+use crate::ui::units::{DB_FLOOR, to_dbfs};
+
+/// Space reserved for the axis labels on every side of the exported image
+const EXPORT_LABEL_AREA_SIZE: u32 = 50;
+
+/// Number of displayed rows kept in the history
+pub const DEFAULT_HISTORY_ROWS: usize = 100;
+
+/// Size of the exported image without the rows, which add `EXPORT_ROW_HEIGHT` pixels each
+const EXPORT_WIDTH: u32 = 1200;
+const EXPORT_MARGIN: u32 = 120;
+const EXPORT_ROW_HEIGHT: u32 = 4;
+
+/// Directory holding the saved waterfall sessions, relative to the working directory
+pub const SESSION_DIR: &str = "./sessions";
+
+/// Format version written to new session files
+const SESSION_VERSION: u32 = 1;
+
+/// The waterfall history with the parameters it was recorded with, saved as JSON for reviewing
+/// a measurement later. Row `i` was captured `i * row_duration` seconds before `saved_at_ms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaterfallSession {
+    pub version: u32,
+    /// Unix time in ms when the session was saved
+    pub saved_at_ms: u64,
+    pub analysis_sample_rate: u32,
+    /// Time covered by one row in seconds
+    pub row_duration: f32,
+    pub frames_per_row: usize,
+    pub smoothing: f32,
+    /// Frequencies of the bins in each row
+    pub frequencies: Vec<i32>,
+    /// Amplitudes relative to full scale, the newest row first
+    pub rows: Vec<Vec<f32>>
+}
+
+impl WaterfallSession {
+    fn path<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+        dir.as_ref().join(format!("{name}.json"))
+    }
+
+    /// Writes the session to `dir`, named after the time it was saved, and returns its name.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<String, AudiaError> {
+        std::fs::create_dir_all(dir.as_ref())
+            .map_err(|error| AudiaError::from(format!("Could not create {}: {error}", dir.as_ref().display())))?;
+
+        let name = format!("waterfall-{}", self.saved_at_ms);
+        let path = Self::path(dir, &name);
+        let contents = serde_json::to_string(self)
+            .map_err(|error| AudiaError::from(format!("Could not serialize session: {error}")))?;
+
+        std::fs::write(&path, contents)
+            .map_err(|error| AudiaError::from(format!("Could not write {}: {error}", path.display())))?;
+
+        log::info!("Saved waterfall session to {}", path.display());
+
+        Ok(name)
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P, name: &str) -> Result<Self, AudiaError> {
+        let path = Self::path(dir, name);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|error| AudiaError::from(format!("Could not read session {}: {error}", path.display())))?;
+
+        let session: Self = serde_json::from_str(&contents)
+            .map_err(|error| AudiaError::from(format!("Invalid session in {}: {error}", path.display())))?;
+
+        if session.version > SESSION_VERSION {
+            return Err(AudiaError::from(format!("Session {} needs a newer version of audia", path.display())));
+        }
+        if session.rows.iter().any(|row| row.len() != session.frequencies.len()) {
+            return Err(AudiaError::from(format!("Session {} has rows that don't match its frequencies", path.display())));
+        }
+
+        Ok(session)
+    }
+
+    /// Names of the sessions in `dir`, the newest first.
+    pub fn list<P: AsRef<Path>>(dir: P) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .map(|entries| entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map(|extension| extension == "json").unwrap_or(false))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .collect())
+            .unwrap_or_default();
+
+        // the names end with the save time in ms, so they sort by time
+        names.sort();
+        names.reverse();
+        names
+    }
+}
+
+/// Which edge of the waterfall the newest row is drawn at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WaterfallDirection {
+    #[default]
+    NewestTop,
+    NewestBottom
+}
+
+impl WaterfallDirection {
+    pub const ALL: [WaterfallDirection; 2] = [WaterfallDirection::NewestTop, WaterfallDirection::NewestBottom];
+}
+
+impl Display for WaterfallDirection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WaterfallDirection::NewestTop => "Newest on top",
+            WaterfallDirection::NewestBottom => "Newest at the bottom"
+        })
+    }
+}
+
+/// Scrolling time-frequency view of the spectrum, the newest row on top unless the direction
+/// is flipped.
+pub struct Waterfall {
+    /// Frequencies of the bins in each row
+    frequencies: Vec<i32>,
+    /// Amplitudes relative to full scale, the newest row first
+    rows: VecDeque<Vec<f32>>,
+    capacity: usize,
+    /// How much of the previous row is blended into a new one, from 0 (none) to just below 1
+    pub smoothing: f32,
+    /// Number of spectrum frames averaged into one displayed row
+    pub frames_per_row: usize,
+    pending: Vec<f32>,
+    pending_frames: usize,
+    pub direction: WaterfallDirection,
+    /// Time covered by one row in seconds, for the time axis
+    pub row_duration: f32,
+    pub style: DisplayStyle,
+    cache: Cache
+}
+
+impl Waterfall {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frequencies: vec![],
+            rows: VecDeque::with_capacity(capacity),
+            capacity,
+            smoothing: 0.0,
+            frames_per_row: 1,
+            pending: vec![],
+            pending_frames: 0,
+            direction: WaterfallDirection::default(),
+            row_duration: 0.0,
+            style: DisplayStyle::default(),
+            cache: Cache::new()
+        }
+    }
+
+    pub fn view(&self) -> Element<UIMessage> {
+        ChartWidget::new(self)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    /// Marks the chart as outdated so it is rendered again on the next frame.
+    pub fn redraw(&self) {
+        self.cache.clear();
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &Vec<f32>> {
+        self.rows.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+        self.pending.clear();
+        self.pending_frames = 0;
+    }
+
+    /// Adds a spectrum frame. Frames are averaged in groups of `frames_per_row` and then blended
+    /// with the previous row according to `smoothing`.
+    pub fn push(&mut self, frame: &[(i32, f32)]) {
+        if self.pending.len() != frame.len() {
+            // a different resolution can't be blended with the history
+            self.clear();
+            self.pending = vec![0.0; frame.len()];
+        }
+
+        self.frequencies = frame.iter().map(|(freq, _)| *freq).collect();
+
+        for (sum, (_, amp)) in self.pending.iter_mut().zip(frame) {
+            *sum += amp;
+        }
+        self.pending_frames += 1;
+
+        if self.pending_frames < self.frames_per_row.max(1) {
+            return;
+        }
+
+        let frames = self.pending_frames as f32;
+        let mut row: Vec<f32> = self.pending.iter().map(|sum| sum / frames).collect();
+
+        self.pending.iter_mut().for_each(|sum| *sum = 0.0);
+        self.pending_frames = 0;
+
+        if let Some(previous) = self.rows.front() {
+            let smoothing = self.smoothing.clamp(0.0, 0.99);
+
+            for (amp, previous) in row.iter_mut().zip(previous) {
+                *amp = smoothing * previous + (1.0 - smoothing) * *amp;
+            }
+        }
+
+        self.rows.push_front(row);
+        self.rows.truncate(self.capacity);
+    }
+
+    /// The history and its parameters, to be saved with `WaterfallSession::save`.
+    pub fn session(&self, saved_at_ms: u64, analysis_sample_rate: u32, row_duration: f32) -> WaterfallSession {
+        WaterfallSession {
+            version: SESSION_VERSION,
+            saved_at_ms,
+            analysis_sample_rate,
+            row_duration,
+            frames_per_row: self.frames_per_row,
+            smoothing: self.smoothing,
+            frequencies: self.frequencies.clone(),
+            rows: self.rows.iter().cloned().collect()
+        }
+    }
+
+    /// Replaces the history with a saved session.
+    pub fn restore(&mut self, session: WaterfallSession) {
+        self.clear();
+        self.frequencies = session.frequencies;
+        self.pending = vec![0.0; self.frequencies.len()];
+        self.frames_per_row = session.frames_per_row.max(1);
+        self.smoothing = session.smoothing;
+        self.row_duration = session.row_duration;
+        self.rows = session.rows.into_iter().take(self.capacity).collect();
+        self.redraw();
+    }
+
+    fn bin_width(&self) -> i32 {
+        match self.frequencies.as_slice() {
+            [first, second, ..] => second - first,
+            _ => 1
+        }
+    }
+
+    fn x_range(&self) -> Range<i32> {
+        0..self.frequencies.last().map(|freq| freq + self.bin_width()).unwrap_or(1)
+    }
+
+    /// Vertical span of the row `index` rows older than the newest one on an axis from 0 to `height`.
+    fn row_span(&self, index: usize, height: i32) -> (i32, i32) {
+        match self.direction {
+            WaterfallDirection::NewestTop => (height - index as i32 - 1, height - index as i32),
+            WaterfallDirection::NewestBottom => (index as i32, index as i32 + 1)
+        }
+    }
+
+    /// Number of rows between the newest row and the position `y` on an axis from 0 to `height`.
+    fn rows_ago(&self, y: i32, height: i32) -> i32 {
+        match self.direction {
+            WaterfallDirection::NewestTop => height - y,
+            WaterfallDirection::NewestBottom => y
+        }
+    }
+
+    fn time_label(&self, y: i32, height: i32, row_duration: f32) -> String {
+        format!("{:.1}", self.rows_ago(y, height) as f32 * row_duration)
+    }
+
+    /// One colored cell per bin and row, the newest row at the edge given by `direction`. The
+    /// cells are produced lazily, so drawing a long history doesn't build them all up front.
+    fn cells(&self, height: i32) -> impl Iterator<Item = PlotRectangle<(i32, i32)>> + '_ {
+        let bin_width = self.bin_width();
+
+        self.rows.iter().enumerate().flat_map(move |(index, row)| {
+            let (bottom, top) = self.row_span(index, height);
+
+            self.frequencies.iter().zip(row).map(move |(freq, amp)| {
+                PlotRectangle::new([(*freq, bottom), (freq + bin_width, top)], Self::color(*amp).filled())
+            })
+        })
+    }
+
+    /// Renders the whole history to a PNG with a frequency axis and a time axis, in the same
+    /// direction as the live view. `row_duration` is the time covered by one row in seconds.
+    pub fn export_png<P: AsRef<Path>>(&self, path: P, row_duration: f32) -> Result<(), AudiaError> {
+        if self.rows.is_empty() {
+            return Err(AudiaError::from("The waterfall is empty, nothing to export"));
+        }
+
+        let rows = self.rows.len() as i32;
+        let height = EXPORT_MARGIN + rows as u32 * EXPORT_ROW_HEIGHT;
+        let to_error = |error| AudiaError::from(format!("Could not export the waterfall: {error:?}"));
+
+        let root = BitMapBackend::new(path.as_ref(), (EXPORT_WIDTH, height)).into_drawing_area();
+        root.fill(&WHITE).map_err(to_error)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .set_all_label_area_size(EXPORT_LABEL_AREA_SIZE)
+            .build_cartesian_2d(self.x_range(), 0..rows)
+            .map_err(to_error)?;
+
+        chart.configure_mesh()
+            .disable_mesh()
+            .x_desc("Frequency (Hz)")
+            .y_desc("Time ago (s)")
+            .y_label_formatter(&|y| self.time_label(*y, rows, row_duration))
+            .draw()
+            .map_err(to_error)?;
+
+        chart.draw_series(self.cells(rows)).map_err(to_error)?;
+        root.present().map_err(to_error)?;
+
+        log::info!("Exported {} waterfall rows to {}", rows, path.as_ref().display());
+
+        Ok(())
+    }
+
+    /// Maps an amplitude to a color from dark blue (silence) to red (full scale).
+    fn color(amplitude: f32) -> HSLColor {
+        let level = (to_dbfs(amplitude) - DB_FLOOR) / -DB_FLOOR;
+
+        HSLColor((1.0 - level as f64) * 0.66, 1.0, 0.1 + 0.4 * level as f64)
+    }
+}
+
+impl Chart<UIMessage> for Waterfall {
+    type State = ();
+
+    fn draw<R: Renderer, F: Fn(&mut Frame)>(&self, renderer: &R, bounds: Size, draw_fn: F) -> Geometry {
+        renderer.draw_cache(&self.cache, bounds, draw_fn)
+    }
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut builder: ChartBuilder<DB>) {
+        let height = self.capacity as i32;
+        let y_range: Range<i32> = 0..height;
+
+        let mut chart = builder
+            .set_all_label_area_size(self.style.label_area_size())
+            .build_cartesian_2d(self.x_range(), y_range)
+            .expect("Failed to build chart");
+
+        self.style.fill_background(&chart);
+
+        let mut mesh = chart.configure_mesh();
+        self.style.apply(&mut mesh);
+        mesh.disable_y_mesh()
+            .y_desc("Time ago (s)")
+            .y_label_formatter(&|y| self.time_label(*y, height, self.row_duration))
+            .draw()
+            .expect("Failed to draw mesh");
+
+        chart.draw_series(self.cells(height))
+            .expect("Failed to draw waterfall");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::waterfall::{Waterfall, WaterfallDirection, WaterfallSession};
+
+    #[test]
+    fn frames_are_aggregated_into_rows() {
+        let mut waterfall = Waterfall::new(10);
+        waterfall.frames_per_row = 2;
+
+        waterfall.push(&[(0, 0.2), (100, 0.4)]);
+        assert_eq!(waterfall.rows().count(), 0);
+
+        waterfall.push(&[(0, 0.4), (100, 0.0)]);
+        let rows: Vec<_> = waterfall.rows().cloned().collect();
+
+        assert_eq!(rows.len(), 1);
+        assert!((rows[0][0] - 0.3).abs() < 1e-6);
+        assert!((rows[0][1] - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn new_rows_are_blended_with_the_previous_one() {
+        let mut waterfall = Waterfall::new(10);
+        waterfall.smoothing = 0.75;
+
+        waterfall.push(&[(0, 1.0)]);
+        waterfall.push(&[(0, 0.0)]);
+
+        let newest = waterfall.rows().next().unwrap();
+        assert!((newest[0] - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn empty_history_is_not_exported() {
+        let waterfall = Waterfall::new(3);
+        let path = std::env::temp_dir().join("audia-empty-waterfall.png");
+
+        assert!(waterfall.export_png(&path, 0.1).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn sessions_are_saved_and_restored() {
+        let dir = std::env::temp_dir().join("audia-test-sessions");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut waterfall = Waterfall::new(10);
+        waterfall.frames_per_row = 2;
+        for amp in [0.1, 0.2, 0.3, 0.4] {
+            waterfall.push(&[(0, amp), (100, amp / 2.0)]);
+        }
+
+        let name = waterfall.session(1_700_000_000_000, 48000, 0.02).save(&dir).unwrap();
+        assert_eq!(WaterfallSession::list(&dir), vec![name.clone()]);
+
+        let mut restored = Waterfall::new(10);
+        restored.restore(WaterfallSession::load(&dir, &name).unwrap());
+
+        assert_eq!(restored.frames_per_row, 2);
+        assert_eq!(restored.rows().collect::<Vec<_>>(), waterfall.rows().collect::<Vec<_>>());
+        assert_eq!(restored.x_range(), waterfall.x_range());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flipped_direction_puts_the_newest_row_at_the_bottom() {
+        let mut waterfall = Waterfall::new(10);
+
+        assert_eq!(waterfall.row_span(0, 10), (9, 10));
+        assert_eq!(waterfall.rows_ago(10, 10), 0);
+
+        waterfall.direction = WaterfallDirection::NewestBottom;
+        assert_eq!(waterfall.row_span(0, 10), (0, 1));
+        assert_eq!(waterfall.row_span(3, 10), (3, 4));
+        // the labels count from the newest row in both directions
+        assert_eq!(waterfall.rows_ago(0, 10), 0);
+        assert_eq!(waterfall.time_label(4, 10, 0.5), "2.0");
+    }
+
+    #[test]
+    fn history_is_limited_to_the_capacity() {
+        let mut waterfall = Waterfall::new(3);
+
+        for _ in 0..5 {
+            waterfall.push(&[(0, 0.5)]);
+        }
+
+        assert_eq!(waterfall.rows().count(), 3);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+use iced::{Element, Length, Size};
+use iced::widget::canvas::{Cache, Frame, Geometry};
+use plotters::backend::DrawingBackend;
+use plotters::chart::ChartBuilder;
+use plotters::series::LineSeries;
+use plotters::style::{Color, RED};
+use plotters_iced::{Chart, ChartWidget, Renderer};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::SampleType;
+use crate::ui::UIMessage;
+use crate::ui::style::DisplayStyle;
+
+/// Selectable numbers of displayed samples
+pub const WINDOW_SIZES: [usize; 5] = [256, 512, 1024, 2048, 4096];
+
+pub const DEFAULT_WINDOW: usize = 256;
+
+/// Where a sweep of the oscilloscope starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Trigger {
+    /// Always shows the most recent samples
+    #[default]
+    Free,
+    RisingEdge,
+    FallingEdge
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 3] = [Trigger::Free, Trigger::RisingEdge, Trigger::FallingEdge];
+
+    fn fires(&self, previous: SampleType, current: SampleType, level: f32) -> bool {
+        match self {
+            Trigger::Free => false,
+            Trigger::RisingEdge => previous < level && current >= level,
+            Trigger::FallingEdge => previous > level && current <= level
+        }
+    }
+}
+
+/// How far before `current` the signal crossed `level`, in samples between 0 and 1.
+fn crossing_lead(previous: SampleType, current: SampleType, level: f32) -> f32 {
+    if current == previous {
+        0.0
+    } else {
+        ((current - level) / (current - previous)).clamp(0.0, 1.0)
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Trigger::Free => "Free running",
+            Trigger::RisingEdge => "Rising edge",
+            Trigger::FallingEdge => "Falling edge"
+        })
+    }
+}
+
+/// Oscilloscope view of the most recent time-domain samples.
+pub struct Waveform {
+    /// Recent samples, twice the window so a trigger point can be found in the older half
+    history: VecDeque<SampleType>,
+    window: usize,
+    pub trigger: Trigger,
+    /// Level the trigger has to cross, relative to full scale
+    pub trigger_level: f32,
+    sample_rate: u32,
+    pub style: DisplayStyle,
+    cache: Cache
+}
+
+impl Waveform {
+    pub fn new(window: usize, sample_rate: u32) -> Self {
+        Self {
+            history: VecDeque::with_capacity(2 * window),
+            window,
+            trigger: Trigger::default(),
+            trigger_level: 0.0,
+            sample_rate,
+            style: DisplayStyle::default(),
+            cache: Cache::new()
+        }
+    }
+
+    pub fn view(&self) -> Element<UIMessage> {
+        ChartWidget::new(self)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    /// Marks the chart as outdated so it is rendered again on the next frame.
+    pub fn redraw(&self) {
+        self.cache.clear();
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Uses the closest of `WINDOW_SIZES`, so a window from the settings file can be picked again.
+    pub fn set_window(&mut self, window: usize) {
+        self.window = WINDOW_SIZES.into_iter()
+            .min_by_key(|size| size.abs_diff(window))
+            .unwrap_or(DEFAULT_WINDOW);
+        self.trim();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.history.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn push(&mut self, samples: &[SampleType]) {
+        self.history.extend(samples);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let excess = self.history.len().saturating_sub(2 * self.window);
+        self.history.drain(..excess);
+    }
+
+    /// Index of the first sample of the sweep, and how far before it the trigger level was
+    /// crossed in samples. The lead is 0 when running free or when no crossing was found.
+    fn sweep_start(&self) -> (usize, f32) {
+        let latest = self.history.len().saturating_sub(self.window);
+
+        (1..=latest)
+            .rev()
+            .find(|&index| self.trigger.fires(self.history[index - 1], self.history[index], self.trigger_level))
+            .map(|index| (index, crossing_lead(self.history[index - 1], self.history[index], self.trigger_level)))
+            .unwrap_or((latest, 0.0))
+    }
+
+    /// The samples of the current sweep. With a trigger, the sweep starts at the latest crossing
+    /// that still leaves a full window; without one the most recent samples are shown.
+    pub fn displayed(&self) -> Vec<SampleType> {
+        let (start, _) = self.sweep_start();
+
+        self.history.range(start..).take(self.window).copied().collect()
+    }
+
+    /// The sweep as (time in samples, amplitude) points. A triggered sweep starts at the
+    /// interpolated crossing, so it doesn't jitter by up to a sample between redraws.
+    pub fn sweep_points(&self) -> Vec<(f32, SampleType)> {
+        let (start, lead) = self.sweep_start();
+        let window = self.window as f32;
+
+        let crossing = (lead > 0.0).then_some((0.0, self.trigger_level));
+        let samples = self.history.range(start..).take(self.window).enumerate()
+            .map(|(index, sample)| (index as f32 + lead, *sample))
+            .filter(|(time, _)| *time <= window);
+
+        crossing.into_iter().chain(samples).collect()
+    }
+}
+
+impl Chart<UIMessage> for Waveform {
+    type State = ();
+
+    fn draw<R: Renderer, F: Fn(&mut Frame)>(&self, renderer: &R, bounds: Size, draw_fn: F) -> Geometry {
+        renderer.draw_cache(&self.cache, bounds, draw_fn)
+    }
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut builder: ChartBuilder<DB>) {
+        let ms_per_sample = 1000.0 / self.sample_rate.max(1) as f32;
+        let duration = self.window as f32 * ms_per_sample;
+
+        let mut chart = builder
+            .set_all_label_area_size(self.style.label_area_size())
+            .build_cartesian_2d(0.0..duration, -1.0f32..1.0)
+            .expect("Failed to build chart");
+
+        self.style.fill_background(&chart);
+
+        let mut mesh = chart.configure_mesh();
+        self.style.apply(&mut mesh);
+        mesh.x_desc("Time (ms)")
+            .y_desc("Amplitude")
+            .draw()
+            .expect("Failed to draw mesh");
+
+        if self.trigger != Trigger::Free {
+            chart.draw_series(LineSeries::new([(0.0, self.trigger_level), (duration, self.trigger_level)], RED.mix(0.4)))
+                .expect("Failed to draw trigger level");
+        }
+
+        chart.draw_series(LineSeries::new(
+            self.sweep_points().into_iter().map(|(time, sample)| (time * ms_per_sample, sample)),
+            self.style.line_color().stroke_width(self.style.line_width())))
+            .expect("Failed to draw waveform");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::waveform::{DEFAULT_WINDOW, Trigger, Waveform};
+
+    #[test]
+    fn free_running_shows_the_latest_window() {
+        let mut waveform = Waveform::new(4, 48000);
+
+        waveform.push(&[0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+
+        assert_eq!(waveform.displayed(), vec![0.2, 0.3, 0.4, 0.5]);
+    }
+
+    #[test]
+    fn window_snaps_to_the_listed_sizes() {
+        let mut waveform = Waveform::new(DEFAULT_WINDOW, 48000);
+
+        waveform.set_window(0);
+        assert_eq!(waveform.window(), 256);
+        waveform.set_window(1500);
+        assert_eq!(waveform.window(), 1024);
+        waveform.set_window(1_000_000);
+        assert_eq!(waveform.window(), 4096);
+    }
+
+    #[test]
+    fn rising_edge_aligns_the_sweep() {
+        let mut waveform = Waveform::new(3, 48000);
+        waveform.trigger = Trigger::RisingEdge;
+
+        // crossings at index 1 and 5, only the first one leaves a full window
+        waveform.push(&[-0.5, 0.5, 0.2, -0.4, -0.2, 0.6]);
+
+        assert_eq!(waveform.displayed(), vec![0.5, 0.2, -0.4]);
+    }
+
+    #[test]
+    fn triggered_sweep_starts_at_the_interpolated_crossing() {
+        let mut waveform = Waveform::new(3, 48000);
+        waveform.trigger = Trigger::FallingEdge;
+        waveform.trigger_level = 0.25;
+
+        // falls through 0.25 three quarters of the way from 1.0 to 0.0
+        waveform.push(&[1.0, 0.0, -1.0, 0.0, 1.0, 1.0]);
+
+        assert_eq!(waveform.sweep_points(), vec![(0.0, 0.25), (0.25, 0.0), (1.25, -1.0), (2.25, 0.0)]);
+    }
+
+    #[test]
+    fn missing_trigger_falls_back_to_the_latest_window() {
+        let mut waveform = Waveform::new(2, 48000);
+        waveform.trigger = Trigger::RisingEdge;
+        waveform.trigger_level = 0.9;
+
+        waveform.push(&[0.0, 0.1, 0.2, 0.3]);
+
+        assert_eq!(waveform.displayed(), vec![0.2, 0.3]);
+    }
+}
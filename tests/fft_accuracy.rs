@@ -0,0 +1,139 @@
+//! Checks the reported peak against synthetic sines over the supported sample rates and frame
+//! sizes. Analyzing with the wrong sample rate (as happened with a hardcoded 48 kHz) scales every
+//! reported frequency and fails these tests for all other rates.
+
+use std::f32::consts::PI;
+
+use spectrum_analyzer::FrequencyLimit;
+
+use audia::engine::analysis::{self, SpectrumPoint};
+
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 96000];
+const FRAME_SIZES: [usize; 5] = [256, 512, 1024, 2048, 4096];
+const FREQUENCIES: [f32; 6] = [110.0, 440.0, 997.0, 1500.0, 2000.0, 5000.0];
+
+/// Peaks closer to DC than this many bins interfere with their mirror image and aren't checked
+const MIN_BINS: f32 = 4.0;
+
+fn sine(freq: f32, amplitude: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| amplitude * (2.0 * PI * freq * i as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+fn bin_width(sample_rate: u32, frame_size: usize) -> f32 {
+    sample_rate as f32 / frame_size as f32
+}
+
+fn analyze(freq: f32, amplitude: f32, sample_rate: u32, frame_size: usize) -> (Vec<SpectrumPoint>, SpectrumPoint) {
+    let spectrum = analysis::spectrum(&sine(freq, amplitude, sample_rate, frame_size), sample_rate, FrequencyLimit::All)
+        .expect("Could not extract frequency spectrum");
+    let peak = analysis::peak(&spectrum).expect("Empty spectrum");
+
+    (spectrum, peak)
+}
+
+/// Calls `check` for every combination where the frequency is well inside the analyzed range.
+fn for_each_case(mut check: impl FnMut(f32, u32, usize)) {
+    let mut cases = 0;
+
+    for sample_rate in SAMPLE_RATES {
+        for frame_size in FRAME_SIZES {
+            let width = bin_width(sample_rate, frame_size);
+
+            for freq in FREQUENCIES {
+                if freq < MIN_BINS * width || freq > sample_rate as f32 / 2.0 - MIN_BINS * width {
+                    continue;
+                }
+
+                check(freq, sample_rate, frame_size);
+                cases += 1;
+            }
+        }
+    }
+
+    assert!(cases > 50, "only {cases} cases were checked");
+}
+
+#[test]
+fn spectrum_covers_up_to_nyquist() {
+    for sample_rate in SAMPLE_RATES {
+        for frame_size in FRAME_SIZES {
+            let (spectrum, _) = analyze(1000.0, 1.0, sample_rate, frame_size);
+            let width = bin_width(sample_rate, frame_size);
+
+            assert_eq!(spectrum.len(), frame_size / 2 + 1);
+            assert!((spectrum[1].0 - width).abs() < 1e-3);
+            assert!((spectrum.last().unwrap().0 - sample_rate as f32 / 2.0).abs() < 1e-2);
+        }
+    }
+}
+
+#[test]
+fn strongest_bin_is_nearest_to_the_frequency() {
+    for_each_case(|freq, sample_rate, frame_size| {
+        let (spectrum, _) = analyze(freq, 1.0, sample_rate, frame_size);
+        let index = analysis::peak_bin(&spectrum).unwrap();
+        let width = bin_width(sample_rate, frame_size);
+
+        assert!((spectrum[index].0 - freq).abs() <= width,
+            "{freq} Hz at {sample_rate} Hz / {frame_size}: peak bin at {} Hz", spectrum[index].0);
+    });
+}
+
+#[test]
+fn interpolated_peak_is_within_a_fraction_of_a_bin() {
+    for_each_case(|freq, sample_rate, frame_size| {
+        let (_, (peak_freq, _)) = analyze(freq, 1.0, sample_rate, frame_size);
+        let width = bin_width(sample_rate, frame_size);
+
+        assert!((peak_freq - freq).abs() <= 0.1 * width,
+            "{freq} Hz at {sample_rate} Hz / {frame_size}: estimated {peak_freq} Hz, bin width {width} Hz");
+    });
+}
+
+#[test]
+fn full_scale_sine_reads_close_to_one() {
+    for_each_case(|freq, sample_rate, frame_size| {
+        let (_, (_, peak_amp)) = analyze(freq, 1.0, sample_rate, frame_size);
+
+        // the Hann window loses up to 1.42 dB between bin centers
+        assert!((0.84..=1.01).contains(&peak_amp),
+            "{freq} Hz at {sample_rate} Hz / {frame_size}: amplitude {peak_amp}");
+    });
+}
+
+#[test]
+fn amplitude_scales_with_the_input() {
+    for_each_case(|freq, sample_rate, frame_size| {
+        let (_, (_, full)) = analyze(freq, 1.0, sample_rate, frame_size);
+        let (_, (_, quiet)) = analyze(freq, 0.1, sample_rate, frame_size);
+
+        assert!((quiet / full - 0.1).abs() < 1e-3,
+            "{freq} Hz at {sample_rate} Hz / {frame_size}: ratio {}", quiet / full);
+    });
+}
+
+#[test]
+fn wrong_sample_rate_shifts_the_peak() {
+    let samples = sine(1000.0, 1.0, 96000, 4096);
+    let spectrum = analysis::spectrum(&samples, 48000, FrequencyLimit::All).unwrap();
+    let (misreported, _) = analysis::peak(&spectrum).unwrap();
+
+    assert!((misreported - 500.0).abs() < 5.0, "estimated {misreported} Hz");
+}
+
+#[test]
+fn peak_is_found_within_a_limited_range() {
+    // a louder tone above the limit must not be reported
+    let sample_rate = 48000;
+    let mut samples = sine(440.0, 0.5, sample_rate, 4096);
+    for (sample, loud) in samples.iter_mut().zip(sine(8000.0, 1.0, sample_rate, 4096)) {
+        *sample += loud;
+    }
+
+    let spectrum = analysis::spectrum(&samples, sample_rate, FrequencyLimit::Max(2200.0)).unwrap();
+    let (peak_freq, _) = analysis::peak(&spectrum).unwrap();
+
+    assert!((peak_freq - 440.0).abs() < 2.0, "estimated {peak_freq} Hz");
+}
@@ -0,0 +1,64 @@
+//! Counts the heap allocations of moving packets from a capture callback to the consumer, once
+//! with a new packet for every callback and once with the packets recycled through the pool.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use audia::engine::{PacketPool, PacketType};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const CALLBACKS: usize = 1000;
+const CALLBACK_SAMPLES: usize = 512;
+const CAPACITY: usize = 8;
+
+/// Allocations while `CALLBACKS` packets go through the channel, each received right away.
+fn count_allocations(mut callback: impl FnMut(&[i16]) -> PacketType, mut consume: impl FnMut(PacketType)) -> usize {
+    let (tx, rx) = crossbeam_channel::bounded::<PacketType>(CAPACITY);
+    let data = vec![1000i16; CALLBACK_SAMPLES];
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+
+    for _ in 0..CALLBACKS {
+        tx.try_send(callback(&data)).unwrap();
+        consume(rx.try_recv().unwrap());
+    }
+
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[test]
+fn recycled_packets_are_not_allocated_again() {
+    let convert = |sample: &i16| *sample as f32 / i16::MAX as f32;
+
+    let allocating = count_allocations(|data| data.iter().map(convert).collect(), drop);
+
+    let pool = PacketPool::new(CAPACITY);
+    let pooled = count_allocations(
+        |data| {
+            let mut packet = pool.take(data.len());
+            packet.extend(data.iter().map(convert));
+            packet
+        },
+        |packet| pool.recycle(packet));
+
+    assert!(allocating >= CALLBACKS, "{allocating} allocations without the pool");
+    // only the first packet is allocated, it is reused from then on
+    assert!(pooled <= 1, "{pooled} allocations with the pool");
+}